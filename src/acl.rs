@@ -0,0 +1,587 @@
+//! 访问控制：用户、密码以及每个用户可以执行的命令和访问的 key。
+//!
+//! 规则的写法和 Redis 6 的 ACL 一致，比如 `ACL SETUSER alice on >secret ~cache:* +@read`。
+//! 密码只保存 SHA-256 摘要。连接建立时如果 `default` 用户是 `on nopass`，
+//! 连接直接以 `default` 的身份登录，否则要先 AUTH。
+//!
+//! 每条命令执行之前由 `server` 调用 [`User::check`]，脚本里的 `redis.call` 也一样。
+//! SUBSCRIBE 和 PUBLISH 还要检查频道，规则写法是 `&news:*`。
+
+use crate::{
+    cmd::{
+        self,
+        Spec,
+    },
+    frame::Frame,
+};
+use bytes::Bytes;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        HashSet,
+    },
+    fmt::Write as _,
+    fs,
+    io,
+    path::Path,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// The user new connections are logged in as, and the one `requirepass`
+/// sets the password of.
+pub const DEFAULT_USER: &str = "default";
+
+/// Command categories that can be used in `+@category` rules.
+pub const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "string",
+    "fast",
+    "slow",
+    "connection",
+    "transaction",
+    "scripting",
+    "blocking",
+    "pubsub",
+    "admin",
+    "dangerous",
+];
+
+//管理类命令，默认不应该开放给普通用户
+const ADMIN: &[&str] = &[
+    "ACL",
+    "BGREWRITEAOF",
+    "BGSAVE",
+    "CONFIG",
+    "LASTSAVE",
+    "PSYNC",
+    "REPLCONF",
+    "REPLICAOF",
+    "SAVE",
+    "SHUTDOWN",
+    "SLAVEOF",
+    "SYNC",
+];
+
+/// Whether `spec` is in `category`, e.g. `GET` is in `read`, `string` and
+/// `fast`. Every command is in `all`.
+pub fn in_category(spec: &Spec, category: &str) -> bool {
+    let name = spec.name;
+    match category {
+        "all" => true,
+        "keyspace" => matches!(
+            name,
+            "EXISTS"
+                | "TTL"
+                | "PTTL"
+                | "DBSIZE"
+                | "DUMP"
+                | "DEL"
+                | "EXPIRE"
+                | "PEXPIRE"
+                | "EXPIREAT"
+                | "PEXPIREAT"
+                | "PERSIST"
+                | "FLUSHDB"
+                | "FLUSHALL"
+                | "RESTORE"
+                | "MIGRATE"
+        ),
+        "read" => !spec.write && !spec.noscript && !matches!(name, "PING" | "ECHO"),
+        "write" => spec.write || name == "MIGRATE",
+        "string" => matches!(
+            name,
+            "GET"
+                | "MGET"
+                | "STRLEN"
+                | "SET"
+                | "MSET"
+                | "APPEND"
+                | "INCR"
+                | "DECR"
+                | "INCRBY"
+                | "DECRBY"
+        ),
+        "fast" => matches!(
+            name,
+            "PING"
+                | "ECHO"
+                | "GET"
+                | "STRLEN"
+                | "EXISTS"
+                | "TTL"
+                | "PTTL"
+                | "DBSIZE"
+                | "APPEND"
+                | "INCR"
+                | "DECR"
+                | "INCRBY"
+                | "DECRBY"
+                | "EXPIRE"
+                | "PEXPIRE"
+                | "EXPIREAT"
+                | "PEXPIREAT"
+                | "PERSIST"
+                | "ASKING"
+                | "MULTI"
+                | "DISCARD"
+                | "ROLE"
+                | "LASTSAVE"
+                | "AUTH"
+                | "PUBLISH"
+        ),
+        "slow" => !in_category(spec, "fast"),
+        "connection" => matches!(name, "PING" | "ECHO" | "AUTH" | "ASKING" | "CLIENT"),
+        "transaction" => matches!(name, "MULTI" | "EXEC" | "DISCARD"),
+        "scripting" => matches!(name, "EVAL" | "EVALSHA" | "SCRIPT"),
+        "blocking" => name == "WAIT",
+        "pubsub" => matches!(name, "SUBSCRIBE" | "UNSUBSCRIBE" | "PUBLISH"),
+        "admin" => ADMIN.contains(&name),
+        "dangerous" => {
+            ADMIN.contains(&name)
+                || matches!(
+                    name,
+                    "FLUSHDB"
+                        | "FLUSHALL"
+                        | "RESTORE"
+                        | "MIGRATE"
+                        | "INFO"
+                        | "ROLE"
+                        | "CLUSTER"
+                        | "CLIENT"
+                )
+        }
+        _ => false,
+    }
+}
+
+/// A user and what it is allowed to do.
+///
+/// A new user is disabled, has no passwords and can't run any command or
+/// access any key until rules grant it.
+#[derive(Debug, Clone, Default)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    //不需要密码，任何密码都能通过 AUTH
+    nopass: bool,
+    //SHA-256 的十六进制小写形式
+    passwords: Vec<String>,
+    commands: HashSet<&'static str>,
+    //ACL GETUSER / ACL LIST 显示的命令规则，按设置的顺序保存
+    command_rules: Vec<String>,
+    keys: Vec<String>,
+    channels: Vec<String>,
+}
+
+impl User {
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            ..User::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Apply one ACL rule, such as `on`, `>password`, `~key:*` or `+@read`.
+    pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_ascii_lowercase();
+        match &lower[..] {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.apply("~*")?,
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.apply("&*")?,
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.apply("+@all")?,
+            "nocommands" => self.apply("-@all")?,
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply(rule)?;
+                }
+            }
+            _ => match rule.split_at(rule.chars().next().map_or(0, char::len_utf8)) {
+                (">", password) => self.add_password(hash_password(password)),
+                ("<", password) => self.remove_password(&hash_password(password))?,
+                ("#", hash) => self.add_password(parse_hash(hash)?),
+                ("!", hash) => self.remove_password(&parse_hash(hash)?)?,
+                ("~", pattern) => add_pattern(&mut self.keys, pattern),
+                ("&", pattern) => add_pattern(&mut self.channels, pattern),
+                ("+", command) => self.allow(command, true)?,
+                ("-", command) => self.allow(command, false)?,
+                _ => return Err("Syntax error".to_string()),
+            },
+        }
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), String> {
+        let before = self.passwords.len();
+        self.passwords.retain(|password| password != hash);
+        if self.passwords.len() == before {
+            return Err("no such password".to_string());
+        }
+        Ok(())
+    }
+
+    // +command、-command、+@category、-@category
+    fn allow(&mut self, name: &str, allow: bool) -> Result<(), String> {
+        let specs: Vec<&'static Spec> = match name.strip_prefix('@') {
+            Some(category) => {
+                let category = category.to_ascii_lowercase();
+                if category != "all" && !CATEGORIES.contains(&&category[..]) {
+                    return Err("Unknown command or category name in ACL".to_string());
+                }
+                cmd::COMMANDS
+                    .iter()
+                    .filter(|spec| in_category(spec, &category))
+                    .collect()
+            }
+            None => match cmd::lookup(name.as_bytes()) {
+                Some(spec) => vec![spec],
+                None => return Err("Unknown command or category name in ACL".to_string()),
+            },
+        };
+        for spec in specs {
+            if allow {
+                self.commands.insert(spec.name);
+            } else {
+                self.commands.remove(spec.name);
+            }
+        }
+
+        //+@all、-@all 会覆盖之前所有的命令规则
+        let rule = format!(
+            "{}{}",
+            if allow { '+' } else { '-' },
+            name.to_ascii_lowercase()
+        );
+        if name.eq_ignore_ascii_case("@all") {
+            self.command_rules.clear();
+        }
+        self.command_rules.push(rule);
+        Ok(())
+    }
+
+    /// Whether `password` logs in as this user. Disabled users can't log in.
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&hash_password_bytes(password)))
+    }
+
+    /// Check that the user may run the command in `args`, and access every
+    /// key it touches. Returns the `NOPERM` error reply otherwise.
+    pub fn check(&self, spec: &Spec, args: &[Bytes]) -> Result<(), Frame> {
+        if !self.commands.contains(spec.name) {
+            return Err(Frame::Error(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name,
+                spec.name.to_ascii_lowercase()
+            )));
+        }
+        let denied = cmd::key_args(spec, args)
+            .into_iter()
+            .any(|key| !matches_any(&self.keys, key));
+        if denied {
+            return Err(Frame::Error(
+                "NOPERM No permissions to access a key".to_string(),
+            ));
+        }
+        let channels = match spec.name {
+            "SUBSCRIBE" => &args[1..],
+            "PUBLISH" => &args[1..2],
+            _ => &[],
+        };
+        if channels
+            .iter()
+            .any(|channel| !self.can_access_channel(channel))
+        {
+            return Err(Frame::Error(
+                "NOPERM No permissions to access a channel".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the user may publish or subscribe to `channel`.
+    pub fn can_access_channel(&self, channel: &[u8]) -> bool {
+        matches_any(&self.channels, channel)
+    }
+
+    /// The rules that recreate this user, as ACL LIST and the ACL file show
+    /// them: `user <name> on nopass ~* &* +@all`.
+    pub fn describe(&self) -> String {
+        let mut line = format!(
+            "user {} {}",
+            self.name,
+            if self.enabled { "on" } else { "off" }
+        );
+        if self.nopass {
+            line.push_str(" nopass");
+        }
+        for hash in &self.passwords {
+            let _ = write!(line, " #{}", hash);
+        }
+        for pattern in &self.keys {
+            let _ = write!(line, " ~{}", pattern);
+        }
+        for pattern in &self.channels {
+            let _ = write!(line, " &{}", pattern);
+        }
+        let _ = write!(line, " {}", self.command_rules_string());
+        line
+    }
+
+    fn command_rules_string(&self) -> String {
+        if self.command_rules.is_empty() {
+            "-@all".to_string()
+        } else {
+            self.command_rules.join(" ")
+        }
+    }
+
+    /// The reply to ACL GETUSER.
+    pub fn to_frame(&self) -> Frame {
+        let bulk = |s: &str| Frame::Bulk(Bytes::from(s.to_string()));
+        let mut flags = vec![bulk(if self.enabled { "on" } else { "off" })];
+        if self.nopass {
+            flags.push(bulk("nopass"));
+        }
+        let patterns = |prefix: char, patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| format!("{}{}", prefix, pattern))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        Frame::Array(vec![
+            bulk("flags"),
+            Frame::Array(flags),
+            bulk("passwords"),
+            Frame::Array(self.passwords.iter().map(|hash| bulk(hash)).collect()),
+            bulk("commands"),
+            bulk(&self.command_rules_string()),
+            bulk("keys"),
+            bulk(&patterns('~', &self.keys)),
+            bulk("channels"),
+            bulk(&patterns('&', &self.channels)),
+        ])
+    }
+}
+
+/// All the users, shared by every connection.
+#[derive(Debug, Clone)]
+pub struct Acl {
+    users: Arc<Mutex<BTreeMap<String, User>>>,
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl::new()
+    }
+}
+
+impl Acl {
+    /// Only the `default` user, which can do anything without a password.
+    pub fn new() -> Acl {
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), default_user());
+        Acl {
+            users: Arc::new(Mutex::new(users)),
+        }
+    }
+
+    pub fn user(&self, name: &str) -> Option<User> {
+        self.users.lock().unwrap().get(name).cloned()
+    }
+
+    /// The user a new connection is logged in as: `default` if it is
+    /// enabled and needs no password.
+    pub fn initial_user(&self) -> Option<String> {
+        let users = self.users.lock().unwrap();
+        let user = users.get(DEFAULT_USER)?;
+        (user.enabled && user.nopass).then(|| DEFAULT_USER.to_string())
+    }
+
+    pub fn authenticate(&self, name: &str, password: &[u8]) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|user| user.check_password(password))
+    }
+
+    /// Create or update a user. Either all the rules apply or none does.
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut users = self.users.lock().unwrap();
+        let mut user = users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|err| format!("Error in ACL SETUSER modifier '{}': {}", rule, err))?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Delete users, returning how many existed. The `default` user can't
+    /// be deleted.
+    pub fn del_users(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+        let mut users = self.users.lock().unwrap();
+        Ok(names
+            .iter()
+            .filter(|name| users.remove(*name).is_some())
+            .count())
+    }
+
+    pub fn usernames(&self) -> Vec<String> {
+        self.users.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// ACL LIST: every user as a line of rules.
+    pub fn list(&self) -> Vec<String> {
+        self.users
+            .lock()
+            .unwrap()
+            .values()
+            .map(User::describe)
+            .collect()
+    }
+
+    /// Make `password` the only password of the `default` user, or let it
+    /// log in without one when `password` is empty. This is what
+    /// `requirepass` does.
+    pub fn require_pass(&self, password: &str) {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(default_user);
+        user.passwords.clear();
+        if password.is_empty() {
+            user.nopass = true;
+        } else {
+            user.add_password(hash_password(password));
+        }
+    }
+
+    /// Replace all the users with the ones in an ACL file: one `user <name>
+    /// <rules...>` line per user, `#` starts a comment. When the file
+    /// doesn't define `default`, it keeps its initial rules.
+    pub fn load(&self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("can't open ACL file '{}': {}", path.display(), err))?;
+        let mut users = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let at_line = |err: String| format!("{}:{}: {}", path.display(), number + 1, err);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (name, rules) = match &words[..] {
+                [] => continue,
+                [comment, ..] if comment.starts_with('#') => continue,
+                ["user", name, rules @ ..] => (*name, rules),
+                _ => return Err(at_line("lines must start with 'user <name>'".to_string())),
+            };
+            if users.contains_key(name) {
+                return Err(at_line(format!("duplicate user '{}'", name)));
+            }
+            let mut user = User::new(name);
+            for rule in rules {
+                user.apply(rule)
+                    .map_err(|err| at_line(format!("'{}': {}", rule, err)))?;
+            }
+            users.insert(name.to_string(), user);
+        }
+        users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(default_user);
+        *self.users.lock().unwrap() = users;
+        Ok(())
+    }
+
+    /// Write every user to an ACL file that `load` can read back.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = String::new();
+        for line in self.list() {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        //和 CONFIG REWRITE 一样先写临时文件再 rename
+        let tmp = path.with_file_name(format!("temp-{}.acl", std::process::id()));
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+fn default_user() -> User {
+    let mut user = User::new(DEFAULT_USER);
+    for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+        user.apply(rule).unwrap();
+    }
+    user
+}
+
+fn add_pattern(patterns: &mut Vec<String>, pattern: &str) {
+    if !patterns.iter().any(|p| p == pattern) {
+        patterns.push(pattern.to_string());
+    }
+}
+
+fn matches_any(patterns: &[String], text: &[u8]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| cmd::glob_match(pattern.as_bytes(), text))
+}
+
+/// The SHA-256 digest of a password in lowercase hex, as `#<hash>` rules
+/// and ACL GETUSER show it.
+pub fn hash_password(password: &str) -> String {
+    hash_password_bytes(password.as_bytes())
+}
+
+fn hash_password_bytes(password: &[u8]) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(password) {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn parse_hash(hash: &str) -> Result<String, String> {
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(
+            "The password hash must be exactly 64 characters and contain only hexadecimal characters"
+                .to_string(),
+        );
+    }
+    Ok(hash.to_ascii_lowercase())
+}
@@ -0,0 +1,408 @@
+//! Append-only file 持久化。
+//!
+//! 每条写命令在持有 keyspace 锁的时候被编码成 RESP，追加到内存中的 `pending` 缓冲区，
+//! 后台任务再把缓冲区写入文件，并按照 `appendfsync` 策略决定什么时候 fsync：
+//!
+//! * `always`   - 每条写命令回复客户端之前都 fsync
+//! * `everysec` - 每秒 fsync 一次，最多丢失一秒的数据
+//! * `no`       - 从不 fsync，交给操作系统决定
+
+use crate::{
+    cmd,
+    db::{
+        Db,
+        Entry,
+        Feed,
+        State,
+    },
+    frame::{
+        self,
+        Frame,
+    },
+};
+use bytes::Bytes;
+use std::{
+    fmt,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Cursor,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            AtomicU8,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+use tokio::{
+    sync::Notify,
+    time::{
+        self,
+        Duration,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    Always,
+    EverySec,
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FsyncPolicy, String> {
+        match &s.to_ascii_lowercase()[..] {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("invalid appendfsync policy '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        };
+        name.fmt(fmt)
+    }
+}
+
+/// Handle to the append-only file.
+#[derive(Debug, Clone)]
+pub struct Aof {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    //FsyncPolicy 的下标，CONFIG SET appendfsync 可以在运行时修改
+    policy: AtomicU8,
+    //锁的顺序：先 file 再 pending，feed 只需要 pending
+    file: Mutex<File>,
+    pending: Mutex<Pending>,
+    //有新的数据写入 pending 时通知后台任务
+    written: Notify,
+    //最近一次 fsync 成功时的 Pending::fed，小于 fed 说明还有写命令没有落盘
+    synced: AtomicU64,
+    rewriting: AtomicBool,
+    last_write_ok: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    buf: Vec<u8>,
+    //到目前为止追加过的写命令条数
+    fed: u64,
+    //BGREWRITEAOF 期间的写命令，重写完成后追加到新文件的末尾
+    rewrite: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct AofFeed {
+    shared: Arc<Shared>,
+}
+
+impl Feed for AofFeed {
+    fn feed(&mut self, args: &[Bytes]) {
+        let mut pending = self.shared.pending.lock().unwrap();
+        let start = pending.buf.len();
+        encode_command(args, &mut pending.buf);
+        pending.fed += 1;
+        let Pending { buf, rewrite, .. } = &mut *pending;
+        if let Some(rewrite) = rewrite {
+            rewrite.extend_from_slice(&buf[start..]);
+        }
+        drop(pending);
+        self.shared.written.notify_one();
+    }
+}
+
+impl Aof {
+    /// Replay the file at `path` into the keyspace, then open it for
+    /// appending and register it as a feed of `db`.
+    ///
+    /// A command cut off at the end of the file (for example by a crash in the
+    /// middle of a write) is trimmed when `load_truncated` is set, otherwise
+    /// loading fails.
+    pub fn open(db: &Db, path: PathBuf, policy: FsyncPolicy, load_truncated: bool) -> io::Result<Aof> {
+        if path.exists() {
+            let loaded = load(&path, &mut db.lock(), load_truncated)?;
+            println!("DB loaded from append only file: {} commands", loaded);
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let shared = Arc::new(Shared {
+            path,
+            policy: AtomicU8::new(policy as u8),
+            file: Mutex::new(file),
+            pending: Mutex::new(Pending::default()),
+            written: Notify::new(),
+            synced: AtomicU64::new(0),
+            rewriting: AtomicBool::new(false),
+            last_write_ok: AtomicBool::new(true),
+        });
+
+        db.lock().add_feed(Box::new(AofFeed {
+            shared: shared.clone(),
+        }));
+        tokio::spawn(writer(shared.clone()));
+
+        Ok(Aof { shared })
+    }
+
+    pub fn policy(&self) -> FsyncPolicy {
+        self.shared.policy()
+    }
+
+    pub fn set_policy(&self, policy: FsyncPolicy) {
+        self.shared.policy.store(policy as u8, Ordering::Release);
+    }
+
+    pub fn is_rewriting(&self) -> bool {
+        self.shared.rewriting.load(Ordering::Acquire)
+    }
+
+    pub fn last_write_ok(&self) -> bool {
+        self.shared.last_write_ok.load(Ordering::Acquire)
+    }
+
+    /// With the `always` policy, write and fsync everything appended so far.
+    /// Called before replying to a client; does nothing when every write is
+    /// already on disk, e.g. after a read.
+    pub async fn sync_if_always(&self) {
+        if self.shared.policy() == FsyncPolicy::Always && self.shared.has_unsynced() {
+            self.sync().await;
+        }
+    }
+
+    /// Write and fsync everything appended so far, whatever the policy.
+    /// Called on shutdown.
+    pub async fn sync(&self) {
+        let shared = self.shared.clone();
+        let _ = tokio::task::spawn_blocking(move || flush(&shared, true)).await;
+    }
+
+    /// BGREWRITEAOF: write a compacted copy of the keyspace to a new file in
+    /// the background, then swap it in.
+    ///
+    /// Clients are only blocked while the keyspace is cloned; writes that
+    /// happen during the rewrite are buffered and appended to the new file.
+    pub fn rewrite(&self, db: &Db) -> Frame {
+        if self.shared.rewriting.swap(true, Ordering::AcqRel) {
+            return Frame::Error(
+                "ERR Background append only file rewriting already in progress".to_string(),
+            );
+        }
+
+        let snapshot = {
+            let state = db.lock();
+            //在持有 keyspace 锁的时候开始记录，保证快照之后的写命令一条都不会漏掉
+            self.shared.pending.lock().unwrap().rewrite = Some(Vec::new());
+            state.snapshot()
+        };
+
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = rewrite(&shared, snapshot) {
+                println!("background AOF rewrite failed: {}", err);
+                shared.pending.lock().unwrap().rewrite = None;
+            } else {
+                println!("background AOF rewrite finished successfully");
+            }
+            shared.rewriting.store(false, Ordering::Release);
+        });
+
+        Frame::Simple("Background append only file rewriting started".to_string())
+    }
+}
+
+impl Shared {
+    fn policy(&self) -> FsyncPolicy {
+        match self.policy.load(Ordering::Acquire) {
+            0 => FsyncPolicy::Always,
+            1 => FsyncPolicy::EverySec,
+            _ => FsyncPolicy::No,
+        }
+    }
+
+    //后台任务只 write 不 fsync，所以不能只看 pending 是不是空的
+    fn has_unsynced(&self) -> bool {
+        self.pending.lock().unwrap().fed > self.synced.load(Ordering::Acquire)
+    }
+}
+
+//后台任务：把 pending 写入文件；everysec 策略下每秒 fsync 一次
+async fn writer(shared: Arc<Shared>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    loop {
+        let fsync = tokio::select! {
+            _ = shared.written.notified() => false,
+            _ = interval.tick() => shared.policy() == FsyncPolicy::EverySec,
+        };
+        let shared = shared.clone();
+        let _ = tokio::task::spawn_blocking(move || flush(&shared, fsync)).await;
+    }
+}
+
+fn flush(shared: &Shared, fsync: bool) {
+    let mut file = shared.file.lock().unwrap();
+    let (buf, fed) = {
+        let mut pending = shared.pending.lock().unwrap();
+        (std::mem::take(&mut pending.buf), pending.fed)
+    };
+
+    let mut result = file.write_all(&buf);
+    if result.is_ok() && fsync {
+        result = file.sync_data();
+        if result.is_ok() {
+            shared.synced.fetch_max(fed, Ordering::AcqRel);
+        }
+    }
+
+    if let Err(err) = &result {
+        println!("error writing to the AOF file: {}", err);
+    }
+    shared
+        .last_write_ok
+        .store(result.is_ok(), Ordering::Release);
+}
+
+fn rewrite(shared: &Shared, snapshot: Vec<(String, Entry)>) -> io::Result<()> {
+    let tmp = shared.path.with_extension("rewrite.tmp");
+    let mut out = io::BufWriter::new(File::create(&tmp)?);
+
+    let mut buf = Vec::new();
+    for (key, entry) in snapshot {
+        buf.clear();
+        for args in entry_commands(key, entry) {
+            encode_command(&args, &mut buf);
+        }
+        out.write_all(&buf)?;
+    }
+    out.flush()?;
+
+    //替换文件的时候不能有新的写入，所以拿着 file 和 pending 两把锁
+    let mut file = shared.file.lock().unwrap();
+    let mut pending = shared.pending.lock().unwrap();
+    let tail = pending.rewrite.take().unwrap_or_default();
+    out.write_all(&tail)?;
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    std::fs::rename(&tmp, &shared.path)?;
+
+    //pending.buf 里的内容都已经包含在 tail 里了，并且已经落盘
+    pending.buf.clear();
+    shared.synced.fetch_max(pending.fed, Ordering::AcqRel);
+    *file = OpenOptions::new().append(true).open(&shared.path)?;
+    Ok(())
+}
+
+/// The commands that recreate `entry` under `key`.
+pub fn entry_commands(key: String, entry: Entry) -> Vec<Vec<Bytes>> {
+    let key = Bytes::from(key);
+    let mut commands = vec![vec![
+        Bytes::from_static(b"SET"),
+        key.clone(),
+        entry.data,
+    ]];
+    if let Some(when) = entry.expires_at {
+        commands.push(vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            key,
+            Bytes::from(crate::db::to_unix_ms(when).to_string()),
+        ]);
+    }
+    commands
+}
+
+/// Encode a command as a RESP array of bulk strings.
+pub fn encode_command(args: &[Bytes], dst: &mut Vec<u8>) {
+    Frame::Array(args.iter().cloned().map(Frame::Bulk).collect()).encode(dst);
+}
+
+/// Replay the commands in `path`, returning how many were applied.
+fn load(path: &Path, state: &mut State, load_truncated: bool) -> io::Result<usize> {
+    let data = std::fs::read(path)?;
+    let mut cursor = Cursor::new(&data[..]);
+    let mut count = 0;
+
+    loop {
+        let start = cursor.position() as usize;
+        if start == data.len() {
+            return Ok(count);
+        }
+
+        match Frame::check(&mut cursor) {
+            Ok(()) => {}
+            Err(frame::Error::Incomplete) => {
+                return trim_truncated(path, start, data.len(), load_truncated).map(|_| count)
+            }
+            Err(err) => return Err(bad_format(start, err.to_string())),
+        }
+
+        cursor.set_position(start as u64);
+        let frame = Frame::parse(&mut cursor).map_err(|err| bad_format(start, err.to_string()))?;
+        let args = cmd::into_args(frame).map_err(|err| bad_format(start, err.to_string()))?;
+        if let Frame::Error(err) = cmd::execute(state, &args) {
+            return Err(bad_format(start, err));
+        }
+        count += 1;
+    }
+}
+
+//文件末尾有一条不完整的命令，通常是写到一半的时候进程崩溃了
+fn trim_truncated(path: &Path, valid: usize, len: usize, load_truncated: bool) -> io::Result<()> {
+    if !load_truncated {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "AOF {} is truncated at offset {} (file size {}). Start with \
+                 `--aof-load-truncated yes` to trim the incomplete tail.",
+                path.display(),
+                valid,
+                len
+            ),
+        ));
+    }
+
+    println!(
+        "!!! Warning: short read while loading the AOF file {}!!! Truncating the AOF at offset {}",
+        path.display(),
+        valid
+    );
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(valid as u64)
+}
+
+fn bad_format(offset: usize, reason: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Bad file format reading the append only file at offset {}: {}",
+            offset, reason
+        ),
+    )
+}
@@ -1,88 +1,25 @@
-use bytes::Bytes;
-use mini_redis::client;
-use tokio::sync::{
-    mpsc,
-    oneshot,
-};
-
-#[derive(Debug)]
-enum Command {
-    Get {
-        key: String,
-        //接收server resoonse的发送端
-        resp: oneshot::Sender<mini_redis::Result<Option<Bytes>>>,
-    },
-    Set {
-        key: String,
-        val: Bytes,
-        resp: oneshot::Sender<mini_redis::Result<()>>,
-    },
-}
+use my_redis::SharedClient;
 
 #[tokio::main()]
-async fn main() {
-    let (tx1, mut rx) = mpsc::channel(32);
-
-    let manager = tokio::spawn(async move {
-        let mut client = client::connect("127.0.0.1:6379").await.unwrap();
-
-        while let Some(message) = rx.recv().await {
-            use Command::*;
-
-            match message {
-                Get { key, resp } => {
-                    //从server端拿到的返回res
-                    let res = client.get(&key).await;
-                    //将res通过Get的sender: resp发送出去
-                    let _ = resp.send(res);
-                }
-                Set { key, val, resp } => {
-                    //从server端拿到的返回res
-                    let res = client.set(&key, val).await;
-                    //将res通过Set的sender: resp发送出去
-                    let _ = resp.send(res);
-                }
-            }
-        }
-    });
+async fn main() -> mini_redis::Result<()> {
+    //manager 任务独占连接，client 可以克隆到多个任务里使用
+    let client = SharedClient::connect("127.0.0.1:6379").await?;
 
-    let tx2 = tx1.clone();
+    let client1 = client.clone();
     let task1 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let message = Command::Get {
-            key: "foo".to_string(),
-            resp: resp_tx,
-        };
-
-        //发送Get请求
-        tx1.send(message).await.unwrap();
-
         //等待server回复
-        let res_of_get = resp_rx.await;
+        let res_of_get = client1.get("foo").await;
         println!("GOT Result = {:?}", res_of_get);
     });
 
     let task2 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        //构造消息
-        let message = Command::Set {
-            key: "hello".to_string(),
-            val: "world".into(),
-            resp: resp_tx,
-        };
-
-        //发送消息到manager
-        tx2.send(message).await.unwrap();
-
-        //等待接收消息
-        let res_of_set = resp_rx.await;
-
+        let res_of_set = client.set("hello", "world".into()).await;
         println!("SET Result = {:?}", res_of_set)
     });
 
     //set
-    task2.await.unwrap();
+    task2.await?;
     //get
-    task1.await.unwrap();
-    manager.await.unwrap();
+    task1.await?;
+    Ok(())
 }
@@ -0,0 +1,19 @@
+use my_redis::sentinel::{
+    self,
+    SentinelConfig,
+};
+use tokio::net::TcpListener;
+
+#[tokio::main()]
+async fn main() -> mini_redis::Result<()> {
+    //比如 `--bind 127.0.0.1 --port 26379 --monitor mymaster 127.0.0.1 6379 2 --known-sentinel mymaster 127.0.0.1 26380`
+    let mut config = SentinelConfig::default();
+    config.parse_args(std::env::args().skip(1))?;
+
+    let listener = TcpListener::bind((&config.bind[..], config.port)).await?;
+
+    println!("sentinel listening on {}:{}", config.bind, config.port);
+
+    sentinel::run(listener, config).await?;
+    Ok(())
+}
@@ -1,74 +1,57 @@
-use bytes::Bytes;
-use mini_redis::{
-    Connection,
-    Frame,
+use my_redis::{
+    server,
+    Config,
 };
-use std::{
-    collections::HashMap,
-    sync::{
-        Arc,
-        Mutex,
-    },
-};
-use tokio::net::{
-    TcpListener,
-    TcpStream,
-};
-
-type Db = Arc<Mutex<HashMap<String, Bytes>>>;
+use tokio::signal;
 
 #[tokio::main()]
-async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
-
-    println!("listening");
-
-    let db = Arc::new(Mutex::new(HashMap::new()));
-
-    loop {
-        //accept是异步函数返回impl Future = Result<(TcpStream,SocketAddr),Error>
-        let (stream, _) = listener.accept().await.unwrap();
-        let db = db.clone();
-        println!("Accepted");
-        //引入多线程
-        tokio::spawn(async move {
-            process(stream, db).await;
-        });
+async fn main() -> mini_redis::Result<()> {
+    //和 redis-server 一样：可选的配置文件路径，然后是覆盖配置文件的参数，
+    //比如 `redis.conf --port 6380 --appendonly yes --appendfsync always`
+    let mut config = Config::default();
+    let mut args = std::env::args().skip(1).peekable();
+    if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+        config.load_file(path)?;
     }
-}
+    config.parse_args(args)?;
 
-async fn process(socket: TcpStream, db: Db) {
-    use mini_redis::Command::{
-        self,
-        Get,
-        Set,
-    };
+    let listeners = server::bind(&config).await?;
 
-    //mini-redis对读写做了封装，已经将字节流转换为data frame(数据帧 = redis命令 + 数据)
-    let mut connection = Connection::new(socket);
+    if config.port != 0 {
+        println!("listening on {}:{}", config.bind.join(","), config.port);
+    }
+    if config.tls_port != 0 {
+        println!(
+            "listening for TLS on {}:{}",
+            config.bind.join(","),
+            config.tls_port
+        );
+    }
+    if let Some(path) = &config.unixsocket {
+        println!("listening on {}", path.display());
+    }
+    if config.metrics_port != 0 {
+        println!(
+            "serving metrics on {}:{}",
+            config.bind.join(","),
+            config.metrics_port
+        );
+    }
 
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        println!("Got: {:?}", frame);
-        let response = match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                let mut db = db.lock().unwrap();
+    server::serve_with_shutdown(listeners, config, shutdown_signal()).await
+}
 
-                //let b:&Bytes = cmd.value();
-                //不加clone的话，value返回的是&Bytes引用
-                db.insert(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".to_string())
-            }
-            Get(cmd) => {
-                let db = db.lock().unwrap();
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Bulk(value.clone())
-                } else {
-                    Frame::Null
-                }
-            }
-            cmd => panic!("unimplemented {:?}", cmd),
-        };
-        //send error to client (Error: "unimplemented")
-        connection.write_frame(&response).await.unwrap();
+//Ctrl-C 或者 SIGTERM（比如 `kill`、容器停止）都按 SHUTDOWN 处理
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
     }
+    #[cfg(not(unix))]
+    let _ = signal::ctrl_c().await;
 }
@@ -1,53 +1,118 @@
-use std::time::Duration;
+use std::{
+    future::Future,
+    time::Duration,
+};
 
+use crate::{
+    client::{
+        Client,
+        Message,
+        Subscriber,
+        Timeouts,
+    },
+    error::Result,
+    frame::Frame,
+    pipeline::Pipeline,
+    reconnect::{
+        self,
+        ReconnectPolicy,
+    },
+    types::{
+        FromRedisValue,
+        ToRedisArgs,
+    },
+};
 use bytes::Bytes;
-use mini_redis::client;
 use tokio::{
     net::ToSocketAddrs,
     runtime::Runtime,
 };
-//use mini_redis::Result;
-
-/// For performance reasons, boxing is avoided in any hot path. For example, in
-/// `parse`, a custom error `enum` is defined. This is because the error is hit
-/// and handled during normal execution when a partial frame is received on a
-/// socket. `std::error::Error` is implemented for `parse::Error` which allows it to be converted
-/// `Box<dyn std::error::Error>`.
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
-/// A specialized `Result` type for mini-redis operations.
-///
-/// This is defined as a convenience.
-pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct BlockingClient {
-    //
-    inner: client::Client,
+    //断线时按 ReconnectPolicy 自动重连
+    inner: Client,
     //包含了tokio runtime的实例
     runtime: Runtime,
 }
 
+/// A blocking client in subscribe mode, created by
+/// [`BlockingClient::subscribe`].
+///
+/// Like [`Subscriber`], it subscribes again after a reconnect.
+pub struct BlockingSubscriber {
+    inner: Subscriber,
+    runtime: Runtime,
+}
+
+/// Settings for a [`BlockingClient`], created by [`BlockingClient::builder`].
+///
+/// Without timeouts a hung server blocks the calling thread forever. When a
+/// read or write times out the connection is dropped and the next command
+/// reconnects, so a late reply can't be mistaken for the reply to a later
+/// command.
+#[derive(Debug, Clone, Default)]
+pub struct BlockingClientBuilder {
+    policy: ReconnectPolicy,
+    timeouts: Timeouts,
+}
+
 impl BlockingClient {
     //构造函数
     //建立一个到redis server的连接
     pub fn connect<T: ToSocketAddrs>(addr: T) -> Result<BlockingClient> {
-        // 创建一个单线程的tokio runtime
-        let runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
-        // 在tokio runtime上调用block_on方法阻塞当前线程直到future执行完毕
-        // 使用这个runtime来调用异步的connect连接方法
-        let inner = runtime.block_on(client::connect(addr))?;
+        BlockingClient::connect_with_policy(addr, ReconnectPolicy::default())
+    }
 
-        Ok(BlockingClient { inner, runtime })
+    /// Connect, reconnecting according to `policy` when the connection is
+    /// lost.
+    pub fn connect_with_policy<T: ToSocketAddrs>(
+        addr: T,
+        policy: ReconnectPolicy,
+    ) -> Result<BlockingClient> {
+        BlockingClient::builder()
+            .reconnect_policy(policy)
+            .connect(addr)
+    }
+
+    /// Connect to a server listening on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<BlockingClient> {
+        BlockingClient::builder().connect_unix(path)
+    }
+
+    /// Connect to the TLS port of a server at `host`, checking its
+    /// certificate against `host` with the CAs trusted by `tls`.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        host: &str,
+        port: u16,
+        tls: crate::tls::TlsConnector,
+    ) -> Result<BlockingClient> {
+        BlockingClient::builder().connect_tls(host, port, tls)
+    }
+
+    /// Whether the last command failed in a way (e.g. a timeout) that makes
+    /// the next command reconnect first.
+    pub fn is_broken(&self) -> bool {
+        self.inner.is_broken()
+    }
+
+    /// Configure timeouts and reconnecting before connecting.
+    pub fn builder() -> BlockingClientBuilder {
+        BlockingClientBuilder::default()
     }
 
     //同步接口：通过 block_on 将异步形式的 Client 的方法变成同步调用的形式。
-    pub fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+    //回复转换成调用方需要的类型，比如 get::<Option<String>>、query::<i64, _>(("INCR", key))
+    pub fn query<T: FromRedisValue, A: ToRedisArgs>(&mut self, args: A) -> Result<T> {
+        self.runtime.block_on(self.inner.query(args))
+    }
+
+    pub fn get<T: FromRedisValue>(&mut self, key: &str) -> Result<T> {
         self.runtime.block_on(self.inner.get(key))
     }
 
-    pub fn set(&mut self, key: &str, value: Bytes) -> Result<()> {
+    pub fn set<V: ToRedisArgs>(&mut self, key: &str, value: V) -> Result<()> {
         self.runtime.block_on(self.inner.set(key, value))
     }
 
@@ -55,27 +120,162 @@ impl BlockingClient {
     // 过期时间是一个Duration类型的参数。过期的是key,而不是value,如果key不存在,则会自动创建,
     // 如果key已经存在,则会覆盖原来的value,但是过期时间不会覆盖,如果想要覆盖过期时间,
     // 则需要先del删除key,然后再set_expires设置
-    pub fn set_expires(&mut self, key: &str, value: Bytes, expiration: Duration) -> Result<()> {
+    pub fn set_expires<V: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        value: V,
+        expiration: Duration,
+    ) -> Result<()> {
         self.runtime
             .block_on(self.inner.set_expires(key, value, expiration))
     }
 
-    //mini-redis的publish方法:发布一个消息到指定的channel,返回值是一个u64类型的整数,
-    // 表示有多少个客户端订阅了这个channel。 客户端为什么要订阅消息：
-    // 因为客户端可能在执行某个操作的时候,需要等待某个资源准备好,
-    // 而这个资源的准备可能是一个异步的过程,所以客户端需要订阅这个channel,一旦资源准备好,
-    // 就会发布一个消息到这个channel,客户端就会收到这个消息,然后继续执行后续的操作。
-    // channel为什么是字符串：因为channel是一个字符串,所以可以使用通配符来订阅多个channel,
-    // 比如订阅所有以foo开头的channel,则可以订阅foo*这个channel,订阅所有以foo结尾的channel,
-    // 则可以订阅*foo这个channel。 发布到channel的逻辑:首先需要获取到channel的订阅者列表,
-    // 然后遍历这个列表,将消息发送给每一个订阅者。 channel的订阅者列表:
-    // channel的订阅者列表是一个HashMap,其中key是channel的名字,而value是一个HashSet,
-    // 里面存放了所有订阅了这个channel的客户端的id。
+    /// PUBLISH, returning the number of subscribers that got the message.
     pub fn publish(&mut self, channel: &str, message: Bytes) -> Result<u64> {
         self.runtime.block_on(self.inner.publish(channel, message))
     }
 
-    pub fn subscribe() -> Result<()> {
-        Ok(())
+    //一次写出 pipeline 里的所有命令，按顺序返回每条命令的回复
+    pub fn pipeline(&mut self, pipeline: &Pipeline) -> Result<Vec<Frame>> {
+        self.runtime.block_on(self.inner.pipeline(pipeline))
+    }
+
+    //下面是带期限的版本，timeout 覆盖整个调用，不受 builder 里的读写超时影响
+    pub fn query_with_timeout<T: FromRedisValue, A: ToRedisArgs>(
+        &mut self,
+        args: A,
+        timeout: Duration,
+    ) -> Result<T> {
+        block_on_timeout(&self.runtime, timeout, self.inner.query(args))
+    }
+
+    pub fn get_with_timeout<T: FromRedisValue>(
+        &mut self,
+        key: &str,
+        timeout: Duration,
+    ) -> Result<T> {
+        block_on_timeout(&self.runtime, timeout, self.inner.get(key))
+    }
+
+    pub fn set_with_timeout<V: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        value: V,
+        timeout: Duration,
+    ) -> Result<()> {
+        block_on_timeout(&self.runtime, timeout, self.inner.set(key, value))
+    }
+
+    pub fn pipeline_with_timeout(
+        &mut self,
+        pipeline: &Pipeline,
+        timeout: Duration,
+    ) -> Result<Vec<Frame>> {
+        block_on_timeout(&self.runtime, timeout, self.inner.pipeline(pipeline))
+    }
+
+    /// Enter subscribe mode on `channels`.
+    pub fn subscribe(self, channels: &[&str]) -> Result<BlockingSubscriber> {
+        let inner = self.runtime.block_on(self.inner.subscribe(channels))?;
+        Ok(BlockingSubscriber {
+            inner,
+            runtime: self.runtime,
+        })
+    }
+}
+
+impl BlockingSubscriber {
+    /// The channels currently subscribed to.
+    pub fn channels(&self) -> &[String] {
+        self.inner.channels()
+    }
+
+    /// Subscribe to more channels, waiting for the server to confirm each.
+    pub fn subscribe(&mut self, channels: &[&str]) -> Result<()> {
+        self.runtime.block_on(self.inner.subscribe(channels))
+    }
+
+    /// Block until the next message. Returns `None` when the server closed
+    /// the connection and the policy doesn't allow reconnecting.
+    pub fn next_message(&mut self) -> Result<Option<Message>> {
+        self.runtime.block_on(self.inner.next_message())
+    }
+}
+
+impl BlockingClientBuilder {
+    /// Give up on connecting (or reconnecting) after `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> BlockingClientBuilder {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Fail a command when its reply takes longer than `timeout`.
+    pub fn read_timeout(mut self, timeout: Duration) -> BlockingClientBuilder {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Fail a command when sending it takes longer than `timeout`.
+    pub fn write_timeout(mut self, timeout: Duration) -> BlockingClientBuilder {
+        self.timeouts.write = Some(timeout);
+        self
+    }
+
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> BlockingClientBuilder {
+        self.policy = policy;
+        self
+    }
+
+    pub fn connect<T: ToSocketAddrs>(self, addr: T) -> Result<BlockingClient> {
+        // 创建一个单线程的tokio runtime，读写超时用的是它的 timer
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        // 在tokio runtime上调用block_on方法阻塞当前线程直到future执行完毕
+        // 使用这个runtime来调用异步的connect连接方法
+        let inner = runtime.block_on(Client::connect_with(addr, self.policy, self.timeouts))?;
+
+        Ok(BlockingClient { inner, runtime })
+    }
+
+    /// Connect to a server listening on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(self, path: P) -> Result<BlockingClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let target = reconnect::Target::Unix(path.as_ref().to_path_buf());
+        let inner = runtime.block_on(Client::connect_target(target, self.policy, self.timeouts))?;
+
+        Ok(BlockingClient { inner, runtime })
+    }
+
+    /// Connect to the TLS port of a server at `host`, checking its
+    /// certificate against `host` with the CAs trusted by `tls`.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        self,
+        host: &str,
+        port: u16,
+        tls: crate::tls::TlsConnector,
+    ) -> Result<BlockingClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let inner = runtime.block_on(async {
+            let target = reconnect::Target::tls(host, port, tls).await?;
+            Client::connect_target(target, self.policy, self.timeouts).await
+        })?;
+
+        Ok(BlockingClient { inner, runtime })
     }
 }
+
+//整个调用（包括重连）超过 timeout 就返回 Error::Timeout。被打断的请求会让连接处于 broken 状态，
+//下一条命令重新连接
+fn block_on_timeout<T, F>(runtime: &Runtime, timeout: Duration, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    runtime.block_on(reconnect::timeout(Some(timeout), future))
+}
@@ -0,0 +1,402 @@
+//! 单连接的异步客户端，连接池里放的就是它。
+//!
+//! 和 `mini_redis::client::Client` 不同，它可以发送任意命令，并且区分服务端的错误回复
+//! 和连接本身的错误：后者（包括请求发到一半被取消）会让连接和服务端失去同步，
+//! 之后按 [`ReconnectPolicy`] 重新连接，能安全重发的命令自动重发。
+
+use crate::{
+    connection::BoxedStream,
+    error::{
+        Error,
+        Result,
+    },
+    frame::Frame,
+    pipeline::Pipeline,
+    reconnect::{
+        self,
+        ReconnectPolicy,
+        Target,
+    },
+    types::{
+        FromRedisValue,
+        ToRedisArgs,
+    },
+    Connection,
+};
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    time::Duration,
+};
+use tokio::{
+    net::ToSocketAddrs,
+    time,
+};
+
+/// An async client over a single connection.
+pub struct Client {
+    //重连时使用
+    target: Target,
+    policy: ReconnectPolicy,
+    connection: Connection<BoxedStream>,
+    //请求发出去之后、读到完整回复之前为 true，出错之后保持为 true 直到重连
+    broken: bool,
+    timeouts: Timeouts,
+}
+
+//None 表示不限时
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+}
+
+/// A message received on a subscribed channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    pub content: Bytes,
+}
+
+/// A client in subscribe mode, created by [`Client::subscribe`].
+///
+/// Subscriptions are sent again after a reconnect, so the stream of messages
+/// resumes once the server is back. Messages published while disconnected
+/// are lost.
+pub struct Subscriber {
+    client: Client,
+    channels: Vec<String>,
+    //等待订阅确认时先收到的消息
+    pending: VecDeque<Message>,
+}
+
+impl Client {
+    /// Connect with the default [`ReconnectPolicy`].
+    pub async fn connect<T: ToSocketAddrs>(addr: T) -> Result<Client> {
+        Client::connect_with_policy(addr, ReconnectPolicy::default()).await
+    }
+
+    pub async fn connect_with_policy<T: ToSocketAddrs>(
+        addr: T,
+        policy: ReconnectPolicy,
+    ) -> Result<Client> {
+        Client::connect_with(addr, policy, Timeouts::default()).await
+    }
+
+    /// Connect to a server listening on the Unix socket at `path`, with the
+    /// default [`ReconnectPolicy`].
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<Client> {
+        let target = Target::Unix(path.as_ref().to_path_buf());
+        Client::connect_target(target, ReconnectPolicy::default(), Timeouts::default()).await
+    }
+
+    /// Connect to the TLS port of a server at `host`, checking its
+    /// certificate against `host` with the CAs trusted by `tls`.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        host: &str,
+        port: u16,
+        tls: crate::tls::TlsConnector,
+    ) -> Result<Client> {
+        Client::connect_tls_with_policy(host, port, tls, ReconnectPolicy::default()).await
+    }
+
+    /// Like `connect_tls`, reconnecting according to `policy`.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls_with_policy(
+        host: &str,
+        port: u16,
+        tls: crate::tls::TlsConnector,
+        policy: ReconnectPolicy,
+    ) -> Result<Client> {
+        let target = reconnect::Target::tls(host, port, tls).await?;
+        Client::connect_target(target, policy, Timeouts::default()).await
+    }
+
+    pub(crate) async fn connect_with<T: ToSocketAddrs>(
+        addr: T,
+        policy: ReconnectPolicy,
+        timeouts: Timeouts,
+    ) -> Result<Client> {
+        let target = Target::Tcp(reconnect::resolve(addr).await?);
+        Client::connect_target(target, policy, timeouts).await
+    }
+
+    pub(crate) async fn connect_target(
+        target: Target,
+        policy: ReconnectPolicy,
+        timeouts: Timeouts,
+    ) -> Result<Client> {
+        let stream = reconnect::timeout(timeouts.connect, target.open()).await?;
+        Ok(Client {
+            target,
+            policy,
+            connection: Connection::new(stream),
+            broken: false,
+            timeouts,
+        })
+    }
+
+    /// Whether an I/O or protocol error, or a cancelled request, left the
+    /// connection out of sync with the server. The next command reconnects,
+    /// unless the policy disables reconnecting.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Send any command and return the raw reply. Error replies are returned
+    /// as errors but leave the client usable.
+    ///
+    /// A lost connection is re-established with backoff. The command is then
+    /// sent again only if the policy considers it retryable, since the server
+    /// may already have executed it.
+    pub async fn command(&mut self, args: Vec<Bytes>) -> Result<Frame> {
+        let frame = Frame::Array(args.iter().cloned().map(Frame::Bulk).collect());
+        let retryable = self.policy.is_retryable(&args);
+        match self.request(&[frame], retryable).await?.pop() {
+            Some(Frame::Error(err)) => Err(Error::server(err)),
+            Some(reply) => Ok(reply),
+            None => Err(Error::ConnectionClosed),
+        }
+    }
+
+    /// Send all commands queued in `pipeline` with a single write and return
+    /// their replies in order. See [`Pipeline`] for how error replies and
+    /// atomic pipelines are reported.
+    ///
+    /// The pipeline is resent after a reconnect only if every command in it
+    /// is retryable.
+    pub async fn pipeline(&mut self, pipeline: &Pipeline) -> Result<Vec<Frame>> {
+        if pipeline.is_empty() {
+            return Ok(Vec::new());
+        }
+        let retryable = pipeline
+            .commands()
+            .iter()
+            .all(|args| self.policy.is_retryable(args));
+        let replies = self.request(&pipeline.frames(), retryable).await?;
+        pipeline.replies(replies)
+    }
+
+    //发出请求并读取同样数量的回复，连接断开时按 policy 重连
+    async fn request(&mut self, frames: &[Frame], retryable: bool) -> Result<Vec<Frame>> {
+        let mut attempt = 0;
+        loop {
+            if self.broken {
+                if self.policy.max_retries == 0 {
+                    return Err(Error::ConnectionClosed);
+                }
+                //还没有发出请求，重连失败的话任何命令都可以再试
+                let connect = self.target.open();
+                match reconnect::timeout(self.timeouts.connect, connect).await {
+                    Ok(stream) => {
+                        self.connection = Connection::new(stream);
+                        self.broken = false;
+                    }
+                    Err(err) if attempt >= self.policy.max_retries => return Err(err),
+                    Err(_) => {
+                        attempt += 1;
+                        time::sleep(self.policy.backoff(attempt)).await;
+                        continue;
+                    }
+                }
+            }
+
+            //先标记为 broken，只有读完所有回复才恢复，这样中途出错或者被取消都会留下标记
+            self.broken = true;
+            match self.exchange(frames).await {
+                Ok(replies) => {
+                    self.broken = false;
+                    return Ok(replies);
+                }
+                //只有连接断开才值得重发，协议错误重发也还是一样；超时说明 server 很慢，
+                //也不重发，连接保持 broken，下一条命令重连
+                Err(Error::Io(_) | Error::ConnectionClosed)
+                    if retryable && attempt < self.policy.max_retries =>
+                {
+                    attempt += 1;
+                    time::sleep(self.policy.backoff(attempt)).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn exchange(&mut self, frames: &[Frame]) -> Result<Vec<Frame>> {
+        let Timeouts { read, write, .. } = self.timeouts;
+        reconnect::timeout(write, self.connection.write_frames(frames)).await?;
+        let mut replies = Vec::with_capacity(frames.len());
+        while replies.len() < frames.len() {
+            let reply = reconnect::timeout(read, self.connection.read_frame())
+                .await?
+                .ok_or(Error::ConnectionClosed)?;
+            replies.push(reply);
+        }
+        Ok(replies)
+    }
+
+    /// Enter subscribe mode on `channels`.
+    pub async fn subscribe(self, channels: &[&str]) -> Result<Subscriber> {
+        let mut subscriber = Subscriber {
+            client: self,
+            channels: Vec::new(),
+            pending: VecDeque::new(),
+        };
+        subscriber.subscribe(channels).await?;
+        Ok(subscriber)
+    }
+
+    /// PUBLISH, returning the number of subscribers that got the message.
+    pub async fn publish(&mut self, channel: &str, message: Bytes) -> Result<u64> {
+        self.query(("PUBLISH", channel, message)).await
+    }
+
+    pub async fn ping(&mut self, message: Option<Bytes>) -> Result<Bytes> {
+        self.query(("PING", message)).await
+    }
+
+    /// Send a command built from `args` and convert the reply, for example
+    /// `client.query::<HashMap<String, String>, _>(("HGETALL", key))`.
+    pub async fn query<T: FromRedisValue, A: ToRedisArgs>(&mut self, args: A) -> Result<T> {
+        T::from_redis_value(self.command(args.to_args()).await?)
+    }
+
+    /// GET, converting the value. Use an `Option` to tell missing keys apart:
+    /// `get::<Option<i64>>`.
+    pub async fn get<T: FromRedisValue>(&mut self, key: &str) -> Result<T> {
+        self.query(("GET", key)).await
+    }
+
+    pub async fn set<V: ToRedisArgs>(&mut self, key: &str, value: V) -> Result<()> {
+        self.query(("SET", key, value)).await
+    }
+
+    pub async fn set_expires<V: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        value: V,
+        expiration: Duration,
+    ) -> Result<()> {
+        let ms = expiration.as_millis() as u64;
+        self.query(("SET", key, value, "PX", ms)).await
+    }
+
+    /// DEL, returning the number of keys removed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        self.query(("DEL", keys)).await
+    }
+}
+
+impl Subscriber {
+    /// The channels currently subscribed to.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Subscribe to more channels, waiting for the server to confirm each.
+    pub async fn subscribe(&mut self, channels: &[&str]) -> Result<()> {
+        let client = &mut self.client;
+        let mut args = vec![Bytes::from_static(b"SUBSCRIBE")];
+        args.extend(channels.iter().map(|channel| arg(channel)));
+        let frame = Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+        client.connection.write_frame(&frame).await?;
+
+        for channel in channels {
+            loop {
+                let frame = client
+                    .connection
+                    .read_frame()
+                    .await?
+                    .ok_or(Error::ConnectionClosed)?;
+                match parse_push(frame)? {
+                    Push::Subscribed(confirmed) if confirmed == *channel => break,
+                    Push::Message(message) => self.pending.push_back(message),
+                    Push::Subscribed(_) | Push::Other => {}
+                }
+            }
+            self.channels.push(channel.to_string());
+        }
+        Ok(())
+    }
+
+    /// Wait for the next message. Returns `None` when the server closed the
+    /// connection and the policy doesn't allow reconnecting.
+    pub async fn next_message(&mut self) -> Result<Option<Message>> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Ok(Some(message));
+            }
+            if self.client.broken {
+                self.resubscribe().await?;
+            }
+
+            //read_frame 被取消不会丢数据，不需要标记 broken
+            let frame = match self.client.connection.read_frame().await {
+                Ok(Some(frame)) => frame,
+                Ok(None) if self.client.policy.max_retries == 0 => return Ok(None),
+                Err(err) if self.client.policy.max_retries == 0 => return Err(err),
+                Ok(None) | Err(Error::Io(_) | Error::ConnectionClosed) => {
+                    self.client.broken = true;
+                    continue;
+                }
+                Err(err) => {
+                    self.client.broken = true;
+                    return Err(err);
+                }
+            };
+            if let Push::Message(message) = parse_push(frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    //重新连接并恢复所有订阅
+    async fn resubscribe(&mut self) -> Result<()> {
+        let client = &self.client;
+        let connection =
+            reconnect::connect(&client.target, &client.policy, client.timeouts.connect).await?;
+        self.client.connection = connection;
+        self.client.broken = false;
+        let channels = std::mem::take(&mut self.channels);
+        let channels: Vec<&str> = channels.iter().map(String::as_str).collect();
+        if let Err(err) = self.subscribe(&channels).await {
+            self.client.broken = true;
+            self.channels = channels.iter().map(|channel| channel.to_string()).collect();
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+enum Push {
+    Message(Message),
+    Subscribed(String),
+    Other,
+}
+
+// [message, channel, payload] | [subscribe, channel, count]
+fn parse_push(frame: Frame) -> Result<Push> {
+    let parts = match frame {
+        Frame::Array(parts) => parts,
+        Frame::Error(err) => return Err(Error::server(err)),
+        frame => return Err(Error::protocol(format!("unexpected push {}", frame))),
+    };
+    match &parts[..] {
+        [kind, Frame::Bulk(channel), Frame::Bulk(content)] if *kind == "message" => {
+            Ok(Push::Message(Message {
+                channel: String::from_utf8_lossy(channel).into_owned(),
+                content: content.clone(),
+            }))
+        }
+        [kind, Frame::Bulk(channel), _] if *kind == "subscribe" => Ok(Push::Subscribed(
+            String::from_utf8_lossy(channel).into_owned(),
+        )),
+        _ => Ok(Push::Other),
+    }
+}
+
+fn arg(key: &str) -> Bytes {
+    Bytes::copy_from_slice(key.as_bytes())
+}
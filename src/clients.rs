@@ -0,0 +1,551 @@
+//! 服务端对客户端连接的管理。
+//!
+//! `maxclients` 用 semaphore 实现：每个连接持有一个 permit，拿不到 permit 的新连接
+//! 收到错误后被关闭。CONFIG SET 调小上限时，已经发出去的 permit 没法收回，
+//! 先记下欠的数量，等连接断开时把 permit 丢掉而不是还回去。
+//!
+//! 每个连接还在 [`Clients`] 里登记一份信息（地址、名字、最后一条命令等），
+//! 供 CLIENT LIST / CLIENT KILL 使用，连接结束时随 [`ClientHandle`] 一起注销。
+//! CLIENT PAUSE 的状态也放在这里，由执行命令之前的 [`Clients::wait_unpaused`] 检查。
+
+use crate::{
+    cmd,
+    frame::Frame,
+};
+use bytes::Bytes;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+};
+use tokio::{
+    sync::{
+        watch,
+        Notify,
+        OwnedSemaphorePermit,
+        Semaphore,
+    },
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+/// Limits the number of connected clients.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientLimit {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    semaphore: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    max: usize,
+    //调小上限时还没能收回的 permit 数
+    owed: usize,
+}
+
+/// A connection's slot, given back when it's dropped.
+#[derive(Debug)]
+pub(crate) struct ClientPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    limit: ClientLimit,
+}
+
+impl ClientLimit {
+    pub(crate) fn new(max: usize) -> ClientLimit {
+        ClientLimit {
+            shared: Arc::new(Shared {
+                semaphore: Arc::new(Semaphore::new(max)),
+                state: Mutex::new(State { max, owed: 0 }),
+            }),
+        }
+    }
+
+    /// Take a slot for a new connection, `None` when `maxclients` are
+    /// already connected.
+    pub(crate) fn try_acquire(&self) -> Option<ClientPermit> {
+        let permit = self.shared.semaphore.clone().try_acquire_owned().ok()?;
+        Some(ClientPermit {
+            permit: Some(permit),
+            limit: self.clone(),
+        })
+    }
+
+    /// Change the limit. Connected clients over a lowered limit stay
+    /// connected, new ones are rejected until enough of them are gone.
+    pub(crate) fn set_max(&self, max: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        if max >= state.max {
+            let added = max - state.max;
+            let repaid = added.min(state.owed);
+            state.owed -= repaid;
+            self.shared.semaphore.add_permits(added - repaid);
+        } else {
+            let removed = state.max - max;
+            let forgotten = self.shared.semaphore.forget_permits(removed);
+            state.owed += removed - forgotten;
+        }
+        state.max = max;
+    }
+}
+
+impl Drop for ClientPermit {
+    fn drop(&mut self) {
+        let mut state = self.limit.shared.state.lock().unwrap();
+        if let Some(permit) = self.permit.take() {
+            if state.owed > 0 {
+                state.owed -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+/// Every connected client, for the CLIENT command.
+#[derive(Debug, Clone)]
+pub(crate) struct Clients {
+    shared: Arc<Registry>,
+}
+
+#[derive(Debug)]
+struct Registry {
+    //和 Redis 一样从 1 开始，不会重复使用
+    next_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<Client>>>,
+    pause: watch::Sender<Option<Pause>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+    until: Instant,
+    //false 时只暂停写命令
+    all: bool,
+}
+
+#[derive(Debug)]
+struct Client {
+    id: u64,
+    addr: String,
+    laddr: String,
+    created: Instant,
+    killed: AtomicBool,
+    kill: Notify,
+    info: Mutex<ClientInfo>,
+}
+
+/// What a connection last did, updated by its handler and shown by CLIENT
+/// LIST.
+#[derive(Debug)]
+pub(crate) struct ClientInfo {
+    pub(crate) name: String,
+    pub(crate) user: String,
+    /// Lowercase name of the last command.
+    pub(crate) last_command: String,
+    pub(crate) last_interaction: Instant,
+    /// Bytes read from the socket but not parsed yet, and the free space
+    /// left in the read buffer.
+    pub(crate) qbuf: usize,
+    pub(crate) qbuf_free: usize,
+    /// Number of commands queued since MULTI, `None` outside a transaction.
+    pub(crate) multi: Option<usize>,
+    /// The connection sent SYNC/PSYNC and is now a replica.
+    pub(crate) replica: bool,
+    /// Number of channels the connection is subscribed to.
+    pub(crate) subscriptions: usize,
+}
+
+/// A connection's entry in [`Clients`], removed when it's dropped.
+#[derive(Debug)]
+pub(crate) struct ClientHandle {
+    client: Arc<Client>,
+    clients: Clients,
+}
+
+impl Clients {
+    pub(crate) fn new() -> Clients {
+        Clients {
+            shared: Arc::new(Registry {
+                next_id: AtomicU64::new(1),
+                clients: Mutex::new(BTreeMap::new()),
+                pause: watch::Sender::new(None),
+            }),
+        }
+    }
+
+    /// Register a new connection. `addr` and `laddr` are the peer and local
+    /// addresses as shown by CLIENT LIST.
+    pub(crate) fn register(&self, addr: String, laddr: String) -> ClientHandle {
+        let now = Instant::now();
+        let client = Arc::new(Client {
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            addr,
+            laddr,
+            created: now,
+            killed: AtomicBool::new(false),
+            kill: Notify::new(),
+            info: Mutex::new(ClientInfo {
+                name: String::new(),
+                user: String::new(),
+                last_command: "NULL".to_string(),
+                last_interaction: now,
+                qbuf: 0,
+                qbuf_free: 0,
+                multi: None,
+                replica: false,
+                subscriptions: 0,
+            }),
+        });
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .insert(client.id, client.clone());
+        ClientHandle {
+            client,
+            clients: self.clone(),
+        }
+    }
+
+    /// Number of connected clients. Like Redis, replicas aren't counted.
+    pub(crate) fn connected(&self) -> usize {
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|client| !client.info.lock().unwrap().replica)
+            .count()
+    }
+
+    /// The clients section of INFO.
+    pub(crate) fn info(&self, maxclients: usize) -> String {
+        format!(
+            "# Clients\r\nconnected_clients:{}\r\nmaxclients:{}\r\n",
+            self.connected(),
+            maxclients
+        )
+    }
+
+    /// Wait while CLIENT PAUSE holds back commands like this one: all of
+    /// them with `ALL`, only those that may write with `WRITE`.
+    pub(crate) async fn wait_unpaused(&self, writes: bool) {
+        let mut pause = self.shared.pause.subscribe();
+        loop {
+            let until = match *pause.borrow_and_update() {
+                Some(p) if (p.all || writes) && p.until > Instant::now() => p.until,
+                _ => return,
+            };
+            //CLIENT UNPAUSE 或者新的 CLIENT PAUSE 会修改状态，重新检查
+            tokio::select! {
+                _ = time::sleep_until(until) => {}
+                _ = pause.changed() => {}
+            }
+        }
+    }
+
+    // CLIENT ID | INFO | LIST | SETNAME | GETNAME | KILL | PAUSE | UNPAUSE
+    pub(crate) fn command(&self, me: &ClientHandle, args: &[Bytes]) -> Frame {
+        let sub = args[1].to_ascii_uppercase();
+        let result = match (&sub[..], args.len()) {
+            (b"ID", 2) => Ok(Frame::Integer(me.id() as i64)),
+            (b"INFO", 2) => Ok(Frame::Bulk(Bytes::from(me.client.describe()))),
+            (b"LIST", _) => self.list(&args[2..]),
+            (b"SETNAME", 3) => me.set_name(&args[2]).map(|()| cmd::ok()),
+            (b"GETNAME", 2) => Ok(match &me.info().name[..] {
+                "" => Frame::Null,
+                name => Frame::Bulk(Bytes::from(name.to_string())),
+            }),
+            (b"KILL", 3) => {
+                //老的写法 CLIENT KILL ip:port，找不到时报错
+                let addr = String::from_utf8_lossy(&args[2]);
+                match self.kill(|client| client.addr == addr) {
+                    0 => Err(Frame::Error("ERR No such client".to_string())),
+                    _ => Ok(cmd::ok()),
+                }
+            }
+            (b"KILL", n) if n > 3 && n % 2 == 0 => KillFilter::parse(&args[2..]).map(|filter| {
+                let killed = self.kill(|client| filter.matches(me, client));
+                Frame::Integer(killed as i64)
+            }),
+            (b"PAUSE", 3 | 4) => self.pause(&args[2..]),
+            (b"UNPAUSE", 2) => {
+                self.shared.pause.send_replace(None);
+                Ok(cmd::ok())
+            }
+            _ => Err(Frame::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                String::from_utf8_lossy(&args[1])
+            ))),
+        };
+        result.unwrap_or_else(|err| err)
+    }
+
+    // CLIENT LIST [TYPE normal|replica|master|pubsub] [ID id [id ...]]
+    fn list(&self, args: &[Bytes]) -> Result<Frame, Frame> {
+        let clients: Vec<Arc<Client>> = self
+            .shared
+            .clients
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        let selected: Vec<Arc<Client>> = match args {
+            [] => clients,
+            [option, kind] if option.eq_ignore_ascii_case(b"TYPE") => {
+                let kind = ClientType::parse(kind)?;
+                clients
+                    .into_iter()
+                    .filter(|client| kind.matches(client))
+                    .collect()
+            }
+            [option, ids @ ..] if option.eq_ignore_ascii_case(b"ID") && !ids.is_empty() => {
+                let ids = ids.iter().map(parse_id).collect::<Result<Vec<_>, _>>()?;
+                clients
+                    .into_iter()
+                    .filter(|client| ids.contains(&client.id))
+                    .collect()
+            }
+            _ => return Err(Frame::Error("ERR syntax error".to_string())),
+        };
+        let mut list = String::new();
+        for client in selected {
+            list.push_str(&client.describe());
+        }
+        Ok(Frame::Bulk(Bytes::from(list)))
+    }
+
+    fn kill(&self, matches: impl Fn(&Client) -> bool) -> usize {
+        let clients = self.shared.clients.lock().unwrap();
+        let mut killed = 0;
+        for client in clients.values().filter(|client| matches(client)) {
+            client.killed.store(true, Ordering::SeqCst);
+            //notify_one 会保存一个 permit，连接还没开始等待时也不会错过
+            client.kill.notify_one();
+            killed += 1;
+        }
+        killed
+    }
+
+    // CLIENT PAUSE timeout [WRITE|ALL]
+    fn pause(&self, args: &[Bytes]) -> Result<Frame, Frame> {
+        let timeout = match cmd::parse_int(&args[0]) {
+            Ok(ms) if ms >= 0 => Duration::from_millis(ms as u64),
+            _ => {
+                return Err(Frame::Error(
+                    "ERR timeout is not an integer or out of range".to_string(),
+                ))
+            }
+        };
+        let all = match args.get(1) {
+            None => true,
+            Some(mode) if mode.eq_ignore_ascii_case(b"ALL") => true,
+            Some(mode) if mode.eq_ignore_ascii_case(b"WRITE") => false,
+            Some(_) => return Err(Frame::Error("ERR syntax error".to_string())),
+        };
+        let now = Instant::now();
+        self.shared.pause.send_modify(|pause| {
+            //已经有暂停时，取更晚的结束时间和更严格的模式
+            let (until, all) = match *pause {
+                Some(old) if old.until > now => (old.until.max(now + timeout), old.all || all),
+                _ => (now + timeout, all),
+            };
+            *pause = Some(Pause { until, all });
+        });
+        Ok(cmd::ok())
+    }
+}
+
+impl ClientHandle {
+    pub(crate) fn id(&self) -> u64 {
+        self.client.id
+    }
+
+    pub(crate) fn info(&self) -> MutexGuard<'_, ClientInfo> {
+        self.client.info.lock().unwrap()
+    }
+
+    pub(crate) fn is_killed(&self) -> bool {
+        self.client.killed.load(Ordering::SeqCst)
+    }
+
+    /// Completes once CLIENT KILL picked this connection.
+    pub(crate) async fn killed(&self) {
+        while !self.is_killed() {
+            self.client.kill.notified().await;
+        }
+    }
+
+    // CLIENT SETNAME name，空字符串表示去掉名字
+    fn set_name(&self, name: &Bytes) -> Result<(), Frame> {
+        if !name.iter().all(|&b| b.is_ascii_graphic()) {
+            return Err(Frame::Error(
+                "ERR Client names cannot contain spaces, newlines or special characters."
+                    .to_string(),
+            ));
+        }
+        self.info().name = String::from_utf8_lossy(name).into_owned();
+        Ok(())
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.clients
+            .shared
+            .clients
+            .lock()
+            .unwrap()
+            .remove(&self.client.id);
+    }
+}
+
+impl Client {
+    //CLIENT LIST 里的一行，格式和 Redis 相同。回复直接写入 socket，没有输出缓冲区，
+    //所以不输出 obl、oll、omem
+    fn describe(&self) -> String {
+        let info = self.info.lock().unwrap();
+        let now = Instant::now();
+        let mut flags = String::new();
+        if info.replica {
+            flags.push('S');
+        }
+        if info.subscriptions > 0 {
+            flags.push('P');
+        }
+        if info.multi.is_some() {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 sub={} multi={} \
+             qbuf={} qbuf-free={} cmd={} user={}\n",
+            self.id,
+            self.addr,
+            self.laddr,
+            info.name,
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(info.last_interaction).as_secs(),
+            flags,
+            info.subscriptions,
+            info.multi.map_or(-1, |queued| queued as i64),
+            info.qbuf,
+            info.qbuf_free,
+            info.last_command,
+            info.user,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClientType {
+    Normal,
+    Replica,
+    //不会有 master 连进来，这一类总是空的
+    Master,
+    Pubsub,
+}
+
+impl ClientType {
+    fn parse(arg: &Bytes) -> Result<ClientType, Frame> {
+        match &arg.to_ascii_lowercase()[..] {
+            b"normal" => Ok(ClientType::Normal),
+            b"replica" | b"slave" => Ok(ClientType::Replica),
+            b"master" => Ok(ClientType::Master),
+            b"pubsub" => Ok(ClientType::Pubsub),
+            _ => Err(Frame::Error(format!(
+                "ERR Unknown client type '{}'",
+                String::from_utf8_lossy(arg)
+            ))),
+        }
+    }
+
+    fn matches(self, client: &Client) -> bool {
+        let info = client.info.lock().unwrap();
+        match self {
+            ClientType::Normal => !info.replica && info.subscriptions == 0,
+            ClientType::Replica => info.replica,
+            ClientType::Master => false,
+            ClientType::Pubsub => info.subscriptions > 0,
+        }
+    }
+}
+
+// CLIENT KILL 的新写法：ID、ADDR、LADDR、USER、TYPE 和 SKIPME 的组合，全部满足才断开
+#[derive(Debug, Default)]
+struct KillFilter {
+    id: Option<u64>,
+    addr: Option<String>,
+    laddr: Option<String>,
+    user: Option<String>,
+    kind: Option<ClientType>,
+    //默认不断开发出 CLIENT KILL 的连接自己
+    keep_me: bool,
+}
+
+impl KillFilter {
+    fn parse(args: &[Bytes]) -> Result<KillFilter, Frame> {
+        let mut filter = KillFilter {
+            keep_me: true,
+            ..KillFilter::default()
+        };
+        for pair in args.chunks(2) {
+            let value = String::from_utf8_lossy(&pair[1]).into_owned();
+            match &pair[0].to_ascii_uppercase()[..] {
+                b"ID" => filter.id = Some(parse_id(&pair[1])?),
+                b"ADDR" => filter.addr = Some(value),
+                b"LADDR" => filter.laddr = Some(value),
+                b"USER" => filter.user = Some(value),
+                b"TYPE" => filter.kind = Some(ClientType::parse(&pair[1])?),
+                b"SKIPME" => {
+                    filter.keep_me = match &value.to_ascii_lowercase()[..] {
+                        "yes" => true,
+                        "no" => false,
+                        _ => return Err(Frame::Error("ERR syntax error".to_string())),
+                    }
+                }
+                _ => return Err(Frame::Error("ERR syntax error".to_string())),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, me: &ClientHandle, client: &Client) -> bool {
+        !(self.keep_me && client.id == me.id())
+            && self.id.is_none_or(|id| client.id == id)
+            && self.addr.as_ref().is_none_or(|addr| &client.addr == addr)
+            && self
+                .laddr
+                .as_ref()
+                .is_none_or(|laddr| &client.laddr == laddr)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| &client.info.lock().unwrap().user == user)
+            && self.kind.is_none_or(|kind| kind.matches(client))
+    }
+}
+
+fn parse_id(arg: &Bytes) -> Result<u64, Frame> {
+    match cmd::parse_int(arg) {
+        Ok(id) if id > 0 => Ok(id as u64),
+        _ => Err(Frame::Error("ERR Invalid client ID".to_string())),
+    }
+}
@@ -0,0 +1,1043 @@
+//! Cluster 模式：keyspace 按 CRC16 分成 16384 个 hash slot，每个 slot 由一个节点负责。
+//!
+//! * key 的 slot 是 `CRC16(key) % 16384`；key 里有非空的 `{tag}` 时只对 tag 求哈希，
+//!   这样相关的 key 可以放在同一个 slot 里，一起用在多 key 命令中
+//! * 命令里的 key 不归本节点负责时回复 `-MOVED slot ip:port`，迁移过程中已经迁走的 key
+//!   回复 `-ASK slot ip:port`，客户端先发 `ASKING` 再重试
+//! * 节点之间通过 cluster bus（默认是服务端口 + 10000）定期交换 PING/PONG，消息里带着
+//!   自己负责的 slot、config epoch 以及已知节点的 gossip。同一个 slot 被多个节点声明时
+//!   config epoch 大的获胜，`CLUSTER SETSLOT <slot> NODE <myself>` 会让节点进入新的 epoch
+//!
+//! 集群配置不落盘（没有 nodes.conf），重启之后需要重新 MEET 和分配 slot。
+
+use crate::{
+    cmd,
+    db::Db,
+    error::{
+        self,
+        Error,
+    },
+    frame::Frame,
+    peer::{
+        Addr,
+        Links,
+    },
+    rdb,
+    replication,
+    script::Scripting,
+    Connection,
+};
+use bytes::Bytes;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        HashSet,
+    },
+    fmt::Write,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+/// Number of hash slots.
+pub const SLOTS: usize = 16384;
+
+/// Offset from the client port to the default cluster bus port.
+pub const BUS_PORT_OFFSET: u16 = 10000;
+
+/// CRC16/XMODEM, the checksum Redis uses to map keys to slots.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The hash slot of `key`, honouring `{hash tags}`.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    //只看第一个 `{` 和它后面第一个 `}`，中间为空时对整个 key 求哈希
+    let tag = key.iter().position(|b| *b == b'{').and_then(|start| {
+        let len = key[start + 1..].iter().position(|b| *b == b'}')?;
+        (len > 0).then(|| &key[start + 1..start + 1 + len])
+    });
+    crc16(tag.unwrap_or(key)) % SLOTS as u16
+}
+
+/// Cluster state shared by all connections of a cluster enabled server.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    myself: String,
+    node_timeout: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    current_epoch: u64,
+    //包括自己
+    nodes: BTreeMap<String, Node>,
+    //每个 slot 的负责节点 id
+    slots: Vec<Option<String>>,
+    //slot -> 迁移目标节点
+    migrating: HashMap<u16, String>,
+    //slot -> 迁移来源节点
+    importing: HashMap<u16, String>,
+    //CLUSTER MEET 了但还没握手成功的 bus 地址
+    handshakes: Vec<Addr>,
+}
+
+#[derive(Debug)]
+struct Node {
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    config_epoch: u64,
+    last_pong: Instant,
+    //超过 node timeout 没有收到消息
+    pfail: bool,
+}
+
+impl Node {
+    fn client_addr(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    fn bus_addr(&self) -> Addr {
+        (self.ip.clone(), self.bus_port)
+    }
+}
+
+//cluster bus 上的一条消息，编码成一个字符串数组：
+//[PING|MEET|PONG, id, ip, port, bus_port, config_epoch, current_epoch, slots, gossip...]
+//slots 形如 "0-100,200-300"，每条 gossip 是 "id ip port bus_port"
+#[derive(Debug)]
+struct Message {
+    kind: String,
+    id: String,
+    ip: String,
+    port: u16,
+    bus_port: u16,
+    config_epoch: u64,
+    current_epoch: u64,
+    slots: Vec<(u16, u16)>,
+    gossip: Vec<(String, String, u16, u16)>,
+}
+
+impl Message {
+    fn encode(&self) -> Vec<String> {
+        let slots: Vec<String> = self
+            .slots
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect();
+        let mut parts = vec![
+            self.kind.clone(),
+            self.id.clone(),
+            self.ip.clone(),
+            self.port.to_string(),
+            self.bus_port.to_string(),
+            self.config_epoch.to_string(),
+            self.current_epoch.to_string(),
+            slots.join(","),
+        ];
+        for (id, ip, port, bus_port) in &self.gossip {
+            parts.push(format!("{} {} {} {}", id, ip, port, bus_port));
+        }
+        parts
+    }
+
+    fn decode(frame: &Frame) -> Option<Message> {
+        let parts = match frame {
+            Frame::Array(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    Frame::Bulk(part) => String::from_utf8(part.to_vec()).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<String>>>()?,
+            _ => return None,
+        };
+        if parts.len() < 8 {
+            return None;
+        }
+
+        let mut slots = Vec::new();
+        for range in parts[7].split(',').filter(|range| !range.is_empty()) {
+            let (start, end) = range.split_once('-')?;
+            slots.push((start.parse().ok()?, end.parse().ok()?));
+        }
+        let mut gossip = Vec::new();
+        for entry in &parts[8..] {
+            let fields: Vec<&str> = entry.split(' ').collect();
+            if let [id, ip, port, bus_port] = fields[..] {
+                gossip.push((
+                    id.to_string(),
+                    ip.to_string(),
+                    port.parse().ok()?,
+                    bus_port.parse().ok()?,
+                ));
+            }
+        }
+
+        Some(Message {
+            kind: parts[0].to_ascii_uppercase(),
+            id: parts[1].clone(),
+            ip: parts[2].clone(),
+            port: parts[3].parse().ok()?,
+            bus_port: parts[4].parse().ok()?,
+            config_epoch: parts[5].parse().ok()?,
+            current_epoch: parts[6].parse().ok()?,
+            slots,
+            gossip,
+        })
+    }
+}
+
+impl State {
+    fn is_ok(&self) -> bool {
+        self.slots.iter().all(Option::is_some)
+    }
+
+    //按负责节点把 slot 合并成连续的区间
+    fn ranges(&self) -> Vec<(u16, u16, &String)> {
+        let mut ranges: Vec<(u16, u16, &String)> = Vec::new();
+        for (slot, owner) in self.slots.iter().enumerate() {
+            let owner = match owner {
+                Some(owner) => owner,
+                None => continue,
+            };
+            match ranges.last_mut() {
+                Some((_, end, id)) if *id == owner && *end as usize + 1 == slot => {
+                    *end = slot as u16
+                }
+                _ => ranges.push((slot as u16, slot as u16, owner)),
+            }
+        }
+        ranges
+    }
+
+    fn ranges_of(&self, id: &str) -> Vec<(u16, u16)> {
+        self.ranges()
+            .into_iter()
+            .filter(|(_, _, owner)| *owner == id)
+            .map(|(start, end, _)| (start, end))
+            .collect()
+    }
+}
+
+impl Cluster {
+    /// Create the cluster state of a node serving clients on `ip:port`, with
+    /// the bus on `bus_port`. Every slot starts unassigned.
+    pub fn new(ip: String, port: u16, bus_port: u16, node_timeout: Duration) -> Cluster {
+        let myself = replication::new_replid();
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            myself.clone(),
+            Node {
+                ip,
+                port,
+                bus_port,
+                config_epoch: 0,
+                last_pong: Instant::now(),
+                pfail: false,
+            },
+        );
+        println!("Cluster node ID is {}", myself);
+
+        Cluster {
+            shared: Arc::new(Shared {
+                myself,
+                node_timeout,
+                state: Mutex::new(State {
+                    current_epoch: 0,
+                    nodes,
+                    slots: vec![None; SLOTS],
+                    migrating: HashMap::new(),
+                    importing: HashMap::new(),
+                    handshakes: Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Serve the cluster bus on `listener` and gossip with the other nodes.
+    pub fn start(&self, listener: TcpListener) {
+        tokio::spawn(self.clone().serve_bus(listener));
+        tokio::spawn(self.clone().gossip());
+    }
+
+    /// Check that a command touching `keys` can run on this node, returning
+    /// the redirection or error to reply with when it can't.
+    pub async fn check(
+        &self,
+        db: &Db,
+        scripts: &Scripting,
+        keys: &[&Bytes],
+        asking: bool,
+    ) -> Option<Frame> {
+        let (first, rest) = keys.split_first()?;
+        let slot = key_hash_slot(first);
+        if rest.iter().any(|key| key_hash_slot(key) != slot) {
+            return Some(Frame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".to_string(),
+            ));
+        }
+
+        let target = {
+            let state = self.shared.state.lock().unwrap();
+            if !state.is_ok() {
+                return Some(Frame::Error("CLUSTERDOWN The cluster is down".to_string()));
+            }
+            let owner = match &state.slots[slot as usize] {
+                Some(owner) => owner,
+                None => return Some(Frame::Error("CLUSTERDOWN Hash slot not served".to_string())),
+            };
+            if *owner != self.shared.myself {
+                //正在导入的 slot，客户端先发了 ASKING 就在这里执行
+                if asking && state.importing.contains_key(&slot) {
+                    return None;
+                }
+                return Some(redirect("MOVED", slot, &state.nodes[owner]));
+            }
+
+            match state
+                .migrating
+                .get(&slot)
+                .and_then(|id| state.nodes.get(id))
+            {
+                Some(target) => target.client_addr(),
+                None => return None,
+            }
+        };
+
+        //正在迁出：key 都还在本地就直接执行，都已经迁走的让客户端去目标节点。
+        //脚本执行期间持有keyspace的锁，先异步等待，避免阻塞 runtime 的工作线程
+        if let Err(busy) = scripts.wait_idle().await {
+            return Some(busy);
+        }
+        let mut db = db.lock();
+        let missing = keys
+            .iter()
+            .filter(|key| !db.contains_key(&cmd::key_str(key)))
+            .count();
+        match missing {
+            0 => None,
+            n if n == keys.len() => Some(Frame::Error(format!("ASK {} {}", slot, target))),
+            _ => Some(Frame::Error(
+                "TRYAGAIN Multiple keys request during rehashing of slot".to_string(),
+            )),
+        }
+    }
+
+    /// CLUSTER subcommands.
+    pub async fn command(&self, db: &Db, scripts: &Scripting, args: &[Bytes]) -> Frame {
+        let sub = args[1].to_ascii_uppercase();
+        //这几个子命令要读 keyspace，和普通命令一样先等正在执行的脚本
+        if matches!(&sub[..], b"COUNTKEYSINSLOT" | b"GETKEYSINSLOT" | b"SETSLOT") {
+            if let Err(busy) = scripts.wait_idle().await {
+                return busy;
+            }
+        }
+        let result = match (&sub[..], args.len()) {
+            (b"MYID", 2) => Ok(Frame::Bulk(Bytes::from(self.shared.myself.clone()))),
+            (b"INFO", 2) => Ok(self.cluster_info()),
+            (b"NODES", 2) => Ok(self.nodes()),
+            (b"SLOTS", 2) => Ok(self.slots()),
+            (b"SHARDS", 2) => Ok(self.shards()),
+            (b"ADDSLOTS", n) if n > 2 => parse_slots(&args[2..]).and_then(|s| self.add_slots(s)),
+            (b"ADDSLOTSRANGE", n) if n > 2 && n % 2 == 0 => {
+                parse_slot_ranges(&args[2..]).and_then(|s| self.add_slots(s))
+            }
+            (b"DELSLOTS", n) if n > 2 => parse_slots(&args[2..]).and_then(|s| self.del_slots(s)),
+            (b"MEET", 4 | 5) => self.meet(&args[2..]),
+            (b"KEYSLOT", 3) => Ok(Frame::Integer(key_hash_slot(&args[2]) as i64)),
+            (b"COUNTKEYSINSLOT", 3) => parse_slot(&args[2]).map(|slot| {
+                let db = db.lock();
+                let count = db
+                    .iter()
+                    .filter(|(key, _)| key_hash_slot(key.as_bytes()) == slot)
+                    .count();
+                Frame::Integer(count as i64)
+            }),
+            (b"GETKEYSINSLOT", 4) => parse_slot(&args[2]).and_then(|slot| {
+                let count = cmd::parse_int(&args[3])?;
+                if count < 0 {
+                    return Err(Frame::Error("ERR Invalid number of keys".to_string()));
+                }
+                let db = db.lock();
+                let keys = db
+                    .iter()
+                    .filter(|(key, _)| key_hash_slot(key.as_bytes()) == slot)
+                    .take(count as usize)
+                    .map(|(key, _)| Frame::Bulk(Bytes::from(key.clone())))
+                    .collect();
+                Ok(Frame::Array(keys))
+            }),
+            (b"SETSLOT", 4 | 5) => self.set_slot(db, args),
+            _ => Err(Frame::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                String::from_utf8_lossy(&args[1])
+            ))),
+        };
+        result.unwrap_or_else(|err| err)
+    }
+
+    fn cluster_info(&self) -> Frame {
+        let state = self.shared.state.lock().unwrap();
+        let assigned = state.slots.iter().filter(|slot| slot.is_some()).count();
+        let pfail = state
+            .slots
+            .iter()
+            .flatten()
+            .filter(|owner| state.nodes[*owner].pfail)
+            .count();
+        let owners: HashSet<&String> = state.slots.iter().flatten().collect();
+
+        let mut info = String::new();
+        let status = if state.is_ok() { "ok" } else { "fail" };
+        let _ = write!(info, "cluster_state:{}\r\n", status);
+        let _ = write!(info, "cluster_slots_assigned:{}\r\n", assigned);
+        let _ = write!(info, "cluster_slots_ok:{}\r\n", assigned - pfail);
+        let _ = write!(info, "cluster_slots_pfail:{}\r\n", pfail);
+        let _ = write!(info, "cluster_slots_fail:0\r\n");
+        let _ = write!(info, "cluster_known_nodes:{}\r\n", state.nodes.len());
+        let _ = write!(info, "cluster_size:{}\r\n", owners.len());
+        let _ = write!(info, "cluster_current_epoch:{}\r\n", state.current_epoch);
+        let _ = write!(
+            info,
+            "cluster_my_epoch:{}\r\n",
+            state.nodes[&self.shared.myself].config_epoch
+        );
+        Frame::Bulk(Bytes::from(info))
+    }
+
+    // <id> <ip:port@cport> <flags> <master> <ping-sent> <pong-recv> <config-epoch> <link-state> <slot>...
+    fn nodes(&self) -> Frame {
+        let state = self.shared.state.lock().unwrap();
+        let now = unix_ms();
+        let mut out = String::new();
+        for (id, node) in &state.nodes {
+            let myself = *id == self.shared.myself;
+            let flags = match (myself, node.pfail) {
+                (true, _) => "myself,master",
+                (false, true) => "master,fail?",
+                (false, false) => "master",
+            };
+            let pong = if myself {
+                0
+            } else {
+                now.saturating_sub(node.last_pong.elapsed().as_millis() as u64)
+            };
+            let link = if node.pfail {
+                "disconnected"
+            } else {
+                "connected"
+            };
+            let _ = write!(
+                out,
+                "{} {}@{} {} - 0 {} {} {}",
+                id,
+                node.client_addr(),
+                node.bus_port,
+                flags,
+                pong,
+                node.config_epoch,
+                link
+            );
+            for (start, end) in state.ranges_of(id) {
+                if start == end {
+                    let _ = write!(out, " {}", start);
+                } else {
+                    let _ = write!(out, " {}-{}", start, end);
+                }
+            }
+            if myself {
+                for (slot, target) in &state.migrating {
+                    let _ = write!(out, " [{}->-{}]", slot, target);
+                }
+                for (slot, source) in &state.importing {
+                    let _ = write!(out, " [{}-<-{}]", slot, source);
+                }
+            }
+            out.push('\n');
+        }
+        Frame::Bulk(Bytes::from(out))
+    }
+
+    // [[start, end, [ip, port, id]], ...]
+    fn slots(&self) -> Frame {
+        let state = self.shared.state.lock().unwrap();
+        let ranges = state
+            .ranges()
+            .into_iter()
+            .map(|(start, end, id)| {
+                let node = &state.nodes[id];
+                Frame::Array(vec![
+                    Frame::Integer(start as i64),
+                    Frame::Integer(end as i64),
+                    Frame::Array(vec![
+                        bulk(&node.ip),
+                        Frame::Integer(node.port as i64),
+                        bulk(id),
+                    ]),
+                ])
+            })
+            .collect();
+        Frame::Array(ranges)
+    }
+
+    //每个节点一个 shard（没有 replica）
+    fn shards(&self) -> Frame {
+        let state = self.shared.state.lock().unwrap();
+        let shards = state
+            .nodes
+            .iter()
+            .map(|(id, node)| {
+                let slots = state
+                    .ranges_of(id)
+                    .into_iter()
+                    .flat_map(|(start, end)| [start, end])
+                    .map(|slot| Frame::Integer(slot as i64))
+                    .collect();
+                let health = if node.pfail { "fail" } else { "online" };
+                let node = Frame::Array(vec![
+                    bulk("id"),
+                    bulk(id),
+                    bulk("port"),
+                    Frame::Integer(node.port as i64),
+                    bulk("ip"),
+                    bulk(&node.ip),
+                    bulk("endpoint"),
+                    bulk(&node.ip),
+                    bulk("role"),
+                    bulk("master"),
+                    bulk("replication-offset"),
+                    Frame::Integer(0),
+                    bulk("health"),
+                    bulk(health),
+                ]);
+                Frame::Array(vec![
+                    bulk("slots"),
+                    Frame::Array(slots),
+                    bulk("nodes"),
+                    Frame::Array(vec![node]),
+                ])
+            })
+            .collect();
+        Frame::Array(shards)
+    }
+
+    fn add_slots(&self, slots: Vec<u16>) -> Result<Frame, Frame> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| state.slots[**slot as usize].is_some())
+        {
+            return Err(Frame::Error(format!("ERR Slot {} is already busy", slot)));
+        }
+        for slot in slots {
+            state.slots[slot as usize] = Some(self.shared.myself.clone());
+        }
+        Ok(cmd::ok())
+    }
+
+    fn del_slots(&self, slots: Vec<u16>) -> Result<Frame, Frame> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| state.slots[**slot as usize].is_none())
+        {
+            return Err(Frame::Error(format!(
+                "ERR Slot {} is already unassigned",
+                slot
+            )));
+        }
+        for slot in slots {
+            state.slots[slot as usize] = None;
+            state.migrating.remove(&slot);
+            state.importing.remove(&slot);
+        }
+        Ok(cmd::ok())
+    }
+
+    // MEET ip port [bus-port]
+    fn meet(&self, args: &[Bytes]) -> Result<Frame, Frame> {
+        let ip = String::from_utf8_lossy(&args[0]).into_owned();
+        let parse_port = |arg: &Bytes| std::str::from_utf8(arg).ok()?.parse::<u16>().ok();
+        let bus_port = match (parse_port(&args[1]), args.get(2)) {
+            (Some(port), None) => port.checked_add(BUS_PORT_OFFSET),
+            (Some(_), Some(bus_port)) => parse_port(bus_port),
+            (None, _) => None,
+        };
+        let bus_port = bus_port.ok_or_else(|| {
+            Frame::Error(format!(
+                "ERR Invalid node address specified: {}:{}",
+                ip,
+                String::from_utf8_lossy(&args[1])
+            ))
+        })?;
+        //握手由 gossip 任务完成
+        let mut state = self.shared.state.lock().unwrap();
+        let addr = (ip, bus_port);
+        if !state.handshakes.contains(&addr) {
+            state.handshakes.push(addr);
+        }
+        Ok(cmd::ok())
+    }
+
+    // SETSLOT slot IMPORTING node-id | MIGRATING node-id | STABLE | NODE node-id
+    fn set_slot(&self, db: &Db, args: &[Bytes]) -> Result<Frame, Frame> {
+        let slot = parse_slot(&args[2])?;
+        let action = args[3].to_ascii_uppercase();
+        let node = args
+            .get(4)
+            .map(|id| String::from_utf8_lossy(id).into_owned());
+        let myself = &self.shared.myself;
+
+        //NODE 把 slot 交给别的节点之前，本地不能还有这个 slot 的 key
+        let has_keys = || {
+            db.lock()
+                .iter()
+                .any(|(key, _)| key_hash_slot(key.as_bytes()) == slot)
+        };
+        let remaining =
+            matches!(&node, Some(id) if action == b"NODE" && id != myself) && has_keys();
+
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(id) = &node {
+            if !state.nodes.contains_key(id) {
+                return Err(Frame::Error(format!("ERR I don't know about node {}", id)));
+            }
+        }
+        let owner = state.slots[slot as usize].clone();
+
+        match (&action[..], node) {
+            (b"MIGRATING", Some(id)) => {
+                if owner.as_ref() != Some(myself) {
+                    return Err(Frame::Error(format!(
+                        "ERR I'm not the owner of hash slot {}",
+                        slot
+                    )));
+                }
+                state.migrating.insert(slot, id);
+            }
+            (b"IMPORTING", Some(id)) => {
+                if owner.as_ref() == Some(myself) {
+                    return Err(Frame::Error(format!(
+                        "ERR I'm already the owner of hash slot {}",
+                        slot
+                    )));
+                }
+                state.importing.insert(slot, id);
+            }
+            (b"STABLE", None) => {
+                state.migrating.remove(&slot);
+                state.importing.remove(&slot);
+            }
+            (b"NODE", Some(id)) => {
+                if owner.as_ref() == Some(myself) && id != *myself && remaining {
+                    return Err(Frame::Error(format!(
+                        "ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.",
+                        slot
+                    )));
+                }
+                //导入完成，进入新的 epoch，让其他节点接受这个 slot 的新归属
+                if id == *myself && state.importing.contains_key(&slot) {
+                    state.current_epoch += 1;
+                    let epoch = state.current_epoch;
+                    state.nodes.get_mut(myself).unwrap().config_epoch = epoch;
+                }
+                state.migrating.remove(&slot);
+                state.importing.remove(&slot);
+                state.slots[slot as usize] = Some(id);
+            }
+            _ => {
+                return Err(Frame::Error(
+                    "ERR Invalid CLUSTER SETSLOT action or number of arguments".to_string(),
+                ))
+            }
+        }
+        Ok(cmd::ok())
+    }
+
+    fn message(&self, kind: &str) -> Message {
+        let state = self.shared.state.lock().unwrap();
+        let me = &state.nodes[&self.shared.myself];
+        Message {
+            kind: kind.to_string(),
+            id: self.shared.myself.clone(),
+            ip: me.ip.clone(),
+            port: me.port,
+            bus_port: me.bus_port,
+            config_epoch: me.config_epoch,
+            current_epoch: state.current_epoch,
+            slots: state.ranges_of(&self.shared.myself),
+            gossip: state
+                .nodes
+                .iter()
+                .filter(|(id, node)| **id != self.shared.myself && !node.pfail)
+                .map(|(id, node)| (id.clone(), node.ip.clone(), node.port, node.bus_port))
+                .collect(),
+        }
+    }
+
+    //处理其他节点发来的 PING/MEET 或者 PONG 回复
+    fn receive(&self, message: Message) {
+        let myself = &self.shared.myself;
+        if message.id == *myself {
+            return;
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        state.current_epoch = state.current_epoch.max(message.current_epoch);
+        state
+            .handshakes
+            .retain(|addr| *addr != (message.ip.clone(), message.bus_port));
+
+        let node = state
+            .nodes
+            .entry(message.id.clone())
+            .or_insert_with(|| Node {
+                ip: message.ip.clone(),
+                port: message.port,
+                bus_port: message.bus_port,
+                config_epoch: 0,
+                last_pong: Instant::now(),
+                pfail: false,
+            });
+        node.ip = message.ip;
+        node.port = message.port;
+        node.bus_port = message.bus_port;
+        node.config_epoch = message.config_epoch;
+        node.last_pong = Instant::now();
+        node.pfail = false;
+
+        //slot 没人负责、已经是发送者的，或者发送者的 config epoch 更大时接受它的声明
+        for (start, end) in message.slots {
+            for slot in start..=end.min(SLOTS as u16 - 1) {
+                let claim = match &state.slots[slot as usize] {
+                    None => true,
+                    Some(owner) if *owner == message.id => false,
+                    Some(owner) => state
+                        .nodes
+                        .get(owner)
+                        .is_none_or(|owner| owner.config_epoch < message.config_epoch),
+                };
+                if claim {
+                    state.slots[slot as usize] = Some(message.id.clone());
+                    state.migrating.remove(&slot);
+                    state.importing.remove(&slot);
+                }
+            }
+        }
+
+        //gossip 里的新节点直接加进来，之后由 gossip 任务和它通信
+        for (id, ip, port, bus_port) in message.gossip {
+            if id != *myself && !state.nodes.contains_key(&id) {
+                println!("discovered cluster node {} {}:{}", id, ip, port);
+                state.nodes.insert(
+                    id,
+                    Node {
+                        ip,
+                        port,
+                        bus_port,
+                        config_epoch: 0,
+                        last_pong: Instant::now(),
+                        pfail: false,
+                    },
+                );
+            }
+        }
+    }
+
+    async fn serve_bus(self, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    println!("cluster bus accept error: {}", err);
+                    continue;
+                }
+            };
+            let cluster = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = cluster.handle_bus(Connection::new(stream)).await {
+                    println!("cluster bus error: {}", err);
+                }
+            });
+        }
+    }
+
+    async fn handle_bus(self, mut connection: Connection) -> error::Result<()> {
+        while let Some(frame) = connection.read_frame().await? {
+            let response = match Message::decode(&frame) {
+                Some(message) if message.kind == "PING" || message.kind == "MEET" => {
+                    self.receive(message);
+                    Frame::Array(
+                        self.message("PONG")
+                            .encode()
+                            .iter()
+                            .map(|p| bulk(p))
+                            .collect(),
+                    )
+                }
+                _ => Frame::Error("ERR invalid cluster bus message".to_string()),
+            };
+            connection.write_frame(&response).await?;
+        }
+        Ok(())
+    }
+
+    //定期给每个已知节点发 PING，给 MEET 过的地址发 MEET
+    async fn gossip(self) {
+        let period = (self.shared.node_timeout / 10)
+            .clamp(Duration::from_millis(100), Duration::from_secs(1));
+        let mut links = Links::default();
+        let mut interval = time::interval(period);
+
+        loop {
+            interval.tick().await;
+            let (peers, handshakes) = {
+                let mut state = self.shared.state.lock().unwrap();
+                for (id, node) in state.nodes.iter_mut() {
+                    if *id != self.shared.myself
+                        && !node.pfail
+                        && node.last_pong.elapsed() > self.shared.node_timeout
+                    {
+                        println!("cluster node {} is not reachable", id);
+                        node.pfail = true;
+                    }
+                }
+                let peers: Vec<Addr> = state
+                    .nodes
+                    .iter()
+                    .filter(|(id, _)| **id != self.shared.myself)
+                    .map(|(_, node)| node.bus_addr())
+                    .collect();
+                (peers, state.handshakes.clone())
+            };
+
+            let pings = peers.into_iter().map(|addr| (addr, "PING"));
+            let meets = handshakes.into_iter().map(|addr| (addr, "MEET"));
+            for (addr, kind) in pings.chain(meets) {
+                let message = self.message(kind).encode();
+                let args: Vec<&str> = message.iter().map(String::as_str).collect();
+                if let Some(reply) = links.call(&addr, &args, period).await {
+                    if let Some(message) = Message::decode(&reply) {
+                        self.receive(message);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [KEYS key...]
+///
+/// Moves keys to another server with DUMP/RESTORE. `asking` prefixes the
+/// RESTOREs with ASKING, so the target accepts them while importing the slot.
+pub async fn migrate(db: &Db, scripts: &Scripting, args: &[Bytes], asking: bool) -> Frame {
+    let port = match std::str::from_utf8(&args[2])
+        .ok()
+        .and_then(|p| p.parse::<u16>().ok())
+    {
+        Some(port) => port,
+        None => return Frame::Error("ERR Invalid port".to_string()),
+    };
+    let timeout = match cmd::parse_int(&args[5]) {
+        Ok(ms) if ms > 0 => Duration::from_millis(ms as u64),
+        Ok(_) => Duration::from_secs(1),
+        Err(err) => return err,
+    };
+    if let Err(err) = cmd::parse_int(&args[4]) {
+        return err;
+    }
+
+    let mut copy = false;
+    let mut replace = false;
+    let mut keys = Vec::new();
+    let mut options = args[6..].iter();
+    while let Some(option) = options.next() {
+        match &option.to_ascii_uppercase()[..] {
+            b"COPY" => copy = true,
+            b"REPLACE" => replace = true,
+            b"KEYS" => {
+                if !args[3].is_empty() {
+                    return Frame::Error(
+                        "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".to_string(),
+                    );
+                }
+                keys.extend(options.by_ref().cloned());
+            }
+            _ => return Frame::Error("ERR syntax error".to_string()),
+        }
+    }
+    if !args[3].is_empty() {
+        keys.push(args[3].clone());
+    }
+    if let Err(err) = cmd::check_keys(&keys) {
+        return err;
+    }
+
+    //脚本执行期间持有keyspace的锁，先异步等待
+    if let Err(busy) = scripts.wait_idle().await {
+        return busy;
+    }
+    //(key, 剩余 ttl 毫秒, DUMP 的结果)
+    let dumps: Vec<(Bytes, u64, Bytes)> = {
+        let mut db = db.lock();
+        keys.into_iter()
+            .filter_map(|key| {
+                let entry = db.entry(&cmd::key_str(&key))?;
+                //RESTORE 的 ttl 为 0 表示不过期，快要过期的 key 至少给 1ms
+                let ttl = entry.expires_at.map_or(0, |when| {
+                    (when.saturating_duration_since(Instant::now()).as_millis() as u64).max(1)
+                });
+                Some((key, ttl, Bytes::from(rdb::dump(&entry.data))))
+            })
+            .collect()
+    };
+    if dumps.is_empty() {
+        return Frame::Simple("NOKEY".to_string());
+    }
+
+    let host = String::from_utf8_lossy(&args[1]).into_owned();
+    let result = time::timeout(timeout, async {
+        let mut connection = Connection::new(TcpStream::connect((&host[..], port)).await?);
+        for (key, ttl, payload) in &dumps {
+            //ASKING 只对紧跟着的一条命令有效
+            if asking {
+                call(&mut connection, vec![Bytes::from_static(b"ASKING")]).await?;
+            }
+            let mut restore = vec![
+                Bytes::from_static(b"RESTORE"),
+                key.clone(),
+                Bytes::from(ttl.to_string()),
+                payload.clone(),
+            ];
+            if replace {
+                restore.push(Bytes::from_static(b"REPLACE"));
+            }
+            call(&mut connection, restore).await?;
+        }
+        Ok::<_, Error>(())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            return Frame::Error(format!("ERR Target instance replied with error: {}", err))
+        }
+        Err(_) => {
+            return Frame::Error("IOERR error or timeout reading to target instance".to_string())
+        }
+    }
+
+    if !copy {
+        //发送 RESTORE 期间可能开始执行新的脚本
+        if let Err(busy) = scripts.wait_idle().await {
+            return busy;
+        }
+        let mut state = db.lock();
+        for (key, _, _) in dumps {
+            cmd::execute_and_propagate(&mut state, &[Bytes::from_static(b"DEL"), key]);
+        }
+    }
+    cmd::ok()
+}
+
+async fn call(connection: &mut Connection, args: Vec<Bytes>) -> error::Result<Frame> {
+    let frame = Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+    connection.write_frame(&frame).await?;
+    match connection.read_frame().await? {
+        Some(Frame::Error(err)) => Err(Error::server(err)),
+        Some(frame) => Ok(frame),
+        None => Err(Error::ConnectionClosed),
+    }
+}
+
+fn redirect(kind: &str, slot: u16, node: &Node) -> Frame {
+    Frame::Error(format!("{} {} {}", kind, slot, node.client_addr()))
+}
+
+fn parse_slot(arg: &Bytes) -> Result<u16, Frame> {
+    match cmd::parse_int(arg) {
+        Ok(slot) if (0..SLOTS as i64).contains(&slot) => Ok(slot as u16),
+        _ => Err(Frame::Error("ERR Invalid or out of range slot".to_string())),
+    }
+}
+
+fn parse_slots(args: &[Bytes]) -> Result<Vec<u16>, Frame> {
+    let slots = args
+        .iter()
+        .map(parse_slot)
+        .collect::<Result<Vec<u16>, Frame>>()?;
+    unique(slots)
+}
+
+fn parse_slot_ranges(args: &[Bytes]) -> Result<Vec<u16>, Frame> {
+    let mut slots = Vec::new();
+    for range in args.chunks(2) {
+        let (start, end) = (parse_slot(&range[0])?, parse_slot(&range[1])?);
+        if start > end {
+            return Err(Frame::Error(format!(
+                "ERR start slot number {} is greater than end slot number {}",
+                start, end
+            )));
+        }
+        slots.extend(start..=end);
+    }
+    unique(slots)
+}
+
+fn unique(slots: Vec<u16>) -> Result<Vec<u16>, Frame> {
+    let mut seen = HashSet::new();
+    match slots.iter().find(|slot| !seen.insert(**slot)) {
+        Some(slot) => Err(Frame::Error(format!(
+            "ERR Slot {} specified multiple times",
+            slot
+        ))),
+        None => Ok(slots),
+    }
+}
+
+fn bulk(value: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(value.as_bytes()))
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
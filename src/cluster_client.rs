@@ -0,0 +1,324 @@
+//! 集群客户端：根据 CLUSTER SLOTS 把每个 key 发给负责它所在 slot 的节点。
+//!
+//! slot 到节点的映射缓存在客户端里，收到 `-MOVED` 时重新拉取；收到 `-ASK` 时只对这一条
+//! 命令先发 `ASKING` 再去目标节点重试，不更新缓存。跨 slot 的 MGET/MSET/DEL/EXISTS
+//! 按 slot 拆成多条命令分别发送，再把结果合起来。
+
+use crate::{
+    cluster::{
+        self,
+        key_hash_slot,
+    },
+    cmd,
+    error::{
+        Error,
+        Result,
+    },
+    frame::Frame,
+    types::FromRedisValue,
+    Connection,
+};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    io,
+};
+use tokio::{
+    net::TcpStream,
+    time::{
+        self,
+        Duration,
+    },
+};
+
+//MOVED/ASK 最多跟随的次数，防止集群配置出问题时无限重定向
+const MAX_REDIRECTS: usize = 16;
+
+//TRYAGAIN：slot 正在迁移，多 key 命令稍后重试
+const TRYAGAIN_DELAY: Duration = Duration::from_millis(50);
+
+/// A client for a cluster of servers, routing each command to the node that
+/// owns its keys.
+pub struct ClusterClient {
+    //初始节点，节点全部不可用时从这里重新发现集群
+    seeds: Vec<String>,
+    //slot -> 负责节点的 "ip:port"
+    slots: Vec<Option<String>>,
+    connections: HashMap<String, Connection>,
+}
+
+impl ClusterClient {
+    /// Connect to a cluster through any of the `seeds` (`"ip:port"`) and load
+    /// its slot map.
+    pub async fn connect(seeds: &[&str]) -> Result<ClusterClient> {
+        let mut client = ClusterClient {
+            seeds: seeds.iter().map(|seed| seed.to_string()).collect(),
+            slots: vec![None; cluster::SLOTS],
+            connections: HashMap::new(),
+        };
+        client.refresh_slots().await?;
+        Ok(client)
+    }
+
+    /// Reload the slot map from the first node that answers CLUSTER SLOTS.
+    pub async fn refresh_slots(&mut self) -> Result<()> {
+        //先问已知的节点，再问初始节点
+        let mut nodes: Vec<String> = self.slots.iter().flatten().cloned().collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes.extend(self.seeds.iter().cloned());
+
+        let mut last_err = None;
+        for node in nodes {
+            let args = [Bytes::from_static(b"CLUSTER"), Bytes::from_static(b"SLOTS")];
+            match self.call(&node, &args, false).await {
+                Ok(Frame::Array(ranges)) => {
+                    let mut slots = vec![None; cluster::SLOTS];
+                    for range in ranges {
+                        let (start, end, addr) = parse_slot_range(range)
+                            .ok_or_else(|| Error::protocol("invalid CLUSTER SLOTS reply"))?;
+                        slots[start..=end].fill(Some(addr));
+                    }
+                    self.slots = slots;
+                    return Ok(());
+                }
+                Ok(frame) => {
+                    last_err = Some(Error::protocol(format!(
+                        "unexpected CLUSTER SLOTS reply {}",
+                        frame
+                    )))
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(no_nodes))
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        let reply = self
+            .command(&[Bytes::from_static(b"GET"), key_arg(key)])
+            .await?;
+        Option::from_redis_value(reply)
+    }
+
+    pub async fn set(&mut self, key: &str, value: Bytes) -> Result<()> {
+        self.command(&[Bytes::from_static(b"SET"), key_arg(key), value])
+            .await?;
+        Ok(())
+    }
+
+    /// MGET across any number of slots. Values come back in the order of `keys`.
+    pub async fn mget(&mut self, keys: &[&str]) -> Result<Vec<Option<Bytes>>> {
+        let mut values = vec![None; keys.len()];
+        for indexes in group_by_slot(keys) {
+            let mut args = vec![Bytes::from_static(b"MGET")];
+            args.extend(indexes.iter().map(|i| key_arg(keys[*i])));
+            match self.command(&args).await? {
+                Frame::Array(replies) if replies.len() == indexes.len() => {
+                    for (i, reply) in indexes.into_iter().zip(replies) {
+                        values[i] = Option::from_redis_value(reply)?;
+                    }
+                }
+                frame => return Err(Error::protocol(format!("unexpected MGET reply {}", frame))),
+            }
+        }
+        Ok(values)
+    }
+
+    /// MSET across any number of slots. Only atomic per slot.
+    pub async fn mset(&mut self, pairs: &[(&str, Bytes)]) -> Result<()> {
+        let keys: Vec<&str> = pairs.iter().map(|(key, _)| *key).collect();
+        for indexes in group_by_slot(&keys) {
+            let mut args = vec![Bytes::from_static(b"MSET")];
+            for i in indexes {
+                args.push(key_arg(pairs[i].0));
+                args.push(pairs[i].1.clone());
+            }
+            self.command(&args).await?;
+        }
+        Ok(())
+    }
+
+    /// DEL across any number of slots, returning the number of keys removed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<u64> {
+        self.count("DEL", keys).await
+    }
+
+    /// EXISTS across any number of slots.
+    pub async fn exists(&mut self, keys: &[&str]) -> Result<u64> {
+        self.count("EXISTS", keys).await
+    }
+
+    /// Run any command on the node owning its keys, or on any node when it
+    /// has none. Keys in different slots are rejected by the server with
+    /// CROSSSLOT.
+    pub async fn command(&mut self, args: &[Bytes]) -> Result<Frame> {
+        let slot = cmd::lookup(&args[0])
+            .and_then(|spec| cmd::key_args(spec, args).first().copied())
+            .map(|key| key_hash_slot(key));
+
+        let mut node = self.node_for(slot)?;
+        let mut asking = false;
+        let mut last_err = None;
+        for _ in 0..MAX_REDIRECTS {
+            let err = match self.call(&node, args, asking).await {
+                Ok(reply) => return Ok(reply),
+                Err(err) => err,
+            };
+            match Redirect::from_error(&err) {
+                Some(Redirect::Moved(slot, addr)) => {
+                    //slot 的归属变了，很可能不止这一个，整体重新拉取
+                    self.slots[slot] = Some(addr.clone());
+                    let _ = self.refresh_slots().await;
+                    node = addr;
+                    asking = false;
+                }
+                Some(Redirect::Ask(addr)) => {
+                    node = addr;
+                    asking = true;
+                }
+                Some(Redirect::TryAgain) => time::sleep(TRYAGAIN_DELAY).await,
+                None => return Err(err),
+            }
+            last_err = Some(err);
+        }
+        //重定向次数太多，返回最后一次的重定向错误
+        Err(last_err.unwrap_or_else(no_nodes))
+    }
+
+    async fn count(&mut self, command: &'static str, keys: &[&str]) -> Result<u64> {
+        let mut total = 0;
+        for indexes in group_by_slot(keys) {
+            let mut args = vec![Bytes::from_static(command.as_bytes())];
+            args.extend(indexes.iter().map(|i| key_arg(keys[*i])));
+            match self.command(&args).await? {
+                Frame::Integer(n) => total += n as u64,
+                frame => {
+                    return Err(Error::protocol(format!(
+                        "unexpected {} reply {}",
+                        command, frame
+                    )))
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    fn node_for(&self, slot: Option<u16>) -> Result<String> {
+        let node = match slot {
+            Some(slot) => self.slots[slot as usize].clone(),
+            None => self.slots.iter().flatten().next().cloned(),
+        };
+        node.or_else(|| self.seeds.first().cloned())
+            .ok_or_else(no_nodes)
+    }
+
+    //发送一条命令并读取回复，错误回复转换成 Error::ServerError。其他错误说明连接已经不可用，
+    //直接丢掉，下次重连
+    async fn call(&mut self, node: &str, args: &[Bytes], asking: bool) -> Result<Frame> {
+        let connection = match self.connections.get_mut(node) {
+            Some(connection) => connection,
+            None => {
+                let stream = TcpStream::connect(node).await?;
+                self.connections
+                    .entry(node.to_string())
+                    .or_insert(Connection::new(stream))
+            }
+        };
+
+        let result = async {
+            if asking {
+                request(connection, &[Bytes::from_static(b"ASKING")]).await?;
+            }
+            request(connection, args).await
+        }
+        .await;
+        if matches!(&result, Err(err) if err.is_connection_error()) {
+            self.connections.remove(node);
+        }
+        result
+    }
+}
+
+fn no_nodes() -> Error {
+    io::Error::new(
+        io::ErrorKind::NotConnected,
+        "no cluster nodes to connect to",
+    )
+    .into()
+}
+
+async fn request(connection: &mut Connection, args: &[Bytes]) -> Result<Frame> {
+    let frame = Frame::Array(args.iter().cloned().map(Frame::Bulk).collect());
+    connection.write_frame(&frame).await?;
+    match connection.read_frame().await? {
+        Some(Frame::Error(err)) => Err(Error::server(err)),
+        Some(frame) => Ok(frame),
+        None => Err(Error::ConnectionClosed),
+    }
+}
+
+enum Redirect {
+    Moved(usize, String),
+    Ask(String),
+    TryAgain,
+}
+
+impl Redirect {
+    // MOVED <slot> <ip:port> | ASK <slot> <ip:port> | TRYAGAIN ...
+    fn from_error(err: &Error) -> Option<Redirect> {
+        let (code, message) = match err {
+            Error::ServerError { code, message } => (code, message),
+            _ => return None,
+        };
+        let mut parts = message.split(' ');
+        match &code[..] {
+            "MOVED" => {
+                let slot = parts.next()?.parse().ok()?;
+                Some(Redirect::Moved(slot, parts.next()?.to_string()))
+            }
+            "ASK" => Some(Redirect::Ask(parts.nth(1)?.to_string())),
+            "TRYAGAIN" => Some(Redirect::TryAgain),
+            _ => None,
+        }
+    }
+}
+
+// [start, end, [ip, port, id], ...]
+fn parse_slot_range(range: Frame) -> Option<(usize, usize, String)> {
+    let parts = match range {
+        Frame::Array(parts) => parts,
+        _ => return None,
+    };
+    let (start, end) = match (parts.first()?, parts.get(1)?) {
+        (Frame::Integer(start), Frame::Integer(end)) => (*start as usize, *end as usize),
+        _ => return None,
+    };
+    let addr = match parts.get(2)? {
+        Frame::Array(node) => match (node.first()?, node.get(1)?) {
+            (Frame::Bulk(ip), Frame::Integer(port)) => {
+                format!("{}:{}", String::from_utf8_lossy(ip), port)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    (start <= end && end < cluster::SLOTS).then_some((start, end, addr))
+}
+
+//按 slot 分组，返回每组 key 在原数组里的下标，组的顺序是 slot 第一次出现的顺序
+fn group_by_slot(keys: &[&str]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(u16, Vec<usize>)> = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        let slot = key_hash_slot(key.as_bytes());
+        match groups.iter_mut().find(|(s, _)| *s == slot) {
+            Some((_, indexes)) => indexes.push(i),
+            None => groups.push((slot, vec![i])),
+        }
+    }
+    groups.into_iter().map(|(_, indexes)| indexes).collect()
+}
+
+fn key_arg(key: &str) -> Bytes {
+    Bytes::copy_from_slice(key.as_bytes())
+}
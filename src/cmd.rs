@@ -0,0 +1,633 @@
+//! 命令表以及 keyspace 命令的执行。
+//!
+//! 普通连接和脚本里的 `redis.call` 都走 `execute`，保证两边的行为一致。
+//! 连接相关的命令（EVAL、SCRIPT 等）只登记在命令表里，由 `server` 模块处理。
+
+use crate::{
+    db::{
+        self,
+        State,
+    },
+    frame::Frame,
+    rdb,
+};
+use bytes::Bytes;
+use tokio::time::{
+    Duration,
+    Instant,
+};
+
+/// Static description of a command.
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    /// Redis 的约定：正数表示参数个数（包含命令名）必须相等，负数表示至少 `-arity` 个
+    pub arity: i32,
+    /// Modifies the keyspace.
+    pub write: bool,
+    /// Handled by the connection instead of `execute`, and not callable from
+    /// scripts.
+    pub noscript: bool,
+}
+
+const fn read(name: &'static str, arity: i32) -> Spec {
+    Spec {
+        name,
+        arity,
+        write: false,
+        noscript: false,
+    }
+}
+
+const fn write(name: &'static str, arity: i32) -> Spec {
+    Spec {
+        name,
+        arity,
+        write: true,
+        noscript: false,
+    }
+}
+
+const fn conn(name: &'static str, arity: i32) -> Spec {
+    Spec {
+        name,
+        arity,
+        write: false,
+        noscript: true,
+    }
+}
+
+pub const COMMANDS: &[Spec] = &[
+    read("PING", -1),
+    read("ECHO", 2),
+    read("GET", 2),
+    read("MGET", -2),
+    read("STRLEN", 2),
+    read("EXISTS", -2),
+    read("TTL", 2),
+    read("PTTL", 2),
+    read("DBSIZE", 1),
+    read("DUMP", 2),
+    write("SET", -3),
+    write("MSET", -3),
+    write("APPEND", 3),
+    write("DEL", -2),
+    write("INCR", 2),
+    write("DECR", 2),
+    write("INCRBY", 3),
+    write("DECRBY", 3),
+    write("EXPIRE", 3),
+    write("PEXPIRE", 3),
+    write("EXPIREAT", 3),
+    write("PEXPIREAT", 3),
+    write("PERSIST", 2),
+    write("FLUSHDB", -1),
+    write("FLUSHALL", -1),
+    write("RESTORE", -4),
+    conn("EVAL", -3),
+    conn("EVALSHA", -3),
+    conn("SCRIPT", -2),
+    conn("BGREWRITEAOF", 1),
+    conn("SAVE", 1),
+    conn("BGSAVE", -1),
+    conn("LASTSAVE", 1),
+    conn("PSYNC", 3),
+    conn("SYNC", 1),
+    conn("REPLCONF", -1),
+    conn("WAIT", 3),
+    conn("REPLICAOF", 3),
+    conn("SLAVEOF", 3),
+    conn("ROLE", 1),
+    conn("INFO", -1),
+    conn("CLUSTER", -2),
+    conn("ASKING", 1),
+    conn("MIGRATE", -6),
+    conn("MULTI", 1),
+    conn("EXEC", 1),
+    conn("DISCARD", 1),
+    conn("CONFIG", -2),
+    conn("SHUTDOWN", -1),
+    conn("AUTH", -2),
+    conn("ACL", -2),
+    conn("CLIENT", -2),
+    conn("SUBSCRIBE", -2),
+    conn("UNSUBSCRIBE", -1),
+    conn("PUBLISH", 3),
+];
+
+/// Look up a command by name, case-insensitively.
+pub fn lookup(name: &[u8]) -> Option<&'static Spec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+impl Spec {
+    pub fn check_arity(&self, argc: usize) -> bool {
+        let argc = argc as i32;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+}
+
+/// Convert a request frame into its arguments. Requests are always arrays of
+/// bulk strings.
+pub fn into_args(frame: Frame) -> Result<Vec<Bytes>, Frame> {
+    let parts = match frame {
+        Frame::Array(parts) if !parts.is_empty() => parts,
+        frame => return Err(protocol_error(&frame)),
+    };
+
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Frame::Bulk(data) => Ok(data),
+            Frame::Simple(data) => Ok(Bytes::from(data)),
+            Frame::Integer(n) => Ok(Bytes::from(n.to_string())),
+            frame => Err(protocol_error(&frame)),
+        })
+        .collect()
+}
+
+fn protocol_error(frame: &Frame) -> Frame {
+    Frame::Error(format!("ERR Protocol error: unexpected frame {}", frame))
+}
+
+/// Build the reply for a command that is not in the table.
+pub fn unknown_command(args: &[Bytes]) -> Frame {
+    Frame::Error(format!(
+        "ERR unknown command '{}'",
+        String::from_utf8_lossy(&args[0])
+    ))
+}
+
+pub fn wrong_arity(spec: &Spec) -> Frame {
+    Frame::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        spec.name.to_ascii_lowercase()
+    ))
+}
+
+/// Execute a keyspace command against the locked state.
+///
+/// `args[0]` is the command name. Commands flagged `noscript` are rejected,
+/// the caller is expected to handle them before getting here.
+pub fn execute(state: &mut State, args: &[Bytes]) -> Frame {
+    let spec = match lookup(&args[0]) {
+        Some(spec) => spec,
+        None => return unknown_command(args),
+    };
+
+    if !spec.check_arity(args.len()) {
+        return wrong_arity(spec);
+    }
+    if let Err(err) = check_keys(key_args(spec, args)) {
+        return err;
+    }
+
+    let reply = match spec.name {
+        "PING" => Ok(ping(args)),
+        "ECHO" => Ok(Frame::Bulk(args[1].clone())),
+        "GET" => Ok(get(state, &args[1])),
+        "MGET" => Ok(Frame::Array(
+            args[1..].iter().map(|key| get(state, key)).collect(),
+        )),
+        "STRLEN" => Ok(strlen(state, &args[1])),
+        "EXISTS" => Ok(exists(state, &args[1..])),
+        "TTL" => Ok(ttl(state, &args[1], Duration::from_secs(1))),
+        "PTTL" => Ok(ttl(state, &args[1], Duration::from_millis(1))),
+        "DBSIZE" => Ok(Frame::Integer(state.len() as i64)),
+        "DUMP" => Ok(dump(state, &args[1])),
+        "SET" => set(state, args),
+        "MSET" => mset(state, args),
+        "APPEND" => append(state, args),
+        "DEL" => Ok(del(state, &args[1..])),
+        "INCR" => incr_by(state, &args[1], 1),
+        "DECR" => incr_by(state, &args[1], -1),
+        "INCRBY" => parse_int(&args[2]).and_then(|n| incr_by(state, &args[1], n)),
+        "DECRBY" => parse_int(&args[2]).and_then(|n| incr_by(state, &args[1], -n)),
+        "EXPIRE" => expire(state, args, Duration::from_secs(1)),
+        "PEXPIRE" => expire(state, args, Duration::from_millis(1)),
+        "EXPIREAT" => expire_at(state, args, 1000),
+        "PEXPIREAT" => expire_at(state, args, 1),
+        "PERSIST" => Ok(persist(state, &args[1])),
+        "RESTORE" => restore(state, args),
+        "FLUSHDB" | "FLUSHALL" => {
+            state.clear();
+            Ok(ok())
+        }
+        _ => Err(Frame::Error(format!(
+            "ERR '{}' command is not allowed here",
+            spec.name.to_ascii_lowercase()
+        ))),
+    };
+
+    reply.unwrap_or_else(|err| err)
+}
+
+/// Execute a command and, if it changed the keyspace, propagate it to the
+/// feeds registered on `state`.
+pub fn execute_and_propagate(state: &mut State, args: &[Bytes]) -> Frame {
+    let reply = execute(state, args);
+
+    if let Some(spec) = lookup(&args[0]) {
+        if spec.write && changed(spec, &reply) {
+            for args in propagated(state, spec, args) {
+                state.propagate(&args);
+            }
+        }
+    }
+
+    reply
+}
+
+//根据返回值判断命令是否真的修改了数据，没有修改的就不需要写入 AOF
+fn changed(spec: &Spec, reply: &Frame) -> bool {
+    !matches!(
+        (spec.name, reply),
+        (_, Frame::Error(_))
+            | ("SET", Frame::Null)
+            | (
+                "DEL" | "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" | "PERSIST",
+                Frame::Integer(0)
+            )
+    )
+}
+
+//相对的过期时间在重放时会重新计时，所以统一改写成绝对时间的 PEXPIREAT
+fn propagated(state: &mut State, spec: &Spec, args: &[Bytes]) -> Vec<Vec<Bytes>> {
+    let expire_at = |state: &mut State, key: &Bytes| {
+        state
+            .entry(&key_str(key))
+            .and_then(|entry| entry.expires_at)
+            .map(|when| {
+                vec![
+                    Bytes::from_static(b"PEXPIREAT"),
+                    key.clone(),
+                    Bytes::from(db::to_unix_ms(when).to_string()),
+                ]
+            })
+    };
+
+    match spec.name {
+        "SET" => {
+            let mut commands = vec![args[..3].to_vec()];
+            commands.extend(expire_at(state, &args[1]));
+            commands
+        }
+        //RESTORE 的数据已经在 keyspace 里了，用 SET 传播更简单
+        "RESTORE" => match state.get(&key_str(&args[1])) {
+            Some(value) => {
+                let mut commands = vec![vec![Bytes::from_static(b"SET"), args[1].clone(), value]];
+                commands.extend(expire_at(state, &args[1]));
+                commands
+            }
+            None => vec![vec![Bytes::from_static(b"DEL"), args[1].clone()]],
+        },
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" => match expire_at(state, &args[1]) {
+            Some(command) => vec![command],
+            //过期时间不是正数时key被直接删除了
+            None => vec![vec![Bytes::from_static(b"DEL"), args[1].clone()]],
+        },
+        _ => vec![args.to_vec()],
+    }
+}
+
+/// The key arguments of a command, used to route it to a cluster node.
+pub fn key_args<'a>(spec: &Spec, args: &'a [Bytes]) -> Vec<&'a Bytes> {
+    match spec.name {
+        "MGET" | "EXISTS" | "DEL" => args[1..].iter().collect(),
+        "MSET" => args[1..].iter().step_by(2).collect(),
+        "EVAL" | "EVALSHA" => {
+            let numkeys = args.get(2).and_then(|n| parse_int(n).ok()).unwrap_or(0);
+            args.iter().skip(3).take(numkeys.max(0) as usize).collect()
+        }
+        "GET" | "STRLEN" | "TTL" | "PTTL" | "DUMP" | "SET" | "APPEND" | "INCR" | "DECR"
+        | "INCRBY" | "DECRBY" | "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" | "PERSIST"
+        | "RESTORE" => args[1..2].iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn ok() -> Frame {
+    Frame::Simple("OK".to_string())
+}
+
+/// Keys are stored as strings: reject keys that are not valid UTF-8 instead
+/// of letting them collide with another key once converted.
+pub fn check_keys<'a>(keys: impl IntoIterator<Item = &'a Bytes>) -> Result<(), Frame> {
+    if keys
+        .into_iter()
+        .any(|key| std::str::from_utf8(key).is_err())
+    {
+        return Err(Frame::Error(
+            "ERR invalid key: keys must be valid UTF-8".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The key as a string. `execute` checks the keys of every command with
+/// `check_keys` first, so the conversion doesn't lose anything there.
+pub fn key_str(key: &Bytes) -> String {
+    String::from_utf8_lossy(key).into_owned()
+}
+
+pub fn parse_int(arg: &Bytes) -> Result<i64, Frame> {
+    atoi::atoi::<i64>(arg)
+        .ok_or_else(|| Frame::Error("ERR value is not an integer or out of range".to_string()))
+}
+
+/// Redis-style glob matching: `*`, `?`, `[abc]`, `[^a-z]` and `\\` to
+/// escape the next character.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((b'[', rest)) => {
+            match (class_match(rest, text.first().copied()), text.split_first()) {
+                (Some((true, rest)), Some((_, text))) => glob_match(rest, text),
+                //没有闭合的 `[` 按普通字符处理
+                (None, Some((b'[', text))) => glob_match(rest, text),
+                _ => false,
+            }
+        }
+        Some((b'\\', [c, rest @ ..])) | Some((c, rest)) => {
+            text.first() == Some(c) && glob_match(rest, &text[1..])
+        }
+    }
+}
+
+//匹配 `[...]`，pattern 从 `[` 之后开始。返回是否匹配以及 `]` 之后剩下的 pattern
+fn class_match(pattern: &[u8], c: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => return None,
+            [b']', rest @ ..] => return Some((c.is_some() && matched != negate, rest)),
+            [b'\\', x, rest @ ..] => {
+                matched |= c == Some(*x);
+                pattern = rest;
+            }
+            [lo, b'-', hi, rest @ ..] if *hi != b']' => {
+                let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+                matched |= c.is_some_and(|c| *lo <= c && c <= *hi);
+                pattern = rest;
+            }
+            [x, rest @ ..] => {
+                matched |= c == Some(*x);
+                pattern = rest;
+            }
+        }
+    }
+}
+
+fn syntax_error() -> Frame {
+    Frame::Error("ERR syntax error".to_string())
+}
+
+fn invalid_expire(command: &str) -> Frame {
+    Frame::Error(format!("ERR invalid expire time in '{}' command", command))
+}
+
+//`n` 个 `unit_ms` 毫秒之后的时间点。参数是客户端给的，可能大到溢出，这时返回 None
+fn expire_in(n: u64, unit_ms: u64) -> Option<Instant> {
+    let millis = n.checked_mul(unit_ms)?;
+    //换算成 unix 时间之后也不能超出范围，否则传播出去的 PEXPIREAT 没法解析
+    if millis > db::MAX_UNIX_MS.saturating_sub(db::unix_time_ms()) {
+        return None;
+    }
+    Instant::now().checked_add(Duration::from_millis(millis))
+}
+
+//绝对的过期时间，单位是 `unit_ms` 毫秒。负数表示早就过期了
+fn expire_at_unix(at: i64, unit_ms: u64) -> Option<Instant> {
+    db::from_unix_ms((at.max(0) as u64).checked_mul(unit_ms)?)
+}
+
+fn ping(args: &[Bytes]) -> Frame {
+    match args.get(1) {
+        Some(msg) => Frame::Bulk(msg.clone()),
+        None => Frame::Simple("PONG".to_string()),
+    }
+}
+
+fn get(state: &mut State, key: &Bytes) -> Frame {
+    match state.read(&key_str(key)) {
+        Some(entry) => Frame::Bulk(entry.data.clone()),
+        None => Frame::Null,
+    }
+}
+
+fn strlen(state: &mut State, key: &Bytes) -> Frame {
+    let len = state
+        .read(&key_str(key))
+        .map_or(0, |entry| entry.data.len());
+    Frame::Integer(len as i64)
+}
+
+fn exists(state: &mut State, keys: &[Bytes]) -> Frame {
+    let count = keys
+        .iter()
+        .filter(|key| state.read(&key_str(key)).is_some())
+        .count();
+    Frame::Integer(count as i64)
+}
+
+fn ttl(state: &mut State, key: &Bytes, unit: Duration) -> Frame {
+    match state.read(&key_str(key)) {
+        None => Frame::Integer(-2),
+        Some(entry) => match entry.expires_at {
+            None => Frame::Integer(-1),
+            Some(when) => {
+                let left = when.saturating_duration_since(Instant::now());
+                Frame::Integer((left.as_millis() / unit.as_millis()) as i64)
+            }
+        },
+    }
+}
+
+// SET key value [EX seconds|PX milliseconds] [NX|XX]
+fn set(state: &mut State, args: &[Bytes]) -> Result<Frame, Frame> {
+    let key = key_str(&args[1]);
+    let mut expire = None;
+    let mut nx = false;
+    let mut xx = false;
+
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        let option = option.to_ascii_uppercase();
+        match &option[..] {
+            b"EX" | b"PX" => {
+                let n = parse_int(options.next().ok_or_else(syntax_error)?)?;
+                let unit = if &option[..] == b"EX" { 1000 } else { 1 };
+                if n <= 0 {
+                    return Err(invalid_expire("set"));
+                }
+                expire = Some(expire_in(n as u64, unit).ok_or_else(|| invalid_expire("set"))?);
+            }
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    if nx && xx {
+        return Err(syntax_error());
+    }
+
+    let exists = state.contains_key(&key);
+    if (nx && exists) || (xx && !exists) {
+        return Ok(Frame::Null);
+    }
+
+    state.set(key, args[2].clone(), expire);
+    Ok(ok())
+}
+
+fn mset(state: &mut State, args: &[Bytes]) -> Result<Frame, Frame> {
+    if args.len() % 2 != 1 {
+        return Err(wrong_arity(lookup(b"MSET").unwrap()));
+    }
+    for pair in args[1..].chunks(2) {
+        state.set(key_str(&pair[0]), pair[1].clone(), None);
+    }
+    Ok(ok())
+}
+
+fn append(state: &mut State, args: &[Bytes]) -> Result<Frame, Frame> {
+    let key = key_str(&args[1]);
+    let (mut value, expires_at) = match state.entry(&key) {
+        Some(entry) => (entry.data.to_vec(), entry.expires_at),
+        None => (Vec::new(), None),
+    };
+    value.extend_from_slice(&args[2]);
+    let len = value.len();
+    state.insert(
+        key,
+        crate::db::Entry {
+            data: Bytes::from(value),
+            expires_at,
+        },
+    );
+    Ok(Frame::Integer(len as i64))
+}
+
+fn del(state: &mut State, keys: &[Bytes]) -> Frame {
+    let count = keys
+        .iter()
+        .filter(|key| {
+            let key = key_str(key);
+            state.contains_key(&key) && state.remove(&key).is_some()
+        })
+        .count();
+    Frame::Integer(count as i64)
+}
+
+fn incr_by(state: &mut State, key: &Bytes, delta: i64) -> Result<Frame, Frame> {
+    let key = key_str(key);
+    let (current, expires_at) = match state.entry(&key) {
+        Some(entry) => (parse_int(&entry.data)?, entry.expires_at),
+        None => (0, None),
+    };
+    let value = current
+        .checked_add(delta)
+        .ok_or_else(|| Frame::Error("ERR increment or decrement would overflow".to_string()))?;
+    state.insert(
+        key,
+        crate::db::Entry {
+            data: Bytes::from(value.to_string()),
+            expires_at,
+        },
+    );
+    Ok(Frame::Integer(value))
+}
+
+fn expire(state: &mut State, args: &[Bytes], unit: Duration) -> Result<Frame, Frame> {
+    let key = key_str(&args[1]);
+    let n = parse_int(&args[2])?;
+    if n <= 0 {
+        //过期时间不是正数时，Redis 直接删除这个key
+        let existed = state.contains_key(&key) && state.remove(&key).is_some();
+        return Ok(Frame::Integer(existed as i64));
+    }
+    let when = expire_in(n as u64, unit.as_millis() as u64)
+        .ok_or_else(|| invalid_expire(&String::from_utf8_lossy(&args[0]).to_ascii_lowercase()))?;
+    Ok(Frame::Integer(state.set_expires_at(&key, Some(when)) as i64))
+}
+
+fn expire_at(state: &mut State, args: &[Bytes], unit_ms: u64) -> Result<Frame, Frame> {
+    let key = key_str(&args[1]);
+    let at = parse_int(&args[2])?;
+    let when = expire_at_unix(at, unit_ms)
+        .ok_or_else(|| invalid_expire(&String::from_utf8_lossy(&args[0]).to_ascii_lowercase()))?;
+    if when <= Instant::now() {
+        let existed = state.contains_key(&key) && state.remove(&key).is_some();
+        return Ok(Frame::Integer(existed as i64));
+    }
+    Ok(Frame::Integer(state.set_expires_at(&key, Some(when)) as i64))
+}
+
+fn dump(state: &mut State, key: &Bytes) -> Frame {
+    match state.read(&key_str(key)) {
+        Some(entry) => Frame::Bulk(Bytes::from(rdb::dump(&entry.data))),
+        None => Frame::Null,
+    }
+}
+
+// RESTORE key ttl serialized-value [REPLACE] [ABSTTL]
+fn restore(state: &mut State, args: &[Bytes]) -> Result<Frame, Frame> {
+    let key = key_str(&args[1]);
+    let ttl = parse_int(&args[2])?;
+    let mut replace = false;
+    let mut absttl = false;
+    for option in &args[4..] {
+        match &option.to_ascii_uppercase()[..] {
+            b"REPLACE" => replace = true,
+            b"ABSTTL" => absttl = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    if ttl < 0 {
+        return Err(Frame::Error(
+            "ERR Invalid TTL value, must be >= 0".to_string(),
+        ));
+    }
+    if !replace && state.contains_key(&key) {
+        return Err(Frame::Error(
+            "BUSYKEY Target key name already exists.".to_string(),
+        ));
+    }
+    let data = rdb::undump(&args[3]).map_err(|err| Frame::Error(format!("ERR {}", err)))?;
+
+    //ttl 为 0 表示不过期
+    let expires_at = match (ttl, absttl) {
+        (0, _) => None,
+        (at, true) => Some(expire_at_unix(at, 1).ok_or_else(|| invalid_expire("restore"))?),
+        (ms, false) => Some(expire_in(ms as u64, 1).ok_or_else(|| invalid_expire("restore"))?),
+    };
+    if expires_at.is_some_and(|when| when <= Instant::now()) {
+        state.remove(&key);
+    } else {
+        state.insert(key, db::Entry { data, expires_at });
+    }
+    Ok(ok())
+}
+
+fn persist(state: &mut State, key: &Bytes) -> Frame {
+    let key = key_str(key);
+    let had_ttl = matches!(state.entry(&key), Some(entry) if entry.expires_at.is_some());
+    if had_ttl {
+        state.set_expires_at(&key, None);
+    }
+    Frame::Integer(had_ttl as i64)
+}
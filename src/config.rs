@@ -0,0 +1,729 @@
+//! 服务端配置。
+//!
+//! 参数名和 redis.conf 保持一致：先读配置文件，再用命令行上 `--appendonly yes` 这样的参数覆盖。
+//! 运行期间 CONFIG SET 可以修改一部分参数，CONFIG REWRITE 把当前的值写回配置文件。
+
+use crate::{
+    aof::FsyncPolicy,
+    rdb::{
+        self,
+        SaveRule,
+    },
+    replication::{
+        self,
+        OutputBufferLimit,
+    },
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Addresses to accept clients on.
+    pub bind: Vec<String>,
+    pub port: u16,
+    /// Path of a Unix socket to accept clients on as well, `None` to only
+    /// listen on TCP. With `port` 0 the server only listens here.
+    pub unixsocket: Option<PathBuf>,
+    /// Permissions of the Unix socket file, e.g. `0o700`; zero leaves them
+    /// to the umask.
+    pub unixsocketperm: u32,
+    /// Port for TLS connections, 0 to disable them. Needs the `tls` feature.
+    pub tls_port: u16,
+    /// Server certificate chain, PEM.
+    pub tls_cert_file: Option<PathBuf>,
+    /// Private key of the server certificate, PEM.
+    pub tls_key_file: Option<PathBuf>,
+    /// CAs that sign client certificates, PEM.
+    pub tls_ca_cert_file: Option<PathBuf>,
+    /// Whether clients on the TLS port must present a certificate.
+    pub tls_auth_clients: TlsAuthClients,
+    /// Port of the HTTP listener serving Prometheus metrics on `/metrics`,
+    /// 0 to disable it. Needs the `metrics` feature.
+    pub metrics_port: u16,
+    /// Maximum number of connected clients.
+    pub maxclients: usize,
+    /// Close client connections idle for this long, zero to keep them open.
+    /// Connections in subscribe mode are never closed for being idle.
+    pub timeout: Duration,
+    /// Interval of TCP keepalive probes on client connections, zero to
+    /// disable them.
+    pub tcp_keepalive: Duration,
+    /// Disable Nagle's algorithm on client connections.
+    pub tcp_nodelay: bool,
+    pub databases: usize,
+    pub loglevel: LogLevel,
+    /// Password of the `default` user, empty to let it log in without one.
+    pub requirepass: String,
+    /// File the users are loaded from on startup, and by ACL LOAD and ACL
+    /// SAVE.
+    pub aclfile: Option<PathBuf>,
+    /// How long shutdown waits for connections to finish their commands.
+    pub shutdown_timeout: Duration,
+    /// Working directory for persistence files.
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    /// Automatic snapshot rules, empty to disable.
+    pub save: Vec<SaveRule>,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    /// Trim a truncated AOF tail on startup instead of refusing to start.
+    pub aof_load_truncated: bool,
+    /// Master to replicate from, `None` when this server is a master.
+    pub replicaof: Option<(String, u16)>,
+    pub replica_read_only: bool,
+    /// User to authenticate as on the master, empty for `default`.
+    pub masteruser: String,
+    /// Password to authenticate with on the master, empty not to
+    /// authenticate.
+    pub masterauth: String,
+    /// Size in bytes of the replication backlog used for partial resyncs.
+    pub repl_backlog_size: usize,
+    /// When to disconnect a replica that doesn't keep up with the
+    /// replication stream.
+    pub client_output_buffer_limit: OutputBufferLimit,
+    pub cluster_enabled: bool,
+    /// Cluster bus port, 0 for the client port plus 10000.
+    pub cluster_port: u16,
+    /// How long a node can be unreachable before it is flagged as failing.
+    pub cluster_node_timeout: Duration,
+    /// Address other nodes and clients use to reach this node.
+    pub cluster_announce_ip: String,
+    /// The file the configuration was loaded from, updated by CONFIG REWRITE.
+    pub config_file: Option<PathBuf>,
+}
+
+/// How much the server logs, from most to least verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+/// Whether TLS clients have to authenticate with a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    Yes,
+    No,
+    /// Accept clients without a certificate, but check the ones that send
+    /// one.
+    Optional,
+}
+
+/// A parameter that can be read with CONFIG GET.
+#[derive(Debug)]
+pub struct Parameter {
+    pub name: &'static str,
+    /// Can be changed with CONFIG SET; the others only take effect on startup.
+    pub mutable: bool,
+}
+
+const fn immutable(name: &'static str) -> Parameter {
+    Parameter {
+        name,
+        mutable: false,
+    }
+}
+
+const fn mutable(name: &'static str) -> Parameter {
+    Parameter {
+        name,
+        mutable: true,
+    }
+}
+
+pub const PARAMETERS: &[Parameter] = &[
+    immutable("bind"),
+    immutable("port"),
+    immutable("unixsocket"),
+    immutable("unixsocketperm"),
+    immutable("tls-port"),
+    immutable("tls-cert-file"),
+    immutable("tls-key-file"),
+    immutable("tls-ca-cert-file"),
+    immutable("tls-auth-clients"),
+    immutable("metrics-port"),
+    mutable("maxclients"),
+    mutable("timeout"),
+    mutable("tcp-keepalive"),
+    mutable("tcp-nodelay"),
+    immutable("databases"),
+    mutable("loglevel"),
+    mutable("requirepass"),
+    immutable("aclfile"),
+    mutable("shutdown-timeout"),
+    immutable("dir"),
+    immutable("dbfilename"),
+    mutable("save"),
+    immutable("appendonly"),
+    immutable("appendfilename"),
+    mutable("appendfsync"),
+    immutable("aof-load-truncated"),
+    immutable("replicaof"),
+    mutable("replica-read-only"),
+    mutable("masteruser"),
+    mutable("masterauth"),
+    immutable("repl-backlog-size"),
+    mutable("client-output-buffer-limit"),
+    immutable("cluster-enabled"),
+    immutable("cluster-port"),
+    immutable("cluster-node-timeout"),
+    immutable("cluster-announce-ip"),
+];
+
+/// Look up a parameter by name, case-insensitively. The old `slave*` names
+/// are accepted as aliases.
+pub fn lookup(name: &str) -> Option<&'static Parameter> {
+    let name = name.to_ascii_lowercase();
+    let name = match &name[..] {
+        "slaveof" => "replicaof",
+        "slave-read-only" => "replica-read-only",
+        name => name,
+    };
+    PARAMETERS.iter().find(|parameter| parameter.name == name)
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            unixsocket: None,
+            unixsocketperm: 0,
+            tls_port: 0,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: TlsAuthClients::Yes,
+            metrics_port: 0,
+            maxclients: 10000,
+            timeout: Duration::ZERO,
+            tcp_keepalive: Duration::from_secs(300),
+            tcp_nodelay: true,
+            databases: 16,
+            loglevel: LogLevel::Notice,
+            requirepass: String::new(),
+            aclfile: None,
+            shutdown_timeout: Duration::from_secs(10),
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            //和 Redis 的默认值一致
+            save: rdb::parse_save_rules("3600 1 300 100 60 10000").unwrap(),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            aof_load_truncated: true,
+            replicaof: None,
+            replica_read_only: true,
+            masteruser: String::new(),
+            masterauth: String::new(),
+            repl_backlog_size: 1024 * 1024,
+            client_output_buffer_limit: OutputBufferLimit::default(),
+            cluster_enabled: false,
+            cluster_port: 0,
+            cluster_node_timeout: Duration::from_secs(15),
+            cluster_announce_ip: "127.0.0.1".to_string(),
+            config_file: None,
+        }
+    }
+}
+
+impl Config {
+    /// Set a parameter by its redis.conf name.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parameter =
+            lookup(name).ok_or_else(|| format!("unknown config parameter '{}'", name))?;
+        match parameter.name {
+            "bind" => {
+                let addrs: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+                if addrs.is_empty() {
+                    return Err("bind expects at least one address".to_string());
+                }
+                self.bind = addrs;
+            }
+            "unixsocket" => self.unixsocket = optional_path(value),
+            "unixsocketperm" => {
+                self.unixsocketperm = match u32::from_str_radix(value, 8) {
+                    Ok(mode) if mode <= 0o777 => mode,
+                    _ => return Err(format!("invalid unixsocketperm '{}'", value)),
+                }
+            }
+            "tls-port" => {
+                self.tls_port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port '{}'", value))?
+            }
+            "tls-cert-file" => self.tls_cert_file = optional_path(value),
+            "tls-key-file" => self.tls_key_file = optional_path(value),
+            "tls-ca-cert-file" => self.tls_ca_cert_file = optional_path(value),
+            "tls-auth-clients" => self.tls_auth_clients = value.parse()?,
+            "metrics-port" => {
+                self.metrics_port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port '{}'", value))?
+            }
+            "maxclients" => {
+                self.maxclients = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid maxclients '{}'", value)),
+                }
+            }
+            "timeout" => {
+                self.timeout = value
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|_| format!("invalid timeout '{}'", value))?
+            }
+            "tcp-keepalive" => {
+                self.tcp_keepalive = value
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|_| format!("invalid tcp-keepalive '{}'", value))?
+            }
+            "tcp-nodelay" => self.tcp_nodelay = parse_bool(value)?,
+            "databases" => {
+                self.databases = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid number of databases '{}'", value)),
+                }
+            }
+            "loglevel" => self.loglevel = value.parse()?,
+            "requirepass" => self.requirepass = value.to_string(),
+            "aclfile" => self.aclfile = optional_path(value),
+            "shutdown-timeout" => {
+                self.shutdown_timeout = value
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|_| format!("invalid shutdown-timeout '{}'", value))?
+            }
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            "save" => self.save = rdb::parse_save_rules(value)?,
+            "appendonly" => self.appendonly = parse_bool(value)?,
+            "appendfilename" => self.appendfilename = value.to_string(),
+            "appendfsync" => self.appendfsync = value.parse()?,
+            "aof-load-truncated" => self.aof_load_truncated = parse_bool(value)?,
+            "port" => {
+                self.port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port '{}'", value))?
+            }
+            "replicaof" => self.replicaof = parse_replicaof(value)?,
+            "replica-read-only" => self.replica_read_only = parse_bool(value)?,
+            "masteruser" => self.masteruser = value.to_string(),
+            "masterauth" => self.masterauth = value.to_string(),
+            "repl-backlog-size" => self.repl_backlog_size = parse_memory(value)?,
+            "client-output-buffer-limit" => {
+                self.client_output_buffer_limit = replication::parse_output_buffer_limit(value)?
+            }
+            "cluster-enabled" => self.cluster_enabled = parse_bool(value)?,
+            "cluster-port" => {
+                self.cluster_port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port '{}'", value))?
+            }
+            "cluster-node-timeout" => {
+                self.cluster_node_timeout = value
+                    .parse()
+                    .map(Duration::from_millis)
+                    .map_err(|_| format!("invalid milliseconds '{}'", value))?
+            }
+            "cluster-announce-ip" => self.cluster_announce_ip = value.to_string(),
+            _ => unreachable!("parameter {} is not handled", parameter.name),
+        }
+        Ok(())
+    }
+
+    /// The value of a parameter as CONFIG GET reports it.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match lookup(name)?.name {
+            "bind" => self.bind.join(" "),
+            "port" => self.port.to_string(),
+            "unixsocket" => display_path(&self.unixsocket),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm),
+            "tls-port" => self.tls_port.to_string(),
+            "tls-cert-file" => display_path(&self.tls_cert_file),
+            "tls-key-file" => display_path(&self.tls_key_file),
+            "tls-ca-cert-file" => display_path(&self.tls_ca_cert_file),
+            "tls-auth-clients" => self.tls_auth_clients.to_string(),
+            "metrics-port" => self.metrics_port.to_string(),
+            "maxclients" => self.maxclients.to_string(),
+            "timeout" => self.timeout.as_secs().to_string(),
+            "tcp-keepalive" => self.tcp_keepalive.as_secs().to_string(),
+            "tcp-nodelay" => yes_no(self.tcp_nodelay),
+            "databases" => self.databases.to_string(),
+            "loglevel" => self.loglevel.to_string(),
+            "requirepass" => self.requirepass.clone(),
+            "aclfile" => display_path(&self.aclfile),
+            "shutdown-timeout" => self.shutdown_timeout.as_secs().to_string(),
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.dbfilename.clone(),
+            "save" => self
+                .save
+                .iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "appendonly" => yes_no(self.appendonly),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => self.appendfsync.to_string(),
+            "aof-load-truncated" => yes_no(self.aof_load_truncated),
+            "replicaof" => match &self.replicaof {
+                Some((host, port)) => format!("{} {}", host, port),
+                None => String::new(),
+            },
+            "replica-read-only" => yes_no(self.replica_read_only),
+            "masteruser" => self.masteruser.clone(),
+            "masterauth" => self.masterauth.clone(),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "client-output-buffer-limit" => self.client_output_buffer_limit.to_string(),
+            "cluster-enabled" => yes_no(self.cluster_enabled),
+            "cluster-port" => self.cluster_port.to_string(),
+            "cluster-node-timeout" => self.cluster_node_timeout.as_millis().to_string(),
+            "cluster-announce-ip" => self.cluster_announce_ip.clone(),
+            name => unreachable!("parameter {} is not handled", name),
+        };
+        Some(value)
+    }
+
+    /// Read a redis.conf style file: one `name value...` directive per line,
+    /// `#` starts a comment and values with spaces can be quoted. Repeated
+    /// `save` lines add up, like in Redis.
+    ///
+    /// The path is remembered so that CONFIG REWRITE can update the file.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("can't open config file '{}': {}", path.display(), err))?;
+        self.load_str(&text)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        //REWRITE 的时候工作目录可能已经变了
+        self.config_file = Some(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Apply the directives in the contents of a config file.
+    pub fn load_str(&mut self, text: &str) -> Result<(), String> {
+        let mut save: Option<Vec<String>> = None;
+        for (number, line) in text.lines().enumerate() {
+            let at_line = |err: String| format!("line {}: {}", number + 1, err);
+            let words = split_line(line).map_err(at_line)?;
+            let (name, values) = match words.split_first() {
+                Some((name, values)) => (name, values.join(" ")),
+                None => continue,
+            };
+            if values.is_empty() && !name.eq_ignore_ascii_case("save") {
+                return Err(at_line(format!("missing value for '{}'", name)));
+            }
+            //`save ""` 清空之前的规则，否则每行 save 追加一条规则
+            if name.eq_ignore_ascii_case("save") {
+                rdb::parse_save_rules(&values).map_err(at_line)?;
+                let rules = save.get_or_insert_with(Vec::new);
+                if values.is_empty() {
+                    rules.clear();
+                } else {
+                    rules.push(values);
+                }
+                continue;
+            }
+            self.set(name, &values).map_err(at_line)?;
+        }
+        if let Some(rules) = save {
+            self.set("save", &rules.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Write the current values back to the config file. Comments and
+    /// unknown lines are kept, directives are updated in place and
+    /// parameters that differ from the defaults are appended.
+    pub fn rewrite(&self) -> io::Result<()> {
+        let path = self.config_file.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "The server is running without a config file",
+            )
+        })?;
+        let old = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut written = HashSet::new();
+        let mut out = String::new();
+        for line in old.lines() {
+            let parameter = split_line(line)
+                .ok()
+                .and_then(|words| words.first().and_then(|name| lookup(name)));
+            match parameter {
+                //同一个参数的多行（比如 save）合并成当前的值
+                Some(parameter) => {
+                    if written.insert(parameter.name) {
+                        self.write_directive(parameter.name, &mut out);
+                    }
+                }
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+        let defaults = Config::default();
+        for parameter in PARAMETERS {
+            if !written.contains(parameter.name)
+                && self.get(parameter.name) != defaults.get(parameter.name)
+            {
+                self.write_directive(parameter.name, &mut out);
+            }
+        }
+
+        //和 RDB 一样先写临时文件再 rename
+        let tmp = path.with_file_name(format!("temp-{}.conf", std::process::id()));
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)
+    }
+
+    fn write_directive(&self, name: &str, out: &mut String) {
+        let value = self.get(name).unwrap_or_default();
+        match name {
+            "save" if self.save.is_empty() => out.push_str("save \"\"\n"),
+            "save" => {
+                for rule in &self.save {
+                    out.push_str(&format!("save {} {}\n", rule.seconds, rule.changes));
+                }
+            }
+            "replicaof" if self.replicaof.is_none() => out.push_str("replicaof no one\n"),
+            //这些参数本身就是空格分隔的多个值
+            "bind" | "replicaof" | "client-output-buffer-limit" => {
+                out.push_str(&format!("{} {}\n", name, value))
+            }
+            _ => out.push_str(&format!("{} {}\n", name, quote(&value))),
+        }
+    }
+
+    /// Parse `--name value...` options from the command line. Everything up
+    /// to the next `--name` is the value, so `--replicaof 127.0.0.1 6380` works.
+    pub fn parse_args<I: Iterator<Item = String>>(&mut self, args: I) -> Result<(), String> {
+        for_each_option(args, |name, value| self.set(name, value))
+    }
+
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+}
+
+/// Split `--name value...` options and call `set` with each name and its
+/// space separated values.
+pub fn for_each_option<I, F>(args: I, mut set: F) -> Result<(), String>
+where
+    I: Iterator<Item = String>,
+    F: FnMut(&str, &str) -> Result<(), String>,
+{
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument '{}'", arg))?;
+        let mut values = Vec::new();
+        while let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+            values.push(value);
+        }
+        if values.is_empty() {
+            return Err(format!("missing value for '--{}'", name));
+        }
+        set(name, &values.join(" "))?;
+    }
+    Ok(())
+}
+
+// `host port`，或者 `no one` 表示不再作为 replica
+fn parse_replicaof(value: &str) -> Result<Option<(String, u16)>, String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts[..] {
+        [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => Ok(None),
+        [host, port] => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("invalid master port '{}'", port))?;
+            Ok(Some((host.to_string(), port)))
+        }
+        _ => Err(format!(
+            "replicaof expects '<host> <port>', got '{}'",
+            value
+        )),
+    }
+}
+
+/// Parse a memory size such as `1mb` or `64kb`; a bare number is bytes.
+pub fn parse_memory(value: &str) -> Result<usize, String> {
+    let lower = value.to_ascii_lowercase();
+    let units: [(&str, usize); 6] = [
+        ("kb", 1024),
+        ("mb", 1024 * 1024),
+        ("gb", 1024 * 1024 * 1024),
+        ("k", 1000),
+        ("m", 1000 * 1000),
+        ("g", 1000 * 1000 * 1000),
+    ];
+    let (digits, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| lower.strip_suffix(suffix).map(|digits| (digits, *unit)))
+        .unwrap_or((&lower[..], 1));
+    digits
+        .parse::<usize>()
+        .map(|n| n * unit)
+        .map_err(|_| format!("invalid memory size '{}'", value))
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+//空字符串表示没有设置
+fn optional_path(value: &str) -> Option<PathBuf> {
+    (!value.is_empty()).then(|| PathBuf::from(value))
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match &value.to_ascii_lowercase()[..] {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("argument must be 'yes' or 'no', got '{}'", value)),
+    }
+}
+
+//按空白切分一行配置，支持双引号（带 \" \\ \n 转义）和单引号，`#` 开头的是注释
+fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    if chars.peek() == Some(&'#') {
+        return Ok(words);
+    }
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quote = match chars.peek() {
+            None => return Ok(words),
+            Some(&c) if c == '"' || c == '\'' => chars.next(),
+            Some(_) => None,
+        };
+        let mut word = String::new();
+        loop {
+            match (chars.next(), quote) {
+                (None, None) => break,
+                (None, Some(_)) => {
+                    return Err("unbalanced quotes in configuration line".to_string())
+                }
+                (Some(c), None) if c.is_whitespace() => break,
+                (Some(c), Some(q)) if c == q => {
+                    if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                        return Err("closing quote must be followed by a space".to_string());
+                    }
+                    break;
+                }
+                (Some('\\'), Some('"')) => match chars.next() {
+                    Some('n') => word.push('\n'),
+                    Some('t') => word.push('\t'),
+                    Some(c) => word.push(c),
+                    None => return Err("unbalanced quotes in configuration line".to_string()),
+                },
+                (Some(c), _) => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
+
+//写回配置文件时，空值或者带空白、引号的值加上双引号
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogLevel, String> {
+        match &s.to_ascii_lowercase()[..] {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(format!("invalid loglevel '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        };
+        name.fmt(fmt)
+    }
+}
+
+impl FromStr for TlsAuthClients {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TlsAuthClients, String> {
+        match &s.to_ascii_lowercase()[..] {
+            "yes" => Ok(TlsAuthClients::Yes),
+            "no" => Ok(TlsAuthClients::No),
+            "optional" => Ok(TlsAuthClients::Optional),
+            _ => Err(format!("invalid tls-auth-clients '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for TlsAuthClients {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TlsAuthClients::Yes => "yes",
+            TlsAuthClients::No => "no",
+            TlsAuthClients::Optional => "optional",
+        };
+        name.fmt(fmt)
+    }
+}
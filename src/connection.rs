@@ -1,18 +1,56 @@
+use crate::{
+    error::{
+        Error,
+        Result,
+    },
+    frame::{
+        Error::Incomplete,
+        Frame,
+    },
+};
 use bytes::{
     Buf,
+    Bytes,
     BytesMut,
 };
-use mini_redis::Frame; //frame.rs
-use mini_redis::Result;
-use std::io::Cursor;
+use std::{
+    io::{
+        self,
+        Cursor,
+    },
+    net::SocketAddr,
+};
 use tokio::{
-    io::AsyncReadExt,
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        AsyncWriteExt,
+    },
     net::TcpStream,
 };
-use mini_redis::frame::Error::Incomplete;
 
-pub struct Connection {
-    stream: TcpStream,
+/// Largest payload [`Connection::read_payload`] accepts. The length comes
+/// from the peer, so anything bigger is treated as a protocol error instead
+/// of being waited for.
+pub const MAX_PAYLOAD_LEN: u64 = 4 * 1024 * 1024 * 1024;
+
+//`$`、u64 最多 20 位数字加上 \r\n，读到这么多还没有 \r\n 的不是合法的长度
+const MAX_PAYLOAD_HEADER_LEN: usize = 23;
+
+/// A byte stream a [`Connection`] can run over, such as a TCP stream or a
+/// Unix socket.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static> Stream for S {}
+
+/// A stream whose type is only known at runtime, e.g. a client that can be
+/// connected over TCP or a Unix socket.
+pub type BoxedStream = Box<dyn Stream>;
+
+//默认是 TcpStream，服务端之间的连接和原来的代码不用写类型参数
+pub struct Connection<S = TcpStream> {
+    stream: S,
     // 底层调用的Tcpstream::read方法的读取stream的行为是不确定的
     //所以我们要为Connection增加一个read buffer: socket->buffer->parse to freme -> remove the data
     // from buffer 这里使用 BytesMut 作为缓冲区类型，它是 Bytes 的可变版本。
@@ -20,7 +58,13 @@ pub struct Connection {
 }
 
 impl Connection {
-    pub fn new(stream: TcpStream) -> Connection {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Connection<S> {
         Connection {
             stream,
             //Allocate the buffer with 4kb of capacity
@@ -28,6 +72,13 @@ impl Connection {
         }
     }
 
+    /// Bytes read from the stream but not parsed into a frame yet, and the
+    /// room left in the read buffer before it has to grow.
+    pub fn read_buffer(&self) -> (usize, usize) {
+        let len = self.buffer.len();
+        (len, self.buffer.capacity() - len)
+    }
+
     //read_frame 内部使用循环的方式读取数据，直到一个完整的帧被读取到时，才会返回。
     //当远程的对端关闭了连接后，也会返回。
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
@@ -54,7 +105,7 @@ impl Connection {
                 if self.buffer.is_empty() {
                     return Ok(None);
                 } else {
-                    return Err("connection was reset by peer".into());
+                    return Err(Error::ConnectionClosed);
                 }
             }
         }
@@ -88,4 +139,66 @@ impl Connection {
             Err(e) => Err(e.into()),
         }
     }
+
+    //主从复制时 master 发送 RDB 文件的格式是 `$<len>\r\n<data>`，和 bulk string 不同的是末尾没有 \r\n，
+    //所以不能用 read_frame 解析
+    pub async fn read_payload(&mut self) -> Result<Bytes> {
+        loop {
+            //master 生成 RDB 期间可能会发送 \n 作为心跳
+            while self.buffer.first() == Some(&b'\n') {
+                self.buffer.advance(1);
+            }
+
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                if self.buffer[0] != b'$' {
+                    return Err(Error::protocol("protocol error; expected a payload"));
+                }
+                let len = atoi::atoi::<u64>(&self.buffer[1..end])
+                    .ok_or_else(|| Error::protocol("protocol error; invalid payload length"))?;
+                if len > MAX_PAYLOAD_LEN {
+                    return Err(Error::protocol("protocol error; payload too large"));
+                }
+                let len = len as usize;
+                self.buffer.advance(end + 2);
+
+                while self.buffer.len() < len {
+                    if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                        return Err(Error::ConnectionClosed);
+                    }
+                }
+                return Ok(self.buffer.split_to(len).freeze());
+            }
+            if self.buffer.len() >= MAX_PAYLOAD_HEADER_LEN {
+                return Err(Error::protocol("protocol error; invalid payload length"));
+            }
+
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return Err(Error::ConnectionClosed);
+            }
+        }
+    }
+
+    //写入已经编码好的数据，比如复制流
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes).await?;
+        self.stream.flush().await
+    }
+
+    //先把整个 frame 编码到内存中，再一次性写入 socket，避免每个字段都产生一次系统调用
+    pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut buf = Vec::new();
+        frame.encode(&mut buf);
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await
+    }
+
+    //pipeline：多个 frame 编码到同一个缓冲区，一次写入
+    pub async fn write_frames(&mut self, frames: &[Frame]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for frame in frames {
+            frame.encode(&mut buf);
+        }
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await
+    }
 }
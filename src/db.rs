@@ -0,0 +1,367 @@
+use bytes::Bytes;
+use std::{
+    collections::{
+        BTreeSet,
+        HashMap,
+    },
+    fmt,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        TryLockError,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    sync::Notify,
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+/// Server state shared across all connections.
+///
+/// 原来 server.rs 里的 `Arc<Mutex<HashMap<String, Bytes>>>` 只能保存字符串，
+/// 这里增加了过期时间，并把访问 keyspace 的逻辑集中到 `State` 上，
+/// 这样普通命令和脚本里的 `redis.call` 可以共用同一套实现。
+#[derive(Debug, Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    //通知后台清理任务：有更早过期的key，或者server正在关闭
+    background_task: Notify,
+}
+
+#[derive(Debug, Default)]
+pub struct State {
+    entries: HashMap<String, Entry>,
+    //按过期时间排序，后台任务只需要看第一个元素就知道下次什么时候醒来
+    expirations: BTreeSet<(Instant, String)>,
+    shutdown: bool,
+    //写命令的下游，比如 AOF。在持有锁的时候调用，所以看到的顺序和执行顺序一致
+    feeds: Vec<Box<dyn Feed>>,
+    /// Number of writes since the last successful save.
+    pub dirty: u64,
+    /// Lookups by read commands that found, or didn't find, the key.
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+    /// Keys removed because their time to live ran out.
+    pub expired_keys: u64,
+    //所有 key 和 value 的字节数，INFO memory 用，不用每次遍历
+    dataset_bytes: usize,
+}
+
+/// Receives every write command after it has been applied to the keyspace.
+pub trait Feed: Send + fmt::Debug {
+    fn feed(&mut self, args: &[Bytes]);
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub data: Bytes,
+    pub expires_at: Option<Instant>,
+}
+
+impl Db {
+    /// Create a new, empty, `Db` instance and spawn the background task that
+    /// purges expired keys.
+    pub fn new() -> Db {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            background_task: Notify::new(),
+        });
+
+        tokio::spawn(purge_expired_tasks(shared.clone()));
+
+        Db { shared }
+    }
+
+    /// Lock the keyspace. Everything done through the returned guard is atomic
+    /// with respect to other connections.
+    pub fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
+    }
+
+    /// Like [`Db::lock`], but returns `None` instead of waiting while someone
+    /// else, e.g. a long running script, holds the keyspace.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, State>> {
+        self.shared.state.try_lock().ok()
+    }
+
+    /// Wake the background task so it recomputes when the next key expires.
+    pub fn notify_expiration(&self) {
+        self.shared.background_task.notify_one();
+    }
+
+    /// Signal the purge task to exit.
+    pub fn shutdown_purge_task(&self) {
+        let mut state = self.lock();
+        state.shutdown = true;
+        drop(state);
+        self.shared.background_task.notify_one();
+    }
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        Db::new()
+    }
+}
+
+impl State {
+    /// Get the value of a key, treating expired entries as missing.
+    pub fn get(&mut self, key: &str) -> Option<Bytes> {
+        self.entry(key).map(|entry| entry.data.clone())
+    }
+
+    pub fn entry(&mut self, key: &str) -> Option<&Entry> {
+        //惰性删除：后台任务可能还没来得及清理已过期的key
+        if self.is_expired(key) {
+            self.remove(key);
+            self.expired_keys += 1;
+        }
+        self.entries.get(key)
+    }
+
+    /// Like [`State::entry`], for commands that read the key: counted in
+    /// the keyspace hits and misses.
+    pub fn read(&mut self, key: &str) -> Option<&Entry> {
+        if self.entry(key).is_some() {
+            self.keyspace_hits += 1;
+        } else {
+            self.keyspace_misses += 1;
+        }
+        self.entries.get(key)
+    }
+
+    /// Set `key` to `value`, replacing any existing expiration.
+    pub fn set(&mut self, key: String, value: Bytes, expires_at: Option<Instant>) {
+        self.insert(
+            key,
+            Entry {
+                data: value,
+                expires_at,
+            },
+        );
+    }
+
+    /// Insert an entry as-is, used when loading persisted data.
+    pub fn insert(&mut self, key: String, entry: Entry) {
+        //先删掉旧值，否则旧值的过期时间会残留在 expirations 里
+        self.remove(&key);
+        if let Some(when) = entry.expires_at {
+            self.expirations.insert((when, key.clone()));
+        }
+        self.dataset_bytes += key.len() + entry.data.len();
+        self.entries.insert(key, entry);
+    }
+
+    /// Remove a key. Returns the removed entry if it existed.
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        let prev = self.entries.remove(key)?;
+        if let Some(when) = prev.expires_at {
+            self.expirations.remove(&(when, key.to_string()));
+        }
+        self.dataset_bytes -= key.len() + prev.data.len();
+        Some(prev)
+    }
+
+    /// Change the expiration of an existing key. Returns `false` if the key
+    /// does not exist.
+    pub fn set_expires_at(&mut self, key: &str, expires_at: Option<Instant>) -> bool {
+        if self.entry(key).is_none() {
+            return false;
+        }
+        let entry = self.entries.get_mut(key).unwrap();
+        if let Some(when) = entry.expires_at {
+            self.expirations.remove(&(when, key.to_string()));
+        }
+        entry.expires_at = expires_at;
+        if let Some(when) = expires_at {
+            self.expirations.insert((when, key.to_string()));
+        }
+        true
+    }
+
+    pub fn contains_key(&mut self, key: &str) -> bool {
+        self.entry(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of keys with an expiration.
+    pub fn expires(&self) -> usize {
+        self.expirations.len()
+    }
+
+    /// Average time to live of the keys with an expiration, zero when there
+    /// are none.
+    pub fn avg_ttl(&self) -> Duration {
+        if self.expirations.is_empty() {
+            return Duration::ZERO;
+        }
+        let now = Instant::now();
+        let total: Duration = self
+            .expirations
+            .iter()
+            .map(|(when, _)| when.saturating_duration_since(now))
+            .sum();
+        total / self.expirations.len() as u32
+    }
+
+    /// Total size of the keys and values, without any bookkeeping overhead.
+    pub fn dataset_bytes(&self) -> usize {
+        self.dataset_bytes
+    }
+
+    /// Register a downstream consumer of write commands.
+    pub fn add_feed(&mut self, feed: Box<dyn Feed>) {
+        self.feeds.push(feed);
+    }
+
+    /// Pass an applied write command on to every feed.
+    pub fn propagate(&mut self, args: &[Bytes]) {
+        self.dirty += 1;
+        for feed in &mut self.feeds {
+            feed.feed(args);
+        }
+    }
+
+    /// Clone every live entry, used by background rewrites and snapshots.
+    pub fn snapshot(&self) -> Vec<(String, Entry)> {
+        self.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.expirations.clear();
+        self.dataset_bytes = 0;
+    }
+
+    /// Iterate over all live entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| entry.expires_at.is_none_or(|when| when > now))
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        match self.entries.get(key).and_then(|entry| entry.expires_at) {
+            Some(when) => when <= Instant::now(),
+            None => false,
+        }
+    }
+
+    /// Purge all keys whose expiration is in the past.
+    fn purge_expired_keys(&mut self) {
+        let now = Instant::now();
+
+        while let Some((when, key)) = self.expirations.iter().next().cloned() {
+            if when > now {
+                break;
+            }
+            self.remove(&key);
+            self.expired_keys += 1;
+        }
+    }
+
+    fn next_expiration(&self) -> Option<Instant> {
+        self.expirations.iter().next().map(|(when, _)| *when)
+    }
+}
+
+//脚本持有锁时，后台清理任务隔多久再试一次
+const BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+//后台清理任务：等到下一个key过期，或者被通知有新的更早的过期时间
+async fn purge_expired_tasks(shared: Arc<Shared>) {
+    loop {
+        let next = match shared.state.try_lock() {
+            Ok(mut state) => {
+                if state.shutdown {
+                    return;
+                }
+                state.purge_expired_keys();
+                state.next_expiration()
+            }
+            //脚本执行期间一直持有锁，不能在这里阻塞 runtime 的工作线程，过一会儿再试
+            Err(TryLockError::WouldBlock) => Some(Instant::now() + BUSY_RETRY_INTERVAL),
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        };
+
+        match next {
+            Some(when) => {
+                tokio::select! {
+                    _ = time::sleep_until(when) => {}
+                    _ = shared.background_task.notified() => {}
+                }
+            }
+            None => shared.background_task.notified().await,
+        }
+    }
+}
+
+/// Latest expiration time, in milliseconds since the unix epoch. Redis keeps
+/// expirations as signed 64-bit milliseconds, so anything later can't be
+/// propagated or saved.
+pub const MAX_UNIX_MS: u64 = i64::MAX as u64;
+
+/// Milliseconds since the unix epoch.
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Convert an `Instant` into milliseconds since the unix epoch, so that
+/// expirations survive a restart. Saturates at [`MAX_UNIX_MS`].
+pub fn to_unix_ms(when: Instant) -> u64 {
+    let now = Instant::now();
+    let unix_now = unix_time_ms();
+    if when >= now {
+        unix_now
+            .saturating_add((when - now).as_millis() as u64)
+            .min(MAX_UNIX_MS)
+    } else {
+        unix_now.saturating_sub((now - when).as_millis() as u64)
+    }
+}
+
+/// The inverse of `to_unix_ms`. Returns `None` for times after
+/// [`MAX_UNIX_MS`] or too far away for an `Instant`.
+pub fn from_unix_ms(ms: u64) -> Option<Instant> {
+    if ms > MAX_UNIX_MS {
+        return None;
+    }
+    let now = Instant::now();
+    let unix_now = unix_time_ms();
+    if ms >= unix_now {
+        now.checked_add(Duration::from_millis(ms - unix_now))
+    } else {
+        //已经过期了，减不出来的话就当作现在过期
+        Some(
+            now.checked_sub(Duration::from_millis(unix_now - ms))
+                .unwrap_or(now),
+        )
+    }
+}
@@ -0,0 +1,141 @@
+//! 客户端使用的错误类型。
+//!
+//! 原来所有函数都返回 `Box<dyn Error>`，调用方只能比较错误信息的字符串来决定要不要重连、
+//! 要不要跟随 MOVED 重定向。现在按照错误的来源分成几类，服务端的错误回复会解析出错误码。
+
+use crate::types::ConversionError;
+use std::{
+    fmt,
+    io,
+};
+
+/// Errors returned by the clients and by [`Connection`](crate::Connection).
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the socket failed, or connecting failed.
+    Io(io::Error),
+    /// The peer sent something that isn't valid RESP, or a reply that
+    /// doesn't fit the request.
+    Protocol(String),
+    /// An error reply from the server, e.g. `WRONGTYPE Operation against a
+    /// key holding the wrong kind of value`.
+    ServerError {
+        /// The first word of the reply, such as `ERR`, `WRONGTYPE`, `MOVED`
+        /// or `NOSCRIPT`.
+        code: String,
+        /// The rest of the reply.
+        message: String,
+    },
+    /// The server closed the connection, or the client gave up on it.
+    ConnectionClosed,
+    /// The operation didn't complete in time.
+    Timeout,
+    /// A reply couldn't be converted into the requested type.
+    TypeConversion(ConversionError),
+}
+
+/// A specialized `Result` type for client operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Parse an error reply. Redis error replies start with an upper-case
+    /// code; replies without one get the generic `ERR` code.
+    pub fn server(reply: impl Into<String>) -> Error {
+        let reply = reply.into();
+        let (code, message) = reply.split_once(' ').unwrap_or((&reply, ""));
+        if !code.is_empty() && code.bytes().all(|b| b.is_ascii_uppercase() || b == b'_') {
+            Error::ServerError {
+                code: code.to_string(),
+                message: message.to_string(),
+            }
+        } else {
+            Error::ServerError {
+                code: "ERR".to_string(),
+                message: reply,
+            }
+        }
+    }
+
+    pub(crate) fn protocol(message: impl Into<String>) -> Error {
+        Error::Protocol(message.into())
+    }
+
+    /// The error code of a server error reply.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::ServerError { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Whether the connection the error happened on can't be used anymore,
+    /// so that a client has to reconnect before sending more commands.
+    pub fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(_) | Error::ConnectionClosed | Error::Timeout | Error::Protocol(_)
+        )
+    }
+}
+
+//SharedClient 要把同一个错误交给一批等待的调用方，io::Error 不能 Clone，按 kind 和信息重建
+impl Clone for Error {
+    fn clone(&self) -> Error {
+        match self {
+            Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            Error::Protocol(message) => Error::Protocol(message.clone()),
+            Error::ServerError { code, message } => Error::ServerError {
+                code: code.clone(),
+                message: message.clone(),
+            },
+            Error::ConnectionClosed => Error::ConnectionClosed,
+            Error::Timeout => Error::Timeout,
+            Error::TypeConversion(err) => Error::TypeConversion(err.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(fmt),
+            Error::Protocol(message) => message.fmt(fmt),
+            Error::ServerError { code, message } if message.is_empty() => code.fmt(fmt),
+            Error::ServerError { code, message } => write!(fmt, "{} {}", code, message),
+            Error::ConnectionClosed => "connection closed".fmt(fmt),
+            Error::Timeout => "operation timed out".fmt(fmt),
+            Error::TypeConversion(err) => err.fmt(fmt),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::TypeConversion(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<ConversionError> for Error {
+    fn from(err: ConversionError) -> Error {
+        Error::TypeConversion(err)
+    }
+}
+
+impl From<crate::frame::Error> for Error {
+    fn from(err: crate::frame::Error) -> Error {
+        match err {
+            crate::frame::Error::Incomplete => Error::ConnectionClosed,
+            crate::frame::Error::Other(message) => Error::Protocol(message),
+        }
+    }
+}
@@ -1,6 +1,46 @@
+pub mod acl;
 pub mod connection;
 pub use connection::Connection;
 pub mod blocking_client;
 pub use blocking_client::BlockingClient;
-// pub mod frame;
-// pub use frame::Frame;
+pub mod client;
+pub use client::Client;
+pub(crate) mod clients;
+pub mod frame;
+pub use frame::Frame;
+pub mod aof;
+pub mod cluster;
+pub mod cluster_client;
+pub use cluster_client::ClusterClient;
+pub mod cmd;
+pub mod config;
+pub use config::Config;
+pub mod db;
+pub use db::Db;
+pub mod error;
+pub use error::Error;
+#[cfg(feature = "metrics")]
+pub(crate) mod metrics;
+pub(crate) mod peer;
+pub mod pipeline;
+pub use pipeline::Pipeline;
+pub mod pool;
+pub use pool::Pool;
+pub(crate) mod pubsub;
+pub mod rdb;
+pub mod reconnect;
+pub mod replication;
+pub mod script;
+pub mod sentinel;
+pub mod server;
+pub mod shared_client;
+pub use shared_client::SharedClient;
+pub mod shutdown;
+pub(crate) mod stats;
+pub mod sync_client;
+pub use sync_client::SyncClient;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tls")]
+pub use tls::TlsConnector;
+pub mod types;
@@ -0,0 +1,368 @@
+//! Prometheus 指标（`metrics` feature）。
+//!
+//! `metrics-port` 不为 0 时，服务端在 `bind` 的每个地址上额外监听一个 HTTP 端口，
+//! `GET /metrics` 返回 Prometheus 的文本格式，其他路径返回 404。
+//! 只需要应答抓取请求，所以没有引入 HTTP 库：读完请求头，写一个带 Content-Length 的回复，然后关闭连接。
+//!
+//! 数据和 INFO 是同一份：计数器来自 `stats`，keyspace 的数据在 `db::State` 里。
+
+use crate::{
+    aof::Aof,
+    clients::Clients,
+    db::Db,
+    rdb::Rdb,
+    shutdown::Shutdown,
+    stats::{
+        self,
+        Stats,
+        LATENCY_BUCKETS,
+    },
+};
+use std::{
+    fmt::Write as _,
+    io,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    time::{
+        self,
+        Duration,
+    },
+};
+
+//抓取请求的请求头不会很大，超过的直接断开
+const MAX_REQUEST: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Everything the metrics are collected from.
+#[derive(Clone)]
+pub(crate) struct Sources {
+    pub(crate) stats: Stats,
+    pub(crate) clients: Clients,
+    pub(crate) db: Db,
+    pub(crate) rdb: Rdb,
+    pub(crate) aof: Option<Aof>,
+    /// The keyspace figures of the last scrape, served again while a script
+    /// holds the keyspace.
+    pub(crate) keyspace: Arc<Mutex<Keyspace>>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Keyspace {
+    keys: usize,
+    expires: usize,
+    used_memory: usize,
+    expired: u64,
+    hits: u64,
+    misses: u64,
+    dirty: u64,
+}
+
+/// Answer scrapes on `listener` until the server shuts down.
+pub(crate) async fn serve(listener: TcpListener, sources: Sources, mut shutdown: Shutdown) {
+    loop {
+        let stream = tokio::select! {
+            res = listener.accept() => match res {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    println!("metrics accept error: {}", err);
+                    time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = shutdown.recv() => return,
+        };
+        let sources = sources.clone();
+        tokio::spawn(async move {
+            //超时的请求直接断开
+            let res = time::timeout(REQUEST_TIMEOUT, respond(stream, &sources)).await;
+            if let Ok(Err(err)) = res {
+                println!("metrics request error: {}", err);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, sources: &Sources) -> io::Result<()> {
+    let mut request = Vec::new();
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST {
+            return Ok(());
+        }
+        if stream.read_buf(&mut request).await? == 0 {
+            return Ok(());
+        }
+    }
+
+    //请求行：GET /metrics HTTP/1.1，忽略查询参数
+    let line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = line.split(|&b| b == b' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split(|&b| b == b'?').next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        (b"GET", b"/metrics") => ("200 OK", render(sources)),
+        (b"GET", _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn render(sources: &Sources) -> String {
+    let snapshot = sources.stats.snapshot();
+    let mut out = String::new();
+
+    gauge(
+        &mut out,
+        "redis_uptime_in_seconds",
+        "Seconds since the server started.",
+        snapshot.uptime.as_secs(),
+    );
+    gauge(
+        &mut out,
+        "redis_connected_clients",
+        "Connected clients, replicas excluded.",
+        sources.clients.connected(),
+    );
+    counter(
+        &mut out,
+        "redis_connections_received_total",
+        "Connections accepted.",
+        snapshot.connections_received,
+    );
+    counter(
+        &mut out,
+        "redis_rejected_connections_total",
+        "Connections closed because of maxclients.",
+        snapshot.rejected_connections,
+    );
+    counter(
+        &mut out,
+        "redis_commands_processed_total",
+        "Commands executed.",
+        snapshot.commands_processed,
+    );
+    counter(
+        &mut out,
+        "redis_net_input_bytes_total",
+        "Bytes read from client connections.",
+        snapshot.net_input_bytes,
+    );
+    counter(
+        &mut out,
+        "redis_net_output_bytes_total",
+        "Bytes written to client connections.",
+        snapshot.net_output_bytes,
+    );
+
+    header(
+        &mut out,
+        "redis_command_duration_seconds",
+        "histogram",
+        "Time spent executing commands.",
+    );
+    for (name, stats) in &snapshot.commands {
+        let cmd = name.to_ascii_lowercase();
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "redis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}",
+                cmd,
+                bound.as_secs_f64(),
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "redis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}",
+            cmd, stats.calls
+        );
+        let _ = writeln!(
+            out,
+            "redis_command_duration_seconds_sum{{cmd=\"{}\"}} {}",
+            cmd,
+            stats.usec as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "redis_command_duration_seconds_count{{cmd=\"{}\"}} {}",
+            cmd, stats.calls
+        );
+    }
+    header(
+        &mut out,
+        "redis_command_rejected_calls_total",
+        "counter",
+        "Commands refused before they ran, e.g. wrong arity or no permission.",
+    );
+    for (name, stats) in &snapshot.commands {
+        let _ = writeln!(
+            out,
+            "redis_command_rejected_calls_total{{cmd=\"{}\"}} {}",
+            name.to_ascii_lowercase(),
+            stats.rejected_calls
+        );
+    }
+    header(
+        &mut out,
+        "redis_command_failed_calls_total",
+        "counter",
+        "Commands that ran and replied with an error.",
+    );
+    for (name, stats) in &snapshot.commands {
+        let _ = writeln!(
+            out,
+            "redis_command_failed_calls_total{{cmd=\"{}\"}} {}",
+            name.to_ascii_lowercase(),
+            stats.failed_calls
+        );
+    }
+
+    //keyspace 的数据一次加锁取完。脚本执行期间一直持有锁，这时不能阻塞 runtime 的工作线程，
+    //用上一次抓取的数据
+    let Keyspace {
+        keys,
+        expires,
+        used_memory,
+        expired,
+        hits,
+        misses,
+        dirty,
+    } = match sources.db.try_lock() {
+        Some(state) => {
+            let keyspace = Keyspace {
+                keys: state.len(),
+                expires: state.expires(),
+                used_memory: stats::used_memory(&state),
+                expired: state.expired_keys,
+                hits: state.keyspace_hits,
+                misses: state.keyspace_misses,
+                dirty: state.dirty,
+            };
+            *sources.keyspace.lock().unwrap() = keyspace;
+            keyspace
+        }
+        None => *sources.keyspace.lock().unwrap(),
+    };
+    header(&mut out, "redis_db_keys", "gauge", "Keys in the database.");
+    let _ = writeln!(out, "redis_db_keys{{db=\"db0\"}} {}", keys);
+    header(
+        &mut out,
+        "redis_db_keys_expiring",
+        "gauge",
+        "Keys with an expiration in the database.",
+    );
+    let _ = writeln!(out, "redis_db_keys_expiring{{db=\"db0\"}} {}", expires);
+    gauge(
+        &mut out,
+        "redis_memory_used_bytes",
+        "Estimated memory used by the keys and values.",
+        used_memory,
+    );
+    counter(
+        &mut out,
+        "redis_expired_keys_total",
+        "Keys removed because their time to live ran out.",
+        expired,
+    );
+    //没有 maxmemory，一直是 0，保留这个指标是为了让现有的面板能用
+    counter(
+        &mut out,
+        "redis_evicted_keys_total",
+        "Keys evicted because of maxmemory.",
+        0,
+    );
+    counter(
+        &mut out,
+        "redis_keyspace_hits_total",
+        "Read lookups that found the key.",
+        hits,
+    );
+    counter(
+        &mut out,
+        "redis_keyspace_misses_total",
+        "Read lookups that didn't find the key.",
+        misses,
+    );
+
+    gauge(
+        &mut out,
+        "redis_rdb_changes_since_last_save",
+        "Writes since the last successful snapshot.",
+        dirty,
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_last_save_timestamp_seconds",
+        "Unix time of the last successful snapshot.",
+        sources.rdb.lastsave(),
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_bgsave_in_progress",
+        "Whether a snapshot is being written.",
+        sources.rdb.is_saving() as u8,
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_last_bgsave_status",
+        "Whether the last background snapshot succeeded.",
+        sources.rdb.last_bgsave_ok() as u8,
+    );
+    let aof = sources.aof.as_ref();
+    gauge(
+        &mut out,
+        "redis_aof_enabled",
+        "Whether the append only file is enabled.",
+        aof.is_some() as u8,
+    );
+    gauge(
+        &mut out,
+        "redis_aof_rewrite_in_progress",
+        "Whether the append only file is being rewritten.",
+        aof.is_some_and(Aof::is_rewriting) as u8,
+    );
+    gauge(
+        &mut out,
+        "redis_aof_last_write_status",
+        "Whether the last write to the append only file succeeded.",
+        aof.is_none_or(Aof::last_write_ok) as u8,
+    );
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
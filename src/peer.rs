@@ -0,0 +1,83 @@
+//! 服务端之间的连接：replica 连接 master，sentinel 连接它监控的实例和其他 sentinel，
+//! cluster 节点之间的 bus。
+
+use crate::{
+    error::{
+        Error,
+        Result,
+    },
+    frame::Frame,
+    Connection,
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+use tokio::{
+    net::TcpStream,
+    time::{
+        self,
+        Duration,
+    },
+};
+
+pub(crate) type Addr = (String, u16);
+
+/// Send a command made of string arguments.
+pub(crate) async fn send(connection: &mut Connection, args: &[&str]) -> Result<()> {
+    let frame = Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    );
+    connection.write_frame(&frame).await?;
+    Ok(())
+}
+
+/// Send a command and read its reply, turning error replies into errors.
+pub(crate) async fn request(connection: &mut Connection, args: &[&str]) -> Result<Frame> {
+    send(connection, args).await?;
+    match connection.read_frame().await? {
+        Some(Frame::Error(err)) => Err(Error::server(err)),
+        Some(frame) => Ok(frame),
+        None => Err(Error::ConnectionClosed),
+    }
+}
+
+/// Cached connections to other servers, reconnected on demand.
+#[derive(Default)]
+pub(crate) struct Links {
+    connections: HashMap<Addr, Connection>,
+}
+
+impl Links {
+    /// Send a command to `addr` and wait at most `timeout` for the reply.
+    /// Returns `None` on any error, including an error reply.
+    pub(crate) async fn call(
+        &mut self,
+        addr: &Addr,
+        args: &[&str],
+        timeout: Duration,
+    ) -> Option<Frame> {
+        let result = time::timeout(timeout, async {
+            let connection = match self.connections.get_mut(addr) {
+                Some(connection) => connection,
+                None => {
+                    let stream = TcpStream::connect((&addr.0[..], addr.1)).await?;
+                    self.connections
+                        .entry(addr.clone())
+                        .or_insert(Connection::new(stream))
+                }
+            };
+            request(connection, args).await
+        })
+        .await;
+
+        match result {
+            Ok(Ok(frame)) => Some(frame),
+            //出错之后连接里可能还有没读完的回复，直接丢掉重连
+            _ => {
+                self.connections.remove(addr);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! 客户端 pipeline：先把命令攒起来，一次写出去，再按顺序读回所有回复，
+//! 省掉每条命令一次的往返时间。
+//!
+//! `atomic` 模式下整批命令包在 MULTI/EXEC 里，服务端保证中间不会插入其他客户端的命令。
+
+use crate::{
+    error::{
+        Error,
+        Result,
+    },
+    frame::Frame,
+    types::ToRedisArgs,
+};
+use bytes::Bytes;
+use std::time::Duration;
+
+/// A batch of commands sent with a single write.
+///
+/// Run it with [`Client::pipeline`](crate::Client::pipeline) or
+/// [`BlockingClient::pipeline`](crate::BlockingClient::pipeline), which return
+/// one reply per queued command, in order. Error replies to individual
+/// commands are returned as [`Frame::Error`] in that list.
+///
+/// The replies can be converted in one go with
+/// [`FromRedisValue`](crate::types::FromRedisValue), e.g. into a tuple.
+///
+/// An atomic pipeline is wrapped in MULTI/EXEC. If the server rejects a
+/// command while queueing it, the whole transaction is discarded and the
+/// call fails with that command's [`Error::ServerError`].
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    commands: Vec<Vec<Bytes>>,
+    atomic: bool,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Run the batch as a MULTI/EXEC transaction.
+    pub fn atomic(&mut self) -> &mut Pipeline {
+        self.atomic = true;
+        self
+    }
+
+    /// Queue any command.
+    pub fn cmd(&mut self, args: Vec<Bytes>) -> &mut Pipeline {
+        self.commands.push(args);
+        self
+    }
+
+    pub fn ping(&mut self) -> &mut Pipeline {
+        self.cmd(vec![Bytes::from_static(b"PING")])
+    }
+
+    pub fn get(&mut self, key: &str) -> &mut Pipeline {
+        self.cmd(vec![Bytes::from_static(b"GET"), arg(key)])
+    }
+
+    pub fn set<V: ToRedisArgs>(&mut self, key: &str, value: V) -> &mut Pipeline {
+        self.cmd(("SET", key, value).to_args())
+    }
+
+    pub fn set_expires<V: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        value: V,
+        expiration: Duration,
+    ) -> &mut Pipeline {
+        let ms = expiration.as_millis() as u64;
+        self.cmd(("SET", key, value, "PX", ms).to_args())
+    }
+
+    pub fn del(&mut self, keys: &[&str]) -> &mut Pipeline {
+        let mut args = vec![Bytes::from_static(b"DEL")];
+        args.extend(keys.iter().map(|key| arg(key)));
+        self.cmd(args)
+    }
+
+    pub fn incr(&mut self, key: &str) -> &mut Pipeline {
+        self.cmd(vec![Bytes::from_static(b"INCR"), arg(key)])
+    }
+
+    pub fn incr_by(&mut self, key: &str, increment: i64) -> &mut Pipeline {
+        self.cmd(vec![
+            Bytes::from_static(b"INCRBY"),
+            arg(key),
+            Bytes::from(increment.to_string()),
+        ])
+    }
+
+    /// Number of queued commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Remove all queued commands, keeping the atomic flag.
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub(crate) fn commands(&self) -> &[Vec<Bytes>] {
+        &self.commands
+    }
+
+    //实际要发送的 frame，atomic 模式下首尾加上 MULTI 和 EXEC
+    pub(crate) fn frames(&self) -> Vec<Frame> {
+        let mut frames = Vec::with_capacity(self.commands.len() + 2);
+        if self.atomic {
+            frames.push(command(&[Bytes::from_static(b"MULTI")]));
+        }
+        frames.extend(self.commands.iter().map(|args| command(args)));
+        if self.atomic {
+            frames.push(command(&[Bytes::from_static(b"EXEC")]));
+        }
+        frames
+    }
+
+    //把 frames() 对应的回复还原成每条命令的回复
+    pub(crate) fn replies(&self, mut replies: Vec<Frame>) -> Result<Vec<Frame>> {
+        if !self.atomic {
+            return Ok(replies);
+        }
+        //MULTI 的 OK、每条命令的 QUEUED，最后是 EXEC 的结果
+        let exec = replies.pop().ok_or(Error::ConnectionClosed)?;
+        //排队时被拒绝的命令比 EXECABORT 更能说明问题
+        if let Some(Frame::Error(err)) = replies
+            .into_iter()
+            .find(|reply| matches!(reply, Frame::Error(_)))
+        {
+            return Err(Error::server(err));
+        }
+        match exec {
+            Frame::Array(replies) => Ok(replies),
+            Frame::Error(err) => Err(Error::server(err)),
+            //WATCH 的 key 被修改时 EXEC 返回 nil
+            Frame::Null => Err(Error::server("EXECABORT Transaction aborted")),
+            frame => Err(Error::protocol(format!(
+                "unexpected reply to EXEC {}",
+                frame
+            ))),
+        }
+    }
+}
+
+fn command(args: &[Bytes]) -> Frame {
+    Frame::Array(args.iter().cloned().map(Frame::Bulk).collect())
+}
+
+fn arg(key: &str) -> Bytes {
+    Bytes::copy_from_slice(key.as_bytes())
+}
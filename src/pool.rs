@@ -0,0 +1,285 @@
+//! 异步客户端的连接池。
+//!
+//! * 最多同时借出 `max_size` 个连接，借不到的调用方排队，超过 `acquire_timeout` 报错
+//! * 借出之前先 PING 一下，检查失败的连接直接丢掉，换一个或者新建
+//! * 归还时连接已经 broken（I/O 或协议错误、请求被取消）就不再放回池子。池子里的连接
+//!   不自动重连，由池子负责替换
+//! * 后台任务定期关闭空闲超过 `idle_timeout` 的连接，同时保证至少有 `min_size` 个连接
+
+use crate::{
+    client::Client,
+    error::{
+        Error,
+        Result,
+    },
+    reconnect::ReconnectPolicy,
+};
+use std::{
+    collections::VecDeque,
+    ops::{
+        Deref,
+        DerefMut,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        Weak,
+    },
+};
+use tokio::{
+    sync::{
+        OwnedSemaphorePermit,
+        Semaphore,
+    },
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+/// Pool settings.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Connections kept open even when idle.
+    pub min_size: usize,
+    /// Maximum number of connections, idle or in use.
+    pub max_size: usize,
+    /// How long `get` waits for a connection before failing.
+    pub acquire_timeout: Duration,
+    /// Idle connections above `min_size` are closed after this long.
+    pub idle_timeout: Duration,
+    /// PING connections before handing them out.
+    pub health_check: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            min_size: 0,
+            max_size: 10,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(600),
+            health_check: true,
+        }
+    }
+}
+
+/// A snapshot of the pool's counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Connections currently checked out.
+    pub in_use: usize,
+    /// Open connections waiting in the pool.
+    pub idle: usize,
+    /// Callers waiting for a connection.
+    pub waiters: usize,
+}
+
+/// A pool of client connections to one server. Cloning is cheap and shares
+/// the pool.
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    addr: String,
+    config: PoolConfig,
+    //每个借出或者正在建立的连接占一个 permit，保证总数不超过 max_size
+    permits: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    //最近归还的放在后面，优先借出，这样空闲太久的连接集中在前面，方便淘汰
+    idle: VecDeque<Idle>,
+    in_use: usize,
+    waiters: usize,
+}
+
+struct Idle {
+    client: Client,
+    since: Instant,
+}
+
+/// A connection checked out of a [`Pool`]. Derefs to [`Client`] and goes
+/// back to the pool when dropped, unless it broke while in use.
+pub struct PooledClient {
+    client: Option<Client>,
+    shared: Arc<Shared>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Pool {
+    /// Create a pool for the server at `addr` and open `min_size`
+    /// connections in the background. Must be called within a tokio runtime.
+    pub fn new(addr: &str, config: PoolConfig) -> Pool {
+        let max_size = config.max_size.max(1);
+        let shared = Arc::new(Shared {
+            addr: addr.to_string(),
+            permits: Arc::new(Semaphore::new(max_size)),
+            config,
+            state: Mutex::new(State::default()),
+        });
+        tokio::spawn(maintain(Arc::downgrade(&shared)));
+        Pool { shared }
+    }
+
+    /// Check out a connection, waiting at most `acquire_timeout` for one to
+    /// become available.
+    pub async fn get(&self) -> Result<PooledClient> {
+        let waiting = Waiting::new(&self.shared);
+        let result = time::timeout(self.shared.config.acquire_timeout, self.checkout()).await;
+        drop(waiting);
+
+        match result {
+            Ok(Ok((client, permit))) => {
+                self.shared.state.lock().unwrap().in_use += 1;
+                Ok(PooledClient {
+                    client: Some(client),
+                    shared: self.shared.clone(),
+                    _permit: permit,
+                })
+            }
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.shared.state.lock().unwrap();
+        PoolStats {
+            in_use: state.in_use,
+            idle: state.idle.len(),
+            waiters: state.waiters,
+        }
+    }
+
+    async fn checkout(&self) -> Result<(Client, OwnedSemaphorePermit)> {
+        //semaphore 不会被关闭
+        let permit = self
+            .shared
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| Error::ConnectionClosed)?;
+        loop {
+            let idle = self.shared.state.lock().unwrap().idle.pop_back();
+            let mut client = match idle {
+                Some(idle) => idle.client,
+                None => {
+                    return Ok((
+                        Client::connect_with_policy(
+                            &self.shared.addr[..],
+                            ReconnectPolicy::never(),
+                        )
+                        .await?,
+                        permit,
+                    ))
+                }
+            };
+            //服务端可能已经关闭了这个连接（重启、超时断开等）
+            if !self.shared.config.health_check || client.ping(None).await.is_ok() {
+                return Ok((client, permit));
+            }
+        }
+    }
+}
+
+//调用方在排队期间也可能被取消，用 guard 保证计数一定会减回去
+struct Waiting<'a>(&'a Shared);
+
+impl Waiting<'_> {
+    fn new(shared: &Shared) -> Waiting<'_> {
+        shared.state.lock().unwrap().waiters += 1;
+        Waiting(shared)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().waiters -= 1;
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.in_use -= 1;
+        if let Some(client) = self.client.take() {
+            if !client.is_broken() {
+                state.idle.push_back(Idle {
+                    client,
+                    since: Instant::now(),
+                });
+            }
+        }
+    }
+}
+
+//后台维护任务：淘汰空闲太久的连接，补足 min_size。pool 被丢弃之后退出
+async fn maintain(shared: Weak<Shared>) {
+    let period = match shared.upgrade() {
+        Some(shared) => (shared.config.idle_timeout / 2).max(Duration::from_millis(100)),
+        None => return,
+    };
+    let mut interval = time::interval(period);
+    loop {
+        interval.tick().await;
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        let missing = {
+            let mut state = shared.state.lock().unwrap();
+            let min_size = shared.config.min_size;
+            let idle_timeout = shared.config.idle_timeout;
+            while state.idle.len() + state.in_use > min_size
+                && state
+                    .idle
+                    .front()
+                    .is_some_and(|idle| idle.since.elapsed() >= idle_timeout)
+            {
+                state.idle.pop_front();
+            }
+            min_size.saturating_sub(state.idle.len() + state.in_use)
+        };
+
+        for _ in 0..missing {
+            //只在有空闲 permit 时补充，不和等待中的调用方抢
+            let permit = match shared.permits.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+            match Client::connect_with_policy(&shared.addr[..], ReconnectPolicy::never()).await {
+                Ok(client) => shared.state.lock().unwrap().idle.push_back(Idle {
+                    client,
+                    since: Instant::now(),
+                }),
+                Err(err) => {
+                    println!("pool: failed to connect to {}: {}", shared.addr, err);
+                    break;
+                }
+            }
+            drop(permit);
+        }
+    }
+}
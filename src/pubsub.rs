@@ -0,0 +1,168 @@
+//! 发布订阅：SUBSCRIBE 之后连接进入订阅状态，PUBLISH 把消息推给订阅了这个频道的所有连接。
+//!
+//! 每个订阅者有一个有界的队列，由连接任务取出消息写入 socket。和 Redis 的
+//! `client-output-buffer-limit pubsub` 一样，队列满了说明订阅者跟不上，直接断开它，
+//! 而不是让 PUBLISH 等待或者无限制地占用内存。
+//! 消息只发给本机的订阅者，不会复制到 replica，也不会在集群里广播。
+
+use bytes::Bytes;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use tokio::sync::mpsc::{
+    self,
+    error::TrySendError,
+};
+
+//每个订阅者最多积压多少条还没写出去的消息
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// A message published on a channel: the channel and the payload.
+pub(crate) type Message = (Bytes, Bytes);
+
+/// The channels and their subscribers, shared by every connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PubSub {
+    shared: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    channels: HashMap<Bytes, HashSet<u64>>,
+    subscribers: HashMap<u64, mpsc::Sender<Message>>,
+}
+
+/// A connection's subscriptions, removed when it's dropped.
+#[derive(Debug)]
+pub(crate) struct Subscriber {
+    id: u64,
+    pubsub: PubSub,
+    //按订阅的顺序，UNSUBSCRIBE 不带参数时按这个顺序逐个回复
+    channels: Vec<Bytes>,
+    messages: mpsc::Receiver<Message>,
+}
+
+impl PubSub {
+    pub(crate) fn new() -> PubSub {
+        PubSub::default()
+    }
+
+    /// Register a connection entering subscribe mode.
+    pub(crate) fn subscriber(&self) -> Subscriber {
+        let (sender, messages) = mpsc::channel(SUBSCRIBER_BACKLOG);
+        let mut state = self.shared.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.subscribers.insert(id, sender);
+        Subscriber {
+            id,
+            pubsub: self.clone(),
+            channels: Vec::new(),
+            messages,
+        }
+    }
+
+    /// Send `message` to every subscriber of `channel`. Returns the number
+    /// of subscribers that got it.
+    pub(crate) fn publish(&self, channel: &Bytes, message: Bytes) -> usize {
+        let mut state = self.shared.lock().unwrap();
+        let ids = match state.channels.get(channel) {
+            Some(ids) => ids,
+            None => return 0,
+        };
+        let mut received = 0;
+        let mut slow = Vec::new();
+        for id in ids {
+            let sender = match state.subscribers.get(id) {
+                Some(sender) => sender,
+                None => continue,
+            };
+            match sender.try_send((channel.clone(), message.clone())) {
+                Ok(()) => received += 1,
+                Err(TrySendError::Full(_)) => slow.push(*id),
+                //连接已经断开，Subscriber 被丢弃时会自己注销
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+        //丢掉 sender 之后，连接取完队列里的消息就会发现被断开了
+        for id in slow {
+            state.remove(id);
+        }
+        received
+    }
+}
+
+impl State {
+    //不知道订阅了哪些频道，只能全部检查一遍；只在断开跟不上的订阅者时使用
+    fn remove(&mut self, id: u64) {
+        self.subscribers.remove(&id);
+        self.channels.retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
+    }
+
+    fn unsubscribe(&mut self, channel: &Bytes, id: u64) {
+        if let Some(ids) = self.channels.get_mut(channel) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.channels.remove(channel);
+            }
+        }
+    }
+}
+
+impl Subscriber {
+    /// Subscribe to `channel`. Returns the number of channels subscribed to.
+    pub(crate) fn subscribe(&mut self, channel: Bytes) -> usize {
+        if !self.channels.contains(&channel) {
+            let mut state = self.pubsub.shared.lock().unwrap();
+            state
+                .channels
+                .entry(channel.clone())
+                .or_default()
+                .insert(self.id);
+            self.channels.push(channel);
+        }
+        self.channels.len()
+    }
+
+    /// Unsubscribe from `channel`. Returns the number of channels still
+    /// subscribed to.
+    pub(crate) fn unsubscribe(&mut self, channel: &Bytes) -> usize {
+        if let Some(i) = self.channels.iter().position(|c| c == channel) {
+            self.channels.remove(i);
+            let mut state = self.pubsub.shared.lock().unwrap();
+            state.unsubscribe(channel, self.id);
+        }
+        self.channels.len()
+    }
+
+    pub(crate) fn channels(&self) -> &[Bytes] {
+        &self.channels
+    }
+
+    /// Wait for the next message. Returns `None` once the subscriber was
+    /// dropped for falling behind.
+    pub(crate) async fn recv(&mut self) -> Option<Message> {
+        self.messages.recv().await
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let mut state = self.pubsub.shared.lock().unwrap();
+        state.subscribers.remove(&self.id);
+        for channel in &self.channels {
+            state.unsubscribe(channel, self.id);
+        }
+    }
+}
@@ -0,0 +1,605 @@
+//! RDB 快照持久化（SAVE / BGSAVE / LASTSAVE 以及自动保存规则）。
+//!
+//! 文件格式和 Redis 的 RDB version 9 一致，所以 `redis-check-rdb` 等工具可以直接读取：
+//!
+//! ```text
+//! "REDIS0009" | AUX 字段 | SELECTDB 0 | RESIZEDB | 键值对 ... | EOF | CRC64
+//! ```
+//!
+//! 目前 keyspace 里只有字符串类型，所以写出的 value type 都是 0（string）。
+
+use crate::{
+    cmd,
+    db::{
+        self,
+        Db,
+        Entry,
+        State,
+    },
+    frame::Frame,
+};
+use bytes::Bytes;
+use std::{
+    fs::File,
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::time::{
+    self,
+    Duration,
+    Instant,
+};
+
+const RDB_VERSION: u32 = 9;
+
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+
+const TYPE_STRING: u8 = 0;
+
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+//解压后的字符串最大长度，和 Redis 的 proto-max-bulk-len 默认值一样。
+//长度来自文件或者 RESTORE 的参数，不能直接拿来分配内存
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// A `save <seconds> <changes>` rule: snapshot when at least `changes` writes
+/// happened in the last `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Parse the value of the `save` parameter, e.g. `"3600 1 300 100"`. An empty
+/// string disables automatic saving.
+pub fn parse_save_rules(value: &str) -> Result<Vec<SaveRule>, String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if !parts.len().is_multiple_of(2) {
+        return Err("save rules must be pairs of <seconds> <changes>".to_string());
+    }
+    parts
+        .chunks(2)
+        .map(|pair| {
+            let parse = |s: &str| {
+                s.parse::<u64>()
+                    .map_err(|_| format!("invalid save rule value '{}'", s))
+            };
+            Ok(SaveRule {
+                seconds: parse(pair[0])?,
+                changes: parse(pair[1])?,
+            })
+        })
+        .collect()
+}
+
+/// Handle to the snapshot file.
+#[derive(Debug, Clone)]
+pub struct Rdb {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    //最近一次成功保存的 unix 时间（秒）
+    lastsave: AtomicU64,
+    bgsave_in_progress: AtomicBool,
+    last_bgsave_ok: AtomicBool,
+    //CONFIG SET save 可以在运行时修改
+    save_rules: Mutex<Vec<SaveRule>>,
+}
+
+impl Rdb {
+    pub fn new(path: PathBuf) -> Rdb {
+        Rdb {
+            shared: Arc::new(Shared {
+                path,
+                lastsave: AtomicU64::new(unix_secs()),
+                bgsave_in_progress: AtomicBool::new(false),
+                last_bgsave_ok: AtomicBool::new(true),
+                save_rules: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Load the snapshot into `db` if the file exists. Returns the number of
+    /// keys loaded.
+    pub fn load(&self, db: &Db) -> io::Result<usize> {
+        if !self.shared.path.exists() {
+            return Ok(0);
+        }
+        let data = std::fs::read(&self.shared.path)?;
+        let mut state = db.lock();
+        let loaded = decode(&data, &mut state)?;
+        drop(state);
+        db.notify_expiration();
+        Ok(loaded)
+    }
+
+    pub fn lastsave(&self) -> u64 {
+        self.shared.lastsave.load(Ordering::Acquire)
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.shared.bgsave_in_progress.load(Ordering::Acquire)
+    }
+
+    pub fn last_bgsave_ok(&self) -> bool {
+        self.shared.last_bgsave_ok.load(Ordering::Acquire)
+    }
+
+    /// SAVE: write the snapshot while holding the keyspace lock.
+    pub fn save(&self, db: &Db) -> Frame {
+        if self.is_saving() {
+            return Frame::Error("ERR Background save already in progress".to_string());
+        }
+        let mut state = db.lock();
+        let dirty = state.dirty;
+        match write_file(&self.shared.path, state.snapshot()) {
+            Ok(()) => {
+                state.dirty = state.dirty.saturating_sub(dirty);
+                self.shared.lastsave.store(unix_secs(), Ordering::Release);
+                cmd::ok()
+            }
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        }
+    }
+
+    /// BGSAVE: clone the keyspace and write it from a blocking task, so writes
+    /// keep being served while the file is produced.
+    pub fn bgsave(&self, db: &Db) -> Frame {
+        self.bgsave_locked(db, &db.lock())
+    }
+
+    //和 bgsave 一样，快照取自调用方已经锁住的 `state`
+    fn bgsave_locked(&self, db: &Db, state: &State) -> Frame {
+        if self.shared.bgsave_in_progress.swap(true, Ordering::AcqRel) {
+            return Frame::Error("ERR Background save already in progress".to_string());
+        }
+
+        let (snapshot, dirty) = (state.snapshot(), state.dirty);
+
+        let shared = self.shared.clone();
+        let db = db.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = write_file(&shared.path, snapshot);
+            match &result {
+                Ok(()) => {
+                    //快照之后的写入仍然算作未保存的修改
+                    let mut state = db.lock();
+                    state.dirty = state.dirty.saturating_sub(dirty);
+                    drop(state);
+                    shared.lastsave.store(unix_secs(), Ordering::Release);
+                    println!("Background saving terminated with success");
+                }
+                Err(err) => println!("Background saving error: {}", err),
+            }
+            shared
+                .last_bgsave_ok
+                .store(result.is_ok(), Ordering::Release);
+            shared.bgsave_in_progress.store(false, Ordering::Release);
+        });
+
+        Frame::Simple("Background saving started".to_string())
+    }
+
+    /// Spawn the task that checks the `save` rules once a second.
+    pub fn spawn_save_rules(&self, db: &Db, rules: Vec<SaveRule>) {
+        self.set_save_rules(rules);
+        let rdb = self.clone();
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                //脚本执行期间一直持有锁，这时跳过这一轮，不要阻塞 runtime 的工作线程。
+                //快照也在同一次加锁里复制，中间不会有脚本插进来
+                let state = match db.try_lock() {
+                    Some(state) => state,
+                    None => continue,
+                };
+                let elapsed = unix_secs().saturating_sub(rdb.lastsave());
+                let due = rdb
+                    .save_rules()
+                    .into_iter()
+                    .find(|rule| state.dirty >= rule.changes && elapsed >= rule.seconds);
+                if let Some(rule) = due {
+                    if !rdb.is_saving() {
+                        println!(
+                            "{} changes in {} seconds. Saving...",
+                            rule.changes, rule.seconds
+                        );
+                        rdb.bgsave_locked(&db, &state);
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn save_rules(&self) -> Vec<SaveRule> {
+        self.shared.save_rules.lock().unwrap().clone()
+    }
+
+    /// Replace the automatic snapshot rules, empty to disable.
+    pub fn set_save_rules(&self, rules: Vec<SaveRule>) {
+        *self.shared.save_rules.lock().unwrap() = rules;
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+//先写临时文件再 rename，保证任何时候磁盘上的 dump.rdb 都是完整的
+fn write_file(path: &Path, snapshot: Vec<(String, Entry)>) -> io::Result<()> {
+    let tmp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(&encode(snapshot))?;
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Serialise a keyspace snapshot in the RDB format.
+pub fn encode(snapshot: Vec<(String, Entry)>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+
+    write_aux(&mut buf, "redis-ver", b"7.0.0");
+    write_aux(&mut buf, "redis-bits", b"64");
+    write_aux(&mut buf, "ctime", unix_secs().to_string().as_bytes());
+    write_aux(&mut buf, "aof-base", b"0");
+
+    let expires = snapshot
+        .iter()
+        .filter(|(_, entry)| entry.expires_at.is_some())
+        .count();
+
+    buf.push(OPCODE_SELECTDB);
+    write_len(&mut buf, 0);
+    buf.push(OPCODE_RESIZEDB);
+    write_len(&mut buf, snapshot.len() as u64);
+    write_len(&mut buf, expires as u64);
+
+    for (key, entry) in snapshot {
+        if let Some(when) = entry.expires_at {
+            buf.push(OPCODE_EXPIRETIME_MS);
+            buf.extend_from_slice(&db::to_unix_ms(when).to_le_bytes());
+        }
+        buf.push(TYPE_STRING);
+        write_string(&mut buf, key.as_bytes());
+        write_string(&mut buf, &entry.data);
+    }
+
+    buf.push(OPCODE_EOF);
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+fn write_aux(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+    buf.push(OPCODE_AUX);
+    write_string(buf, key.as_bytes());
+    write_string(buf, value);
+}
+
+// 长度编码：前两个bit决定长度占用的字节数
+// 00xxxxxx: 6 bit, 01xxxxxx xxxxxxxx: 14 bit, 0x80: 32 bit, 0x81: 64 bit
+fn write_len(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push(len as u8);
+    } else if len < 1 << 14 {
+        buf.push(((len >> 8) as u8) | 0x40);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        buf.push(0x80);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, data: &[u8]) {
+    write_len(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Serialise a single value for DUMP: the value in RDB encoding, then the RDB
+/// version and a CRC64 of everything before it.
+pub fn dump(data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![TYPE_STRING];
+    write_string(&mut buf, data);
+    buf.extend_from_slice(&(RDB_VERSION as u16).to_le_bytes());
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// The inverse of `dump`, used by RESTORE.
+pub fn undump(payload: &[u8]) -> io::Result<Bytes> {
+    let wrong = || invalid("DUMP payload version or checksum are wrong");
+    if payload.len() < 10 {
+        return Err(wrong());
+    }
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    if crc64(0, body) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(wrong());
+    }
+    let (value, version) = body.split_at(body.len() - 2);
+    if u16::from_le_bytes(version.try_into().unwrap()) as u32 > RDB_VERSION {
+        return Err(wrong());
+    }
+
+    let mut reader = Reader {
+        data: value,
+        pos: 0,
+    };
+    if reader.u8()? != TYPE_STRING {
+        return Err(invalid("Bad data format"));
+    }
+    let data = reader.string()?;
+    if reader.pos != value.len() {
+        return Err(invalid("Bad data format"));
+    }
+    Ok(Bytes::from(data))
+}
+
+/// Load an RDB file into `state`, returning the number of keys loaded.
+pub fn decode(data: &[u8], state: &mut State) -> io::Result<usize> {
+    let mut reader = Reader { data, pos: 0 };
+
+    let magic = reader.take(9)?;
+    if &magic[..5] != b"REDIS" {
+        return Err(invalid("wrong signature trying to load DB from file"));
+    }
+    let version: u32 = std::str::from_utf8(&magic[5..])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid("invalid RDB version"))?;
+    if version > 11 {
+        return Err(invalid(&format!(
+            "can't handle RDB format version {}",
+            version
+        )));
+    }
+
+    let mut loaded = 0;
+    let mut expires_at = None;
+
+    loop {
+        let opcode = reader.u8()?;
+        match opcode {
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_SELECTDB => {
+                let db = reader.len()?;
+                if db != 0 {
+                    return Err(invalid("only database 0 is supported"));
+                }
+            }
+            OPCODE_RESIZEDB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let ms = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+                expires_at =
+                    Some(db::from_unix_ms(ms).ok_or_else(|| invalid("invalid expire time"))?);
+            }
+            OPCODE_EXPIRETIME => {
+                let secs = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+                //秒数是 u32，换算成毫秒不会超出范围
+                expires_at = db::from_unix_ms(secs as u64 * 1000);
+            }
+            OPCODE_IDLE => {
+                reader.len()?;
+            }
+            OPCODE_FREQ => {
+                reader.u8()?;
+            }
+            OPCODE_EOF => {
+                let body_len = reader.pos;
+                if version >= 5 {
+                    let expected = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+                    //checksum 为0表示写入方关闭了校验
+                    if expected != 0 && expected != crc64(0, &data[..body_len]) {
+                        return Err(invalid("wrong RDB checksum"));
+                    }
+                }
+                return Ok(loaded);
+            }
+            TYPE_STRING => {
+                //和命令一样不接受非 UTF-8 的 key，免得两个 key 转换之后变成同一个
+                let key = String::from_utf8(reader.string()?)
+                    .map_err(|_| invalid("key is not valid UTF-8"))?;
+                let value = Bytes::from(reader.string()?);
+                let expires_at = expires_at.take();
+                //已经过期的key不需要加载
+                if expires_at.is_none_or(|when| when > Instant::now()) {
+                    state.insert(
+                        key,
+                        Entry {
+                            data: value,
+                            expires_at,
+                        },
+                    );
+                    loaded += 1;
+                }
+            }
+            other => {
+                return Err(invalid(&format!(
+                    "unsupported RDB value type or opcode {}",
+                    other
+                )))
+            }
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(invalid("unexpected end of RDB file"));
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Returns the length, or `Err(encoding)` for specially encoded strings.
+    fn len_or_encoding(&mut self) -> io::Result<Result<u64, u8>> {
+        let first = self.u8()?;
+        Ok(match first >> 6 {
+            0 => Ok((first & 0x3F) as u64),
+            1 => Ok((((first & 0x3F) as u64) << 8) | self.u8()? as u64),
+            2 if first == 0x80 => Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64),
+            2 if first == 0x81 => Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            2 => return Err(invalid("unknown length encoding")),
+            _ => Err(first & 0x3F),
+        })
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        self.len_or_encoding()?
+            .map_err(|_| invalid("unexpected string encoding"))
+    }
+
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        match self.len_or_encoding()? {
+            Ok(len) => Ok(self.take(len as usize)?.to_vec()),
+            Err(ENC_INT8) => Ok((self.u8()? as i8).to_string().into_bytes()),
+            Err(ENC_INT16) => {
+                let n = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            Err(ENC_INT32) => {
+                let n = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            Err(ENC_LZF) => {
+                let compressed_len = self.len()? as usize;
+                let len = self.len()? as usize;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Err(_) => Err(invalid("unknown string encoding")),
+        }
+    }
+}
+
+//Redis 写 RDB 时会对较长的字符串做 LZF 压缩，加载 Redis 生成的文件时需要解压
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    if len > MAX_STRING_LEN {
+        return Err(invalid("LZF uncompressed length is too large"));
+    }
+    //按实际的输入预分配，解压的过程中再增长
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            //字面量：接下来 ctrl + 1 个字节原样复制
+            let run = ctrl + 1;
+            let literal = input
+                .get(i..i + run)
+                .filter(|_| out.len() + run <= len)
+                .ok_or_else(|| invalid("invalid LZF data"))?;
+            out.extend_from_slice(literal);
+            i += run;
+        } else {
+            //回溯引用：从已经解压的数据中复制
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(|| invalid("invalid LZF data"))? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(|| invalid("invalid LZF data"))? as usize;
+            i += 1;
+            let back = ((ctrl & 0x1F) << 8) + low + 1;
+            //解压出来的数据不能超过声明的长度
+            if back > out.len() || out.len() + run + 2 > len {
+                return Err(invalid("invalid LZF data"));
+            }
+            let start = out.len() - back;
+            for k in 0..run + 2 {
+                out.push(out[start + k]);
+            }
+        }
+    }
+    if out.len() != len {
+        return Err(invalid("invalid LZF data"));
+    }
+    Ok(out)
+}
+
+/// CRC-64/Jones, the checksum Redis appends to RDB files.
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95AC_9329_AC4B_C9B5;
+    static TABLE: std::sync::OnceLock<[u64; 256]> = std::sync::OnceLock::new();
+
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        for (i, slot) in table.iter_mut().enumerate() {
+            let mut c = i as u64;
+            for _ in 0..8 {
+                c = if c & 1 == 1 { (c >> 1) ^ POLY } else { c >> 1 };
+            }
+            *slot = c;
+        }
+        table
+    });
+
+    for &byte in data {
+        crc = table[((crc ^ byte as u64) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
@@ -0,0 +1,213 @@
+//! 客户端断线重连：带随机抖动的指数退避，以及哪些命令可以在重连之后自动重发。
+//!
+//! 连接断开时请求可能已经被服务端执行了，只是回复没有送回来，所以默认只重发只读命令；
+//! 写命令的调用方会收到错误，但下一条命令会在新连接上执行。
+
+use crate::{
+    cmd,
+    connection::BoxedStream,
+    error::{
+        Error,
+        Result,
+    },
+    Connection,
+};
+use bytes::Bytes;
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    net::{
+        self,
+        TcpStream,
+        ToSocketAddrs,
+    },
+    time::{
+        self,
+        Duration,
+    },
+};
+
+/// How a client reconnects after losing its connection.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first retry, doubled on every further attempt.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+    /// Attempts to reconnect (and resend a retryable command) before giving
+    /// up and returning the error. 0 disables reconnecting.
+    pub max_retries: u32,
+    /// Commands that are resent after a reconnect.
+    pub retry: Retry,
+}
+
+/// Which commands can safely be resent when the connection was lost before
+/// their reply arrived.
+#[derive(Debug, Clone, Copy)]
+pub enum Retry {
+    /// Never resend; the error is returned and the next command reconnects.
+    Never,
+    /// Resend commands that don't modify the keyspace, such as GET or PING.
+    IdempotentReads,
+    /// Resend everything, accepting that a write may be applied twice.
+    Always,
+    /// Decide with a custom predicate on the command arguments.
+    Custom(fn(&[Bytes]) -> bool),
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(5),
+            max_retries: 10,
+            retry: Retry::IdempotentReads,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// A policy that never reconnects.
+    pub fn never() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_retries: 0,
+            retry: Retry::Never,
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    /// Whether `args` may be resent after a reconnect.
+    pub fn is_retryable(&self, args: &[Bytes]) -> bool {
+        match self.retry {
+            Retry::Never => false,
+            Retry::IdempotentReads => args
+                .first()
+                .and_then(|name| cmd::lookup(name))
+                .is_some_and(|spec| !spec.write && !spec.noscript),
+            Retry::Always => true,
+            Retry::Custom(retryable) => retryable(args),
+        }
+    }
+
+    /// The delay before retry number `attempt` (starting at 1): a random
+    /// duration up to `initial_backoff * 2^(attempt - 1)`, capped at
+    /// `max_backoff`. The randomness keeps many clients from reconnecting in
+    /// lockstep after a server restart.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        //一半固定一半随机，避免退避时间太短
+        exp / 2 + jitter(exp / 2)
+    }
+}
+
+/// Where a client connects, and reconnects after losing its connection.
+#[derive(Debug, Clone)]
+pub(crate) enum Target {
+    //连接时解析好的地址
+    Tcp(Vec<SocketAddr>),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    #[cfg(feature = "tls")]
+    Tls {
+        addrs: Vec<SocketAddr>,
+        //用来校验服务端证书的名字
+        server_name: tokio_rustls::rustls::pki_types::ServerName<'static>,
+        connector: crate::tls::TlsConnector,
+    },
+}
+
+impl Target {
+    #[cfg(feature = "tls")]
+    pub(crate) async fn tls(
+        host: &str,
+        port: u16,
+        connector: crate::tls::TlsConnector,
+    ) -> Result<Target> {
+        Ok(Target::Tls {
+            addrs: resolve((host, port)).await?,
+            server_name: crate::tls::server_name(host)?,
+            connector,
+        })
+    }
+
+    pub(crate) async fn open(&self) -> io::Result<BoxedStream> {
+        match self {
+            Target::Tcp(addrs) => Ok(Box::new(TcpStream::connect(&addrs[..]).await?)),
+            #[cfg(unix)]
+            Target::Unix(path) => Ok(Box::new(net::UnixStream::connect(path).await?)),
+            #[cfg(feature = "tls")]
+            Target::Tls {
+                addrs,
+                server_name,
+                connector,
+            } => Ok(Box::new(connector.connect(addrs, server_name).await?)),
+        }
+    }
+}
+
+/// Resolve `addr` once, so that the client can reconnect to the same server.
+pub(crate) async fn resolve<T: ToSocketAddrs>(addr: T) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = net::lookup_host(addr).await?.collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "address resolved to nothing",
+        )
+        .into());
+    }
+    Ok(addrs)
+}
+
+/// Connect to `target`, retrying with backoff according to `policy`. Each
+/// attempt gives up after `connect_timeout`.
+pub(crate) async fn connect(
+    target: &Target,
+    policy: &ReconnectPolicy,
+    connect_timeout: Option<Duration>,
+) -> Result<Connection<BoxedStream>> {
+    let mut attempt = 0;
+    loop {
+        match timeout(connect_timeout, target.open()).await {
+            Ok(stream) => return Ok(Connection::new(stream)),
+            Err(err) if attempt >= policy.max_retries => return Err(err),
+            Err(_) => {
+                attempt += 1;
+                time::sleep(policy.backoff(attempt)).await;
+            }
+        }
+    }
+}
+
+/// Run `future`, failing with [`Error::Timeout`] if it takes longer than
+/// `duration`. `None` waits forever.
+pub(crate) async fn timeout<T, E, F>(duration: Option<Duration>, future: F) -> Result<T>
+where
+    F: Future<Output = std::result::Result<T, E>>,
+    E: Into<Error>,
+{
+    let result = match duration {
+        Some(duration) => time::timeout(duration, future)
+            .await
+            .map_err(|_| Error::Timeout)?,
+        None => future.await,
+    };
+    result.map_err(Into::into)
+}
+
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u128;
+    Duration::from_nanos((nanos % max.as_nanos().max(1)) as u64)
+}
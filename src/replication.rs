@@ -0,0 +1,940 @@
+//! 主从复制。
+//!
+//! master 把每条写命令编码成 RESP 追加到复制积压缓冲区（backlog），同时转发给所有 replica。
+//! `master_repl_offset` 是复制流的总字节数，replica 用它来断点续传：
+//!
+//! * `PSYNC ? -1` 或者 backlog 里已经没有需要的数据：`+FULLRESYNC <replid> <offset>`，
+//!   然后发送一份 RDB 快照，再发送快照之后的命令流
+//! * 否则：`+CONTINUE <replid>`，从 backlog 里补发断开期间的命令
+//!
+//! replica 每秒发送一次 `REPLCONF ACK <offset>`，WAIT 命令依赖这个 offset。
+//!
+//! 发给每个 replica 的数据先进入它自己的 channel。积压的字节数超过
+//! `client-output-buffer-limit replica` 时断开这个 replica，不让读得慢的 replica 耗尽内存。
+
+use crate::{
+    aof::{
+        self,
+        Aof,
+    },
+    cmd,
+    config,
+    connection::BoxedStream,
+    db::{
+        Db,
+        Feed,
+    },
+    error::{
+        self,
+        Error,
+    },
+    frame::Frame,
+    peer::{
+        request,
+        send,
+    },
+    rdb,
+    Connection,
+};
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    fmt::{
+        self,
+        Write as _,
+    },
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    net::TcpStream,
+    sync::{
+        mpsc,
+        Notify,
+    },
+    task::JoinHandle,
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+/// How often the master pings its replicas through the replication stream.
+const PING_PERIOD: Duration = Duration::from_secs(10);
+/// A replica drops the link when nothing arrived from the master for this long.
+const REPL_TIMEOUT: Duration = Duration::from_secs(60);
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// `client-output-buffer-limit replica <hard> <soft> <soft seconds>`: a
+/// replica is disconnected as soon as `hard` bytes are waiting to be sent to
+/// it, or when at least `soft` bytes have been waiting for `soft_seconds` in
+/// a row. Zero disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl Default for OutputBufferLimit {
+    //和 Redis 的默认值一致
+    fn default() -> OutputBufferLimit {
+        OutputBufferLimit {
+            hard: 256 * 1024 * 1024,
+            soft: 64 * 1024 * 1024,
+            soft_seconds: 60,
+        }
+    }
+}
+
+impl fmt::Display for OutputBufferLimit {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "replica {} {} {}",
+            self.hard, self.soft, self.soft_seconds
+        )
+    }
+}
+
+/// Parse the value of the `client-output-buffer-limit` parameter, e.g.
+/// `"replica 256mb 64mb 60"`. Only the replica class is supported, `slave`
+/// is accepted as an alias.
+pub fn parse_output_buffer_limit(value: &str) -> Result<OutputBufferLimit, String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts[..] {
+        [class, hard, soft, soft_seconds]
+            if class.eq_ignore_ascii_case("replica") || class.eq_ignore_ascii_case("slave") =>
+        {
+            Ok(OutputBufferLimit {
+                hard: config::parse_memory(hard)?,
+                soft: config::parse_memory(soft)?,
+                soft_seconds: soft_seconds
+                    .parse()
+                    .map_err(|_| format!("invalid soft limit seconds '{}'", soft_seconds))?,
+            })
+        }
+        [class, _, _, _] => Err(format!(
+            "unsupported client class '{}', only 'replica' limits can be set",
+            class
+        )),
+        _ => Err(format!(
+            "client-output-buffer-limit expects '<class> <hard> <soft> <soft seconds>', got '{}'",
+            value
+        )),
+    }
+}
+
+/// Replication state of this server, as a master and possibly as a replica.
+#[derive(Debug, Clone)]
+pub struct Replication {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    //replica 回复 ACK 时通知 WAIT
+    acked: Notify,
+    //CONFIG SET replica-read-only 可以在运行时修改
+    read_only: AtomicBool,
+    //连接 master 时用来 AUTH 的用户名和密码，空字符串表示不需要
+    master_auth: Mutex<(String, String)>,
+    /// Port this server accepts clients on, announced to the master.
+    listening_port: u16,
+}
+
+#[derive(Debug)]
+struct State {
+    replid: String,
+    /// The previous replication id and the first offset it is no longer valid
+    /// for. Set when a replica is promoted, so that the other replicas of the
+    /// old master can still partially resync from it.
+    replid2: Option<(String, u64)>,
+    /// master_repl_offset: total bytes of replication stream produced.
+    offset: u64,
+    backlog: Backlog,
+    replicas: Vec<Replica>,
+    next_replica_id: u64,
+    /// Set when this server is a replica.
+    master: Option<MasterLink>,
+    //和 master 之间的连接任务，切换 master 的时候要停掉
+    link_task: Option<JoinHandle<()>>,
+    next_link_id: u64,
+    //是否已经有来自 master 的数据，没有的话只能 PSYNC ? -1
+    synced: bool,
+    output_limit: OutputBufferLimit,
+}
+
+#[derive(Debug)]
+struct Replica {
+    id: u64,
+    addr: SocketAddr,
+    listening_port: u16,
+    ack_offset: u64,
+    last_ack: Instant,
+    tx: mpsc::UnboundedSender<Bytes>,
+    buffer: Arc<OutputBuffer>,
+    //积压的数据从什么时候开始超过软限制
+    soft_limit_since: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct OutputBuffer {
+    //已经放进 channel、还没有写到连接上的字节数
+    len: AtomicUsize,
+    //超过限制时通知连接任务断开，不再发送 channel 里剩下的数据
+    overflowed: Notify,
+}
+
+impl Replica {
+    fn exceeds(&mut self, limit: &OutputBufferLimit) -> bool {
+        let len = self.buffer.len.load(Ordering::Acquire);
+        if limit.hard > 0 && len >= limit.hard {
+            return true;
+        }
+        if limit.soft > 0 && len >= limit.soft {
+            let since = *self.soft_limit_since.get_or_insert_with(Instant::now);
+            return since.elapsed() >= Duration::from_secs(limit.soft_seconds);
+        }
+        self.soft_limit_since = None;
+        false
+    }
+}
+
+#[derive(Debug)]
+struct MasterLink {
+    //旧的连接任务被 abort 之前可能还会改状态，用 id 区分
+    id: u64,
+    host: String,
+    port: u16,
+    up: bool,
+    sync_in_progress: bool,
+    last_io: Instant,
+}
+
+/// Fixed size ring buffer holding the tail of the replication stream.
+#[derive(Debug)]
+struct Backlog {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    /// Replication offset of the first byte in `buf`. Offsets start at 1.
+    first_offset: u64,
+}
+
+impl Backlog {
+    fn new(capacity: usize, first_offset: u64) -> Backlog {
+        Backlog {
+            buf: VecDeque::new(),
+            capacity,
+            first_offset,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend(bytes);
+        if self.buf.len() > self.capacity {
+            let excess = self.buf.len() - self.capacity;
+            self.buf.drain(..excess);
+            self.first_offset += excess as u64;
+        }
+    }
+
+    /// Everything from `from` to the end, if the backlog still has it.
+    fn since(&self, from: u64) -> Option<Vec<u8>> {
+        let end = self.first_offset + self.buf.len() as u64;
+        if from < self.first_offset || from > end {
+            return None;
+        }
+        Some(
+            self.buf
+                .range((from - self.first_offset) as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct ReplFeed {
+    shared: Arc<Shared>,
+}
+
+impl Feed for ReplFeed {
+    fn feed(&mut self, args: &[Bytes]) {
+        let mut state = self.shared.state.lock().unwrap();
+        //replica 转发的是从 master 收到的原始复制流，见 `append`
+        if state.master.is_some() {
+            return;
+        }
+        let mut buf = Vec::new();
+        aof::encode_command(args, &mut buf);
+        state.append(&buf);
+    }
+}
+
+impl State {
+    //PSYNC 带来的 replid 是当前的，或者是晋升之前的 master 的并且 offset 还在它的范围内
+    fn can_continue(&self, replid: &str, offset: i64) -> bool {
+        if offset <= 0 {
+            return false;
+        }
+        match &self.replid2 {
+            _ if replid == self.replid => true,
+            Some((replid2, until)) => replid == replid2 && offset as u64 <= *until,
+            None => false,
+        }
+    }
+
+    fn is_current_link(&self, id: u64) -> bool {
+        self.master.as_ref().is_some_and(|master| master.id == id)
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+        self.backlog.push(bytes);
+        let bytes = Bytes::copy_from_slice(bytes);
+        let limit = self.output_limit;
+        self.replicas.retain_mut(|replica| {
+            replica.buffer.len.fetch_add(bytes.len(), Ordering::AcqRel);
+            if replica.exceeds(&limit) {
+                println!(
+                    "Client replica {} scheduled to be closed ASAP for overcoming of output buffer limits.",
+                    replica.addr
+                );
+                replica.buffer.overflowed.notify_one();
+                return false;
+            }
+            //发送失败说明 replica 的连接已经断开，由连接任务自己清理
+            let _ = replica.tx.send(bytes.clone());
+            true
+        });
+    }
+}
+
+impl Replication {
+    /// Create the replication state and register it as a feed of `db`.
+    /// `listening_port` is the port this server accepts clients on.
+    pub fn new(db: &Db, backlog_size: usize, read_only: bool, listening_port: u16) -> Replication {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                replid: new_replid(),
+                replid2: None,
+                offset: 0,
+                backlog: Backlog::new(backlog_size, 1),
+                replicas: Vec::new(),
+                next_replica_id: 0,
+                master: None,
+                link_task: None,
+                next_link_id: 0,
+                synced: false,
+                output_limit: OutputBufferLimit::default(),
+            }),
+            acked: Notify::new(),
+            read_only: AtomicBool::new(read_only),
+            master_auth: Mutex::new((String::new(), String::new())),
+            listening_port,
+        });
+
+        db.lock().add_feed(Box::new(ReplFeed {
+            shared: shared.clone(),
+        }));
+
+        let replication = Replication { shared };
+        tokio::spawn(ping_replicas(replication.clone()));
+        replication
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.shared.state.lock().unwrap().master.is_some()
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        self.shared.read_only.store(read_only, Ordering::Release);
+    }
+
+    /// Credentials to AUTH with when connecting to the master, used from
+    /// the next connection on. An empty `user` means the `default` user, an
+    /// empty `password` not to authenticate.
+    pub fn set_master_auth(&self, user: &str, password: &str) {
+        *self.shared.master_auth.lock().unwrap() = (user.to_string(), password.to_string());
+    }
+
+    /// Change the output buffer limit, checked from the next write on.
+    pub fn set_output_buffer_limit(&self, limit: OutputBufferLimit) {
+        self.shared.state.lock().unwrap().output_limit = limit;
+    }
+
+    /// Writes from normal clients are rejected on a read-only replica.
+    pub fn rejects_writes(&self) -> bool {
+        self.shared.read_only.load(Ordering::Acquire) && self.is_replica()
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.shared.state.lock().unwrap().offset
+    }
+
+    fn append_command(&self, args: &[&'static [u8]]) {
+        let args: Vec<Bytes> = args.iter().map(|arg| Bytes::from_static(arg)).collect();
+        let mut buf = Vec::new();
+        aof::encode_command(&args, &mut buf);
+        self.shared.state.lock().unwrap().append(&buf);
+    }
+
+    /// Handle `PSYNC replid offset` from a replica. Takes over the connection
+    /// and streams the replication feed until the replica disconnects.
+    pub async fn serve_replica(
+        &self,
+        db: &Db,
+        mut connection: Connection<BoxedStream>,
+        addr: SocketAddr,
+        listening_port: u16,
+        args: &[Bytes],
+    ) -> error::Result<()> {
+        let requested_id = String::from_utf8_lossy(&args[1]).into_owned();
+        let requested_offset = cmd::parse_int(&args[2]).unwrap_or(-1);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let buffer = Arc::new(OutputBuffer::default());
+
+        //先锁 db 再锁复制状态，和 feed 的加锁顺序一致。在锁内拍快照并登记 replica，
+        //这样快照之后的每一条写命令都会进入这个 replica 的 channel
+        let (id, preamble, snapshot) = {
+            let keyspace = db.lock();
+            let mut state = self.shared.state.lock().unwrap();
+
+            let partial = if state.can_continue(&requested_id, requested_offset) {
+                state.backlog.since(requested_offset as u64)
+            } else {
+                None
+            };
+
+            let id = state.next_replica_id;
+            state.next_replica_id += 1;
+            state.replicas.push(Replica {
+                id,
+                addr,
+                listening_port,
+                ack_offset: 0,
+                last_ack: Instant::now(),
+                tx,
+                buffer: buffer.clone(),
+                soft_limit_since: None,
+            });
+
+            match partial {
+                Some(missing) => {
+                    let mut preamble = format!("+CONTINUE {}\r\n", state.replid).into_bytes();
+                    preamble.extend_from_slice(&missing);
+                    (id, preamble, None)
+                }
+                None => {
+                    let preamble =
+                        format!("+FULLRESYNC {} {}\r\n", state.replid, state.offset).into_bytes();
+                    (id, preamble, Some(keyspace.snapshot()))
+                }
+            }
+        };
+
+        let result = tokio::select! {
+            biased;
+            _ = buffer.overflowed.notified() => {
+                Err(io::Error::other("replica output buffer limit reached").into())
+            }
+            result = self.stream_to_replica(
+                id,
+                &mut connection,
+                preamble,
+                snapshot,
+                &mut rx,
+                &buffer,
+            ) => result,
+        };
+
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .replicas
+            .retain(|replica| replica.id != id);
+        println!("replica {} disconnected", addr);
+        result
+    }
+
+    async fn stream_to_replica(
+        &self,
+        id: u64,
+        connection: &mut Connection<BoxedStream>,
+        preamble: Vec<u8>,
+        snapshot: Option<Vec<(String, crate::db::Entry)>>,
+        rx: &mut mpsc::UnboundedReceiver<Bytes>,
+        buffer: &OutputBuffer,
+    ) -> error::Result<()> {
+        connection.write_bytes(&preamble).await?;
+        if let Some(snapshot) = snapshot {
+            let payload = rdb::encode(snapshot);
+            let mut buf = format!("${}\r\n", payload.len()).into_bytes();
+            buf.extend_from_slice(&payload);
+            connection.write_bytes(&buf).await?;
+        }
+
+        loop {
+            tokio::select! {
+                bytes = rx.recv() => match bytes {
+                    Some(bytes) => {
+                        connection.write_bytes(&bytes).await?;
+                        buffer.len.fetch_sub(bytes.len(), Ordering::AcqRel);
+                    }
+                    None => return Ok(()),
+                },
+                frame = connection.read_frame() => match frame? {
+                    Some(frame) => self.handle_replica_frame(id, frame),
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    // replica 在复制连接上只会发送 REPLCONF ACK <offset>
+    fn handle_replica_frame(&self, id: u64, frame: Frame) {
+        let args = match cmd::into_args(frame) {
+            Ok(args) => args,
+            Err(_) => return,
+        };
+        let is_ack = args.len() == 3
+            && args[0].eq_ignore_ascii_case(b"REPLCONF")
+            && args[1].eq_ignore_ascii_case(b"ACK");
+        if !is_ack {
+            return;
+        }
+        if let Ok(offset) = cmd::parse_int(&args[2]) {
+            let mut state = self.shared.state.lock().unwrap();
+            if let Some(replica) = state.replicas.iter_mut().find(|r| r.id == id) {
+                replica.ack_offset = offset.max(0) as u64;
+                replica.last_ack = Instant::now();
+            }
+            drop(state);
+            self.shared.acked.notify_waiters();
+        }
+    }
+
+    /// WAIT numreplicas timeout: block until `numreplicas` replicas acknowledged
+    /// every write made so far, or the timeout (0 = forever) expires.
+    pub async fn wait(&self, numreplicas: usize, timeout: Duration) -> Frame {
+        if self.is_replica() {
+            return Frame::Error("ERR WAIT cannot be used with replica instances".to_string());
+        }
+
+        let target = self.offset();
+        let count = || {
+            self.shared
+                .state
+                .lock()
+                .unwrap()
+                .replicas
+                .iter()
+                .filter(|replica| replica.ack_offset >= target)
+                .count()
+        };
+
+        if count() < numreplicas {
+            //让 replica 立刻回复 ACK，而不是等下一次定时发送
+            self.append_command(&[b"REPLCONF", b"GETACK", b"*"]);
+        }
+
+        let deadline = (!timeout.is_zero()).then(|| Instant::now() + timeout);
+        loop {
+            let notified = self.shared.acked.notified();
+            let acked = count();
+            if acked >= numreplicas {
+                return Frame::Integer(acked as i64);
+            }
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, notified).await.is_err() {
+                        return Frame::Integer(count() as i64);
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// The `# Replication` section of INFO.
+    pub fn info(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+        let mut info = String::from("# Replication\r\n");
+
+        match &state.master {
+            None => {
+                let _ = write!(info, "role:master\r\n");
+                let _ = write!(info, "connected_slaves:{}\r\n", state.replicas.len());
+                for (i, replica) in state.replicas.iter().enumerate() {
+                    let _ = write!(
+                        info,
+                        "slave{}:ip={},port={},state=online,offset={},lag={}\r\n",
+                        i,
+                        replica.addr.ip(),
+                        replica.listening_port,
+                        replica.ack_offset,
+                        replica.last_ack.elapsed().as_secs()
+                    );
+                }
+            }
+            Some(master) => {
+                let _ = write!(info, "role:slave\r\n");
+                let _ = write!(info, "master_host:{}\r\n", master.host);
+                let _ = write!(info, "master_port:{}\r\n", master.port);
+                let status = if master.up { "up" } else { "down" };
+                let _ = write!(info, "master_link_status:{}\r\n", status);
+                let _ = write!(
+                    info,
+                    "master_last_io_seconds_ago:{}\r\n",
+                    master.last_io.elapsed().as_secs()
+                );
+                let _ = write!(
+                    info,
+                    "master_sync_in_progress:{}\r\n",
+                    master.sync_in_progress as u8
+                );
+                let _ = write!(info, "slave_repl_offset:{}\r\n", state.offset);
+                let _ = write!(
+                    info,
+                    "slave_read_only:{}\r\n",
+                    self.shared.read_only.load(Ordering::Acquire) as u8
+                );
+                let _ = write!(info, "connected_slaves:{}\r\n", state.replicas.len());
+            }
+        }
+
+        let _ = write!(info, "master_replid:{}\r\n", state.replid);
+        let _ = write!(info, "master_repl_offset:{}\r\n", state.offset);
+        let _ = write!(info, "repl_backlog_active:1\r\n");
+        let _ = write!(info, "repl_backlog_size:{}\r\n", state.backlog.capacity);
+        let _ = write!(
+            info,
+            "repl_backlog_first_byte_offset:{}\r\n",
+            state.backlog.first_offset
+        );
+        let _ = write!(info, "repl_backlog_histlen:{}\r\n", state.backlog.buf.len());
+        info
+    }
+
+    /// REPLICAOF host port: become a replica of `host:port` and keep the link
+    /// up in the background. Replaces the link to any previous master.
+    pub fn replicate_from(&self, db: &Db, aof: Option<Aof>, host: String, port: u16) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(task) = state.link_task.take() {
+            task.abort();
+        }
+        if state.master.is_none() {
+            //master 降级成 replica：断开自己的 replica，它们会重新连上来同步
+            state.replicas.clear();
+        }
+        let id = state.next_link_id;
+        state.next_link_id += 1;
+        state.master = Some(MasterLink {
+            id,
+            host: host.clone(),
+            port,
+            up: false,
+            sync_in_progress: false,
+            last_io: Instant::now(),
+        });
+
+        let replication = self.clone();
+        let db = db.clone();
+        state.link_task = Some(tokio::spawn(async move {
+            loop {
+                if let Err(err) = replication
+                    .sync_with_master(id, &db, aof.as_ref(), &host, port)
+                    .await
+                {
+                    println!("replication link with {}:{} failed: {}", host, port, err);
+                }
+                replication.set_link(id, |master| {
+                    master.up = false;
+                    master.sync_in_progress = false;
+                });
+                time::sleep(Duration::from_secs(1)).await;
+            }
+        }));
+    }
+
+    /// REPLICAOF NO ONE: stop replicating and start accepting writes.
+    ///
+    /// A new replication id is generated, but the old one is kept as
+    /// `replid2` so replicas of the old master can continue from this one.
+    pub fn promote(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.master.take().is_none() {
+            return;
+        }
+        if let Some(task) = state.link_task.take() {
+            task.abort();
+        }
+        let old = std::mem::replace(&mut state.replid, new_replid());
+        state.replid2 = Some((old, state.offset + 1));
+        println!("MASTER MODE enabled");
+    }
+
+    /// ROLE: `master offset [[ip port offset]...]` or
+    /// `slave host port state offset`.
+    pub fn role(&self) -> Frame {
+        let state = self.shared.state.lock().unwrap();
+        let bulk = |value: String| Frame::Bulk(Bytes::from(value));
+        match &state.master {
+            None => Frame::Array(vec![
+                bulk("master".to_string()),
+                Frame::Integer(state.offset as i64),
+                Frame::Array(
+                    state
+                        .replicas
+                        .iter()
+                        .map(|replica| {
+                            Frame::Array(vec![
+                                bulk(replica.addr.ip().to_string()),
+                                bulk(replica.listening_port.to_string()),
+                                bulk(replica.ack_offset.to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+            Some(master) => {
+                let link = match (master.up, master.sync_in_progress) {
+                    (true, _) => "connected",
+                    (false, true) => "sync",
+                    (false, false) => "connect",
+                };
+                Frame::Array(vec![
+                    bulk("slave".to_string()),
+                    bulk(master.host.clone()),
+                    Frame::Integer(master.port as i64),
+                    bulk(link.to_string()),
+                    Frame::Integer(state.offset as i64),
+                ])
+            }
+        }
+    }
+
+    //只修改仍然是当前连接的 link，被替换掉的旧任务不能再改状态
+    fn set_link(&self, id: u64, f: impl FnOnce(&mut MasterLink)) {
+        if let Some(master) = &mut self.shared.state.lock().unwrap().master {
+            if master.id == id {
+                f(master);
+            }
+        }
+    }
+
+    async fn sync_with_master(
+        &self,
+        id: u64,
+        db: &Db,
+        aof: Option<&Aof>,
+        host: &str,
+        port: u16,
+    ) -> error::Result<()> {
+        let stream = TcpStream::connect((host, port)).await?;
+        let mut connection = Connection::new(stream);
+
+        //master 设置了密码时先 AUTH，否则后面的命令都会被拒绝
+        let (user, password) = self.shared.master_auth.lock().unwrap().clone();
+        match (&user[..], &password[..]) {
+            (_, "") => {}
+            ("", password) => {
+                request(&mut connection, &["AUTH", password]).await?;
+            }
+            (user, password) => {
+                request(&mut connection, &["AUTH", user, password]).await?;
+            }
+        }
+
+        //握手：PING, REPLCONF listening-port, REPLCONF capa, PSYNC
+        request(&mut connection, &["PING"]).await?;
+        request(
+            &mut connection,
+            &[
+                "REPLCONF",
+                "listening-port",
+                &self.shared.listening_port.to_string(),
+            ],
+        )
+        .await?;
+        request(&mut connection, &["REPLCONF", "capa", "psync2"]).await?;
+
+        let (replid, offset) = {
+            let state = self.shared.state.lock().unwrap();
+            if state.synced {
+                (state.replid.clone(), (state.offset + 1).to_string())
+            } else {
+                ("?".to_string(), "-1".to_string())
+            }
+        };
+        send(&mut connection, &["PSYNC", &replid, &offset]).await?;
+
+        let reply = match connection.read_frame().await? {
+            Some(Frame::Simple(reply)) => reply,
+            Some(frame) => return Err(frame.to_error()),
+            None => return Err(Error::ConnectionClosed),
+        };
+        let mut parts = reply.split(' ');
+        match parts.next() {
+            Some("FULLRESYNC") => {
+                let invalid = || Error::protocol(format!("invalid FULLRESYNC reply: {}", reply));
+                let replid = parts.next().ok_or_else(invalid)?.to_string();
+                let offset: u64 = parts
+                    .next()
+                    .and_then(|offset| offset.parse().ok())
+                    .ok_or_else(invalid)?;
+
+                self.set_link(id, |master| master.sync_in_progress = true);
+                let payload = connection.read_payload().await?;
+                let loaded = {
+                    let mut keyspace = db.lock();
+                    let mut state = self.shared.state.lock().unwrap();
+                    if !state.is_current_link(id) {
+                        return Ok(());
+                    }
+                    keyspace.clear();
+                    let loaded = rdb::decode(&payload, &mut keyspace)?;
+                    state.replid = replid;
+                    state.replid2 = None;
+                    state.offset = offset;
+                    state.backlog = Backlog::new(state.backlog.capacity, offset + 1);
+                    state.synced = true;
+                    loaded
+                };
+                db.notify_expiration();
+                println!("MASTER <-> REPLICA sync: loaded {} keys", loaded);
+
+                //数据整个被替换了，AOF 也需要重写
+                if let Some(aof) = aof {
+                    aof.rewrite(db);
+                }
+            }
+            Some("CONTINUE") => {
+                if let Some(new_id) = parts.next() {
+                    let mut state = self.shared.state.lock().unwrap();
+                    if new_id != state.replid {
+                        //master 是晋升上来的，记住旧的 replid，下级 replica 还可以用它续传
+                        let old = std::mem::replace(&mut state.replid, new_id.to_string());
+                        state.replid2 = Some((old, state.offset + 1));
+                    }
+                }
+                println!("MASTER <-> REPLICA sync: partial resynchronization accepted");
+            }
+            _ => {
+                return Err(Error::protocol(format!(
+                    "unexpected PSYNC reply: {}",
+                    reply
+                )))
+            }
+        }
+
+        self.set_link(id, |master| {
+            master.up = true;
+            master.sync_in_progress = false;
+            master.last_io = Instant::now();
+        });
+
+        let mut ack = time::interval(ACK_PERIOD);
+        loop {
+            tokio::select! {
+                frame = time::timeout(REPL_TIMEOUT, connection.read_frame()) => {
+                    let frame = match frame {
+                        Ok(frame) => frame?,
+                        Err(_) => return Err(Error::Timeout),
+                    };
+                    match frame {
+                        Some(frame) => {
+                            if self.apply_from_master(id, db, frame) {
+                                self.send_ack(&mut connection).await?;
+                            }
+                        }
+                        None => return Err(Error::ConnectionClosed),
+                    }
+                }
+                _ = ack.tick() => self.send_ack(&mut connection).await?,
+            }
+        }
+    }
+
+    /// Apply one command of the replication stream. Returns `true` if the
+    /// master asked for an acknowledgement.
+    fn apply_from_master(&self, id: u64, db: &Db, frame: Frame) -> bool {
+        let mut raw = Vec::new();
+        frame.encode(&mut raw);
+        let args = match cmd::into_args(frame) {
+            Ok(args) => args,
+            Err(_) => return false,
+        };
+
+        let mut keyspace = db.lock();
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.is_current_link(id) {
+            return false;
+        }
+        let getack = args[0].eq_ignore_ascii_case(b"REPLCONF");
+        if !getack && !args[0].eq_ignore_ascii_case(b"PING") {
+            //feed 会再去锁复制状态，先放开
+            drop(state);
+            cmd::execute_and_propagate(&mut keyspace, &args);
+            state = self.shared.state.lock().unwrap();
+        }
+        //原样追加到自己的 backlog，保持和 master 相同的 offset，下级 replica 也能续传
+        state.append(&raw);
+        if let Some(master) = &mut state.master {
+            master.last_io = Instant::now();
+        }
+        drop(state);
+        drop(keyspace);
+        db.notify_expiration();
+
+        getack
+    }
+
+    async fn send_ack(&self, connection: &mut Connection) -> error::Result<()> {
+        let offset = self.offset().to_string();
+        send(connection, &["REPLCONF", "ACK", &offset]).await
+    }
+}
+
+//定时通过复制流发送 PING，replica 据此判断连接是否还活着
+async fn ping_replicas(replication: Replication) {
+    let mut interval = time::interval(PING_PERIOD);
+    loop {
+        interval.tick().await;
+        let has_replicas = {
+            let state = replication.shared.state.lock().unwrap();
+            state.master.is_none() && !state.replicas.is_empty()
+        };
+        if has_replicas {
+            replication.append_command(&[b"PING"]);
+        }
+    }
+}
+
+/// A random 40 character replication id, also used as a sentinel run id.
+pub(crate) fn new_replid() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!("{}-{}-{:p}", nanos, std::process::id(), &nanos);
+    sha1_smol::Sha1::from(seed).digest().to_string()
+}
@@ -0,0 +1,438 @@
+//! EVAL / EVALSHA / SCRIPT 的实现。
+//!
+//! 脚本在 `spawn_blocking` 的线程里执行，执行期间一直持有 keyspace 的锁，
+//! 所以脚本对其他连接来说是原子的。脚本运行超过 `time_limit` 之后，其他连接会收到
+//! `-BUSY`，这时只能用 SCRIPT KILL 结束脚本（前提是脚本还没有写过数据）。
+//!
+//! 脚本只能用 table、string、math、utf8 这几个标准库，没有 os、io 和 package。
+
+use crate::{
+    acl::User,
+    cmd,
+    db::Db,
+    frame::Frame,
+};
+use bytes::Bytes;
+use mlua::{
+    HookTriggers,
+    Lua,
+    LuaOptions,
+    StdLib,
+    Value as LuaValue,
+    Variadic,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+use tokio::{
+    sync::Notify,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Default for how long a script may run before other clients get `-BUSY`.
+pub const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(5);
+
+/// Who runs a script and what its commands may do.
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// Commands the script calls are checked against this user's permissions.
+    pub user: User,
+    /// Refuse writes, as on a read-only replica.
+    pub read_only: bool,
+}
+
+/// Script cache and the state of the currently running script.
+#[derive(Debug, Clone)]
+pub struct Scripting {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    //sha1 -> 脚本内容
+    cache: Mutex<HashMap<String, Bytes>>,
+    //同一时间最多只有一个脚本在执行（它持有keyspace的锁）
+    running: Mutex<Option<Running>>,
+    //脚本结束时通知等待中的连接
+    done: Notify,
+    time_limit: Duration,
+}
+
+#[derive(Debug)]
+struct Running {
+    started: Instant,
+    kill: Arc<AtomicBool>,
+    wrote: Arc<AtomicBool>,
+}
+
+impl Scripting {
+    pub fn new(time_limit: Duration) -> Scripting {
+        Scripting {
+            shared: Arc::new(Shared {
+                cache: Mutex::new(HashMap::new()),
+                running: Mutex::new(None),
+                done: Notify::new(),
+                time_limit,
+            }),
+        }
+    }
+
+    /// Wait until no script is holding the keyspace.
+    ///
+    /// Returns a `-BUSY` error once the running script has exceeded the time
+    /// limit, so that clients are not stuck behind a runaway script.
+    pub async fn wait_idle(&self) -> Result<(), Frame> {
+        self.wait(None).await
+    }
+
+    //等待当前脚本结束；如果传入了 claim，就在同一次加锁里把自己登记为正在运行的脚本
+    async fn wait(&self, mut claim: Option<Running>) -> Result<(), Frame> {
+        loop {
+            let notified = self.shared.done.notified();
+            let deadline = {
+                let mut running = self.shared.running.lock().unwrap();
+                match &*running {
+                    None => {
+                        *running = claim.take();
+                        return Ok(());
+                    }
+                    Some(current) => current.started + self.shared.time_limit,
+                }
+            };
+
+            if Instant::now() >= deadline {
+                return Err(busy());
+            }
+
+            let _ = tokio::time::timeout_at(deadline, notified).await;
+        }
+    }
+
+    /// SCRIPT LOAD: cache the script and return its SHA1 digest.
+    pub fn load(&self, body: Bytes) -> String {
+        let sha = sha1_hex(&body);
+        self.shared.cache.lock().unwrap().insert(sha.clone(), body);
+        sha
+    }
+
+    pub fn exists(&self, sha: &[u8]) -> bool {
+        let sha = String::from_utf8_lossy(sha).to_ascii_lowercase();
+        self.shared.cache.lock().unwrap().contains_key(&sha)
+    }
+
+    pub fn flush(&self) {
+        self.shared.cache.lock().unwrap().clear();
+    }
+
+    /// SCRIPT KILL: ask the running script to abort.
+    pub fn kill(&self) -> Frame {
+        match &*self.shared.running.lock().unwrap() {
+            None => Frame::Error("NOTBUSY No scripts in execution right now.".to_string()),
+            Some(running) if running.wrote.load(Ordering::Acquire) => Frame::Error(
+                "UNKILLABLE Sorry the script already executed write commands against the \
+                 dataset. You can either wait the script termination or kill the server in a \
+                 hard way using the SHUTDOWN NOSAVE command."
+                    .to_string(),
+            ),
+            Some(running) => {
+                running.kill.store(true, Ordering::Release);
+                cmd::ok()
+            }
+        }
+    }
+
+    /// EVAL script numkeys [key ...] [arg ...]
+    pub async fn eval(&self, db: &Db, args: &[Bytes], caller: Caller) -> Frame {
+        let body = args[1].clone();
+        self.load(body.clone());
+        self.run(db, body, &args[2..], caller).await
+    }
+
+    /// EVALSHA sha1 numkeys [key ...] [arg ...]
+    pub async fn evalsha(&self, db: &Db, args: &[Bytes], caller: Caller) -> Frame {
+        let sha = String::from_utf8_lossy(&args[1]).to_ascii_lowercase();
+        let body = self.shared.cache.lock().unwrap().get(&sha).cloned();
+        match body {
+            Some(body) => self.run(db, body, &args[2..], caller).await,
+            None => Frame::Error("NOSCRIPT No matching script. Please use EVAL.".to_string()),
+        }
+    }
+
+    async fn run(&self, db: &Db, body: Bytes, args: &[Bytes], caller: Caller) -> Frame {
+        let numkeys = match cmd::parse_int(&args[0]) {
+            Ok(n) if n >= 0 && (n as usize) < args.len() => n as usize,
+            Ok(n) if n < 0 => {
+                return Frame::Error("ERR Number of keys can't be negative".to_string())
+            }
+            Ok(_) => {
+                return Frame::Error(
+                    "ERR Number of keys can't be greater than number of args".to_string(),
+                )
+            }
+            Err(err) => return err,
+        };
+        let keys = args[1..=numkeys].to_vec();
+        let argv = args[numkeys + 1..].to_vec();
+
+        //只允许一个脚本执行，其他 EVAL 排队等待
+        let kill = Arc::new(AtomicBool::new(false));
+        let wrote = Arc::new(AtomicBool::new(false));
+        let claim = Running {
+            started: Instant::now(),
+            kill: kill.clone(),
+            wrote: wrote.clone(),
+        };
+        if let Err(err) = self.wait(Some(claim)).await {
+            return err;
+        }
+
+        let db = db.clone();
+        let reply = tokio::task::spawn_blocking(move || {
+            let mut state = db.lock();
+            let reply = run_script(&mut state, &body, keys, argv, &caller, kill, wrote);
+            drop(state);
+            db.notify_expiration();
+            reply
+        })
+        .await
+        .unwrap_or_else(|err| Frame::Error(format!("ERR script task failed: {}", err)));
+
+        *self.shared.running.lock().unwrap() = None;
+        self.shared.done.notify_waiters();
+        reply
+    }
+}
+
+fn busy() -> Frame {
+    Frame::Error("BUSY Redis is busy running a script. You can only call SCRIPT KILL.".to_string())
+}
+
+pub fn sha1_hex(body: &[u8]) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+fn run_script(
+    state: &mut crate::db::State,
+    body: &[u8],
+    keys: Vec<Bytes>,
+    argv: Vec<Bytes>,
+    caller: &Caller,
+    kill: Arc<AtomicBool>,
+    wrote: Arc<AtomicBool>,
+) -> Frame {
+    //只加载没有副作用的标准库，脚本不能访问文件、环境变量或者执行外部命令
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8;
+    let lua = match Lua::new_with(libs, LuaOptions::default()) {
+        Ok(lua) => lua,
+        Err(err) => return Frame::Error(script_error(err)),
+    };
+
+    //每执行1000条指令检查一次是否被 SCRIPT KILL
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(1000),
+        move |_lua, _debug| {
+            if kill.load(Ordering::Acquire) {
+                Err(mlua::Error::runtime(
+                    "ERR Script killed by user with SCRIPT KILL...",
+                ))
+            } else {
+                Ok(())
+            }
+        },
+    );
+
+    //redis.call 和 redis.pcall 都需要可变借用 state，所以放进 RefCell
+    let state = RefCell::new(state);
+
+    let result = lua.scope(|scope| {
+        let globals = lua.globals();
+        //基础库里能读文件和加载模块的函数
+        for name in ["loadfile", "dofile", "require"] {
+            globals.set(name, LuaValue::Nil)?;
+        }
+        globals.set("KEYS", bytes_to_table(&lua, &keys)?)?;
+        globals.set("ARGV", bytes_to_table(&lua, &argv)?)?;
+
+        let redis = lua.create_table()?;
+
+        let call = scope.create_function(|lua, args: Variadic<LuaValue>| {
+            match redis_call(&mut state.borrow_mut(), caller, &wrote, args) {
+                Frame::Error(msg) => Err(mlua::Error::runtime(msg)),
+                frame => frame_to_lua(lua, frame),
+            }
+        })?;
+        redis.set("call", call)?;
+
+        //pcall 不抛出错误，而是把错误作为 {err = ...} 表返回
+        let pcall = scope.create_function(|lua, args: Variadic<LuaValue>| {
+            frame_to_lua(lua, redis_call(&mut state.borrow_mut(), caller, &wrote, args))
+        })?;
+        redis.set("pcall", pcall)?;
+
+        redis.set(
+            "error_reply",
+            lua.create_function(|lua, msg: String| frame_to_lua(lua, Frame::Error(msg)))?,
+        )?;
+        redis.set(
+            "status_reply",
+            lua.create_function(|lua, msg: String| frame_to_lua(lua, Frame::Simple(msg)))?,
+        )?;
+        globals.set("redis", redis)?;
+
+        let value: LuaValue = lua.load(body).set_name("@user_script").eval()?;
+        Ok(lua_to_frame(value))
+    });
+
+    match result {
+        Ok(frame) => frame,
+        Err(err) => Frame::Error(script_error(err)),
+    }
+}
+
+//执行脚本里的一条命令
+fn redis_call(
+    state: &mut crate::db::State,
+    caller: &Caller,
+    wrote: &AtomicBool,
+    args: Variadic<LuaValue>,
+) -> Frame {
+    let mut argv = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        match arg {
+            LuaValue::String(s) => argv.push(Bytes::copy_from_slice(s.as_bytes())),
+            LuaValue::Integer(n) => argv.push(Bytes::from(n.to_string())),
+            LuaValue::Number(n) => argv.push(Bytes::from(n.to_string())),
+            _ => {
+                return Frame::Error(
+                    "ERR Lua redis() command arguments must be strings or integers".to_string(),
+                )
+            }
+        }
+    }
+
+    if argv.is_empty() {
+        return Frame::Error(
+            "ERR Please specify at least one argument for redis.call()".to_string(),
+        );
+    }
+
+    match cmd::lookup(&argv[0]) {
+        Some(spec) if spec.noscript => {
+            Frame::Error("ERR This Redis command is not allowed from scripts".to_string())
+        }
+        Some(spec) => {
+            //参数个数不对时交给 execute 报错
+            if spec.check_arity(argv.len()) {
+                if let Err(denied) = caller.user.check(spec, &argv) {
+                    return denied;
+                }
+            }
+            if spec.write && caller.read_only {
+                return Frame::Error(
+                    "READONLY You can't write against a read only replica.".to_string(),
+                );
+            }
+            if spec.write {
+                wrote.store(true, Ordering::Release);
+            }
+            cmd::execute_and_propagate(state, &argv)
+        }
+        None => cmd::unknown_command(&argv),
+    }
+}
+
+fn bytes_to_table<'lua>(lua: &'lua Lua, items: &[Bytes]) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    for (i, item) in items.iter().enumerate() {
+        table.raw_set(i + 1, lua.create_string(item)?)?;
+    }
+    Ok(table)
+}
+
+/// Convert a command reply into a Lua value, following the Redis conventions.
+pub fn frame_to_lua(lua: &Lua, frame: Frame) -> mlua::Result<LuaValue<'_>> {
+    Ok(match frame {
+        Frame::Simple(status) => {
+            let table = lua.create_table()?;
+            table.set("ok", status)?;
+            LuaValue::Table(table)
+        }
+        Frame::Error(msg) => {
+            let table = lua.create_table()?;
+            table.set("err", msg)?;
+            LuaValue::Table(table)
+        }
+        Frame::Integer(n) => LuaValue::Integer(n),
+        Frame::Bulk(data) => LuaValue::String(lua.create_string(&data)?),
+        Frame::Null => LuaValue::Boolean(false),
+        Frame::Array(items) => {
+            let table = lua.create_table()?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.raw_set(i + 1, frame_to_lua(lua, item)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
+
+/// Convert a script's return value into a reply frame.
+///
+/// Numbers are truncated to integers, `true` becomes 1, `false`/`nil` become
+/// a null reply, and arrays stop at the first `nil` just like Redis does.
+pub fn lua_to_frame(value: LuaValue) -> Frame {
+    match value {
+        LuaValue::Nil | LuaValue::Boolean(false) => Frame::Null,
+        LuaValue::Boolean(true) => Frame::Integer(1),
+        LuaValue::Integer(n) => Frame::Integer(n),
+        LuaValue::Number(n) => Frame::Integer(n as i64),
+        LuaValue::String(s) => Frame::Bulk(Bytes::copy_from_slice(s.as_bytes())),
+        LuaValue::Table(table) => {
+            if let Ok(Some(msg)) = table.raw_get::<_, Option<String>>("err") {
+                return Frame::Error(msg);
+            }
+            if let Ok(Some(status)) = table.raw_get::<_, Option<String>>("ok") {
+                return Frame::Simple(status);
+            }
+            let items = table
+                .sequence_values::<LuaValue>()
+                .map_while(|value| value.ok())
+                .map(lua_to_frame)
+                .collect();
+            Frame::Array(items)
+        }
+        _ => Frame::Null,
+    }
+}
+
+//redis.call 抛出的错误会被 mlua 包装成 CallbackError，这里取出最里面的错误信息
+fn script_error(err: mlua::Error) -> String {
+    let mut err = &err;
+    while let mlua::Error::CallbackError { cause, .. } = err {
+        err = cause;
+    }
+    match err {
+        mlua::Error::RuntimeError(msg) if msg.starts_with("ERR ") || is_error_code(msg) => {
+            msg.clone()
+        }
+        err => format!("ERR Error running script: {}", err),
+    }
+}
+
+//Redis 的错误以大写的错误码开头，比如 WRONGTYPE、NOSCRIPT
+fn is_error_code(msg: &str) -> bool {
+    msg.split(' ')
+        .next()
+        .is_some_and(|code| code.len() > 1 && code.bytes().all(|b| b.is_ascii_uppercase()))
+}
@@ -0,0 +1,926 @@
+//! Sentinel：监控一组 master/replica，master 挂掉之后自动把一个 replica 提升为 master。
+//!
+//! 每个 sentinel 定期 PING 它监控的 master：
+//!
+//! * 超过 `down-after-milliseconds` 没有正常回复，标记为主观下线（`+sdown`）
+//! * 通过 `SENTINEL is-master-down-by-addr` 询问其他 sentinel，认为下线的数量达到
+//!   quorum 之后标记为客观下线（`+odown`）
+//! * 在新的 epoch 里请求其他 sentinel 投票，得到多数票的 sentinel 负责故障转移：
+//!   挑选复制 offset 最大的 replica 执行 `REPLICAOF NO ONE`，其余 replica 改为复制它
+//! * 新的配置通过 `SENTINEL hello` 发给其他 sentinel，所有 sentinel 通过 pub/sub
+//!   发布 `+switch-master` 事件
+//!
+//! 旧 master 恢复之后会被当作 replica 重新配置。
+
+use crate::{
+    cmd,
+    config,
+    error,
+    frame::Frame,
+    peer::{
+        Addr,
+        Links,
+    },
+    replication,
+    Connection,
+};
+use bytes::Bytes;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    net::TcpListener,
+    sync::broadcast,
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+pub const DEFAULT_PORT: u16 = 26379;
+
+/// Sentinel configuration, set with the sentinel.conf names without the
+/// `sentinel` prefix, for example `--monitor mymaster 127.0.0.1 6379 2`.
+#[derive(Debug, Clone)]
+pub struct SentinelConfig {
+    /// Address to listen on.
+    pub bind: String,
+    pub port: u16,
+    pub masters: Vec<MasterConfig>,
+}
+
+/// A monitored master.
+#[derive(Debug, Clone)]
+pub struct MasterConfig {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// Number of sentinels that must agree the master is down.
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub failover_timeout: Duration,
+    /// The other sentinels monitoring this master.
+    pub sentinels: Vec<Addr>,
+}
+
+impl Default for SentinelConfig {
+    fn default() -> SentinelConfig {
+        SentinelConfig {
+            bind: "127.0.0.1".to_string(),
+            port: DEFAULT_PORT,
+            masters: Vec::new(),
+        }
+    }
+}
+
+impl SentinelConfig {
+    /// Set a parameter by its sentinel.conf name.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        match (&name.to_ascii_lowercase()[..], &parts[..]) {
+            ("bind", [addr]) => self.bind = addr.to_string(),
+            ("port", [port]) => self.port = parse_port(port)?,
+            ("monitor", [name, host, port, quorum]) => {
+                let quorum = quorum
+                    .parse()
+                    .map_err(|_| format!("invalid quorum '{}'", quorum))?;
+                self.masters.push(MasterConfig {
+                    name: name.to_string(),
+                    host: host.to_string(),
+                    port: parse_port(port)?,
+                    quorum,
+                    down_after: Duration::from_secs(30),
+                    failover_timeout: Duration::from_secs(180),
+                    sentinels: Vec::new(),
+                });
+            }
+            ("down-after-milliseconds", [name, ms]) => {
+                self.master(name)?.down_after = parse_ms(ms)?;
+            }
+            ("failover-timeout", [name, ms]) => {
+                self.master(name)?.failover_timeout = parse_ms(ms)?;
+            }
+            ("known-sentinel", [name, host, port]) => {
+                let port = parse_port(port)?;
+                self.master(name)?.sentinels.push((host.to_string(), port));
+            }
+            _ => return Err(format!("invalid sentinel option '{} {}'", name, value)),
+        }
+        Ok(())
+    }
+
+    /// Parse `--name value...` options from the command line.
+    pub fn parse_args<I: Iterator<Item = String>>(&mut self, args: I) -> Result<(), String> {
+        config::for_each_option(args, |name, value| self.set(name, value))
+    }
+
+    fn master(&mut self, name: &str) -> Result<&mut MasterConfig, String> {
+        self.masters
+            .iter_mut()
+            .find(|master| master.name == name)
+            .ok_or_else(|| format!("no such master '{}', add it with --monitor first", name))
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    port.parse().map_err(|_| format!("invalid port '{}'", port))
+}
+
+fn parse_ms(ms: &str) -> Result<Duration, String> {
+    ms.parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("invalid milliseconds '{}'", ms))
+}
+
+#[derive(Debug, Clone)]
+struct Sentinel {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    run_id: String,
+    state: Mutex<State>,
+    //pub/sub 事件：(channel, message)
+    events: broadcast::Sender<(String, String)>,
+}
+
+#[derive(Debug)]
+struct State {
+    current_epoch: u64,
+    masters: HashMap<String, Master>,
+}
+
+#[derive(Debug)]
+struct Master {
+    config: MasterConfig,
+    addr: Addr,
+    /// Epoch of the failover that produced the current address.
+    config_epoch: u64,
+    //从 master 的 INFO 里发现的 replica，以及故障转移后降级的旧 master
+    replicas: Vec<Addr>,
+    last_ok: Instant,
+    s_down: bool,
+    o_down: bool,
+    //这个 epoch 里投票给了谁
+    leader: Option<String>,
+    leader_epoch: u64,
+    failover_started: Option<Instant>,
+}
+
+impl Master {
+    fn describe(&self) -> String {
+        format!(
+            "master {} {} {}",
+            self.config.name, self.addr.0, self.addr.1
+        )
+    }
+
+    //客观下线，并且最近没有发起过（或者投票给别人发起）故障转移
+    fn can_failover(&self) -> bool {
+        self.o_down
+            && self
+                .failover_started
+                .is_none_or(|started| started.elapsed() > self.config.failover_timeout * 2)
+    }
+
+    fn switch_to(&mut self, addr: Addr, config_epoch: u64) -> Addr {
+        self.replicas.retain(|replica| *replica != addr);
+        let old = std::mem::replace(&mut self.addr, addr);
+        self.replicas.push(old.clone());
+        self.config_epoch = config_epoch;
+        self.last_ok = Instant::now();
+        self.s_down = false;
+        self.o_down = false;
+        self.failover_started = None;
+        old
+    }
+}
+
+/// Run a sentinel on an already bound listener.
+pub async fn run(listener: TcpListener, config: SentinelConfig) -> error::Result<()> {
+    let (events, _) = broadcast::channel(1024);
+    let masters = config
+        .masters
+        .iter()
+        .map(|master| {
+            let state = Master {
+                config: master.clone(),
+                addr: (master.host.clone(), master.port),
+                config_epoch: 0,
+                replicas: Vec::new(),
+                last_ok: Instant::now(),
+                s_down: false,
+                o_down: false,
+                leader: None,
+                leader_epoch: 0,
+                failover_started: None,
+            };
+            (master.name.clone(), state)
+        })
+        .collect();
+    let sentinel = Sentinel {
+        shared: Arc::new(Shared {
+            run_id: replication::new_replid(),
+            state: Mutex::new(State {
+                current_epoch: 0,
+                masters,
+            }),
+            events,
+        }),
+    };
+    println!("Sentinel ID is {}", sentinel.shared.run_id);
+
+    for master in &config.masters {
+        sentinel.event(
+            "+monitor",
+            &format!(
+                "master {} {} {} quorum {}",
+                master.name, master.host, master.port, master.quorum
+            ),
+        );
+        tokio::spawn(sentinel.clone().monitor(master.name.clone()));
+    }
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let sentinel = sentinel.clone();
+        tokio::spawn(async move {
+            if let Err(err) = sentinel.handle(Connection::new(stream)).await {
+                println!("connection error: {}", err);
+            }
+        });
+    }
+}
+
+impl Sentinel {
+    fn event(&self, channel: &str, message: &str) {
+        println!("{} {}", channel, message);
+        let _ = self
+            .shared
+            .events
+            .send((channel.to_string(), message.to_string()));
+    }
+
+    fn with_master<T>(&self, name: &str, f: impl FnOnce(&mut Master, &mut u64) -> T) -> T {
+        let mut state = self.shared.state.lock().unwrap();
+        let State {
+            current_epoch,
+            masters,
+        } = &mut *state;
+        f(masters.get_mut(name).unwrap(), current_epoch)
+    }
+
+    //每个 master 一个监控任务
+    async fn monitor(self, name: String) {
+        let config = self.with_master(&name, |master, _| master.config.clone());
+        let period = config.down_after.min(Duration::from_secs(1));
+        let mut links = Links::default();
+        let mut mismatches = HashMap::new();
+        let mut interval = time::interval(period);
+
+        loop {
+            interval.tick().await;
+            let addr = self.with_master(&name, |master, _| master.addr.clone());
+
+            let master_ok = links.call(&addr, &["PING"], period).await.is_some();
+            if master_ok {
+                self.with_master(&name, |master, _| master.last_ok = Instant::now());
+                if let Some(Frame::Bulk(info)) =
+                    links.call(&addr, &["INFO", "replication"], period).await
+                {
+                    self.discover_replicas(&name, &String::from_utf8_lossy(&info));
+                }
+            }
+
+            self.check_subjectively_down(&name);
+            self.check_objectively_down(&name, &mut links, period).await;
+            if self.with_master(&name, |master, _| master.can_failover()) {
+                //随机等一会儿，避免所有 sentinel 同时发起选举，谁都拿不到多数票
+                time::sleep(jitter(period)).await;
+                self.try_failover(&name, &addr, &mut links, period).await;
+            }
+            self.send_hello(&name, &mut links, period).await;
+            if master_ok {
+                self.reconfigure_replicas(&name, &mut links, &mut mismatches, period)
+                    .await;
+            }
+        }
+    }
+
+    fn discover_replicas(&self, name: &str, info: &str) {
+        let found: Vec<Addr> = info
+            .lines()
+            .filter(|line| line.starts_with("slave"))
+            .filter_map(|line| {
+                let fields = line.split_once(':')?.1;
+                let field = |key: &str| {
+                    fields
+                        .split(',')
+                        .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+                };
+                Some((field("ip")?.to_string(), field("port")?.parse().ok()?))
+            })
+            .collect();
+
+        let added: Vec<Addr> = self.with_master(name, |master, _| {
+            let added: Vec<Addr> = found
+                .into_iter()
+                .filter(|addr| !master.replicas.contains(addr) && *addr != master.addr)
+                .collect();
+            master.replicas.extend(added.iter().cloned());
+            added
+        });
+        for (host, port) in added {
+            self.event(
+                "+slave",
+                &format!("slave {}:{} {} {} @ {}", host, port, host, port, name),
+            );
+        }
+    }
+
+    fn check_subjectively_down(&self, name: &str) {
+        let change = self.with_master(name, |master, _| {
+            let down = master.last_ok.elapsed() > master.config.down_after;
+            if down == master.s_down {
+                return None;
+            }
+            master.s_down = down;
+            let odown_cleared = !down && std::mem::take(&mut master.o_down);
+            Some((down, odown_cleared, master.describe()))
+        });
+        match change {
+            Some((true, _, describe)) => self.event("+sdown", &describe),
+            Some((false, odown_cleared, describe)) => {
+                self.event("-sdown", &describe);
+                if odown_cleared {
+                    self.event("-odown", &describe);
+                }
+            }
+            None => {}
+        }
+    }
+
+    //询问其他 sentinel 是否也认为 master 下线了
+    async fn check_objectively_down(&self, name: &str, links: &mut Links, timeout: Duration) {
+        let (s_down, addr, epoch, config) = self.with_master(name, |master, epoch| {
+            (
+                master.s_down,
+                master.addr.clone(),
+                *epoch,
+                master.config.clone(),
+            )
+        });
+        if !s_down {
+            return;
+        }
+
+        let mut agreed = 1;
+        for peer in &config.sentinels {
+            let reply = links
+                .call(
+                    peer,
+                    &[
+                        "SENTINEL",
+                        "is-master-down-by-addr",
+                        &addr.0,
+                        &addr.1.to_string(),
+                        &epoch.to_string(),
+                        "*",
+                    ],
+                    timeout,
+                )
+                .await;
+            if let Some(Frame::Array(reply)) = reply {
+                if reply.first() == Some(&Frame::Integer(1)) {
+                    agreed += 1;
+                }
+            }
+        }
+
+        let o_down = agreed >= config.quorum;
+        let changed = self.with_master(name, |master, _| {
+            //询问期间可能已经切换了 master
+            if master.addr != addr || master.o_down == o_down {
+                return None;
+            }
+            master.o_down = o_down;
+            Some(master.describe())
+        });
+        if let Some(describe) = changed {
+            if o_down {
+                let quorum = format!("#quorum {}/{}", agreed, config.quorum);
+                self.event("+odown", &format!("{} {}", describe, quorum));
+            } else {
+                self.event("-odown", &describe);
+            }
+        }
+    }
+
+    async fn try_failover(&self, name: &str, addr: &Addr, links: &mut Links, timeout: Duration) {
+        let run_id = self.shared.run_id.clone();
+        //进入新的 epoch，先投自己一票
+        let started = self.with_master(name, |master, current_epoch| {
+            //等待期间可能已经收到了别的 sentinel 完成故障转移的 hello
+            if master.addr != *addr || !master.can_failover() {
+                return None;
+            }
+            *current_epoch += 1;
+            master.leader = Some(run_id.clone());
+            master.leader_epoch = *current_epoch;
+            master.failover_started = Some(Instant::now());
+            Some((*current_epoch, master.config.clone()))
+        });
+        let (epoch, config) = match started {
+            Some(started) => started,
+            None => return,
+        };
+        let describe = format!("master {} {} {}", name, addr.0, addr.1);
+        self.event("+new-epoch", &epoch.to_string());
+        self.event("+try-failover", &describe);
+
+        let mut votes = 1;
+        for peer in &config.sentinels {
+            let reply = links
+                .call(
+                    peer,
+                    &[
+                        "SENTINEL",
+                        "is-master-down-by-addr",
+                        &addr.0,
+                        &addr.1.to_string(),
+                        &epoch.to_string(),
+                        &run_id,
+                    ],
+                    timeout,
+                )
+                .await;
+            if let Some(Frame::Array(reply)) = reply {
+                let leader = reply.get(1).is_some_and(|leader| *leader == &run_id[..]);
+                let leader_epoch = reply.get(2) == Some(&Frame::Integer(epoch as i64));
+                if leader && leader_epoch {
+                    votes += 1;
+                }
+            }
+        }
+
+        //既要达到 quorum，也要是所有 sentinel 的多数
+        let total = config.sentinels.len() + 1;
+        let needed = config.quorum.max(total / 2 + 1);
+        if votes < needed {
+            println!(
+                "failover for {} not started: got {} votes, need {}",
+                name, votes, needed
+            );
+            return;
+        }
+        self.event("+elected-leader", &describe);
+
+        let replicas = self.with_master(name, |master, _| master.replicas.clone());
+        let promoted = match select_replica(&replicas, links, timeout).await {
+            Some(promoted) => promoted,
+            None => {
+                self.event("-failover-abort-no-good-slave", &describe);
+                return;
+            }
+        };
+        let slave = format!(
+            "slave {}:{} {} {} @ {} {} {}",
+            promoted.0, promoted.1, promoted.0, promoted.1, name, addr.0, addr.1
+        );
+        self.event("+selected-slave", &slave);
+
+        if links
+            .call(&promoted, &["REPLICAOF", "NO", "ONE"], timeout)
+            .await
+            .is_none()
+        {
+            self.event("-failover-abort-slave-timeout", &slave);
+            return;
+        }
+        self.event("+promoted-slave", &slave);
+
+        let port = promoted.1.to_string();
+        for replica in replicas.iter().filter(|replica| **replica != promoted) {
+            if links
+                .call(replica, &["REPLICAOF", &promoted.0, &port], timeout)
+                .await
+                .is_some()
+            {
+                self.event(
+                    "+slave-reconf-sent",
+                    &format!(
+                        "slave {}:{} {} {} @ {} {} {}",
+                        replica.0, replica.1, replica.0, replica.1, name, addr.0, addr.1
+                    ),
+                );
+            }
+        }
+
+        self.with_master(name, |master, _| master.switch_to(promoted.clone(), epoch));
+        self.event("+failover-end", &describe);
+        self.event(
+            "+switch-master",
+            &format!(
+                "{} {} {} {} {}",
+                name, addr.0, addr.1, promoted.0, promoted.1
+            ),
+        );
+        //马上通知其他 sentinel，不等下一次 hello
+        self.send_hello(name, links, timeout).await;
+    }
+
+    async fn send_hello(&self, name: &str, links: &mut Links, timeout: Duration) {
+        let (addr, config_epoch, sentinels) = self.with_master(name, |master, _| {
+            (
+                master.addr.clone(),
+                master.config_epoch,
+                master.config.sentinels.clone(),
+            )
+        });
+        let port = addr.1.to_string();
+        let config_epoch = config_epoch.to_string();
+        for peer in &sentinels {
+            links
+                .call(
+                    peer,
+                    &["SENTINEL", "hello", name, &addr.0, &port, &config_epoch],
+                    timeout,
+                )
+                .await;
+        }
+    }
+
+    /// While the master is reachable, point instances with the wrong role at
+    /// it, for example the old master after it comes back.
+    ///
+    /// An instance is only fixed after it has been wrong for a while: right
+    /// after a failover the new configuration may not have reached this
+    /// sentinel yet, and the "wrong" instance is actually the new master.
+    async fn reconfigure_replicas(
+        &self,
+        name: &str,
+        links: &mut Links,
+        mismatches: &mut HashMap<Addr, Instant>,
+        timeout: Duration,
+    ) {
+        let (addr, replicas) = self.with_master(name, |master, _| {
+            (master.addr.clone(), master.replicas.clone())
+        });
+
+        let port = addr.1.to_string();
+        for replica in &replicas {
+            let role = match links.call(replica, &["ROLE"], timeout).await {
+                Some(Frame::Array(role)) => role,
+                _ => continue,
+            };
+            let (event, wrong) = match &role[..] {
+                [role, ..] if *role == "master" => ("+convert-to-slave", true),
+                [role, host, Frame::Integer(port), ..] if *role == "slave" => {
+                    let right = *host == &addr.0[..] && *port == addr.1 as i64;
+                    ("+fix-slave-config", !right)
+                }
+                _ => continue,
+            };
+            if !wrong {
+                mismatches.remove(replica);
+                continue;
+            }
+            let since = *mismatches
+                .entry(replica.clone())
+                .or_insert_with(Instant::now);
+            if since.elapsed() < timeout * 4 {
+                continue;
+            }
+            mismatches.remove(replica);
+            if links
+                .call(replica, &["REPLICAOF", &addr.0, &port], timeout)
+                .await
+                .is_some()
+            {
+                self.event(
+                    event,
+                    &format!(
+                        "slave {}:{} {} {} @ {} {} {}",
+                        replica.0, replica.1, replica.0, replica.1, name, addr.0, addr.1
+                    ),
+                );
+            }
+        }
+    }
+
+    async fn handle(self, mut connection: Connection) -> error::Result<()> {
+        while let Some(frame) = connection.read_frame().await? {
+            let args = match cmd::into_args(frame) {
+                Ok(args) => args,
+                Err(err) => {
+                    connection.write_frame(&err).await?;
+                    continue;
+                }
+            };
+            let name = args[0].to_ascii_uppercase();
+            let response = match &name[..] {
+                b"SUBSCRIBE" if args.len() > 1 => return self.subscribe(connection, &args).await,
+                b"PING" => Frame::Simple("PONG".to_string()),
+                b"ROLE" => self.role(),
+                b"SENTINEL" if args.len() > 1 => self.command(&args),
+                _ => Frame::Error(format!(
+                    "ERR unknown command '{}' or wrong number of arguments",
+                    String::from_utf8_lossy(&args[0])
+                )),
+            };
+            connection.write_frame(&response).await?;
+        }
+        Ok(())
+    }
+
+    fn role(&self) -> Frame {
+        let state = self.shared.state.lock().unwrap();
+        let names = state
+            .masters
+            .keys()
+            .map(|name| Frame::Bulk(Bytes::from(name.clone())))
+            .collect();
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"sentinel")),
+            Frame::Array(names),
+        ])
+    }
+
+    // SENTINEL <subcommand> ...
+    fn command(&self, args: &[Bytes]) -> Frame {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let sub = args[1].to_ascii_lowercase();
+        let mut state = self.shared.state.lock().unwrap();
+
+        match (&sub[..], &args[2..]) {
+            ("myid", []) => bulk(&self.shared.run_id),
+            ("get-master-addr-by-name", [name]) => match state.masters.get(name) {
+                Some(master) => {
+                    Frame::Array(vec![bulk(&master.addr.0), bulk(&master.addr.1.to_string())])
+                }
+                None => Frame::Null,
+            },
+            ("masters", []) => Frame::Array(state.masters.values().map(master_fields).collect()),
+            ("master", [name]) => match state.masters.get(name) {
+                Some(master) => master_fields(master),
+                None => Frame::Error("ERR No such master with that name".to_string()),
+            },
+            ("replicas" | "slaves", [name]) => match state.masters.get(name) {
+                Some(master) => Frame::Array(
+                    master
+                        .replicas
+                        .iter()
+                        .map(|(host, port)| {
+                            Frame::Array(vec![
+                                bulk("name"),
+                                bulk(&format!("{}:{}", host, port)),
+                                bulk("ip"),
+                                bulk(host),
+                                bulk("port"),
+                                bulk(&port.to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+                None => Frame::Error("ERR No such master with that name".to_string()),
+            },
+            // is-master-down-by-addr <ip> <port> <current-epoch> <runid>
+            // runid 是 `*` 时只是询问状态，否则同时请求投票
+            ("is-master-down-by-addr", [host, port, epoch, run_id]) => {
+                let epoch: u64 = match epoch.parse() {
+                    Ok(epoch) => epoch,
+                    Err(_) => return Frame::Error("ERR invalid epoch".to_string()),
+                };
+                let port: u16 = port.parse().unwrap_or(0);
+                let State {
+                    current_epoch,
+                    masters,
+                } = &mut *state;
+                let master = masters
+                    .values_mut()
+                    .find(|master| master.addr.0 == *host && master.addr.1 == port);
+                let master = match master {
+                    Some(master) => master,
+                    None => {
+                        return Frame::Array(vec![Frame::Integer(0), bulk("*"), Frame::Integer(0)])
+                    }
+                };
+                if run_id != "*" && epoch > master.leader_epoch {
+                    //每个 epoch 只投一票，投给第一个来请求的 sentinel
+                    master.leader = Some(run_id.clone());
+                    master.leader_epoch = epoch;
+                    *current_epoch = (*current_epoch).max(epoch);
+                    //投给了别人就先不要自己发起故障转移，等它完成或者超时
+                    if *run_id != self.shared.run_id {
+                        master.failover_started = Some(Instant::now());
+                    }
+                }
+                let leader = match (&master.leader, run_id == "*") {
+                    (Some(leader), false) => bulk(leader),
+                    _ => bulk("*"),
+                };
+                Frame::Array(vec![
+                    Frame::Integer(master.s_down as i64),
+                    leader,
+                    Frame::Integer(master.leader_epoch as i64),
+                ])
+            }
+            // hello <master-name> <master-ip> <master-port> <config-epoch>
+            ("hello", [name, host, port, config_epoch]) => {
+                let (port, config_epoch) = match (port.parse::<u16>(), config_epoch.parse::<u64>())
+                {
+                    (Ok(port), Ok(config_epoch)) => (port, config_epoch),
+                    _ => return Frame::Error("ERR invalid hello message".to_string()),
+                };
+                state.current_epoch = state.current_epoch.max(config_epoch);
+                let switched = match state.masters.get_mut(name) {
+                    Some(master) if config_epoch > master.config_epoch => {
+                        let old = master.switch_to((host.clone(), port), config_epoch);
+                        Some(old)
+                    }
+                    _ => None,
+                };
+                drop(state);
+                if let Some(old) = switched {
+                    self.event("+config-update-from", &format!("sentinel @ {}", name));
+                    self.event(
+                        "+switch-master",
+                        &format!("{} {} {} {} {}", name, old.0, old.1, host, port),
+                    );
+                }
+                cmd::ok()
+            }
+            _ => Frame::Error(format!(
+                "ERR unknown sentinel subcommand or wrong number of arguments for '{}'",
+                args[1]
+            )),
+        }
+    }
+
+    // SUBSCRIBE channel...，之后这个连接只接收事件
+    async fn subscribe(&self, mut connection: Connection, args: &[Bytes]) -> error::Result<()> {
+        let mut events = self.shared.events.subscribe();
+        let mut channels = HashSet::new();
+        let mut args = args.to_vec();
+
+        loop {
+            let name = args[0].to_ascii_uppercase();
+            match &name[..] {
+                b"SUBSCRIBE" | b"UNSUBSCRIBE" => {
+                    let subscribe = &name[..] == b"SUBSCRIBE";
+                    for channel in &args[1..] {
+                        let channel = String::from_utf8_lossy(channel).into_owned();
+                        if subscribe {
+                            channels.insert(channel.clone());
+                        } else {
+                            channels.remove(&channel);
+                        }
+                        let kind = if subscribe {
+                            "subscribe"
+                        } else {
+                            "unsubscribe"
+                        };
+                        connection
+                            .write_frame(&Frame::Array(vec![
+                                bulk(kind),
+                                bulk(&channel),
+                                Frame::Integer(channels.len() as i64),
+                            ]))
+                            .await?;
+                    }
+                }
+                b"PING" => {
+                    connection
+                        .write_frame(&Frame::Array(vec![bulk("pong"), bulk("")]))
+                        .await?
+                }
+                _ => {
+                    connection
+                        .write_frame(&Frame::Error(
+                            "ERR only (UN)SUBSCRIBE / PING are allowed in this context".to_string(),
+                        ))
+                        .await?
+                }
+            }
+
+            args = loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Ok((channel, message)) if channels.contains(&channel) => {
+                            connection
+                                .write_frame(&Frame::Array(vec![
+                                    bulk("message"),
+                                    bulk(&channel),
+                                    bulk(&message),
+                                ]))
+                                .await?;
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    },
+                    frame = connection.read_frame() => match frame? {
+                        Some(frame) => match cmd::into_args(frame) {
+                            Ok(args) => break args,
+                            Err(err) => connection.write_frame(&err).await?,
+                        },
+                        None => return Ok(()),
+                    },
+                }
+            };
+        }
+    }
+}
+
+//选复制 offset 最大的 replica，相同的话选地址小的，保证结果是确定的
+async fn select_replica(replicas: &[Addr], links: &mut Links, timeout: Duration) -> Option<Addr> {
+    let mut best: Option<(u64, Addr)> = None;
+    for replica in replicas {
+        let info = match links.call(replica, &["INFO", "replication"], timeout).await {
+            Some(Frame::Bulk(info)) => String::from_utf8_lossy(&info).into_owned(),
+            _ => continue,
+        };
+        if !info.contains("role:slave\r\n") {
+            continue;
+        }
+        let offset = info
+            .lines()
+            .find_map(|line| line.strip_prefix("slave_repl_offset:"))
+            .and_then(|offset| offset.parse().ok())
+            .unwrap_or(0);
+        let better = match &best {
+            Some((best_offset, best_addr)) => {
+                offset > *best_offset || (offset == *best_offset && replica < best_addr)
+            }
+            None => true,
+        };
+        if better {
+            best = Some((offset, replica.clone()));
+        }
+    }
+    best.map(|(_, addr)| addr)
+}
+
+fn master_fields(master: &Master) -> Frame {
+    let mut flags = vec!["master"];
+    if master.s_down {
+        flags.push("s_down");
+    }
+    if master.o_down {
+        flags.push("o_down");
+    }
+    let fields = [
+        ("name", master.config.name.clone()),
+        ("ip", master.addr.0.clone()),
+        ("port", master.addr.1.to_string()),
+        ("flags", flags.join(",")),
+        ("num-slaves", master.replicas.len().to_string()),
+        (
+            "num-other-sentinels",
+            master.config.sentinels.len().to_string(),
+        ),
+        ("quorum", master.config.quorum.to_string()),
+        ("config-epoch", master.config_epoch.to_string()),
+        (
+            "down-after-milliseconds",
+            master.config.down_after.as_millis().to_string(),
+        ),
+        (
+            "failover-timeout",
+            master.config.failover_timeout.as_millis().to_string(),
+        ),
+    ];
+    Frame::Array(
+        fields
+            .iter()
+            .flat_map(|(name, value)| [bulk(name), bulk(value)])
+            .collect(),
+    )
+}
+
+fn bulk(value: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(value.as_bytes()))
+}
+
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u128;
+    Duration::from_nanos((nanos % max.as_nanos().max(1)) as u64)
+}
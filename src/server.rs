@@ -0,0 +1,1450 @@
+//! 服务端：accept 循环以及每个连接的命令分发。
+//!
+//! 原来这些代码都在 `src/bin/server.rs` 里，随着命令变多搬到了库里，
+//! bin 只负责读取配置、绑定端口然后调用 `serve_with_shutdown`。
+
+use crate::{
+    acl::{
+        self,
+        Acl,
+        User,
+    },
+    aof::Aof,
+    clients::{
+        ClientHandle,
+        ClientLimit,
+        ClientPermit,
+        Clients,
+    },
+    cluster::{
+        self,
+        Cluster,
+    },
+    cmd,
+    connection::BoxedStream,
+    config::{
+        self,
+        Config,
+        LogLevel,
+    },
+    db::Db,
+    error,
+    frame::Frame,
+    pubsub::{
+        PubSub,
+        Subscriber,
+    },
+    rdb::Rdb,
+    replication::Replication,
+    script::{
+        self,
+        Caller,
+        Scripting,
+    },
+    shutdown::{
+        Shutdown,
+        ShutdownMode,
+    },
+    stats::{
+        self,
+        Metered,
+        Stats,
+    },
+    Connection,
+};
+use bytes::Bytes;
+use socket2::{
+    SockRef,
+    TcpKeepalive,
+};
+use std::{
+    future::{
+        self,
+        Future,
+    },
+    fs,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+#[cfg(feature = "metrics")]
+use crate::metrics;
+#[cfg(feature = "tls")]
+use crate::tls;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use tokio::net::{
+    UnixListener,
+    UnixStream,
+};
+use tokio::{
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::{
+        broadcast,
+        mpsc,
+    },
+    time::{
+        self,
+        Duration,
+    },
+};
+
+//客户端连上 TLS 端口之后要在这段时间内完成握手
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Per-connection handler.
+struct Handler {
+    db: Db,
+    scripts: Scripting,
+    aof: Option<Aof>,
+    rdb: Rdb,
+    replication: Replication,
+    //replica 通过 REPLCONF listening-port 告诉我们它对外服务的端口，在 INFO 里展示
+    listening_port: u16,
+    cluster: Option<Cluster>,
+    //上一条命令是 ASKING，只对下一条命令有效
+    asking: bool,
+    //MULTI 之后排队的命令，EXEC 时一起执行
+    multi: Option<Multi>,
+    //CONFIG SET 修改的是所有连接共享的这一份
+    config: Arc<Mutex<Config>>,
+    connection: Connection<BoxedStream>,
+    //通过 Unix socket 连接的客户端没有地址
+    peer_addr: Option<SocketAddr>,
+    //收到退出通知后不再读取新的命令
+    shutdown: Shutdown,
+    //SHUTDOWN 命令通过它通知 accept 循环
+    shutdown_request: mpsc::Sender<ShutdownMode>,
+    //连接任务结束时随 Handler 一起丢弃，所有的都丢弃后 serve 才继续退出流程
+    _shutdown_complete: mpsc::Sender<()>,
+    //CONFIG SET maxclients 通过它调整上限
+    client_limit: ClientLimit,
+    _client_permit: ClientPermit,
+    //CLIENT 命令看到的所有连接，以及这个连接自己的登记信息
+    clients: Clients,
+    client: ClientHandle,
+    stats: Stats,
+    acl: Acl,
+    //当前登录的用户，None 表示还没有通过 AUTH
+    user: Option<String>,
+    pubsub: PubSub,
+    //订阅了至少一个频道时进入订阅状态，只能执行 (UN)SUBSCRIBE 和 PING
+    subscriber: Option<Subscriber>,
+}
+
+#[derive(Default)]
+struct Multi {
+    queued: Vec<Vec<Bytes>>,
+    //排队时有命令出错，EXEC 直接放弃整个事务
+    aborted: bool,
+}
+
+/// A bound socket the server accepts clients on.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    /// Clients on this listener have to complete a TLS handshake first.
+    #[cfg(feature = "tls")]
+    Tls(TcpListener, tls::TlsAcceptor),
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Listener {
+        Listener::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Listener {
+        Listener::Unix(listener)
+    }
+}
+
+//accept 任务交给主循环的连接，TCP 连接要先按配置设置 socket 选项
+enum Incoming {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<TcpStream>>, SocketAddr),
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Incoming> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok(Incoming::Tcp(stream, addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Incoming::Unix(stream))
+            }
+            //握手由 accept 任务另外启动的任务完成
+            #[cfg(feature = "tls")]
+            Listener::Tls(listener, _) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok(Incoming::Tcp(stream, addr))
+            }
+        }
+    }
+
+    fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            //replica 和其他节点用明文连接
+            _ => None,
+        }
+    }
+
+    //退出时删掉 socket 文件
+    fn socket_path(&self) -> Option<PathBuf> {
+        match self {
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(PathBuf::from)),
+            _ => None,
+        }
+    }
+}
+
+/// Bind a listener on `port` for every address in `bind`, unless `port` is
+/// 0, one on `tls-port` for every address if that is set, and one on
+/// `unixsocket` if it is set.
+pub async fn bind(config: &Config) -> io::Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(config.bind.len() * 2 + 1);
+    if config.port != 0 {
+        for addr in &config.bind {
+            listeners.push(TcpListener::bind((&addr[..], config.port)).await?.into());
+        }
+    }
+    if config.tls_port != 0 {
+        listeners.extend(bind_tls(config).await?);
+    }
+    if let Some(path) = &config.unixsocket {
+        listeners.push(bind_unix(path, config.unixsocketperm)?);
+    }
+    Ok(listeners)
+}
+
+#[cfg(feature = "tls")]
+async fn bind_tls(config: &Config) -> io::Result<Vec<Listener>> {
+    //证书有问题时启动失败，而不是等到第一个客户端连上来
+    let acceptor = tls::acceptor(config)?;
+    let mut listeners = Vec::with_capacity(config.bind.len());
+    for addr in &config.bind {
+        let listener = TcpListener::bind((&addr[..], config.tls_port)).await?;
+        listeners.push(Listener::Tls(listener, acceptor.clone()));
+    }
+    Ok(listeners)
+}
+
+#[cfg(not(feature = "tls"))]
+async fn bind_tls(_: &Config) -> io::Result<Vec<Listener>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tls-port is set but TLS support was not compiled in (enable the tls feature)",
+    ))
+}
+
+#[cfg(feature = "metrics")]
+async fn bind_metrics(config: &Config) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    if config.metrics_port != 0 {
+        for addr in &config.bind {
+            listeners.push(TcpListener::bind((&addr[..], config.metrics_port)).await?);
+        }
+    }
+    Ok(listeners)
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, perm: u32) -> io::Result<Listener> {
+    //上次没有正常退出时留下的 socket 文件会让 bind 失败，和 Redis 一样先删掉
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    if perm != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(perm))?;
+    }
+    Ok(listener.into())
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_: &std::path::Path, _: u32) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+/// Run the server on an already bound listener, until a client sends
+/// SHUTDOWN.
+///
+/// Returns an error if persisted data could not be loaded.
+pub async fn run(listener: TcpListener, config: Config) -> mini_redis::Result<()> {
+    serve(vec![listener.into()], config).await
+}
+
+/// Run the server on already bound listeners, until a client sends
+/// SHUTDOWN. The first TCP listener's port is the one announced to replicas
+/// and cluster nodes.
+pub async fn serve(listeners: Vec<Listener>, config: Config) -> mini_redis::Result<()> {
+    serve_with_shutdown(listeners, config, future::pending::<()>()).await
+}
+
+/// Like [`serve`], also shutting down when `signal` completes, e.g. on
+/// SIGTERM.
+///
+/// Shutting down stops accepting connections, lets every connection finish
+/// the command it is running (waiting at most `shutdown-timeout`), then
+/// saves the snapshot if `save` rules are configured or SHUTDOWN SAVE was
+/// sent. Returns an error if that final save fails.
+pub async fn serve_with_shutdown(
+    listeners: Vec<Listener>,
+    config: Config,
+    signal: impl Future,
+) -> mini_redis::Result<()> {
+    if listeners.is_empty() {
+        return Err("no address to listen on".into());
+    }
+    //只监听 Unix socket 时没有端口可以告诉 replica 和其他节点
+    let tcp_addr = listeners.iter().find_map(Listener::tcp_addr);
+    let socket_paths: Vec<PathBuf> = listeners.iter().filter_map(Listener::socket_path).collect();
+    //metrics 端口在加载数据之前绑定，端口被占用时尽早失败
+    #[cfg(feature = "metrics")]
+    let metrics_listeners = bind_metrics(&config).await?;
+    #[cfg(not(feature = "metrics"))]
+    if config.metrics_port != 0 {
+        return Err(
+            "metrics-port is set but metrics support was not compiled in \
+                    (enable the metrics feature)"
+                .into(),
+        );
+    }
+    let db = Db::new();
+    let scripts = Scripting::new(script::DEFAULT_TIME_LIMIT);
+
+    //和 Redis 一样，开启了 AOF 时只从 AOF 加载数据，否则加载 RDB 快照
+    let rdb = Rdb::new(config.rdb_path());
+    let aof = if config.appendonly {
+        Some(Aof::open(
+            &db,
+            config.aof_path(),
+            config.appendfsync,
+            config.aof_load_truncated,
+        )?)
+    } else {
+        let loaded = rdb.load(&db)?;
+        println!("DB loaded from disk: {} keys", loaded);
+        None
+    };
+    rdb.spawn_save_rules(&db, config.save.clone());
+
+    let replication = Replication::new(
+        &db,
+        config.repl_backlog_size,
+        config.replica_read_only,
+        tcp_addr.map_or(0, |addr| addr.port()),
+    );
+    replication.set_master_auth(&config.masteruser, &config.masterauth);
+    replication.set_output_buffer_limit(config.client_output_buffer_limit);
+    if let Some((host, port)) = config.replicaof.clone() {
+        replication.replicate_from(&db, aof.clone(), host, port);
+    }
+
+    let cluster = if config.cluster_enabled {
+        let local = tcp_addr.ok_or("cluster mode needs a TCP port")?;
+        let bus_port = match config.cluster_port {
+            0 => local.port() + cluster::BUS_PORT_OFFSET,
+            port => port,
+        };
+        let bus = TcpListener::bind((local.ip(), bus_port)).await?;
+        let cluster = Cluster::new(
+            config.cluster_announce_ip.clone(),
+            local.port(),
+            bus_port,
+            config.cluster_node_timeout,
+        );
+        cluster.start(bus);
+        Some(cluster)
+    } else {
+        None
+    };
+
+    let acl = Acl::new();
+    if let Some(path) = &config.aclfile {
+        acl.load(path)?;
+    }
+    if !config.requirepass.is_empty() {
+        acl.require_pass(&config.requirepass);
+    }
+
+    let client_limit = ClientLimit::new(config.maxclients);
+    let clients = Clients::new();
+    let stats = Stats::new(tcp_addr.map_or(0, |addr| addr.port()));
+    let pubsub = PubSub::new();
+    let config = Arc::new(Mutex::new(config));
+    let (notify_shutdown, _) = broadcast::channel(1);
+    let (shutdown_complete, mut all_closed) = mpsc::channel::<()>(1);
+    let (shutdown_request, mut requested) = mpsc::channel(1);
+
+    //每个 listener 一个 accept 任务，接受的连接都交给下面的循环
+    let (accepted, mut incoming) = mpsc::channel(16);
+    for listener in listeners {
+        let shutdown = Shutdown::new(notify_shutdown.subscribe());
+        tokio::spawn(accept(listener, accepted.clone(), shutdown));
+    }
+    drop(accepted);
+
+    #[cfg(feature = "metrics")]
+    {
+        let sources = metrics::Sources {
+            stats: stats.clone(),
+            clients: clients.clone(),
+            db: db.clone(),
+            rdb: rdb.clone(),
+            aof: aof.clone(),
+            keyspace: Default::default(),
+        };
+        for listener in metrics_listeners {
+            let shutdown = Shutdown::new(notify_shutdown.subscribe());
+            tokio::spawn(metrics::serve(listener, sources.clone(), shutdown));
+        }
+    }
+
+    tokio::pin!(signal);
+    let mode = loop {
+        let accepted = tokio::select! {
+            Some(accepted) = incoming.recv() => accepted,
+            Some(mode) = requested.recv() => break mode,
+            _ = &mut signal => break ShutdownMode::Default,
+        };
+        stats.connection_received();
+        //CLIENT LIST 里的 addr 和 laddr；和 Redis 一样，Unix socket 显示为 `path:0`
+        let (stream, peer_addr, laddr): (BoxedStream, _, _) = {
+            let config = config.lock().unwrap();
+            if config.loglevel <= LogLevel::Verbose {
+                println!("Accepted");
+            }
+            match accepted {
+                Incoming::Tcp(stream, addr) => {
+                    configure_socket(&stream, &config);
+                    let laddr = local_addr(&stream);
+                    (Box::new(stream), Some(addr), laddr)
+                }
+                #[cfg(unix)]
+                Incoming::Unix(stream) => {
+                    let laddr = stream
+                        .local_addr()
+                        .ok()
+                        .and_then(|addr| Some(format!("{}:0", addr.as_pathname()?.display())))
+                        .unwrap_or_default();
+                    (Box::new(stream), None, laddr)
+                }
+                #[cfg(feature = "tls")]
+                Incoming::Tls(stream, addr) => {
+                    configure_socket(stream.get_ref().0, &config);
+                    let laddr = local_addr(stream.get_ref().0);
+                    (stream, Some(addr), laddr)
+                }
+            }
+        };
+        let addr = peer_addr.map_or_else(|| laddr.clone(), |addr| addr.to_string());
+        let stream: BoxedStream = Box::new(Metered::new(stream, stats.clone()));
+        let client_permit = match client_limit.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                stats.connection_rejected();
+                tokio::spawn(reject(stream));
+                continue;
+            }
+        };
+
+        let handler = Handler {
+            db: db.clone(),
+            scripts: scripts.clone(),
+            aof: aof.clone(),
+            rdb: rdb.clone(),
+            replication: replication.clone(),
+            listening_port: 0,
+            cluster: cluster.clone(),
+            asking: false,
+            multi: None,
+            config: config.clone(),
+            connection: Connection::new(stream),
+            peer_addr,
+            shutdown: Shutdown::new(notify_shutdown.subscribe()),
+            shutdown_request: shutdown_request.clone(),
+            _shutdown_complete: shutdown_complete.clone(),
+            client_limit: client_limit.clone(),
+            _client_permit: client_permit,
+            clients: clients.clone(),
+            client: clients.register(addr, laddr),
+            stats: stats.clone(),
+            acl: acl.clone(),
+            user: acl.initial_user(),
+            pubsub: pubsub.clone(),
+            subscriber: None,
+        };
+        //引入多线程
+        tokio::spawn(async move {
+            if let Err(err) = handler.process().await {
+                println!("connection error: {}", err);
+            }
+        });
+    };
+
+    println!("Shutting down");
+    //通知所有连接和 accept 任务，然后等连接执行完手上的命令
+    drop(notify_shutdown);
+    drop(shutdown_complete);
+    let drain = config.lock().unwrap().shutdown_timeout;
+    if time::timeout(drain, all_closed.recv()).await.is_err() {
+        println!("Timed out waiting for clients to disconnect");
+    }
+
+    for path in socket_paths {
+        let _ = fs::remove_file(path);
+    }
+    if let Some(aof) = &aof {
+        aof.sync().await;
+    }
+    let save = match mode {
+        ShutdownMode::Save => true,
+        ShutdownMode::NoSave => false,
+        ShutdownMode::Default => !rdb.save_rules().is_empty(),
+    };
+    if save {
+        println!("Saving the final RDB snapshot before exiting");
+        if let Frame::Error(err) = rdb.save(&db) {
+            return Err(format!("error trying to save the DB: {}", err).into());
+        }
+    }
+    Ok(())
+}
+
+//accept 失败（比如文件描述符用完了，EMFILE）时不退出，等一会再试，
+//等待时间从 10ms 开始翻倍，最多 1 秒
+async fn accept(listener: Listener, accepted: mpsc::Sender<Incoming>, mut shutdown: Shutdown) {
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        let res = tokio::select! {
+            res = listener.accept() => res,
+            _ = shutdown.recv() => return,
+        };
+        let incoming = match res {
+            Ok(incoming) => incoming,
+            Err(err) => {
+                println!("accept error: {}, retrying in {:?}", err, backoff);
+                tokio::select! {
+                    _ = time::sleep(backoff) => {}
+                    _ = shutdown.recv() => return,
+                }
+                backoff = (backoff * 2).min(Duration::from_secs(1));
+                continue;
+            }
+        };
+        backoff = Duration::from_millis(10);
+        match (&listener, incoming) {
+            //握手放到单独的任务里，握手慢的客户端不会挡住后面的连接
+            #[cfg(feature = "tls")]
+            (Listener::Tls(_, acceptor), Incoming::Tcp(stream, addr)) => {
+                tokio::spawn(handshake(acceptor.clone(), stream, addr, accepted.clone()));
+            }
+            (_, incoming) => {
+                if accepted.send(incoming).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn handshake(
+    acceptor: tls::TlsAcceptor,
+    stream: TcpStream,
+    addr: SocketAddr,
+    accepted: mpsc::Sender<Incoming>,
+) {
+    match time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => {
+            let _ = accepted.send(Incoming::Tls(Box::new(stream), addr)).await;
+        }
+        Ok(Err(err)) => println!("TLS handshake with {} failed: {}", addr, err),
+        Err(_) => println!("TLS handshake with {} timed out", addr),
+    }
+}
+
+fn local_addr(stream: &TcpStream) -> String {
+    stream
+        .local_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default()
+}
+
+fn configure_socket(stream: &TcpStream, config: &Config) {
+    //和 Redis 一样默认关掉 Nagle：pipeline 的回复是一条条写出的，否则后面的回复要等对端的延迟 ACK
+    let _ = stream.set_nodelay(config.tcp_nodelay);
+    //keepalive 探测发现对端已经不在（比如机器断电）时，读操作会出错，连接随之关闭
+    if !config.tcp_keepalive.is_zero() {
+        let keepalive = TcpKeepalive::new()
+            .with_time(config.tcp_keepalive)
+            .with_interval(config.tcp_keepalive / 3);
+        let _ = SockRef::from(stream).set_tcp_keepalive(&keepalive);
+    }
+}
+
+//超过 maxclients 的连接：和 Redis 一样回复一个错误后关闭
+async fn reject(stream: BoxedStream) {
+    let mut connection = Connection::new(stream);
+    let err = Frame::Error("ERR max number of clients reached".to_string());
+    let _ = connection.write_frame(&err).await;
+}
+
+impl Handler {
+    async fn process(mut self) -> error::Result<()> {
+        //CLIENT KILL 断开自己时先回复，再在这里退出
+        while !self.shutdown.is_shutdown() && !self.client.is_killed() {
+            //只在等待下一条命令时计算空闲时间，WAIT、EVAL 这样执行很久的命令不受影响；
+            //订阅状态的连接只等着接收消息，复制连接不走这里，都不会因为空闲被关闭
+            let idle = match self.subscriber {
+                Some(_) => Duration::ZERO,
+                None => self.config.lock().unwrap().timeout,
+            };
+            //read_frame 可以安全地取消，退出时不会丢掉读了一半的命令之外的东西
+            let frame = tokio::select! {
+                res = self.connection.read_frame() => res?,
+                message = next_message(&mut self.subscriber) => match message {
+                    Some(message) => {
+                        self.connection.write_frame(&message).await?;
+                        continue;
+                    }
+                    None => {
+                        println!("Closing subscriber that fell behind");
+                        return Ok(());
+                    }
+                },
+                _ = self.shutdown.recv() => return Ok(()),
+                _ = self.client.killed() => return Ok(()),
+                _ = idle_timeout(idle) => {
+                    if self.config.lock().unwrap().loglevel <= LogLevel::Verbose {
+                        println!("Closing idle client");
+                    }
+                    return Ok(());
+                }
+            };
+            let frame = match frame {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+            if self.config.lock().unwrap().loglevel == LogLevel::Debug {
+                println!("Got: {:?}", frame);
+            }
+            let response = match cmd::into_args(frame) {
+                Ok(args) => match self.begin(&args) {
+                    Err(err) => self.abort_multi(err),
+                    //每个频道回复一次，不是一条回复
+                    Ok(()) if is_subscribe(&args) && self.multi.is_none() => {
+                        self.wait_unpaused(&args).await;
+                        for reply in self.subscribe(&args) {
+                            self.connection.write_frame(&reply).await?;
+                        }
+                        self.finish();
+                        continue;
+                    }
+                    Ok(()) if self.subscriber.is_some() => subscribed_reply(&args),
+                    Ok(()) if is_sync(&args) => {
+                        //这个连接从此变成复制连接，不再按普通客户端处理
+                        return self.serve_replica(args).await;
+                    }
+                    Ok(()) if is_shutdown(&args) && self.multi.is_none() => {
+                        match self.request_shutdown(&args).await {
+                            //和 Redis 一样，成功时不回复，直接关闭连接
+                            Ok(()) => return Ok(()),
+                            Err(err) => err,
+                        }
+                    }
+                    Ok(()) => {
+                        self.wait_unpaused(&args).await;
+                        self.apply(args).await
+                    }
+                },
+                Err(err) => err,
+            };
+            //appendfsync always: 写命令落盘之后才回复客户端，没有写入的命令直接回复
+            if let Some(aof) = &self.aof {
+                aof.sync_if_always().await;
+            }
+            self.connection.write_frame(&response).await?;
+            self.finish();
+        }
+        Ok(())
+    }
+
+    //登记收到的命令，然后检查权限
+    fn begin(&mut self, args: &[Bytes]) -> Result<(), Frame> {
+        let (qbuf, qbuf_free) = self.connection.read_buffer();
+        {
+            let mut info = self.client.info();
+            info.last_command = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+            info.last_interaction = time::Instant::now();
+            info.qbuf = qbuf;
+            info.qbuf_free = qbuf_free;
+        }
+        self.authorize(args)
+    }
+
+    //回复写出之后更新 CLIENT LIST 里的用户和事务状态
+    fn finish(&mut self) {
+        let mut info = self.client.info();
+        info.last_interaction = time::Instant::now();
+        info.user = self.user.clone().unwrap_or_default();
+        info.multi = self.multi.as_ref().map(|multi| multi.queued.len());
+    }
+
+    //CLIENT PAUSE 期间等待：事务里排队的命令不执行，不用等，到 EXEC 时按整个事务判断；
+    //CLIENT 命令本身不受影响，否则没法 CLIENT UNPAUSE
+    async fn wait_unpaused(&self, args: &[Bytes]) {
+        let spec = match cmd::lookup(&args[0]) {
+            Some(spec) => spec,
+            None => return,
+        };
+        let writes = match (spec.name, &self.multi) {
+            ("CLIENT", _) => return,
+            ("EXEC", Some(multi)) => multi
+                .queued
+                .iter()
+                .any(|args| cmd::lookup(&args[0]).is_some_and(|spec| spec.write)),
+            (_, Some(_)) => return,
+            //脚本可能写入；和 Redis 一样，PUBLISH 也当作写命令暂停
+            ("EVAL" | "EVALSHA" | "PUBLISH", None) => true,
+            (_, None) => spec.write,
+        };
+        self.clients.wait_unpaused(writes).await;
+    }
+
+    async fn apply(&mut self, args: Vec<Bytes>) -> Frame {
+        let spec = match cmd::lookup(&args[0]) {
+            Some(spec) => spec,
+            None => return self.abort_multi(cmd::unknown_command(&args)),
+        };
+
+        if !spec.check_arity(args.len()) {
+            self.stats.reject(spec.name);
+            return self.abort_multi(cmd::wrong_arity(spec));
+        }
+
+        if spec.name != "ASKING" {
+            let asking = std::mem::take(&mut self.asking);
+            if let Some(cluster) = &self.cluster {
+                let keys = cmd::key_args(spec, &args);
+                if let Some(redirect) = cluster.check(&self.db, &self.scripts, &keys, asking).await
+                {
+                    return self.abort_multi(redirect);
+                }
+            }
+        }
+
+        if let Some(multi) = &mut self.multi {
+            match spec.name {
+                "MULTI" => return Frame::Error("ERR MULTI calls can not be nested".to_string()),
+                "EXEC" | "DISCARD" => {}
+                //事务里只能执行 keyspace 命令
+                _ if spec.noscript => {
+                    return self.abort_multi(Frame::Error(format!(
+                        "ERR '{}' command is not allowed inside a transaction",
+                        spec.name.to_ascii_lowercase()
+                    )));
+                }
+                _ => {
+                    multi.queued.push(args);
+                    return Frame::Simple("QUEUED".to_string());
+                }
+            }
+        }
+
+        let start = time::Instant::now();
+        let response = self.execute(spec, args).await;
+        self.stats.record(spec.name, start.elapsed(), &response);
+        response
+    }
+
+    async fn execute(&mut self, spec: &'static cmd::Spec, args: Vec<Bytes>) -> Frame {
+        match spec.name {
+            "MULTI" => {
+                self.multi = Some(Multi::default());
+                cmd::ok()
+            }
+            "EXEC" => self.exec().await,
+            "DISCARD" => match self.multi.take() {
+                Some(_) => cmd::ok(),
+                None => Frame::Error("ERR DISCARD without MULTI".to_string()),
+            },
+            "EVAL" => {
+                let caller = self.script_caller();
+                self.scripts.eval(&self.db, &args, caller).await
+            }
+            "EVALSHA" => {
+                let caller = self.script_caller();
+                self.scripts.evalsha(&self.db, &args, caller).await
+            }
+            "AUTH" => self.auth(&args),
+            "ACL" => self.acl_command(&args),
+            "CLIENT" => self.clients.command(&self.client, &args),
+            "PUBLISH" => Frame::Integer(self.pubsub.publish(&args[1], args[2].clone()) as i64),
+            "SCRIPT" => self.script(&args),
+            "BGREWRITEAOF" => match &self.aof {
+                Some(aof) => {
+                    //复制快照要持有keyspace的锁，和普通命令一样先等正在执行的脚本
+                    if let Err(busy) = self.scripts.wait_idle().await {
+                        return busy;
+                    }
+                    aof.rewrite(&self.db)
+                }
+                None => Frame::Error("ERR Append only file is disabled".to_string()),
+            },
+            "SAVE" | "BGSAVE" => {
+                //保存快照要持有keyspace的锁，和普通命令一样先等正在执行的脚本
+                if let Err(busy) = self.scripts.wait_idle().await {
+                    return busy;
+                }
+                match spec.name {
+                    "SAVE" => self.rdb.save(&self.db),
+                    _ => self.rdb.bgsave(&self.db),
+                }
+            }
+            "LASTSAVE" => Frame::Integer(self.rdb.lastsave() as i64),
+            "REPLCONF" => self.replconf(&args),
+            "WAIT" => self.wait(&args).await,
+            "REPLICAOF" | "SLAVEOF" => self.replicaof(&args),
+            "ROLE" => self.replication.role(),
+            "INFO" => self.info(&args).await,
+            "CLUSTER" | "ASKING" => match &self.cluster {
+                Some(cluster) if spec.name == "CLUSTER" => {
+                    cluster.command(&self.db, &self.scripts, &args).await
+                }
+                Some(_) => {
+                    self.asking = true;
+                    cmd::ok()
+                }
+                None => Frame::Error("ERR This instance has cluster support disabled".to_string()),
+            },
+            "MIGRATE" => {
+                cluster::migrate(&self.db, &self.scripts, &args, self.cluster.is_some()).await
+            }
+            "CONFIG" => self.config_command(&args),
+            _ => {
+                if spec.write && self.replication.rejects_writes() {
+                    return Frame::Error(
+                        "READONLY You can't write against a read only replica.".to_string(),
+                    );
+                }
+                //脚本执行期间持有keyspace的锁，先在这里异步等待，避免阻塞 runtime 的工作线程
+                if let Err(busy) = self.scripts.wait_idle().await {
+                    return busy;
+                }
+                let response = cmd::execute_and_propagate(&mut self.db.lock(), &args);
+                if spec.write {
+                    self.db.notify_expiration();
+                }
+                response
+            }
+        }
+    }
+
+    //未知命令和参数个数不对的命令交给 apply 报错；AUTH 不需要登录，也不受 ACL 限制。
+    //每条命令都重新取一次用户，ACL SETUSER 修改的权限对已经登录的连接立即生效
+    fn authorize(&mut self, args: &[Bytes]) -> Result<(), Frame> {
+        let spec = match cmd::lookup(&args[0]) {
+            Some(spec) if spec.name != "AUTH" && spec.check_arity(args.len()) => spec,
+            _ => return Ok(()),
+        };
+        let res = match self.current_user() {
+            Some(user) => user.check(spec, args),
+            None => Err(Frame::Error("NOAUTH Authentication required.".to_string())),
+        };
+        if res.is_err() {
+            self.stats.reject(spec.name);
+        }
+        res
+    }
+
+    //登录的用户被 ACL DELUSER 删掉之后，连接要重新 AUTH
+    fn current_user(&mut self) -> Option<User> {
+        let user = self.acl.user(self.user.as_deref()?);
+        if user.is_none() {
+            self.user = None;
+        }
+        user
+    }
+
+    //脚本里的命令和普通命令受同样的限制：ACL 权限，以及只读 replica 不能写
+    fn script_caller(&mut self) -> Caller {
+        Caller {
+            user: self.current_user().unwrap_or_default(),
+            read_only: self.replication.rejects_writes(),
+        }
+    }
+
+    // AUTH [username] password
+    fn auth(&mut self, args: &[Bytes]) -> Frame {
+        let (name, password) = match args {
+            [_, password] => {
+                let nopass = self.acl.initial_user().is_some();
+                if nopass {
+                    return Frame::Error(
+                        "ERR AUTH <password> called without any password configured for the \
+                         default user. Are you sure your configuration is correct?"
+                            .to_string(),
+                    );
+                }
+                (acl::DEFAULT_USER.to_string(), password)
+            }
+            [_, name, password] => (String::from_utf8_lossy(name).into_owned(), password),
+            _ => return Frame::Error("ERR syntax error".to_string()),
+        };
+        if self.acl.authenticate(&name, password) {
+            self.user = Some(name);
+            cmd::ok()
+        } else {
+            Frame::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            )
+        }
+    }
+
+    // ACL SETUSER | GETUSER | DELUSER | LIST | USERS | WHOAMI | CAT | LOAD | SAVE
+    fn acl_command(&mut self, args: &[Bytes]) -> Frame {
+        let sub = args[1].to_ascii_uppercase();
+        let strings = |args: &[Bytes]| -> Vec<String> {
+            args.iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        };
+        let bulks = |items: Vec<String>| {
+            Frame::Array(
+                items
+                    .into_iter()
+                    .map(|item| Frame::Bulk(Bytes::from(item)))
+                    .collect(),
+            )
+        };
+        match (&sub[..], args.len()) {
+            (b"SETUSER", n) if n >= 3 => {
+                let name = String::from_utf8_lossy(&args[2]);
+                match self.acl.set_user(&name, &strings(&args[3..])) {
+                    Ok(()) => cmd::ok(),
+                    Err(err) => Frame::Error(format!("ERR {}", err)),
+                }
+            }
+            (b"GETUSER", 3) => match self.acl.user(&String::from_utf8_lossy(&args[2])) {
+                Some(user) => user.to_frame(),
+                None => Frame::Null,
+            },
+            (b"DELUSER", n) if n >= 3 => match self.acl.del_users(&strings(&args[2..])) {
+                Ok(deleted) => Frame::Integer(deleted as i64),
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            (b"LIST", 2) => bulks(self.acl.list()),
+            (b"USERS", 2) => bulks(self.acl.usernames()),
+            (b"WHOAMI", 2) => match &self.user {
+                Some(name) => Frame::Bulk(Bytes::from(name.clone())),
+                None => Frame::Null,
+            },
+            (b"CAT", 2) => bulks(acl::CATEGORIES.iter().map(|c| c.to_string()).collect()),
+            (b"CAT", 3) => {
+                let category = String::from_utf8_lossy(&args[2]).to_ascii_lowercase();
+                if !acl::CATEGORIES.contains(&&category[..]) {
+                    return Frame::Error(format!("ERR Unknown category '{}'", category));
+                }
+                bulks(
+                    cmd::COMMANDS
+                        .iter()
+                        .filter(|spec| acl::in_category(spec, &category))
+                        .map(|spec| spec.name.to_ascii_lowercase())
+                        .collect(),
+                )
+            }
+            (b"LOAD", 2) | (b"SAVE", 2) => {
+                let path =
+                    match self.config.lock().unwrap().aclfile.clone() {
+                        Some(path) => path,
+                        None => return Frame::Error(
+                            "ERR This Redis instance is not configured to use an ACL file. You \
+                             may want to specify users via the ACL SETUSER command and then \
+                             issue a CONFIG REWRITE (assuming you have a Redis configuration \
+                             file set) in order to store users in the Redis configuration."
+                                .to_string(),
+                        ),
+                    };
+                let res = if &sub[..] == b"LOAD" {
+                    self.acl.load(&path)
+                } else {
+                    self.acl.save(&path).map_err(|err| err.to_string())
+                };
+                match res {
+                    Ok(()) => cmd::ok(),
+                    Err(err) => Frame::Error(format!("ERR {}", err)),
+                }
+            }
+            _ => Frame::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                String::from_utf8_lossy(&args[1])
+            )),
+        }
+    }
+
+    // SUBSCRIBE channel [channel ...] | UNSUBSCRIBE [channel ...]
+    fn subscribe(&mut self, args: &[Bytes]) -> Vec<Frame> {
+        let spec = cmd::lookup(&args[0]).unwrap();
+        if !spec.check_arity(args.len()) {
+            self.stats.reject(spec.name);
+            return vec![cmd::wrong_arity(spec)];
+        }
+        let start = time::Instant::now();
+        let subscriber = self
+            .subscriber
+            .get_or_insert_with(|| self.pubsub.subscriber());
+        let replies = if spec.name == "SUBSCRIBE" {
+            args[1..]
+                .iter()
+                .map(|channel| {
+                    let count = subscriber.subscribe(channel.clone());
+                    push("subscribe", Frame::Bulk(channel.clone()), count)
+                })
+                .collect()
+        } else {
+            //不带参数时退订所有频道，没有订阅任何频道时也回复一次
+            let channels = match &args[1..] {
+                [] => subscriber.channels().to_vec(),
+                channels => channels.to_vec(),
+            };
+            if channels.is_empty() {
+                vec![push("unsubscribe", Frame::Null, 0)]
+            } else {
+                channels
+                    .into_iter()
+                    .map(|channel| {
+                        let count = subscriber.unsubscribe(&channel);
+                        push("unsubscribe", Frame::Bulk(channel), count)
+                    })
+                    .collect()
+            }
+        };
+        let subscriptions = subscriber.channels().len();
+        if subscriptions == 0 {
+            self.subscriber = None;
+        }
+        self.client.info().subscriptions = subscriptions;
+        self.stats.record(spec.name, start.elapsed(), &replies[0]);
+        replies
+    }
+
+    //事务排队期间出错的命令会让 EXEC 失败
+    fn abort_multi(&mut self, err: Frame) -> Frame {
+        if let Some(multi) = &mut self.multi {
+            multi.aborted = true;
+        }
+        err
+    }
+
+    //整个事务在同一次加锁里执行，其他客户端的命令不会插到中间
+    async fn exec(&mut self) -> Frame {
+        let multi = match self.multi.take() {
+            Some(multi) => multi,
+            None => return Frame::Error("ERR EXEC without MULTI".to_string()),
+        };
+        if multi.aborted {
+            return Frame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            );
+        }
+
+        let writes = multi
+            .queued
+            .iter()
+            .any(|args| cmd::lookup(&args[0]).is_some_and(|spec| spec.write));
+        if writes && self.replication.rejects_writes() {
+            return Frame::Error(
+                "READONLY You can't write against a read only replica.".to_string(),
+            );
+        }
+        if let Err(busy) = self.scripts.wait_idle().await {
+            return busy;
+        }
+        let replies = {
+            let mut state = self.db.lock();
+            multi
+                .queued
+                .iter()
+                .map(|args| {
+                    let start = time::Instant::now();
+                    let reply = cmd::execute_and_propagate(&mut state, args);
+                    //排队时已经检查过，命令一定存在
+                    if let Some(spec) = cmd::lookup(&args[0]) {
+                        self.stats.record(spec.name, start.elapsed(), &reply);
+                    }
+                    reply
+                })
+                .collect()
+        };
+        if writes {
+            self.db.notify_expiration();
+        }
+        Frame::Array(replies)
+    }
+
+    async fn serve_replica(self, args: Vec<Bytes>) -> error::Result<()> {
+        let spec = cmd::lookup(&args[0]).unwrap();
+        let mut connection = self.connection;
+        if !spec.check_arity(args.len()) {
+            connection.write_frame(&cmd::wrong_arity(spec)).await?;
+            return Ok(());
+        }
+        //replica 要通过 TCP 连回来，Unix socket 上不能复制
+        let addr = match self.peer_addr {
+            Some(addr) => addr,
+            None => {
+                let err = Frame::Error("ERR replication needs a TCP connection".to_string());
+                connection.write_frame(&err).await?;
+                return Ok(());
+            }
+        };
+        //SYNC 是老版本的协议，相当于 PSYNC ? -1
+        let args = if args.len() == 1 {
+            vec![
+                args[0].clone(),
+                Bytes::from_static(b"?"),
+                Bytes::from_static(b"-1"),
+            ]
+        } else {
+            args
+        };
+        self.client.info().replica = true;
+        let mut shutdown = self.shutdown;
+        tokio::select! {
+            res = self
+                .replication
+                .serve_replica(&self.db, connection, addr, self.listening_port, &args) => res,
+            //退出时断开复制连接，replica 会重连其他 master 或者等我们重启后部分同步
+            _ = shutdown.recv() => Ok(()),
+            _ = self.client.killed() => Ok(()),
+        }
+    }
+
+    // SHUTDOWN [NOSAVE|SAVE]
+    async fn request_shutdown(&mut self, args: &[Bytes]) -> Result<(), Frame> {
+        let mode = match args {
+            [_] => ShutdownMode::Default,
+            [_, mode] if mode.eq_ignore_ascii_case(b"NOSAVE") => ShutdownMode::NoSave,
+            [_, mode] if mode.eq_ignore_ascii_case(b"SAVE") => ShutdownMode::Save,
+            _ => return Err(Frame::Error("ERR syntax error".to_string())),
+        };
+        //已经满了说明别的连接已经发起了 SHUTDOWN
+        let _ = self.shutdown_request.try_send(mode);
+        //等 accept 循环发出退出通知再关闭连接，客户端看到连接断开时服务端确实已经在退出了
+        self.shutdown.recv().await;
+        Ok(())
+    }
+
+    // REPLCONF listening-port <port> | capa <...> | ACK <offset> | GETACK *
+    fn replconf(&mut self, args: &[Bytes]) -> Frame {
+        if args.len().is_multiple_of(2) {
+            return Frame::Error("ERR syntax error".to_string());
+        }
+        for pair in args[1..].chunks(2) {
+            if pair[0].eq_ignore_ascii_case(b"listening-port") {
+                match std::str::from_utf8(&pair[1])
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                {
+                    Some(port) => self.listening_port = port,
+                    None => return Frame::Error("ERR invalid listening port".to_string()),
+                }
+            }
+        }
+        cmd::ok()
+    }
+
+    // REPLICAOF host port | NO ONE
+    fn replicaof(&self, args: &[Bytes]) -> Frame {
+        if args[1].eq_ignore_ascii_case(b"NO") && args[2].eq_ignore_ascii_case(b"ONE") {
+            self.replication.promote();
+            return cmd::ok();
+        }
+        let port = match std::str::from_utf8(&args[2])
+            .ok()
+            .and_then(|p| p.parse().ok())
+        {
+            Some(port) => port,
+            None => return Frame::Error("ERR Invalid master port".to_string()),
+        };
+        let host = String::from_utf8_lossy(&args[1]).into_owned();
+        self.replication
+            .replicate_from(&self.db, self.aof.clone(), host, port);
+        cmd::ok()
+    }
+
+    // WAIT numreplicas timeout
+    async fn wait(&self, args: &[Bytes]) -> Frame {
+        let (numreplicas, timeout) = match (cmd::parse_int(&args[1]), cmd::parse_int(&args[2])) {
+            (Ok(n), Ok(ms)) if n >= 0 && ms >= 0 => (n as usize, Duration::from_millis(ms as u64)),
+            (Ok(_), Ok(_)) => {
+                return Frame::Error("ERR timeout is negative".to_string());
+            }
+            (Err(err), _) | (_, Err(err)) => return err,
+        };
+        self.replication.wait(numreplicas, timeout).await
+    }
+
+    // INFO [section [section ...]]
+    async fn info(&self, args: &[Bytes]) -> Frame {
+        let requested: Vec<Vec<u8>> = match args {
+            [_] => vec![b"default".to_vec()],
+            _ => args[1..]
+                .iter()
+                .map(|section| section.to_ascii_lowercase())
+                .collect(),
+        };
+        //和 Redis 的顺序一致；commandstats 不在 default 里，只有 all 或者单独指定时才输出
+        let sections = [
+            ("server", true),
+            ("clients", true),
+            ("memory", true),
+            ("stats", true),
+            ("replication", true),
+            ("commandstats", false),
+            ("cluster", true),
+            ("keyspace", true),
+        ];
+        let wanted: Vec<&str> = sections
+            .into_iter()
+            .filter(|(name, default)| {
+                requested.iter().any(|section| match &section[..] {
+                    b"all" | b"everything" => true,
+                    b"default" => *default,
+                    section => section == name.as_bytes(),
+                })
+            })
+            .map(|(name, _)| name)
+            .collect();
+        //memory、stats 和 keyspace 要读 keyspace，和普通命令一样先异步等待正在执行的脚本
+        if wanted
+            .iter()
+            .any(|name| matches!(*name, "memory" | "stats" | "keyspace"))
+        {
+            if let Err(busy) = self.scripts.wait_idle().await {
+                return busy;
+            }
+        }
+        let mut parts = Vec::new();
+        for name in wanted {
+            parts.push(match name {
+                "server" => self.stats.server_info(self.cluster.is_some()),
+                "clients" => {
+                    let maxclients = self.config.lock().unwrap().maxclients;
+                    self.clients.info(maxclients)
+                }
+                "memory" => stats::memory_info(&self.db.lock()),
+                "stats" => self.stats.stats_info(&self.db.lock()),
+                "replication" => self.replication.info(),
+                "commandstats" => self.stats.commandstats_info(),
+                "cluster" => format!(
+                    "# Cluster\r\ncluster_enabled:{}\r\n",
+                    self.cluster.is_some() as u8
+                ),
+                _ => stats::keyspace_info(&self.db.lock()),
+            });
+        }
+        Frame::Bulk(Bytes::from(parts.join("\r\n")))
+    }
+
+    // CONFIG GET pattern [pattern ...] | SET parameter value [parameter value ...] | REWRITE
+    fn config_command(&self, args: &[Bytes]) -> Frame {
+        let sub = args[1].to_ascii_uppercase();
+        match (&sub[..], args.len()) {
+            (b"GET", n) if n > 2 => {
+                let config = self.config.lock().unwrap();
+                let mut reply = Vec::new();
+                for parameter in config::PARAMETERS {
+                    let requested = args[2..].iter().any(|pattern| {
+                        cmd::glob_match(&pattern.to_ascii_lowercase(), parameter.name.as_bytes())
+                    });
+                    if requested {
+                        reply.push(Frame::Bulk(Bytes::from_static(parameter.name.as_bytes())));
+                        reply.push(Frame::Bulk(Bytes::from(
+                            config.get(parameter.name).unwrap_or_default(),
+                        )));
+                    }
+                }
+                Frame::Array(reply)
+            }
+            (b"SET", n) if n > 3 && n % 2 == 0 => self.config_set(&args[2..]),
+            (b"REWRITE", 2) => {
+                let config = self.config.lock().unwrap();
+                if config.config_file.is_none() {
+                    return Frame::Error(
+                        "ERR The server is running without a config file".to_string(),
+                    );
+                }
+                match config.rewrite() {
+                    Ok(()) => cmd::ok(),
+                    Err(err) => Frame::Error(format!("ERR Rewriting config file: {}", err)),
+                }
+            }
+            _ => Frame::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                String::from_utf8_lossy(&args[1])
+            )),
+        }
+    }
+
+    //所有参数都检查通过才生效，然后同步到持久化和复制模块
+    fn config_set(&self, pairs: &[Bytes]) -> Frame {
+        let mut config = self.config.lock().unwrap();
+        let mut updated = config.clone();
+        for pair in pairs.chunks(2) {
+            let name = String::from_utf8_lossy(&pair[0]);
+            let value = String::from_utf8_lossy(&pair[1]);
+            let failed = |reason: &str| {
+                Frame::Error(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, reason
+                ))
+            };
+            match config::lookup(&name) {
+                None => {
+                    return Frame::Error(format!(
+                        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    ))
+                }
+                Some(parameter) if !parameter.mutable => {
+                    return failed("can't set immutable config");
+                }
+                Some(_) => {
+                    if let Err(err) = updated.set(&name, &value) {
+                        return failed(&err);
+                    }
+                }
+            }
+        }
+
+        self.rdb.set_save_rules(updated.save.clone());
+        if let Some(aof) = &self.aof {
+            aof.set_policy(updated.appendfsync);
+        }
+        self.replication.set_read_only(updated.replica_read_only);
+        self.replication
+            .set_master_auth(&updated.masteruser, &updated.masterauth);
+        self.replication
+            .set_output_buffer_limit(updated.client_output_buffer_limit);
+        self.client_limit.set_max(updated.maxclients);
+        //只在真的修改了 requirepass 时才覆盖 default 用户的密码，不影响 ACL SETUSER 设置的密码
+        if updated.requirepass != config.requirepass {
+            self.acl.require_pass(&updated.requirepass);
+        }
+        *config = updated;
+        cmd::ok()
+    }
+
+    // SCRIPT LOAD|EXISTS|FLUSH|KILL
+    fn script(&self, args: &[Bytes]) -> Frame {
+        let sub = args[1].to_ascii_uppercase();
+        match (&sub[..], args.len()) {
+            (b"LOAD", 3) => Frame::Bulk(self.scripts.load(args[2].clone()).into()),
+            (b"EXISTS", n) if n > 2 => Frame::Array(
+                args[2..]
+                    .iter()
+                    .map(|sha| Frame::Integer(self.scripts.exists(sha) as i64))
+                    .collect(),
+            ),
+            (b"FLUSH", _) => {
+                self.scripts.flush();
+                cmd::ok()
+            }
+            (b"KILL", 2) => self.scripts.kill(),
+            _ => Frame::Error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                String::from_utf8_lossy(&args[1])
+            )),
+        }
+    }
+}
+
+//timeout 为 0 时永远不会超时
+async fn idle_timeout(idle: Duration) {
+    if idle.is_zero() {
+        future::pending().await
+    } else {
+        time::sleep(idle).await
+    }
+}
+
+//没有订阅时永远不会完成
+async fn next_message(subscriber: &mut Option<Subscriber>) -> Option<Frame> {
+    match subscriber {
+        Some(subscriber) => {
+            let (channel, content) = subscriber.recv().await?;
+            Some(Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"message")),
+                Frame::Bulk(channel),
+                Frame::Bulk(content),
+            ]))
+        }
+        None => future::pending().await,
+    }
+}
+
+// [subscribe|unsubscribe, channel, count]
+fn push(kind: &'static str, channel: Frame, count: usize) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from_static(kind.as_bytes())),
+        channel,
+        Frame::Integer(count as i64),
+    ])
+}
+
+//订阅状态下 PING 的回复也是数组，其他命令都不能执行
+fn subscribed_reply(args: &[Bytes]) -> Frame {
+    match args {
+        [name] | [name, _] if name.eq_ignore_ascii_case(b"PING") => Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"pong")),
+            Frame::Bulk(args.get(1).cloned().unwrap_or_default()),
+        ]),
+        _ => Frame::Error(format!(
+            "ERR Can't execute '{}': only (UN)SUBSCRIBE / PING are allowed in this context",
+            String::from_utf8_lossy(&args[0]).to_ascii_lowercase()
+        )),
+    }
+}
+
+fn is_subscribe(args: &[Bytes]) -> bool {
+    args[0].eq_ignore_ascii_case(b"SUBSCRIBE") || args[0].eq_ignore_ascii_case(b"UNSUBSCRIBE")
+}
+
+fn is_shutdown(args: &[Bytes]) -> bool {
+    args[0].eq_ignore_ascii_case(b"SHUTDOWN")
+}
+
+fn is_sync(args: &[Bytes]) -> bool {
+    args[0].eq_ignore_ascii_case(b"PSYNC") || args[0].eq_ignore_ascii_case(b"SYNC")
+}
@@ -0,0 +1,368 @@
+//! 可以在多个任务之间共享的客户端，由 `src/bin/client.rs` 里的 mpsc + oneshot 模式演变而来。
+//!
+//! 所有 `SharedClient` 的克隆都把请求发到同一个 manager 任务，manager 独占一条连接：
+//! 每次把 channel 里已经排队的请求一起写出去，再按 FIFO 顺序读取回复，逐个通过
+//! oneshot 交还给等待的调用方。channel 是有界的，manager 处理不过来时 `send` 会等待。
+//!
+//! 连接断开时 manager 按 [`ReconnectPolicy`] 重连，还没收到回复的请求里可以重发的重发，
+//! 其余的返回错误。
+
+use crate::{
+    connection::BoxedStream,
+    error::{
+        Error,
+        Result,
+    },
+    frame::Frame,
+    reconnect::{
+        self,
+        ReconnectPolicy,
+        Target,
+    },
+    types::{
+        FromRedisValue,
+        ToRedisArgs,
+    },
+    Connection,
+};
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::{
+    net::ToSocketAddrs,
+    sync::{
+        mpsc,
+        oneshot,
+    },
+    time,
+};
+
+/// Default number of requests that can wait for the manager task.
+pub const DEFAULT_CAPACITY: usize = 32;
+
+//一次最多合并写出的请求数，避免一批请求太大，前面的调用方迟迟拿不到回复
+const MAX_BATCH: usize = 128;
+
+/// A cloneable handle to a connection owned by a background manager task.
+///
+/// Requests from all clones are pipelined over the single connection and
+/// answered in the order they were sent.
+#[derive(Debug, Clone)]
+pub struct SharedClient {
+    tx: mpsc::Sender<Request>,
+}
+
+#[derive(Debug)]
+struct Request {
+    args: Vec<Bytes>,
+    //接收 server response 的发送端
+    resp: oneshot::Sender<Result<Frame>>,
+}
+
+impl SharedClient {
+    /// Connect to `addr` and spawn the manager task, with room for
+    /// [`DEFAULT_CAPACITY`] queued requests.
+    pub async fn connect<T: ToSocketAddrs>(addr: T) -> Result<SharedClient> {
+        SharedClient::connect_with_capacity(addr, DEFAULT_CAPACITY).await
+    }
+
+    /// Like `connect`, with room for `capacity` queued requests before
+    /// callers have to wait.
+    pub async fn connect_with_capacity<T: ToSocketAddrs>(
+        addr: T,
+        capacity: usize,
+    ) -> Result<SharedClient> {
+        SharedClient::connect_with_policy(addr, capacity, ReconnectPolicy::default()).await
+    }
+
+    /// Like `connect_with_capacity`, reconnecting according to `policy`.
+    pub async fn connect_with_policy<T: ToSocketAddrs>(
+        addr: T,
+        capacity: usize,
+        policy: ReconnectPolicy,
+    ) -> Result<SharedClient> {
+        let target = Target::Tcp(reconnect::resolve(addr).await?);
+        SharedClient::connect_target(target, capacity, policy).await
+    }
+
+    /// Connect to a server listening on the Unix socket at `path`, with the
+    /// default capacity and [`ReconnectPolicy`].
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<SharedClient> {
+        let target = Target::Unix(path.as_ref().to_path_buf());
+        SharedClient::connect_target(target, DEFAULT_CAPACITY, ReconnectPolicy::default()).await
+    }
+
+    pub(crate) async fn connect_target(
+        target: Target,
+        capacity: usize,
+        policy: ReconnectPolicy,
+    ) -> Result<SharedClient> {
+        let stream = target.open().await?;
+        let (tx, rx) = mpsc::channel(capacity);
+        let manager = Manager {
+            target,
+            policy,
+            connection: Some(Connection::new(stream)),
+        };
+        tokio::spawn(manager.run(rx));
+        Ok(SharedClient { tx })
+    }
+
+    /// Send any command and return the raw reply. Error replies are returned
+    /// as errors.
+    pub async fn command(&self, args: Vec<Bytes>) -> Result<Frame> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Request { args, resp })
+            .await
+            .map_err(|_| Error::ConnectionClosed)?;
+        //manager 在回复之前退出（比如 panic）时 oneshot 的发送端被丢弃
+        rx.await.map_err(|_| Error::ConnectionClosed)?
+    }
+
+    /// Send any command built from `args` and convert the reply, e.g.
+    /// `query::<i64, _>(("INCRBY", key, 5))`.
+    pub async fn query<T: FromRedisValue, A: ToRedisArgs>(&self, args: A) -> Result<T> {
+        T::from_redis_value(self.command(args.to_args()).await?)
+    }
+
+    pub async fn ping(&self, message: Option<Bytes>) -> Result<Bytes> {
+        let mut args = vec![Bytes::from_static(b"PING")];
+        args.extend(message);
+        Bytes::from_redis_value(self.command(args).await?)
+    }
+
+    pub async fn echo(&self, message: Bytes) -> Result<Bytes> {
+        let frame = self
+            .command(vec![Bytes::from_static(b"ECHO"), message])
+            .await?;
+        Bytes::from_redis_value(frame)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let frame = self
+            .command(vec![Bytes::from_static(b"GET"), arg(key)])
+            .await?;
+        Option::from_redis_value(frame)
+    }
+
+    pub async fn set(&self, key: &str, value: Bytes) -> Result<()> {
+        self.command(vec![Bytes::from_static(b"SET"), arg(key), value])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_expires(&self, key: &str, value: Bytes, expiration: Duration) -> Result<()> {
+        let ms = Bytes::from(expiration.as_millis().to_string());
+        let args = vec![
+            Bytes::from_static(b"SET"),
+            arg(key),
+            value,
+            Bytes::from_static(b"PX"),
+            ms,
+        ];
+        self.command(args).await?;
+        Ok(())
+    }
+
+    pub async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Bytes>>> {
+        let mut args = vec![Bytes::from_static(b"MGET")];
+        args.extend(keys.iter().map(|key| arg(key)));
+        Vec::from_redis_value(self.command(args).await?)
+    }
+
+    pub async fn mset(&self, pairs: &[(&str, Bytes)]) -> Result<()> {
+        let mut args = vec![Bytes::from_static(b"MSET")];
+        for (key, value) in pairs {
+            args.push(arg(key));
+            args.push(value.clone());
+        }
+        self.command(args).await?;
+        Ok(())
+    }
+
+    /// APPEND, returning the new length.
+    pub async fn append(&self, key: &str, value: Bytes) -> Result<i64> {
+        self.integer(vec![Bytes::from_static(b"APPEND"), arg(key), value])
+            .await
+    }
+
+    pub async fn strlen(&self, key: &str) -> Result<i64> {
+        self.integer(vec![Bytes::from_static(b"STRLEN"), arg(key)])
+            .await
+    }
+
+    /// DEL, returning the number of keys removed.
+    pub async fn del(&self, keys: &[&str]) -> Result<i64> {
+        self.keys_command("DEL", keys).await
+    }
+
+    /// EXISTS, returning how many of `keys` exist.
+    pub async fn exists(&self, keys: &[&str]) -> Result<i64> {
+        self.keys_command("EXISTS", keys).await
+    }
+
+    pub async fn incr(&self, key: &str) -> Result<i64> {
+        self.integer(vec![Bytes::from_static(b"INCR"), arg(key)])
+            .await
+    }
+
+    pub async fn decr(&self, key: &str) -> Result<i64> {
+        self.integer(vec![Bytes::from_static(b"DECR"), arg(key)])
+            .await
+    }
+
+    pub async fn incr_by(&self, key: &str, increment: i64) -> Result<i64> {
+        let args = vec![
+            Bytes::from_static(b"INCRBY"),
+            arg(key),
+            Bytes::from(increment.to_string()),
+        ];
+        self.integer(args).await
+    }
+
+    pub async fn decr_by(&self, key: &str, decrement: i64) -> Result<i64> {
+        let args = vec![
+            Bytes::from_static(b"DECRBY"),
+            arg(key),
+            Bytes::from(decrement.to_string()),
+        ];
+        self.integer(args).await
+    }
+
+    /// PEXPIRE, returning whether the key exists.
+    pub async fn expire(&self, key: &str, expiration: Duration) -> Result<bool> {
+        let args = vec![
+            Bytes::from_static(b"PEXPIRE"),
+            arg(key),
+            Bytes::from(expiration.as_millis().to_string()),
+        ];
+        Ok(self.integer(args).await? == 1)
+    }
+
+    /// PERSIST, returning whether a timeout was removed.
+    pub async fn persist(&self, key: &str) -> Result<bool> {
+        let args = vec![Bytes::from_static(b"PERSIST"), arg(key)];
+        Ok(self.integer(args).await? == 1)
+    }
+
+    /// PTTL: `None` when the key doesn't exist or has no timeout.
+    pub async fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        let ms = self
+            .integer(vec![Bytes::from_static(b"PTTL"), arg(key)])
+            .await?;
+        Ok((ms >= 0).then(|| Duration::from_millis(ms as u64)))
+    }
+
+    pub async fn dbsize(&self) -> Result<i64> {
+        self.integer(vec![Bytes::from_static(b"DBSIZE")]).await
+    }
+
+    pub async fn flushdb(&self) -> Result<()> {
+        self.command(vec![Bytes::from_static(b"FLUSHDB")]).await?;
+        Ok(())
+    }
+
+    async fn keys_command(&self, command: &'static str, keys: &[&str]) -> Result<i64> {
+        let mut args = vec![Bytes::from_static(command.as_bytes())];
+        args.extend(keys.iter().map(|key| arg(key)));
+        self.integer(args).await
+    }
+
+    async fn integer(&self, args: Vec<Bytes>) -> Result<i64> {
+        i64::from_redis_value(self.command(args).await?)
+    }
+}
+
+struct Manager {
+    target: Target,
+    policy: ReconnectPolicy,
+    //None：连接已经断开，处理下一批请求之前重连
+    connection: Option<Connection<BoxedStream>>,
+}
+
+impl Manager {
+    //manager 独占连接。每轮先等到一个请求，再顺手取走 channel 里已经排队的请求，
+    //一起写出去之后按顺序读回复
+    async fn run(mut self, mut rx: mpsc::Receiver<Request>) {
+        let mut batch = Vec::with_capacity(MAX_BATCH);
+        while rx.recv_many(&mut batch, MAX_BATCH).await > 0 {
+            self.process(&mut batch).await;
+        }
+    }
+
+    async fn process(&mut self, batch: &mut Vec<Request>) {
+        let mut attempt = 0;
+        loop {
+            let connection = match &mut self.connection {
+                Some(connection) => connection,
+                None => match reconnect::connect(&self.target, &self.policy, None).await {
+                    Ok(connection) => self.connection.insert(connection),
+                    Err(err) => {
+                        fail(batch.drain(..), &err);
+                        return;
+                    }
+                },
+            };
+
+            let err = match exchange(connection, batch).await {
+                Ok(()) => return,
+                Err(err) => err,
+            };
+            //连接已经不可用，还没收到回复的请求里能重发的留下，其余的报错
+            self.connection = None;
+            let reconnectable = matches!(err, Error::Io(_) | Error::ConnectionClosed);
+            if !reconnectable || attempt >= self.policy.max_retries {
+                fail(batch.drain(..), &err);
+                return;
+            }
+            let (retry, give_up): (Vec<Request>, Vec<Request>) = batch
+                .drain(..)
+                .partition(|request| self.policy.is_retryable(&request.args));
+            fail(give_up.into_iter(), &err);
+            if retry.is_empty() {
+                return;
+            }
+            *batch = retry;
+            attempt += 1;
+            time::sleep(self.policy.backoff(attempt)).await;
+        }
+    }
+}
+
+//写出整批请求，按 FIFO 顺序把回复交给对应的调用方。出错时 batch 里剩下的是还没收到回复的请求
+async fn exchange(
+    connection: &mut Connection<BoxedStream>,
+    batch: &mut Vec<Request>,
+) -> Result<()> {
+    //整批编码后一次写出，逐条写的话小包会被 Nagle 算法和延迟 ACK 拖慢
+    let frames: Vec<Frame> = batch
+        .iter()
+        .map(|request| Frame::Array(request.args.iter().cloned().map(Frame::Bulk).collect()))
+        .collect();
+    connection.write_frames(&frames).await?;
+    while !batch.is_empty() {
+        let frame = connection
+            .read_frame()
+            .await?
+            .ok_or(Error::ConnectionClosed)?;
+        let request = batch.remove(0);
+        let result = match frame {
+            Frame::Error(err) => Err(Error::server(err)),
+            frame => Ok(frame),
+        };
+        //调用方可能已经不再等待（比如被取消了），忽略
+        let _ = request.resp.send(result);
+    }
+    Ok(())
+}
+
+fn fail(requests: impl Iterator<Item = Request>, err: &Error) {
+    for request in requests {
+        let _ = request.resp.send(Err(err.clone()));
+    }
+}
+
+fn arg(key: &str) -> Bytes {
+    Bytes::copy_from_slice(key.as_bytes())
+}
@@ -0,0 +1,58 @@
+//! 服务端的优雅退出，沿用 mini-redis 的做法。
+//!
+//! 收到 SIGINT/SIGTERM 或者 SHUTDOWN 命令之后：
+//!
+//! 1. accept 循环停止接受新连接；
+//! 2. 丢弃 `broadcast::Sender`，每个连接任务都会收到通知，执行完手上的命令后退出；
+//! 3. 每个连接任务持有一个 `mpsc::Sender`，全部退出后 `recv` 返回 `None`，
+//!    以此等待所有连接结束，最多等 `shutdown-timeout`；
+//! 4. 按照 SHUTDOWN 的参数和 `save` 配置做最后一次 RDB 保存，AOF 落盘。
+
+use tokio::sync::broadcast;
+
+/// How SHUTDOWN treats the final snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShutdownMode {
+    /// Save if any `save` rules are configured.
+    #[default]
+    Default,
+    /// Always save, even without `save` rules.
+    Save,
+    /// Exit without saving.
+    NoSave,
+}
+
+/// Listens for the server shutdown signal.
+///
+/// The signal is sent once, by dropping the `broadcast::Sender`. Each
+/// connection task owns a `Shutdown` and stops reading commands once it
+/// fires.
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    is_shutdown: bool,
+    notify: broadcast::Receiver<()>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(notify: broadcast::Receiver<()>) -> Shutdown {
+        Shutdown {
+            is_shutdown: false,
+            notify,
+        }
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.is_shutdown
+    }
+
+    /// Wait for the shutdown signal, returning at once if it was already
+    /// received.
+    pub(crate) async fn recv(&mut self) {
+        if self.is_shutdown {
+            return;
+        }
+        //发送端被丢弃时返回 Err(Closed)，不会真的收到消息
+        let _ = self.notify.recv().await;
+        self.is_shutdown = true;
+    }
+}
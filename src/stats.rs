@@ -0,0 +1,427 @@
+//! 服务端的统计信息，INFO 的 server、memory、stats、keyspace 和 commandstats 部分在这里生成。
+//!
+//! 连接数、命令数这些计数器是所有连接共享的原子变量；每条命令的调用次数和耗时按命令名记在一张表里。
+//! instantaneous_ops_per_sec 和 Redis 一样由后台任务每 100ms 采样一次命令总数，
+//! 取最近 16 次采样算出平均值。keyspace 的命中、过期等计数在 `db::State` 里，
+//! 它们本来就在 keyspace 的锁里更新。
+//!
+//! 客户端连接的收发字节数由包在 socket 外面的 [`Metered`] 统计。
+//! 打开 `metrics` feature 时，这些数据也以 Prometheus 的格式输出，见 `metrics` 模块。
+
+use crate::{
+    db::{
+        Entry,
+        State,
+    },
+    frame::Frame,
+    replication,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    fmt::Write as _,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        Weak,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+};
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        ReadBuf,
+    },
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+const SAMPLES: usize = 16;
+
+/// Upper bounds of the command latency histogram buckets.
+pub(crate) const LATENCY_BUCKETS: [Duration; 10] = [
+    Duration::from_micros(10),
+    Duration::from_micros(50),
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// Counters shown by INFO, shared by all connections.
+#[derive(Debug, Clone)]
+pub(crate) struct Stats {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    started: Instant,
+    //和 Redis 的 run_id 一样，每次启动都不同
+    run_id: String,
+    //只监听 Unix socket 时是 0
+    tcp_port: u16,
+    connections_received: AtomicU64,
+    rejected_connections: AtomicU64,
+    commands_processed: AtomicU64,
+    net_input_bytes: AtomicU64,
+    net_output_bytes: AtomicU64,
+    commands: Mutex<BTreeMap<&'static str, CommandStats>>,
+    //(采样时间, 当时的命令总数)
+    samples: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CommandStats {
+    pub(crate) calls: u64,
+    pub(crate) usec: u64,
+    //还没执行就被拒绝，比如参数个数不对、没有权限
+    pub(crate) rejected_calls: u64,
+    //执行了但是回复了错误
+    pub(crate) failed_calls: u64,
+    //落在每个区间里的调用次数（不是累计的），比最后一个上限还慢的只算在 calls 里
+    pub(crate) buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// The counters at one point in time, for the metrics endpoint.
+#[cfg(feature = "metrics")]
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    pub(crate) uptime: Duration,
+    pub(crate) connections_received: u64,
+    pub(crate) rejected_connections: u64,
+    pub(crate) commands_processed: u64,
+    pub(crate) net_input_bytes: u64,
+    pub(crate) net_output_bytes: u64,
+    pub(crate) commands: Vec<(&'static str, CommandStats)>,
+}
+
+impl Stats {
+    /// Start counting, and spawn the task sampling the number of commands
+    /// for `instantaneous_ops_per_sec`.
+    pub(crate) fn new(tcp_port: u16) -> Stats {
+        let shared = Arc::new(Shared {
+            started: Instant::now(),
+            run_id: replication::new_replid(),
+            tcp_port,
+            connections_received: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            commands_processed: AtomicU64::new(0),
+            net_input_bytes: AtomicU64::new(0),
+            net_output_bytes: AtomicU64::new(0),
+            commands: Mutex::new(BTreeMap::new()),
+            samples: Mutex::new(VecDeque::with_capacity(SAMPLES)),
+        });
+        tokio::spawn(sample_ops(Arc::downgrade(&shared)));
+        Stats { shared }
+    }
+
+    pub(crate) fn connection_received(&self) {
+        self.shared
+            .connections_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection was closed right away because of `maxclients`.
+    pub(crate) fn connection_rejected(&self) {
+        self.shared
+            .rejected_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a command that ran for `elapsed` and replied `reply`.
+    pub(crate) fn record(&self, name: &'static str, elapsed: Duration, reply: &Frame) {
+        self.shared
+            .commands_processed
+            .fetch_add(1, Ordering::Relaxed);
+        let mut commands = self.shared.commands.lock().unwrap();
+        let stats = commands.entry(name).or_default();
+        stats.calls += 1;
+        stats.usec += elapsed.as_micros() as u64;
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| elapsed <= *bound) {
+            stats.buckets[i] += 1;
+        }
+        if matches!(reply, Frame::Error(_)) {
+            stats.failed_calls += 1;
+        }
+    }
+
+    /// Count a command refused before it ran.
+    pub(crate) fn reject(&self, name: &'static str) {
+        let mut commands = self.shared.commands.lock().unwrap();
+        commands.entry(name).or_default().rejected_calls += 1;
+    }
+
+    /// The server section of INFO.
+    pub(crate) fn server_info(&self, cluster: bool) -> String {
+        let uptime = self.shared.started.elapsed().as_secs();
+        let mut info = String::from("# Server\r\n");
+        let _ = write!(info, "redis_version:{}\r\n", env!("CARGO_PKG_VERSION"));
+        let mode = if cluster { "cluster" } else { "standalone" };
+        let _ = write!(info, "redis_mode:{}\r\n", mode);
+        let _ = write!(
+            info,
+            "os:{} {}\r\n",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+        let _ = write!(info, "arch_bits:{}\r\n", usize::BITS);
+        let _ = write!(info, "process_id:{}\r\n", std::process::id());
+        let _ = write!(info, "run_id:{}\r\n", self.shared.run_id);
+        let _ = write!(info, "tcp_port:{}\r\n", self.shared.tcp_port);
+        let _ = write!(info, "uptime_in_seconds:{}\r\n", uptime);
+        let _ = write!(info, "uptime_in_days:{}\r\n", uptime / 86400);
+        info
+    }
+
+    /// The stats section of INFO.
+    pub(crate) fn stats_info(&self, state: &State) -> String {
+        let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut info = String::from("# Stats\r\n");
+        let _ = write!(
+            info,
+            "total_connections_received:{}\r\n",
+            counter(&self.shared.connections_received)
+        );
+        let _ = write!(
+            info,
+            "total_commands_processed:{}\r\n",
+            counter(&self.shared.commands_processed)
+        );
+        let _ = write!(info, "instantaneous_ops_per_sec:{}\r\n", self.ops_per_sec());
+        let _ = write!(
+            info,
+            "total_net_input_bytes:{}\r\n",
+            counter(&self.shared.net_input_bytes)
+        );
+        let _ = write!(
+            info,
+            "total_net_output_bytes:{}\r\n",
+            counter(&self.shared.net_output_bytes)
+        );
+        let _ = write!(
+            info,
+            "rejected_connections:{}\r\n",
+            counter(&self.shared.rejected_connections)
+        );
+        let _ = write!(info, "expired_keys:{}\r\n", state.expired_keys);
+        //没有 maxmemory，不会淘汰 key
+        let _ = write!(info, "evicted_keys:0\r\n");
+        let _ = write!(info, "keyspace_hits:{}\r\n", state.keyspace_hits);
+        let _ = write!(info, "keyspace_misses:{}\r\n", state.keyspace_misses);
+        info
+    }
+
+    /// The commandstats section of INFO, one line per command called at
+    /// least once.
+    pub(crate) fn commandstats_info(&self) -> String {
+        let commands = self.shared.commands.lock().unwrap();
+        let mut info = String::from("# Commandstats\r\n");
+        for (name, stats) in commands.iter() {
+            let per_call = match stats.calls {
+                0 => 0.0,
+                calls => stats.usec as f64 / calls as f64,
+            };
+            let _ = write!(
+                info,
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},\
+                 failed_calls={}\r\n",
+                name.to_ascii_lowercase(),
+                stats.calls,
+                stats.usec,
+                per_call,
+                stats.rejected_calls,
+                stats.failed_calls
+            );
+        }
+        info
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let commands = self.shared.commands.lock().unwrap();
+        Snapshot {
+            uptime: self.shared.started.elapsed(),
+            connections_received: counter(&self.shared.connections_received),
+            rejected_connections: counter(&self.shared.rejected_connections),
+            commands_processed: counter(&self.shared.commands_processed),
+            net_input_bytes: counter(&self.shared.net_input_bytes),
+            net_output_bytes: counter(&self.shared.net_output_bytes),
+            commands: commands
+                .iter()
+                .map(|(name, stats)| (*name, stats.clone()))
+                .collect(),
+        }
+    }
+
+    fn ops_per_sec(&self) -> u64 {
+        let samples = self.shared.samples.lock().unwrap();
+        match (samples.front(), samples.back()) {
+            (Some((first_at, first)), Some((last_at, last))) if last_at > first_at => {
+                let elapsed = last_at.duration_since(*first_at).as_secs_f64();
+                ((last - first) as f64 / elapsed).round() as u64
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Wraps a client connection's stream to count the bytes read and written.
+pub(crate) struct Metered<S> {
+    stream: S,
+    stats: Stats,
+}
+
+impl<S> Metered<S> {
+    pub(crate) fn new(stream: S, stats: Stats) -> Metered<S> {
+        Metered { stream, stats }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.stats
+            .shared
+            .net_input_bytes
+            .fetch_add(read as u64, Ordering::Relaxed);
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = res {
+            self.stats
+                .shared
+                .net_output_bytes
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// The memory section of INFO.
+///
+/// There is no allocator hook, so `used_memory` is an estimate: the size
+/// of the keys and values plus a fixed overhead per key.
+pub(crate) fn memory_info(state: &State) -> String {
+    let used = used_memory(state);
+    let rss = rss();
+    let mut info = String::from("# Memory\r\n");
+    let _ = write!(info, "used_memory:{}\r\n", used);
+    let _ = write!(info, "used_memory_human:{}\r\n", human_bytes(used as u64));
+    let _ = write!(info, "used_memory_rss:{}\r\n", rss);
+    let _ = write!(info, "used_memory_rss_human:{}\r\n", human_bytes(rss));
+    let _ = write!(info, "used_memory_dataset:{}\r\n", state.dataset_bytes());
+    //不支持 maxmemory，和 Redis 没有设置上限时一样显示 0
+    let _ = write!(info, "maxmemory:0\r\n");
+    let _ = write!(info, "maxmemory_human:0B\r\n");
+    info
+}
+
+/// Estimated memory used by the keyspace.
+pub(crate) fn used_memory(state: &State) -> usize {
+    state.dataset_bytes() + state.len() * std::mem::size_of::<(String, Entry)>()
+}
+
+/// The keyspace section of INFO. Like Redis, empty databases aren't
+/// listed.
+pub(crate) fn keyspace_info(state: &State) -> String {
+    let mut info = String::from("# Keyspace\r\n");
+    if !state.is_empty() {
+        let _ = write!(
+            info,
+            "db0:keys={},expires={},avg_ttl={}\r\n",
+            state.len(),
+            state.expires(),
+            state.avg_ttl().as_millis()
+        );
+    }
+    info
+}
+
+//进程的常驻内存，只有 Linux 上能拿到，其他平台显示 0
+#[cfg(target_os = "linux")]
+fn rss() -> u64 {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map_or(0, |kb| kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rss() -> u64 {
+    0
+}
+
+//和 Redis 的 bytesToHuman 一样：1023B、1.50K、2.00M、3.00G
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    for (size, unit) in UNITS {
+        if bytes >= size {
+            return format!("{:.2}{}", bytes as f64 / size as f64, unit);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+//Stats 全部丢弃之后（服务端退出）任务跟着结束
+async fn sample_ops(shared: Weak<Shared>) {
+    let mut interval = time::interval(SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let processed = shared.commands_processed.load(Ordering::Relaxed);
+        let mut samples = shared.samples.lock().unwrap();
+        if samples.len() == SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((Instant::now(), processed));
+    }
+}
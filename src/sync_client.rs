@@ -0,0 +1,143 @@
+//! 可以在多个线程之间共享的同步客户端。
+//!
+//! `BlockingClient` 的方法都要 `&mut self`，多个线程共用时只能套一层 Mutex，命令也只能一条一条地发。
+//! `SyncClient` 把 [`SharedClient`] 包装成同步接口：后台的 runtime 上跑着 manager 任务，
+//! 各个线程的 `block_on` 只是把请求放进 channel 再等回复，同时发出的请求在同一条连接上
+//! 合并写出。
+//!
+//! runtime 用多线程的版本：current-thread runtime 只有在某个线程调用 `block_on` 时才会推进，
+//! 同一时刻也只能有一个线程驱动它。
+
+use crate::{
+    error::Result,
+    frame::Frame,
+    reconnect::ReconnectPolicy,
+    shared_client::{
+        self,
+        SharedClient,
+    },
+    types::{
+        FromRedisValue,
+        ToRedisArgs,
+    },
+};
+use bytes::Bytes;
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::ToSocketAddrs,
+    runtime::Runtime,
+};
+
+/// A blocking client that can be shared between threads.
+///
+/// All methods take `&self`, and clones share one connection: commands sent
+/// concurrently from different threads are pipelined over it, and every
+/// caller gets the reply to its own command. The connection is owned by a
+/// task on a background runtime, which shuts down when the last clone is
+/// dropped.
+///
+/// Don't call it from inside an async runtime; use [`SharedClient`] there.
+#[derive(Debug, Clone)]
+pub struct SyncClient {
+    inner: SharedClient,
+    runtime: Arc<Runtime>,
+}
+
+impl SyncClient {
+    pub fn connect<T: ToSocketAddrs>(addr: T) -> Result<SyncClient> {
+        SyncClient::connect_with_policy(addr, ReconnectPolicy::default())
+    }
+
+    /// Connect, reconnecting according to `policy` when the connection is
+    /// lost.
+    pub fn connect_with_policy<T: ToSocketAddrs>(
+        addr: T,
+        policy: ReconnectPolicy,
+    ) -> Result<SyncClient> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(SharedClient::connect_with_policy(
+            addr,
+            shared_client::DEFAULT_CAPACITY,
+            policy,
+        ))?;
+        Ok(SyncClient {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Connect to a server listening on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<SyncClient> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(SharedClient::connect_unix(path))?;
+        Ok(SyncClient {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Send any command and return the raw reply. Error replies are returned
+    /// as errors.
+    pub fn command(&self, args: Vec<Bytes>) -> Result<Frame> {
+        self.runtime.block_on(self.inner.command(args))
+    }
+
+    /// Send any command built from `args` and convert the reply.
+    pub fn query<T: FromRedisValue, A: ToRedisArgs>(&self, args: A) -> Result<T> {
+        self.runtime.block_on(self.inner.query(args))
+    }
+
+    pub fn ping(&self) -> Result<()> {
+        self.query("PING")
+    }
+
+    /// GET, converting the value. Use an `Option` to tell missing keys apart.
+    pub fn get<T: FromRedisValue>(&self, key: &str) -> Result<T> {
+        self.query(("GET", key))
+    }
+
+    pub fn set<V: ToRedisArgs>(&self, key: &str, value: V) -> Result<()> {
+        self.query(("SET", key, value))
+    }
+
+    pub fn set_expires<V: ToRedisArgs>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: Duration,
+    ) -> Result<()> {
+        self.query(("SET", key, value, "PX", expiration.as_millis() as u64))
+    }
+
+    /// DEL, returning the number of keys removed.
+    pub fn del(&self, keys: &[&str]) -> Result<i64> {
+        self.query(("DEL", keys))
+    }
+
+    pub fn incr(&self, key: &str) -> Result<i64> {
+        self.query(("INCR", key))
+    }
+
+    pub fn incr_by(&self, key: &str, increment: i64) -> Result<i64> {
+        self.query(("INCRBY", key, increment))
+    }
+
+    /// PUBLISH, returning the number of subscribers that got the message.
+    pub fn publish(&self, channel: &str, message: Bytes) -> Result<u64> {
+        self.query(("PUBLISH", channel, message))
+    }
+}
+
+//后台只跑 manager 任务，一个 worker 就够了
+fn runtime() -> Result<Runtime> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("my-redis-sync-client")
+        .enable_all()
+        .build()?;
+    Ok(runtime)
+}
@@ -0,0 +1,202 @@
+//! TLS 支持（`tls` feature），基于 rustls。
+//!
+//! 服务端在 `tls-port` 上单独监听 TLS 连接，证书和私钥来自 `tls-cert-file`、`tls-key-file`。
+//! `tls-auth-clients` 打开时客户端也要出示由 `tls-ca-cert-file` 里的 CA 签发的证书。
+//!
+//! 客户端通过 [`TlsConnector`] 连接，信任的 CA 需要显式给出，不使用系统的根证书。
+//!
+//! 加密算法统一用 ring，不依赖进程级别的默认 CryptoProvider。
+
+use crate::config::{
+    Config,
+    TlsAuthClients,
+};
+use std::{
+    fmt,
+    io,
+    path::Path,
+    sync::Arc,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{
+    self,
+    crypto::CryptoProvider,
+    pki_types::{
+        pem::PemObject,
+        CertificateDer,
+        PrivateKeyDer,
+        ServerName,
+    },
+    server::WebPkiClientVerifier,
+    ClientConfig,
+    RootCertStore,
+    ServerConfig,
+};
+pub use tokio_rustls::TlsAcceptor;
+
+/// How a client connects to a TLS port: which CAs it trusts and, when the
+/// server authenticates clients, which certificate it presents.
+#[derive(Clone)]
+pub struct TlsConnector {
+    roots: Arc<RootCertStore>,
+    config: Arc<ClientConfig>,
+}
+
+impl TlsConnector {
+    /// Trust the server certificates signed by a CA in `ca_cert_file`, a
+    /// PEM file. Doesn't present a client certificate.
+    pub fn new<P: AsRef<Path>>(ca_cert_file: P) -> io::Result<TlsConnector> {
+        let roots = Arc::new(load_roots(ca_cert_file.as_ref())?);
+        let config = client_builder(roots.clone())?.with_no_client_auth();
+        Ok(TlsConnector {
+            roots,
+            config: Arc::new(config),
+        })
+    }
+
+    /// Present the certificate chain in `cert_file` and the private key in
+    /// `key_file`, both PEM, to servers that authenticate clients.
+    pub fn with_client_cert<P: AsRef<Path>, Q: AsRef<Path>>(
+        self,
+        cert_file: P,
+        key_file: Q,
+    ) -> io::Result<TlsConnector> {
+        let certs = load_certs(cert_file.as_ref())?;
+        let key = load_key(key_file.as_ref())?;
+        let config = client_builder(self.roots.clone())?
+            .with_client_auth_cert(certs, key)
+            .map_err(invalid_data)?;
+        Ok(TlsConnector {
+            roots: self.roots,
+            config: Arc::new(config),
+        })
+    }
+
+    /// Open a TCP connection to `addrs` and run the handshake, checking the
+    /// server certificate against `server_name`.
+    pub(crate) async fn connect(
+        &self,
+        addrs: &[std::net::SocketAddr],
+        server_name: &ServerName<'static>,
+    ) -> io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+        let stream = TcpStream::connect(addrs).await?;
+        tokio_rustls::TlsConnector::from(self.config.clone())
+            .connect(server_name.clone(), stream)
+            .await
+    }
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("roots", &self.roots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parse `host` into the name checked against the server certificate.
+pub(crate) fn server_name(host: &str) -> io::Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string()).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid server name '{}': {}", host, err),
+        )
+    })
+}
+
+/// Build the acceptor for the TLS port from the `tls-*` parameters.
+pub fn acceptor(config: &Config) -> io::Result<TlsAcceptor> {
+    let (cert_file, key_file) = match (&config.tls_cert_file, &config.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tls-port needs tls-cert-file and tls-key-file",
+            ))
+        }
+    };
+    let certs = load_certs(cert_file)?;
+    let key = load_key(key_file)?;
+
+    let provider = provider();
+    let verifier = match (&config.tls_ca_cert_file, config.tls_auth_clients) {
+        (_, TlsAuthClients::No) => WebPkiClientVerifier::no_client_auth(),
+        (Some(ca_cert_file), auth) => {
+            let roots = Arc::new(load_roots(ca_cert_file)?);
+            let builder = WebPkiClientVerifier::builder_with_provider(roots, provider.clone());
+            let builder = match auth {
+                TlsAuthClients::Optional => builder.allow_unauthenticated(),
+                _ => builder,
+            };
+            builder.build().map_err(invalid_data)?
+        }
+        (None, _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tls-auth-clients needs tls-ca-cert-file",
+            ))
+        }
+    };
+    let server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn client_builder(
+    roots: Arc<RootCertStore>,
+) -> io::Result<rustls::ConfigBuilder<ClientConfig, rustls::client::WantsClientCert>> {
+    Ok(ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_root_certificates(roots))
+}
+
+fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(invalid_data)?;
+    }
+    Ok(roots)
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| pem_error(path, err))?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificates in '{}'", path.display()),
+        ));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|err| pem_error(path, err))
+}
+
+fn pem_error(path: &Path, err: rustls::pki_types::pem::Error) -> io::Error {
+    match err {
+        rustls::pki_types::pem::Error::Io(err) => io::Error::new(
+            err.kind(),
+            format!("can't read '{}': {}", path.display(), err),
+        ),
+        err => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid PEM file '{}': {}", path.display(), err),
+        ),
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,315 @@
+//! 客户端的类型转换：`ToRedisArgs` 把 Rust 的值编码成命令参数，`FromRedisValue` 把回复的
+//! frame 转换成需要的类型。
+//!
+//! 整数、浮点数和字符串之间按 Redis 的习惯互相转换，比如 `GET` 返回的 bulk string
+//! `"42"` 可以直接取成 `i64`。转换失败返回 [`Error::TypeConversion`]。
+
+use crate::{
+    error::{
+        Error,
+        Result,
+    },
+    frame::Frame,
+};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+};
+
+/// Values that can be used as command arguments.
+pub trait ToRedisArgs {
+    /// Append the arguments for `self` to `out`. Most values are a single
+    /// argument; collections and tuples add one per element and `None` adds
+    /// nothing.
+    fn write_args(&self, out: &mut Vec<Bytes>);
+
+    fn to_args(&self) -> Vec<Bytes> {
+        let mut out = Vec::new();
+        self.write_args(&mut out);
+        out
+    }
+}
+
+/// Types a reply can be converted into.
+pub trait FromRedisValue: Sized {
+    /// Convert a reply. Error replies are returned as errors.
+    fn from_redis_value(frame: Frame) -> Result<Self>;
+}
+
+/// A reply that doesn't fit the requested type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    /// The type that was requested.
+    pub target: &'static str,
+    /// The reply that couldn't be converted.
+    pub reply: String,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "cannot convert reply {:?} to {}",
+            self.reply, self.target
+        )
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+//错误回复原样返回，其他的报告为转换失败
+fn invalid(frame: Frame, target: &'static str) -> Error {
+    match frame {
+        Frame::Error(err) => Error::server(err),
+        frame => Error::TypeConversion(ConversionError {
+            target,
+            reply: frame.to_string(),
+        }),
+    }
+}
+
+impl<T: ToRedisArgs + ?Sized> ToRedisArgs for &T {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        (**self).write_args(out)
+    }
+}
+
+impl ToRedisArgs for Bytes {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        out.push(self.clone());
+    }
+}
+
+impl ToRedisArgs for str {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        out.push(Bytes::copy_from_slice(self.as_bytes()));
+    }
+}
+
+impl ToRedisArgs for String {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        self.as_str().write_args(out)
+    }
+}
+
+//Redis 里的 bool 一般用 1 和 0 表示
+impl ToRedisArgs for bool {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        out.push(Bytes::from_static(if *self { b"1" } else { b"0" }));
+    }
+}
+
+macro_rules! number_to_args {
+    ($($t:ty),*) => {
+        $(
+            impl ToRedisArgs for $t {
+                fn write_args(&self, out: &mut Vec<Bytes>) {
+                    out.push(Bytes::from(self.to_string()));
+                }
+            }
+        )*
+    };
+}
+
+number_to_args!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl<T: ToRedisArgs> ToRedisArgs for [T] {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        for item in self {
+            item.write_args(out);
+        }
+    }
+}
+
+impl<T: ToRedisArgs> ToRedisArgs for Vec<T> {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        self.as_slice().write_args(out)
+    }
+}
+
+impl<T: ToRedisArgs, const N: usize> ToRedisArgs for [T; N] {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        self.as_slice().write_args(out)
+    }
+}
+
+impl<T: ToRedisArgs> ToRedisArgs for Option<T> {
+    fn write_args(&self, out: &mut Vec<Bytes>) {
+        if let Some(value) = self {
+            value.write_args(out);
+        }
+    }
+}
+
+impl FromRedisValue for Frame {
+    fn from_redis_value(frame: Frame) -> Result<Frame> {
+        match frame {
+            Frame::Error(err) => Err(Error::server(err)),
+            frame => Ok(frame),
+        }
+    }
+}
+
+impl FromRedisValue for () {
+    fn from_redis_value(frame: Frame) -> Result<()> {
+        match frame {
+            Frame::Error(err) => Err(Error::server(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromRedisValue for Bytes {
+    fn from_redis_value(frame: Frame) -> Result<Bytes> {
+        match frame {
+            Frame::Bulk(value) => Ok(value),
+            Frame::Simple(value) => Ok(Bytes::from(value)),
+            Frame::Integer(n) => Ok(Bytes::from(n.to_string())),
+            frame => Err(invalid(frame, "Bytes")),
+        }
+    }
+}
+
+impl FromRedisValue for String {
+    fn from_redis_value(frame: Frame) -> Result<String> {
+        match frame {
+            Frame::Bulk(value) => match std::str::from_utf8(&value) {
+                Ok(value) => Ok(value.to_string()),
+                Err(_) => Err(invalid(Frame::Bulk(value), "String")),
+            },
+            Frame::Simple(value) => Ok(value),
+            Frame::Integer(n) => Ok(n.to_string()),
+            frame => Err(invalid(frame, "String")),
+        }
+    }
+}
+
+impl FromRedisValue for bool {
+    fn from_redis_value(frame: Frame) -> Result<bool> {
+        match frame {
+            Frame::Integer(n) => Ok(n != 0),
+            Frame::Simple(ref status) if status == "OK" => Ok(true),
+            Frame::Bulk(ref value) if &value[..] == b"1" => Ok(true),
+            Frame::Bulk(ref value) if &value[..] == b"0" => Ok(false),
+            frame => Err(invalid(frame, "bool")),
+        }
+    }
+}
+
+//整数回复直接转换（检查范围），字符串回复先解析
+macro_rules! number_from_value {
+    ($($t:ident),*) => {
+        $(
+            impl FromRedisValue for $t {
+                fn from_redis_value(frame: Frame) -> Result<$t> {
+                    let parsed = match &frame {
+                        Frame::Integer(n) => $t::try_from(*n).ok(),
+                        Frame::Bulk(value) => std::str::from_utf8(value)
+                            .ok()
+                            .and_then(|value| value.parse().ok()),
+                        Frame::Simple(value) => value.parse().ok(),
+                        _ => None,
+                    };
+                    parsed.ok_or_else(|| invalid(frame, stringify!($t)))
+                }
+            }
+        )*
+    };
+}
+
+number_from_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! float_from_value {
+    ($($t:ident),*) => {
+        $(
+            impl FromRedisValue for $t {
+                fn from_redis_value(frame: Frame) -> Result<$t> {
+                    let parsed = match &frame {
+                        Frame::Integer(n) => Some(*n as $t),
+                        Frame::Bulk(value) => std::str::from_utf8(value)
+                            .ok()
+                            .and_then(|value| value.parse().ok()),
+                        Frame::Simple(value) => value.parse().ok(),
+                        _ => None,
+                    };
+                    parsed.ok_or_else(|| invalid(frame, stringify!($t)))
+                }
+            }
+        )*
+    };
+}
+
+float_from_value!(f32, f64);
+
+impl<T: FromRedisValue> FromRedisValue for Option<T> {
+    fn from_redis_value(frame: Frame) -> Result<Option<T>> {
+        match frame {
+            Frame::Null => Ok(None),
+            frame => T::from_redis_value(frame).map(Some),
+        }
+    }
+}
+
+impl<T: FromRedisValue> FromRedisValue for Vec<T> {
+    fn from_redis_value(frame: Frame) -> Result<Vec<T>> {
+        match frame {
+            Frame::Array(items) => items.into_iter().map(T::from_redis_value).collect(),
+            Frame::Null => Ok(Vec::new()),
+            frame => Err(invalid(frame, "Vec")),
+        }
+    }
+}
+
+//HGETALL、CONFIG GET 之类的回复是 [field, value, field, value, ...]
+impl<K, V> FromRedisValue for HashMap<K, V>
+where
+    K: FromRedisValue + Eq + Hash,
+    V: FromRedisValue,
+{
+    fn from_redis_value(frame: Frame) -> Result<HashMap<K, V>> {
+        let items = match frame {
+            Frame::Array(items) if items.len() % 2 == 0 => items,
+            Frame::Null => return Ok(HashMap::new()),
+            frame => return Err(invalid(frame, "HashMap")),
+        };
+        let mut map = HashMap::with_capacity(items.len() / 2);
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            map.insert(K::from_redis_value(key)?, V::from_redis_value(value)?);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! tuple {
+    ($len:expr; $($name:ident),+) => {
+        impl<$($name: ToRedisArgs),+> ToRedisArgs for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn write_args(&self, out: &mut Vec<Bytes>) {
+                let ($($name,)+) = self;
+                $($name.write_args(out);)+
+            }
+        }
+
+        impl<$($name: FromRedisValue),+> FromRedisValue for ($($name,)+) {
+            fn from_redis_value(frame: Frame) -> Result<($($name,)+)> {
+                match frame {
+                    Frame::Array(items) if items.len() == $len => {
+                        let mut items = items.into_iter();
+                        Ok(($($name::from_redis_value(items.next().unwrap())?,)+))
+                    }
+                    frame => Err(invalid(frame, "tuple")),
+                }
+            }
+        }
+    };
+}
+
+tuple!(1; A);
+tuple!(2; A, B);
+tuple!(3; A, B, C);
+tuple!(4; A, B, C, D);
+tuple!(5; A, B, C, D, E);
+tuple!(6; A, B, C, D, E, F);
@@ -0,0 +1,326 @@
+mod common;
+
+use common::{
+    connect,
+    start_server_with,
+};
+use my_redis::{
+    acl,
+    types::FromRedisValue,
+    Client,
+    Config,
+    Error,
+    Frame,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+};
+use tokio::time::{
+    self,
+    Duration,
+    Instant,
+};
+
+async fn auth(client: &mut Client, user: &str, password: &str) -> Result<(), Error> {
+    client.query(("AUTH", user, password)).await
+}
+
+fn code(err: Error) -> String {
+    err.code().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn requirepass_protects_the_default_user() {
+    let addr = start_server_with(Config {
+        requirepass: "secret".to_string(),
+        ..Config::default()
+    })
+    .await;
+    let mut client = connect(addr).await;
+
+    let err = client.get::<Option<String>>("key").await.unwrap_err();
+    assert_eq!(code(err), "NOAUTH");
+    let err = client.query::<(), _>(("AUTH", "wrong")).await.unwrap_err();
+    assert_eq!(code(err), "WRONGPASS");
+
+    let () = client.query(("AUTH", "secret")).await.unwrap();
+    client.set("key", "value").await.unwrap();
+    let whoami: String = client.query(("ACL", "WHOAMI")).await.unwrap();
+    assert_eq!(whoami, "default");
+
+    //去掉密码之后新的连接不需要 AUTH
+    let () = client
+        .query(("CONFIG", "SET", "requirepass", ""))
+        .await
+        .unwrap();
+    let mut other = connect(addr).await;
+    let value: Option<String> = other.get("key").await.unwrap();
+    assert_eq!(value.as_deref(), Some("value"));
+    let err = other.query::<(), _>(("AUTH", "secret")).await.unwrap_err();
+    assert!(err.to_string().contains("without any password"), "{}", err);
+}
+
+#[tokio::test]
+async fn users_are_limited_to_their_commands_and_keys() {
+    let addr = start_server_with(Config::default()).await;
+    let mut admin = connect(addr).await;
+    let () = admin
+        .query((
+            "ACL",
+            "SETUSER",
+            "alice",
+            ("on", ">wonderland", "~cache:*", "+@read", "+set"),
+        ))
+        .await
+        .unwrap();
+
+    let mut alice = connect(addr).await;
+    auth(&mut alice, "alice", "wonderland").await.unwrap();
+    alice.set("cache:a", "1").await.unwrap();
+    let value: Option<String> = alice.get("cache:a").await.unwrap();
+    assert_eq!(value.as_deref(), Some("1"));
+
+    let err = alice.get::<Option<String>>("other").await.unwrap_err();
+    assert_eq!(code(err), "NOPERM");
+    let err = alice.del(&["cache:a"]).await.unwrap_err();
+    assert_eq!(code(err), "NOPERM");
+    assert!(
+        err_message(alice.query::<(), _>(("CONFIG", "GET", "*")).await)
+            .contains("has no permissions to run the 'config' command")
+    );
+
+    //权限的修改对已经登录的连接立即生效
+    let () = admin
+        .query(("ACL", "SETUSER", "alice", "+del"))
+        .await
+        .unwrap();
+    assert_eq!(alice.del(&["cache:a"]).await.unwrap(), 1);
+
+    //事务里被拒绝的命令让 EXEC 失败
+    let () = admin
+        .query(("ACL", "SETUSER", "alice", "+@transaction"))
+        .await
+        .unwrap();
+    let () = alice.query("MULTI").await.unwrap();
+    let err = alice.query::<(), _>(("INCR", "other")).await.unwrap_err();
+    assert_eq!(code(err), "NOPERM");
+    let err = alice.query::<(), _>("EXEC").await.unwrap_err();
+    assert_eq!(code(err), "EXECABORT");
+
+    //脚本里的命令按执行脚本的用户检查
+    let () = admin
+        .query(("ACL", "SETUSER", "alice", "+eval"))
+        .await
+        .unwrap();
+    let err = alice
+        .query::<(), _>(("EVAL", "return redis.call('GET', 'other')", 0))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("NOPERM"), "{}", err);
+
+    //wrong password, disabled user, unknown user
+    let mut other = connect(addr).await;
+    assert!(auth(&mut other, "alice", "nope").await.is_err());
+    assert!(auth(&mut other, "bob", "wonderland").await.is_err());
+    let () = admin
+        .query(("ACL", "SETUSER", "alice", "off"))
+        .await
+        .unwrap();
+    let err = auth(&mut other, "alice", "wonderland").await.unwrap_err();
+    assert_eq!(code(err), "WRONGPASS");
+}
+
+#[tokio::test]
+async fn users_are_limited_to_their_channels() {
+    let addr = start_server_with(Config::default()).await;
+    let mut admin = connect(addr).await;
+    let () = admin
+        .query((
+            "ACL",
+            "SETUSER",
+            "bob",
+            ("on", ">builder", "&news:*", "+@pubsub"),
+        ))
+        .await
+        .unwrap();
+
+    let mut bob = connect(addr).await;
+    auth(&mut bob, "bob", "builder").await.unwrap();
+    assert_eq!(bob.publish("news:today", "hello".into()).await.unwrap(), 0);
+    let err = bob.publish("sports", "goal".into()).await.unwrap_err();
+    assert_eq!(code(err), "NOPERM");
+    let err = bob
+        .subscribe(&["news:today", "sports"])
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(code(err), "NOPERM");
+}
+
+fn err_message<T>(res: Result<T, Error>) -> String {
+    match res {
+        Ok(_) => panic!("expected an error"),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn users_can_be_inspected_and_deleted() {
+    let addr = start_server_with(Config::default()).await;
+    let mut admin = connect(addr).await;
+    let hash = acl::hash_password("hashed");
+    let () = admin
+        .query((
+            "ACL",
+            "SETUSER",
+            "bob",
+            (
+                "on",
+                format!("#{}", hash),
+                "~*",
+                "&news.*",
+                "+@all",
+                "-@dangerous",
+            ),
+        ))
+        .await
+        .unwrap();
+
+    let users: Vec<String> = admin.query(("ACL", "USERS")).await.unwrap();
+    assert_eq!(users, ["bob", "default"]);
+    let list: Vec<String> = admin.query(("ACL", "LIST")).await.unwrap();
+    assert_eq!(
+        list,
+        [
+            format!("user bob on #{} ~* &news.* +@all -@dangerous", hash),
+            "user default on nopass ~* &* +@all".to_string(),
+        ]
+    );
+
+    let user: HashMap<String, Frame> = admin.query(("ACL", "GETUSER", "bob")).await.unwrap();
+    let field = |name: &str| Vec::<String>::from_redis_value(user[name].clone()).unwrap();
+    assert_eq!(field("flags"), ["on"]);
+    assert_eq!(field("passwords"), [hash.as_str()]);
+    assert_eq!(user["commands"], Frame::Bulk("+@all -@dangerous".into()));
+    assert_eq!(user["channels"], Frame::Bulk("&news.*".into()));
+    let missing: Option<Vec<Frame>> = admin.query(("ACL", "GETUSER", "nobody")).await.unwrap();
+    assert!(missing.is_none());
+
+    let mut bob = connect(addr).await;
+    auth(&mut bob, "bob", "hashed").await.unwrap();
+    let err = bob.query::<(), _>("FLUSHALL").await.unwrap_err();
+    assert_eq!(code(err), "NOPERM");
+
+    let categories: Vec<String> = admin.query(("ACL", "CAT")).await.unwrap();
+    assert!(categories.contains(&"dangerous".to_string()));
+    let dangerous: Vec<String> = admin.query(("ACL", "CAT", "dangerous")).await.unwrap();
+    assert!(dangerous.contains(&"flushall".to_string()));
+
+    let err = admin
+        .query::<(), _>(("ACL", "SETUSER", "carol", "+nosuchcommand"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("'+nosuchcommand'"), "{}", err);
+    let users: Vec<String> = admin.query(("ACL", "USERS")).await.unwrap();
+    assert_eq!(users.len(), 2);
+
+    let err = admin
+        .query::<i64, _>(("ACL", "DELUSER", "default"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("cannot be removed"), "{}", err);
+    let deleted: i64 = admin
+        .query(("ACL", "DELUSER", "bob", "nobody"))
+        .await
+        .unwrap();
+    assert_eq!(deleted, 1);
+    //被删掉的用户的连接要重新登录
+    let err = bob.ping(None).await.unwrap_err();
+    assert_eq!(code(err), "NOAUTH");
+}
+
+#[tokio::test]
+async fn users_are_loaded_from_and_saved_to_the_acl_file() {
+    let dir = std::env::temp_dir().join(format!("my-redis-acl-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join("users.acl");
+    fs::write(
+        &path,
+        "# users\nuser default on >admin ~* &* +@all\nuser reader on >books ~* +@read\n",
+    )
+    .unwrap();
+
+    let addr = start_server_with(Config {
+        aclfile: Some(path.clone()),
+        ..Config::default()
+    })
+    .await;
+
+    let mut reader = connect(addr).await;
+    let err = reader.ping(None).await.unwrap_err();
+    assert_eq!(code(err), "NOAUTH");
+    auth(&mut reader, "reader", "books").await.unwrap();
+    let err = reader.set("key", "value").await.unwrap_err();
+    assert_eq!(code(err), "NOPERM");
+
+    let mut admin = connect(addr).await;
+    auth(&mut admin, "default", "admin").await.unwrap();
+    let () = admin
+        .query(("ACL", "SETUSER", "writer", ("on", ">pens", "~*", "+@write")))
+        .await
+        .unwrap();
+    let () = admin.query(("ACL", "SAVE")).await.unwrap();
+    let saved = fs::read_to_string(&path).unwrap();
+    assert!(saved.contains(&format!(
+        "user writer on #{} ~* +@write",
+        acl::hash_password("pens")
+    )));
+
+    //ACL LOAD 用文件的内容替换所有用户；有错误时保持原样
+    fs::write(
+        &path,
+        "user default on >admin ~* &* +@all\nuser broken on +nosuchcommand\n",
+    )
+    .unwrap();
+    let err = admin.query::<(), _>(("ACL", "LOAD")).await.unwrap_err();
+    assert!(err.to_string().contains(":2:"), "{}", err);
+    let users: Vec<String> = admin.query(("ACL", "USERS")).await.unwrap();
+    assert_eq!(users, ["default", "reader", "writer"]);
+
+    fs::write(&path, saved.replace("user reader", "user reader2")).unwrap();
+    let () = admin.query(("ACL", "LOAD")).await.unwrap();
+    let users: Vec<String> = admin.query(("ACL", "USERS")).await.unwrap();
+    assert_eq!(users, ["default", "reader2", "writer"]);
+}
+
+#[tokio::test]
+async fn replicas_authenticate_with_masterauth() {
+    let master = start_server_with(Config {
+        requirepass: "secret".to_string(),
+        ..Config::default()
+    })
+    .await;
+    let replica = start_server_with(Config {
+        replicaof: Some((master.ip().to_string(), master.port())),
+        masterauth: "secret".to_string(),
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = connect(master).await;
+    let () = client.query(("AUTH", "secret")).await.unwrap();
+    client.set("replicated", "yes").await.unwrap();
+
+    let mut client = connect(replica).await;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let value: Option<String> = client.get("replicated").await.unwrap();
+        if value.is_some() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "replica did not sync");
+        time::sleep(Duration::from_millis(20)).await;
+    }
+}
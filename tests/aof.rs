@@ -0,0 +1,212 @@
+mod common;
+
+use common::{
+    bulk,
+    call,
+    connect_raw,
+    ok,
+    run_server,
+    test_dir,
+};
+use my_redis::{
+    aof::FsyncPolicy,
+    script,
+    Config,
+    Connection,
+    Frame,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use tokio::{
+    task::JoinHandle,
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+fn aof_config(dir: &Path) -> Config {
+    Config {
+        dir: dir.to_path_buf(),
+        save: Vec::new(),
+        appendonly: true,
+        //每条写命令回复之前都已经落盘，测试可以直接读文件，停掉服务端也不会丢数据
+        appendfsync: FsyncPolicy::Always,
+        ..Config::default()
+    }
+}
+
+//停掉服务端再用同一个目录启动，数据只能来自 AOF
+async fn restart(server: JoinHandle<mini_redis::Result<()>>, dir: &Path) -> Connection {
+    server.abort();
+    let (addr, _server) = run_server(aof_config(dir)).await;
+    connect_raw(addr).await
+}
+
+fn aof_path(dir: &Path) -> PathBuf {
+    dir.join("appendonly.aof")
+}
+
+//BGREWRITEAOF 并等它完成。重写后的文件只用 SET 和 PEXPIREAT 重建每个 key，
+//所以调用之前至少要执行过一次 INCR，文件里没有 INCR 了就说明新文件已经换上
+async fn rewrite(connection: &mut Connection, dir: &Path) {
+    assert_eq!(
+        call(connection, &["BGREWRITEAOF"]).await,
+        Frame::Simple("Background append only file rewriting started".to_string())
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let data = std::fs::read(aof_path(dir)).unwrap();
+        if !data.windows(4).any(|w| w == b"INCR") {
+            return;
+        }
+        assert!(Instant::now() < deadline, "AOF was not rewritten");
+        time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn ttl(connection: &mut Connection, key: &str) -> i64 {
+    match call(connection, &["TTL", key]).await {
+        Frame::Integer(ttl) => ttl,
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn writes_are_replayed_after_a_restart() {
+    let dir = test_dir("aof-replay");
+    let (addr, server) = run_server(aof_config(&dir)).await;
+    let mut c = connect_raw(addr).await;
+    assert_eq!(call(&mut c, &["SET", "key", "value"]).await, ok());
+    assert_eq!(call(&mut c, &["SET", "gone", "soon"]).await, ok());
+    assert_eq!(call(&mut c, &["DEL", "gone"]).await, Frame::Integer(1));
+    for _ in 0..3 {
+        call(&mut c, &["INCR", "counter"]).await;
+    }
+
+    let mut c = restart(server, &dir).await;
+    assert_eq!(call(&mut c, &["GET", "key"]).await, bulk("value"));
+    assert_eq!(call(&mut c, &["GET", "gone"]).await, Frame::Null);
+    assert_eq!(call(&mut c, &["GET", "counter"]).await, bulk("3"));
+}
+
+#[tokio::test]
+async fn truncated_tail_is_trimmed_when_allowed() {
+    let dir = test_dir("aof-truncated");
+    let complete = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n".to_vec();
+    let mut data = complete.clone();
+    //进程在写第二条命令的时候崩溃了
+    data.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$5\r\nother");
+    std::fs::write(aof_path(&dir), &data).unwrap();
+
+    //不允许截断时拒绝启动，文件保持原样
+    let config = Config {
+        aof_load_truncated: false,
+        ..aof_config(&dir)
+    };
+    let (_, server) = run_server(config).await;
+    let err = server.await.unwrap().unwrap_err();
+    assert!(err.to_string().contains("truncated"), "{}", err);
+    assert_eq!(std::fs::read(aof_path(&dir)).unwrap(), data);
+
+    let (addr, server) = run_server(aof_config(&dir)).await;
+    let mut c = connect_raw(addr).await;
+    assert_eq!(call(&mut c, &["GET", "key"]).await, bulk("value"));
+    assert_eq!(call(&mut c, &["GET", "other"]).await, Frame::Null);
+
+    //截掉之后新的命令接在完整的那条后面，下次启动还能正常加载
+    assert_eq!(call(&mut c, &["SET", "after", "trim"]).await, ok());
+    assert!(std::fs::read(aof_path(&dir))
+        .unwrap()
+        .starts_with(&complete));
+    let mut c = restart(server, &dir).await;
+    assert_eq!(call(&mut c, &["GET", "key"]).await, bulk("value"));
+    assert_eq!(call(&mut c, &["GET", "after"]).await, bulk("trim"));
+}
+
+#[tokio::test]
+async fn bgrewriteaof_writes_an_equivalent_file() {
+    let dir = test_dir("aof-rewrite");
+    let (addr, server) = run_server(aof_config(&dir)).await;
+    let mut c = connect_raw(addr).await;
+    for _ in 0..100 {
+        call(&mut c, &["INCR", "counter"]).await;
+    }
+    assert_eq!(call(&mut c, &["SET", "key", "value"]).await, ok());
+    assert_eq!(
+        call(&mut c, &["EXPIRE", "key", "1000"]).await,
+        Frame::Integer(1)
+    );
+    let before = std::fs::metadata(aof_path(&dir)).unwrap().len();
+
+    rewrite(&mut c, &dir).await;
+    assert!(std::fs::metadata(aof_path(&dir)).unwrap().len() < before);
+
+    //重写之后的写命令继续追加到新文件
+    call(&mut c, &["INCR", "counter"]).await;
+
+    let mut c = restart(server, &dir).await;
+    assert_eq!(call(&mut c, &["GET", "counter"]).await, bulk("101"));
+    assert_eq!(call(&mut c, &["GET", "key"]).await, bulk("value"));
+    let ttl = ttl(&mut c, "key").await;
+    assert!(ttl > 990 && ttl <= 1000, "{}", ttl);
+}
+
+#[tokio::test]
+async fn expiries_survive_a_restart() {
+    let dir = test_dir("aof-expire");
+    let (addr, server) = run_server(aof_config(&dir)).await;
+    let mut c = connect_raw(addr).await;
+    assert_eq!(
+        call(&mut c, &["SET", "later", "v", "EX", "1000"]).await,
+        ok()
+    );
+    assert_eq!(call(&mut c, &["SET", "soon", "v", "PX", "200"]).await, ok());
+    //最大的 unix 毫秒时间，重放的时候不能溢出
+    assert_eq!(call(&mut c, &["SET", "last", "v"]).await, ok());
+    let max = i64::MAX.to_string();
+    assert_eq!(
+        call(&mut c, &["PEXPIREAT", "last", &max]).await,
+        Frame::Integer(1)
+    );
+    call(&mut c, &["INCR", "counter"]).await;
+    rewrite(&mut c, &dir).await;
+    time::sleep(Duration::from_millis(300)).await;
+
+    let mut c = restart(server, &dir).await;
+    let ttl = ttl(&mut c, "later").await;
+    assert!(ttl > 990 && ttl <= 1000, "{}", ttl);
+    assert_eq!(call(&mut c, &["GET", "soon"]).await, Frame::Null);
+    match call(&mut c, &["PTTL", "last"]).await {
+        Frame::Integer(ttl) => assert!(ttl > 0, "{}", ttl),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    assert_eq!(call(&mut c, &["GET", "last"]).await, bulk("v"));
+    assert_eq!(call(&mut c, &["GET", "counter"]).await, bulk("1"));
+}
+
+#[tokio::test]
+async fn bgrewriteaof_waits_for_a_running_script() {
+    let dir = test_dir("aof-rewrite-busy");
+    let (addr, _server) = run_server(aof_config(&dir)).await;
+    let mut c = connect_raw(addr).await;
+    call(&mut c, &["INCR", "counter"]).await;
+
+    let mut runner = connect_raw(addr).await;
+    let script =
+        tokio::spawn(async move { call(&mut runner, &["EVAL", "while true do end", "0"]).await });
+    time::sleep(script::DEFAULT_TIME_LIMIT + Duration::from_millis(200)).await;
+
+    //复制快照要读 keyspace，和普通命令一样收到 BUSY
+    match call(&mut c, &["BGREWRITEAOF"]).await {
+        Frame::Error(err) => assert!(err.starts_with("BUSY"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    assert_eq!(call(&mut c, &["SCRIPT", "KILL"]).await, ok());
+    script.await.unwrap();
+    rewrite(&mut c, &dir).await;
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::start_server;
+use my_redis::{
+    reconnect::ReconnectPolicy,
+    BlockingClient,
+    Error,
+    Pipeline,
+};
+use std::{
+    net::SocketAddr,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::{
+    io,
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    runtime::Runtime,
+};
+
+//第一个连接只收不回，模拟卡住的 server；之后的连接转发给正常的 server
+async fn start_hung_server() -> SocketAddr {
+    let backend = start_server().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        //留着不关闭，也不回复
+        let (_hung, _) = listener.accept().await.unwrap();
+        loop {
+            let (mut inbound, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut outbound = TcpStream::connect(backend).await.unwrap();
+                let _ = io::copy_bidirectional(&mut inbound, &mut outbound).await;
+            });
+        }
+    });
+    addr
+}
+
+#[test]
+fn subscribers_receive_published_messages() {
+    let runtime = Runtime::new().unwrap();
+    let addr = runtime.block_on(start_server());
+    let mut subscriber = BlockingClient::connect(addr)
+        .unwrap()
+        .subscribe(&["news"])
+        .unwrap();
+    subscriber.subscribe(&["sports"]).unwrap();
+    assert_eq!(subscriber.channels(), &["news", "sports"]);
+
+    let mut publisher = BlockingClient::connect(addr).unwrap();
+    assert_eq!(publisher.publish("sports", "goal".into()).unwrap(), 1);
+    let message = subscriber.next_message().unwrap().unwrap();
+    assert_eq!(message.channel, "sports");
+    assert_eq!(message.content, "goal");
+}
+
+#[test]
+fn read_timeout_leaves_the_client_reconnectable() {
+    let runtime = Runtime::new().unwrap();
+    let addr = runtime.block_on(start_hung_server());
+    let mut client = BlockingClient::builder()
+        .connect_timeout(Duration::from_secs(1))
+        .read_timeout(Duration::from_millis(100))
+        .write_timeout(Duration::from_millis(100))
+        .connect(addr)
+        .unwrap();
+
+    let start = Instant::now();
+    let err = client.set("key", "lost").unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{:?}", err);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(client.is_broken());
+
+    //下一条命令在新连接上执行，不会读到上一条命令迟到的回复
+    client.set("key", "value").unwrap();
+    assert_eq!(client.get::<String>("key").unwrap(), "value");
+    assert!(!client.is_broken());
+}
+
+#[test]
+fn per_call_timeouts() {
+    let runtime = Runtime::new().unwrap();
+    let addr = runtime.block_on(start_hung_server());
+    let mut client = BlockingClient::connect(addr).unwrap();
+
+    let err = client
+        .get_with_timeout::<Option<String>>("key", Duration::from_millis(100))
+        .unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{:?}", err);
+    assert!(client.is_broken());
+
+    let timeout = Duration::from_secs(5);
+    client.set_with_timeout("key", 1, timeout).unwrap();
+    assert_eq!(
+        client
+            .query_with_timeout::<i64, _>(("INCR", "key"), timeout)
+            .unwrap(),
+        2
+    );
+    let replies = client
+        .pipeline_with_timeout(Pipeline::new().incr("key").get("key"), timeout)
+        .unwrap();
+    assert_eq!(replies.len(), 2);
+}
+
+#[test]
+fn timeouts_without_reconnecting() {
+    let runtime = Runtime::new().unwrap();
+    let addr = runtime.block_on(start_hung_server());
+    let mut client = BlockingClient::builder()
+        .read_timeout(Duration::from_millis(100))
+        .reconnect_policy(ReconnectPolicy::never())
+        .connect(addr)
+        .unwrap();
+
+    let err = client.get::<Option<String>>("key").unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{:?}", err);
+    //不允许重连时连接一直不可用
+    let err = client.get::<Option<String>>("key").unwrap_err();
+    assert!(matches!(err, Error::ConnectionClosed), "{:?}", err);
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::{
+    connect,
+    start_server,
+};
+use tokio::time::{
+    self,
+    Duration,
+    Instant,
+};
+
+//CLIENT INFO / CLIENT LIST 一行里某个字段的值
+fn field<'a>(line: &'a str, name: &str) -> &'a str {
+    line.split_whitespace()
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn clients_are_listed_with_their_names() {
+    let addr = start_server().await;
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+
+    let name: Option<String> = first.query(("CLIENT", "GETNAME")).await.unwrap();
+    assert_eq!(name, None);
+    let () = first
+        .query(("CLIENT", "SETNAME", "worker-1"))
+        .await
+        .unwrap();
+    let name: Option<String> = first.query(("CLIENT", "GETNAME")).await.unwrap();
+    assert_eq!(name.as_deref(), Some("worker-1"));
+    let err = first
+        .query::<(), _>(("CLIENT", "SETNAME", "has space"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("cannot contain spaces"), "{}", err);
+
+    let first_id: i64 = first.query(("CLIENT", "ID")).await.unwrap();
+    let second_id: i64 = second.query(("CLIENT", "ID")).await.unwrap();
+    assert!(second_id > first_id);
+
+    let info: String = first.query(("CLIENT", "INFO")).await.unwrap();
+    assert_eq!(field(&info, "id"), first_id.to_string());
+    assert_eq!(field(&info, "name"), "worker-1");
+    assert_eq!(field(&info, "laddr"), addr.to_string());
+    assert_eq!(field(&info, "cmd"), "client");
+    assert_eq!(field(&info, "user"), "default");
+    assert_eq!(field(&info, "flags"), "N");
+
+    second.set("key", "value").await.unwrap();
+    let list: String = first.query(("CLIENT", "LIST")).await.unwrap();
+    let lines: Vec<&str> = list.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(field(lines[1], "id"), second_id.to_string());
+    assert_eq!(field(lines[1], "name"), "");
+    assert_eq!(field(lines[1], "cmd"), "set");
+
+    let list: String = first
+        .query(("CLIENT", "LIST", "ID", second_id))
+        .await
+        .unwrap();
+    assert_eq!(list.lines().count(), 1);
+    let list: String = first
+        .query(("CLIENT", "LIST", "TYPE", "replica"))
+        .await
+        .unwrap();
+    assert_eq!(list, "");
+
+    //MULTI 之后 flags 里有 x，multi 是排队的命令数
+    let () = second.query("MULTI").await.unwrap();
+    let () = second.query(("INCR", "counter")).await.unwrap();
+    let list: String = first
+        .query(("CLIENT", "LIST", "ID", second_id))
+        .await
+        .unwrap();
+    assert_eq!(field(&list, "flags"), "x");
+    assert_eq!(field(&list, "multi"), "1");
+
+    //断开的连接从列表里消失
+    drop(second);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let list: String = first.query(("CLIENT", "LIST")).await.unwrap();
+        if list.lines().count() == 1 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "client was not removed");
+        time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn clients_can_be_killed_by_id_or_address() {
+    let addr = start_server().await;
+    let mut admin = connect(addr).await;
+    let mut victim = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    let info: String = victim.query(("CLIENT", "INFO")).await.unwrap();
+    let victim_addr = field(&info, "addr").to_string();
+    let () = admin
+        .query(("CLIENT", "KILL", victim_addr.as_str()))
+        .await
+        .unwrap();
+    assert!(victim.ping(None).await.is_err());
+    let err = admin
+        .query::<(), _>(("CLIENT", "KILL", victim_addr.as_str()))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("No such client"), "{}", err);
+
+    let other_id: i64 = other.query(("CLIENT", "ID")).await.unwrap();
+    let killed: i64 = admin
+        .query(("CLIENT", "KILL", "ID", other_id))
+        .await
+        .unwrap();
+    assert_eq!(killed, 1);
+    assert!(other.ping(None).await.is_err());
+
+    //默认 SKIPME yes，不会断开自己
+    let killed: i64 = admin
+        .query(("CLIENT", "KILL", "USER", "default"))
+        .await
+        .unwrap();
+    assert_eq!(killed, 0);
+    admin.ping(None).await.unwrap();
+
+    //SKIPME no 时先回复，然后断开
+    let killed: i64 = admin
+        .query(("CLIENT", "KILL", "USER", "default", "SKIPME", "no"))
+        .await
+        .unwrap();
+    assert_eq!(killed, 1);
+    assert!(admin.ping(None).await.is_err());
+}
+
+#[tokio::test]
+async fn client_pause_write_holds_back_writes() {
+    let addr = start_server().await;
+    let mut admin = connect(addr).await;
+    let mut client = connect(addr).await;
+
+    let () = admin
+        .query(("CLIENT", "PAUSE", 300, "WRITE"))
+        .await
+        .unwrap();
+    let start = Instant::now();
+    let value: Option<String> = client.get("key").await.unwrap();
+    assert_eq!(value, None);
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    client.set("key", "1").await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(250));
+
+    //CLIENT UNPAUSE 让等待的写命令马上执行
+    let () = admin.query(("CLIENT", "PAUSE", 10_000)).await.unwrap();
+    let writer = tokio::spawn(async move {
+        let start = Instant::now();
+        client.set("key", "2").await.unwrap();
+        start.elapsed()
+    });
+    time::sleep(Duration::from_millis(100)).await;
+    let () = admin.query(("CLIENT", "UNPAUSE")).await.unwrap();
+    let waited = writer.await.unwrap();
+    assert!(waited >= Duration::from_millis(100));
+    assert!(waited < Duration::from_secs(5));
+    let value: Option<String> = admin.get("key").await.unwrap();
+    assert_eq!(value.as_deref(), Some("2"));
+}
@@ -0,0 +1,365 @@
+mod common;
+
+use common::{
+    bulk,
+    call,
+    connect_port,
+    free_port,
+    ok,
+    wait_until,
+    Process,
+};
+use my_redis::{
+    cluster,
+    ClusterClient,
+    Frame,
+};
+use std::process::{
+    Command,
+    Stdio,
+};
+
+struct Node {
+    port: u16,
+    bus_port: u16,
+    _process: Process,
+}
+
+fn start_node() -> Node {
+    let (port, bus_port) = (free_port(), free_port());
+    let dir = std::env::temp_dir().join(format!("my-redis-cluster-test-{}", port));
+    let args = [
+        "--port".to_string(),
+        port.to_string(),
+        "--dir".to_string(),
+        dir.display().to_string(),
+        "--save".to_string(),
+        String::new(),
+        "--cluster-enabled".to_string(),
+        "yes".to_string(),
+        "--cluster-port".to_string(),
+        bus_port.to_string(),
+        "--cluster-node-timeout".to_string(),
+        "2000".to_string(),
+    ];
+    let child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(args)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    Node {
+        port,
+        bus_port,
+        _process: Process(child),
+    }
+}
+
+fn error(message: String) -> Frame {
+    Frame::Error(message)
+}
+
+async fn myid(port: u16) -> String {
+    match call(&mut connect_port(port).await, &["CLUSTER", "MYID"]).await {
+        Frame::Bulk(id) => String::from_utf8(id.to_vec()).unwrap(),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
+
+/// Start three nodes, split the slots evenly between them and wait until
+/// they all know each other.
+async fn start_cluster() -> [Node; 3] {
+    let nodes = [start_node(), start_node(), start_node()];
+
+    let ranges = [("0", "5460"), ("5461", "10922"), ("10923", "16383")];
+    for (node, (start, end)) in nodes.iter().zip(ranges) {
+        let mut connection = connect_port(node.port).await;
+        assert_eq!(
+            call(&mut connection, &["CLUSTER", "ADDSLOTSRANGE", start, end]).await,
+            ok()
+        );
+    }
+    let mut ca = connect_port(nodes[0].port).await;
+    for node in &nodes[1..] {
+        let (port, bus_port) = (node.port.to_string(), node.bus_port.to_string());
+        let reply = call(&mut ca, &["CLUSTER", "MEET", "127.0.0.1", &port, &bus_port]).await;
+        assert_eq!(reply, ok());
+    }
+
+    //通过 gossip，B 和 C 也互相认识
+    for node in &nodes {
+        wait_until(node.port, &["CLUSTER", "INFO"], |frame| match frame {
+            Frame::Bulk(info) => {
+                let info = String::from_utf8_lossy(info);
+                info.contains("cluster_state:ok\r\n") && info.contains("cluster_known_nodes:3\r\n")
+            }
+            _ => false,
+        })
+        .await;
+    }
+    nodes
+}
+
+async fn setslot(port: u16, slot: &str, action: &str, node: &str) {
+    let args = ["CLUSTER", "SETSLOT", slot, action, node];
+    assert_eq!(call(&mut connect_port(port).await, &args).await, ok());
+}
+
+#[test]
+fn keys_map_to_slots() {
+    //Redis 集群规范里的例子
+    assert_eq!(cluster::crc16(b"123456789"), 0x31c3);
+    assert_eq!(cluster::key_hash_slot(b"123456789"), 12739);
+    assert_eq!(cluster::key_hash_slot(b"foo"), 12182);
+    //只对 {} 里的部分求哈希
+    assert_eq!(
+        cluster::key_hash_slot(b"{user1000}.following"),
+        cluster::key_hash_slot(b"{user1000}.followers")
+    );
+    //第一个 {} 是空的，对整个 key 求哈希
+    assert_eq!(
+        cluster::key_hash_slot(b"foo{}{bar}"),
+        cluster::crc16(b"foo{}{bar}") % 16384
+    );
+    assert_eq!(
+        cluster::key_hash_slot(b"foo{{bar}}zap"),
+        cluster::key_hash_slot(b"{bar")
+    );
+}
+
+#[tokio::test]
+async fn cluster_redirects_and_migrates_slots() {
+    let nodes = start_cluster().await;
+    let [a, b, c] = [nodes[0].port, nodes[1].port, nodes[2].port];
+    let mut ca = connect_port(a).await;
+
+    //foo 在 slot 12182，由 C 负责
+    let moved = error(format!("MOVED 12182 127.0.0.1:{}", c));
+    assert_eq!(call(&mut ca, &["SET", "foo", "bar"]).await, moved);
+    assert_eq!(
+        call(&mut ca, &["CLUSTER", "KEYSLOT", "foo"]).await,
+        Frame::Integer(12182)
+    );
+    let mut cc = connect_port(c).await;
+    assert_eq!(call(&mut cc, &["SET", "foo", "bar"]).await, ok());
+    assert_eq!(call(&mut cc, &["GET", "foo"]).await, bulk("bar"));
+
+    //多 key 命令要求所有 key 在同一个 slot
+    let crossslot = error("CROSSSLOT Keys in request don't hash to the same slot".to_string());
+    assert_eq!(
+        call(&mut cc, &["MSET", "foo", "1", "bar", "2"]).await,
+        crossslot
+    );
+    assert_eq!(
+        call(&mut cc, &["MSET", "{foo}a", "1", "{foo}b", "2"]).await,
+        ok()
+    );
+    assert_eq!(
+        call(&mut cc, &["MGET", "{foo}a", "{foo}b"]).await,
+        Frame::Array(vec![bulk("1"), bulk("2")])
+    );
+    //没有 key 的命令在任何节点上都可以执行
+    assert_eq!(
+        call(&mut ca, &["PING"]).await,
+        Frame::Simple("PONG".to_string())
+    );
+
+    let slots = call(&mut ca, &["CLUSTER", "SLOTS"]).await;
+    let c_id = myid(c).await;
+    match &slots {
+        Frame::Array(ranges) => {
+            assert_eq!(ranges.len(), 3, "{:?}", slots);
+            let expected = Frame::Array(vec![
+                Frame::Integer(10923),
+                Frame::Integer(16383),
+                Frame::Array(vec![
+                    bulk("127.0.0.1"),
+                    Frame::Integer(c as i64),
+                    bulk(&c_id),
+                ]),
+            ]);
+            assert!(ranges.contains(&expected), "{:?}", slots);
+        }
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+
+    //把 slot 12182 从 C 迁移到 B
+    let b_id = myid(b).await;
+    let mut cb = connect_port(b).await;
+    assert_eq!(
+        call(
+            &mut cb,
+            &["CLUSTER", "SETSLOT", "12182", "IMPORTING", &c_id]
+        )
+        .await,
+        ok()
+    );
+    assert_eq!(
+        call(
+            &mut cc,
+            &["CLUSTER", "SETSLOT", "12182", "MIGRATING", &b_id]
+        )
+        .await,
+        ok()
+    );
+
+    //还没迁走的 key 照常在 C 上读写，不存在的 key 让客户端去 B
+    assert_eq!(call(&mut cc, &["GET", "foo"]).await, bulk("bar"));
+    let ask = error(format!("ASK 12182 127.0.0.1:{}", b));
+    assert_eq!(call(&mut cc, &["GET", "{foo}missing"]).await, ask);
+    //B 只在 ASKING 之后接受这个 slot 的命令
+    assert_eq!(
+        call(&mut cb, &["GET", "{foo}missing"]).await,
+        error(format!("MOVED 12182 127.0.0.1:{}", c))
+    );
+    assert_eq!(call(&mut cb, &["ASKING"]).await, ok());
+    assert_eq!(call(&mut cb, &["GET", "{foo}missing"]).await, Frame::Null);
+
+    let b_port = b.to_string();
+    let reply = call(
+        &mut cc,
+        &[
+            "MIGRATE",
+            "127.0.0.1",
+            &b_port,
+            "",
+            "0",
+            "5000",
+            "KEYS",
+            "foo",
+            "{foo}a",
+            "{foo}b",
+        ],
+    )
+    .await;
+    assert_eq!(reply, ok());
+    assert_eq!(call(&mut cc, &["GET", "foo"]).await, ask);
+    assert_eq!(
+        call(&mut cc, &["CLUSTER", "COUNTKEYSINSLOT", "12182"]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(call(&mut cb, &["ASKING"]).await, ok());
+    assert_eq!(call(&mut cb, &["GET", "foo"]).await, bulk("bar"));
+    assert_eq!(
+        call(
+            &mut cc,
+            &["MIGRATE", "127.0.0.1", &b_port, "foo", "0", "5000"]
+        )
+        .await,
+        Frame::Simple("NOKEY".to_string())
+    );
+
+    assert_eq!(
+        call(&mut cb, &["CLUSTER", "SETSLOT", "12182", "NODE", &b_id]).await,
+        ok()
+    );
+    assert_eq!(
+        call(&mut cc, &["CLUSTER", "SETSLOT", "12182", "NODE", &b_id]).await,
+        ok()
+    );
+    assert_eq!(call(&mut cb, &["GET", "foo"]).await, bulk("bar"));
+    assert_eq!(
+        call(&mut cb, &["CLUSTER", "COUNTKEYSINSLOT", "12182"]).await,
+        Frame::Integer(3)
+    );
+    let moved = error(format!("MOVED 12182 127.0.0.1:{}", b));
+    assert_eq!(call(&mut cc, &["GET", "foo"]).await, moved);
+
+    //A 没有参与迁移，通过 B 的新 config epoch 得知 slot 的新归属
+    wait_until(a, &["GET", "foo"], |frame| *frame == moved).await;
+}
+
+#[tokio::test]
+async fn cluster_client_routes_and_follows_redirects() {
+    let nodes = start_cluster().await;
+    let [a, b, c] = [nodes[0].port, nodes[1].port, nodes[2].port];
+    let seed = format!("127.0.0.1:{}", a);
+    let mut client = ClusterClient::connect(&[&seed]).await.unwrap();
+
+    //a、b、c 分别在 C、A、B 负责的 slot 里，MSET/MGET 按 slot 拆开发送
+    let pairs = [("a", "1".into()), ("b", "2".into()), ("c", "3".into())];
+    client.mset(&pairs).await.unwrap();
+    let values = client.mget(&["a", "b", "c", "missing"]).await.unwrap();
+    let expected = vec![Some("1".into()), Some("2".into()), Some("3".into()), None];
+    assert_eq!(values, expected);
+    assert_eq!(
+        call(&mut connect_port(c).await, &["GET", "a"]).await,
+        bulk("1")
+    );
+    assert_eq!(
+        call(&mut connect_port(a).await, &["GET", "b"]).await,
+        bulk("2")
+    );
+    assert_eq!(
+        call(&mut connect_port(b).await, &["GET", "c"]).await,
+        bulk("3")
+    );
+    assert_eq!(client.exists(&["a", "b", "c", "missing"]).await.unwrap(), 3);
+
+    //slot 12182 从 C 迁移到 B 的过程中，新 key 通过 ASK 写到 B
+    client.set("foo", "bar".into()).await.unwrap();
+    let (b_id, c_id) = (myid(b).await, myid(c).await);
+    setslot(b, "12182", "IMPORTING", &c_id).await;
+    setslot(c, "12182", "MIGRATING", &b_id).await;
+    client.set("{foo}new", "x".into()).await.unwrap();
+    let count = ["CLUSTER", "COUNTKEYSINSLOT", "12182"];
+    assert_eq!(
+        call(&mut connect_port(b).await, &count).await,
+        Frame::Integer(1)
+    );
+
+    let b_port = b.to_string();
+    let migrate = ["MIGRATE", "127.0.0.1", &b_port, "foo", "0", "5000"];
+    assert_eq!(call(&mut connect_port(c).await, &migrate).await, ok());
+    assert_eq!(client.get("foo").await.unwrap(), Some("bar".into()));
+
+    //迁移完成之后 C 回复 MOVED，客户端更新 slot 映射
+    setslot(b, "12182", "NODE", &b_id).await;
+    setslot(c, "12182", "NODE", &b_id).await;
+    assert_eq!(client.get("foo").await.unwrap(), Some("bar".into()));
+    assert_eq!(client.get("{foo}new").await.unwrap(), Some("x".into()));
+
+    let keys = ["a", "b", "c", "foo", "{foo}new", "missing"];
+    assert_eq!(client.del(&keys).await.unwrap(), 5);
+}
+
+#[tokio::test]
+async fn slot_checks_wait_for_a_running_script() {
+    let nodes = start_cluster().await;
+    let [b, c] = [nodes[1].port, nodes[2].port];
+    let mut cc = connect_port(c).await;
+    assert_eq!(call(&mut cc, &["SET", "foo", "bar"]).await, ok());
+    //slot 正在迁出时，检查 key 在不在本地要读 keyspace
+    setslot(c, "12182", "MIGRATING", &myid(b).await).await;
+
+    let mut runner = connect_port(c).await;
+    let script =
+        tokio::spawn(async move { call(&mut runner, &["EVAL", "while true do end", "0"]).await });
+    wait_until(
+        c,
+        &["PING"],
+        |frame| matches!(frame, Frame::Error(err) if err.starts_with("BUSY")),
+    )
+    .await;
+
+    for args in [
+        &["GET", "foo"][..],
+        &["CLUSTER", "COUNTKEYSINSLOT", "12182"],
+        &["CLUSTER", "GETKEYSINSLOT", "12182", "10"],
+    ] {
+        match call(&mut cc, args).await {
+            Frame::Error(err) => assert!(err.starts_with("BUSY"), "{:?}: {}", args, err),
+            frame => panic!("unexpected reply to {:?}: {:?}", args, frame),
+        }
+    }
+
+    assert_eq!(call(&mut cc, &["SCRIPT", "KILL"]).await, ok());
+    match script.await.unwrap() {
+        Frame::Error(err) => assert!(err.contains("Script killed"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    assert_eq!(call(&mut cc, &["GET", "foo"]).await, bulk("bar"));
+    assert_eq!(
+        call(&mut cc, &["CLUSTER", "COUNTKEYSINSLOT", "12182"]).await,
+        Frame::Integer(1)
+    );
+}
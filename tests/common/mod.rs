@@ -0,0 +1,142 @@
+//! 集成测试共用的辅助函数：启动服务端、建立连接、发送原始命令。
+//!
+//! 每个测试文件用 `mod common;` 引入，用不到的函数不算 dead code。
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use my_redis::{
+    reconnect::ReconnectPolicy,
+    server,
+    Client,
+    Config,
+    Connection,
+    Frame,
+};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    process::Child,
+};
+use tokio::{
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    task::JoinHandle,
+    time::{
+        self,
+        Duration,
+        Instant,
+    },
+};
+
+/// Start a server with the default configuration on a free port.
+pub async fn start_server() -> SocketAddr {
+    start_server_with(Config::default()).await
+}
+
+/// Start a server with `config` on a free port. Persistence goes to a
+/// directory of its own and nothing is saved automatically.
+pub async fn start_server_with(config: Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        //不读写工作目录里的持久化文件
+        dir: std::env::temp_dir().join(format!("my-redis-test-{}", addr.port())),
+        save: Vec::new(),
+        ..config
+    };
+    tokio::spawn(async move { server::run(listener, config).await });
+    addr
+}
+
+/// Start a server with `config` exactly as given, for tests that care about
+/// the persistence settings.
+pub async fn run_server(config: Config) -> (SocketAddr, JoinHandle<mini_redis::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = tokio::spawn(async move { server::run(listener, config).await });
+    (addr, handle)
+}
+
+/// An empty directory for the files of one test.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("my-redis-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A client that reports a lost connection instead of reconnecting.
+pub async fn connect(addr: SocketAddr) -> Client {
+    Client::connect_with_policy(addr, ReconnectPolicy::never())
+        .await
+        .unwrap()
+}
+
+/// A bare connection, for sending commands the client has no method for.
+pub async fn connect_raw(addr: SocketAddr) -> Connection {
+    Connection::new(TcpStream::connect(addr).await.unwrap())
+}
+
+/// Connect to a server running in a child process, waiting for it to start
+/// listening.
+pub async fn connect_port(port: u16) -> Connection {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => return Connection::new(stream),
+            Err(err) => assert!(Instant::now() < deadline, "connect {}: {}", port, err),
+        }
+        time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+pub async fn call(connection: &mut Connection, args: &[&str]) -> Frame {
+    let frame = Frame::Array(args.iter().map(|arg| bulk(arg)).collect());
+    connection.write_frame(&frame).await.unwrap();
+    connection.read_frame().await.unwrap().unwrap()
+}
+
+/// Send `args` to the server on `port` until `check` accepts the reply.
+pub async fn wait_until(port: u16, args: &[&str], check: impl Fn(&Frame) -> bool) -> Frame {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut connection = connect_port(port).await;
+    loop {
+        let frame = call(&mut connection, args).await;
+        if check(&frame) {
+            return frame;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for {:?} on {}, last reply {:?}",
+            args,
+            port,
+            frame
+        );
+        time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+pub fn bulk(value: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(value.as_bytes()))
+}
+
+pub fn ok() -> Frame {
+    Frame::Simple("OK".to_string())
+}
+
+pub fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// A child process that is killed when the test ends, panics included.
+pub struct Process(pub Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
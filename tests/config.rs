@@ -0,0 +1,190 @@
+mod common;
+
+use common::{
+    run_server,
+    test_dir,
+};
+use my_redis::{
+    cmd::glob_match,
+    config::LogLevel,
+    Client,
+    Config,
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::Duration,
+};
+
+#[test]
+fn config_files_and_arguments() {
+    let mut config = Config::default();
+    config
+        .load_str(
+            "# 注释和空行被忽略\n\
+             \n\
+             bind 127.0.0.1 ::1\n\
+             port 6380\n\
+             maxclients 128\n\
+             timeout 300\n\
+             databases 4\n\
+             loglevel warning\n\
+             dir \"/tmp/my redis\"\n\
+             save 900 1\n\
+             save 300 10\n\
+             slaveof 10.0.0.1 6379\n",
+        )
+        .unwrap();
+    assert_eq!(config.bind, vec!["127.0.0.1", "::1"]);
+    assert_eq!(config.port, 6380);
+    assert_eq!(config.maxclients, 128);
+    assert_eq!(config.timeout, Duration::from_secs(300));
+    assert_eq!(config.databases, 4);
+    assert_eq!(config.loglevel, LogLevel::Warning);
+    assert_eq!(config.dir, PathBuf::from("/tmp/my redis"));
+    assert_eq!(config.get("save").unwrap(), "900 1 300 10");
+    assert_eq!(config.get("replicaof").unwrap(), "10.0.0.1 6379");
+
+    //命令行参数覆盖配置文件
+    let args = ["--port", "7000", "--save", "", "--replicaof", "no", "one"];
+    config
+        .parse_args(args.iter().map(|arg| arg.to_string()))
+        .unwrap();
+    assert_eq!(config.port, 7000);
+    assert!(config.save.is_empty());
+    assert_eq!(config.replicaof, None);
+
+    let err = Config::default()
+        .load_str("port 6379\nmaxclients lots\n")
+        .unwrap_err();
+    assert!(err.starts_with("line 2:"), "{}", err);
+    let err = Config::default().load_str("dir \"unclosed\n").unwrap_err();
+    assert!(err.contains("quotes"), "{}", err);
+    let err = Config::default()
+        .load_str("no-such-option 1\n")
+        .unwrap_err();
+    assert!(err.contains("unknown config parameter"), "{}", err);
+}
+
+#[test]
+fn glob_patterns() {
+    assert!(glob_match(b"*", b"anything"));
+    assert!(glob_match(b"max*", b"maxclients"));
+    assert!(glob_match(b"*fsync", b"appendfsync"));
+    assert!(glob_match(b"h?llo", b"hello"));
+    assert!(!glob_match(b"h?llo", b"hllo"));
+    assert!(glob_match(b"h[ae]llo", b"hallo"));
+    assert!(!glob_match(b"h[^e]llo", b"hello"));
+    assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+    assert!(glob_match(b"h\\*llo", b"h*llo"));
+    assert!(!glob_match(b"h\\*llo", b"hello"));
+}
+
+#[tokio::test]
+async fn config_get_and_set() {
+    let config = Config {
+        dir: test_dir("config-get-set"),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let mut client = Client::connect(run_server(config).await.0).await.unwrap();
+
+    let values: HashMap<String, String> = client
+        .query(("CONFIG", "GET", "maxclients", "*fsync"))
+        .await
+        .unwrap();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values["maxclients"], "10000");
+    assert_eq!(values["appendfsync"], "everysec");
+
+    let () = client
+        .query((
+            "CONFIG",
+            "SET",
+            ("maxclients", 50),
+            ("timeout", 30),
+            ("save", "60 100"),
+        ))
+        .await
+        .unwrap();
+    let values: HashMap<String, String> = client
+        .query(("CONFIG", "GET", "maxclients", "timeout", "save"))
+        .await
+        .unwrap();
+    assert_eq!(values["maxclients"], "50");
+    assert_eq!(values["timeout"], "30");
+    assert_eq!(values["save"], "60 100");
+
+    //有一个参数不合法时整个 CONFIG SET 都不生效
+    let err = client
+        .query::<(), _>(("CONFIG", "SET", "timeout", 60, "loglevel", "loud"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("'loglevel'"), "{}", err);
+    let timeout: HashMap<String, u64> = client.query(("CONFIG", "GET", "timeout")).await.unwrap();
+    assert_eq!(timeout["timeout"], 30);
+
+    let err = client
+        .query::<(), _>(("CONFIG", "SET", "port", 7000))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("immutable"), "{}", err);
+    let err = client
+        .query::<(), _>(("CONFIG", "SET", "no-such-option", 1))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Unknown option"), "{}", err);
+    let err = client
+        .query::<(), _>(("CONFIG", "REWRITE"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("without a config file"), "{}", err);
+}
+
+#[tokio::test]
+async fn config_rewrite_updates_the_file() {
+    let dir = test_dir("config-rewrite");
+    let path = dir.join("redis.conf");
+    std::fs::write(
+        &path,
+        "# 手写的注释会保留\n\
+         maxclients 100\n\
+         save 900 1\n\
+         save 300 10\n\
+         appendfsync no\n",
+    )
+    .unwrap();
+    let mut config = Config {
+        dir: dir.clone(),
+        ..Config::default()
+    };
+    config.load_file(&path).unwrap();
+    let mut client = Client::connect(run_server(config).await.0).await.unwrap();
+
+    let () = client
+        .query((
+            "CONFIG",
+            "SET",
+            ("maxclients", 200),
+            ("save", ""),
+            ("loglevel", "verbose"),
+        ))
+        .await
+        .unwrap();
+    let () = client.query(("CONFIG", "REWRITE")).await.unwrap();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.starts_with("# 手写的注释会保留\n"), "{}", text);
+    assert!(text.contains("maxclients 200\n"), "{}", text);
+    assert!(text.contains("save \"\"\n"), "{}", text);
+    assert!(text.contains("appendfsync no\n"), "{}", text);
+    assert!(text.contains("loglevel verbose\n"), "{}", text);
+    assert_eq!(text.matches("save").count(), 1, "{}", text);
+
+    let mut reloaded = Config::default();
+    reloaded.load_file(&path).unwrap();
+    assert_eq!(reloaded.maxclients, 200);
+    assert!(reloaded.save.is_empty());
+    assert_eq!(reloaded.loglevel, LogLevel::Verbose);
+    assert_eq!(reloaded.get("dir").unwrap(), dir.display().to_string());
+}
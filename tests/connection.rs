@@ -0,0 +1,42 @@
+use my_redis::{
+    connection::MAX_PAYLOAD_LEN,
+    Connection,
+    Error,
+};
+use tokio::io::{
+    self,
+    AsyncWriteExt,
+};
+
+#[tokio::test]
+async fn payloads_follow_heartbeats() {
+    let (client, mut master) = io::duplex(64);
+    let mut connection = Connection::new(client);
+
+    master.write_all(b"\n\n$5\r\nREDIS").await.unwrap();
+    let payload = connection.read_payload().await.unwrap();
+    assert_eq!(payload, "REDIS");
+}
+
+#[tokio::test]
+async fn oversized_payload_lengths_are_rejected() {
+    let (client, mut master) = io::duplex(64);
+    let mut connection = Connection::new(client);
+
+    //长度来自对端，超过上限的直接报错，不会一直等着读
+    let header = format!("${}\r\n", MAX_PAYLOAD_LEN + 1);
+    master.write_all(header.as_bytes()).await.unwrap();
+    let err = connection.read_payload().await.unwrap_err();
+    assert!(
+        matches!(&err, Error::Protocol(message) if message.contains("too large")),
+        "{:?}",
+        err
+    );
+
+    //一直没有 \r\n 的长度也一样
+    let (client, mut master) = io::duplex(64);
+    let mut connection = Connection::new(client);
+    master.write_all(&[b'9'; 32]).await.unwrap();
+    let err = connection.read_payload().await.unwrap_err();
+    assert!(matches!(err, Error::Protocol(_)), "{:?}", err);
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::start_server;
+use my_redis::{
+    reconnect::ReconnectPolicy,
+    Client,
+    Error,
+    Pipeline,
+};
+use std::net::SocketAddr;
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpListener,
+};
+
+//接受连接之后回复 `reply` 再断开的 server
+async fn start_fake_server(reply: &'static [u8]) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(reply).await.unwrap();
+    });
+    addr
+}
+
+#[test]
+fn error_replies_are_parsed() {
+    match Error::server("WRONGTYPE Operation against a key holding the wrong kind of value") {
+        Error::ServerError { code, message } => {
+            assert_eq!(code, "WRONGTYPE");
+            assert_eq!(
+                message,
+                "Operation against a key holding the wrong kind of value"
+            );
+        }
+        err => panic!("{:?}", err),
+    }
+    let err = Error::server("MOVED 3999 127.0.0.1:6381");
+    assert_eq!(err.code(), Some("MOVED"));
+    assert_eq!(err.to_string(), "MOVED 3999 127.0.0.1:6381");
+    //没有错误码的回复归为 ERR
+    let err = Error::server("something went wrong");
+    assert_eq!(err.code(), Some("ERR"));
+    assert_eq!(err.to_string(), "ERR something went wrong");
+    assert!(!err.is_connection_error());
+    assert!(Error::ConnectionClosed.is_connection_error());
+}
+
+#[tokio::test]
+async fn errors_are_classified() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("text", "abc").await.unwrap();
+    let err = client.query::<i64, _>(("INCR", "text")).await.unwrap_err();
+    assert_eq!(err.code(), Some("ERR"));
+    let err = client.query::<(), _>(("NOSUCHCOMMAND",)).await.unwrap_err();
+    assert!(matches!(err, Error::ServerError { .. }), "{:?}", err);
+    let err = client
+        .pipeline(Pipeline::new().atomic().cmd(vec!["GET".into()]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("ERR"));
+    //服务端错误不影响连接
+    assert!(!client.is_broken());
+
+    //连接不上
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed = listener.local_addr().unwrap();
+    drop(listener);
+    let err = Client::connect(closed).await.err().unwrap();
+    assert!(matches!(err, Error::Io(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn broken_connections_are_reported() {
+    let policy = ReconnectPolicy::never();
+
+    //server 没有回复就断开
+    let mut client = Client::connect_with_policy(start_fake_server(b"").await, policy.clone())
+        .await
+        .unwrap();
+    let err = client.get::<Option<String>>("key").await.unwrap_err();
+    assert!(err.is_connection_error(), "{:?}", err);
+    assert!(client.is_broken());
+
+    //回复不是合法的 RESP
+    let mut client = Client::connect_with_policy(start_fake_server(b"?what\r\n").await, policy)
+        .await
+        .unwrap();
+    let err = client.get::<Option<String>>("key").await.unwrap_err();
+    assert!(matches!(err, Error::Protocol(_)), "{:?}", err);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    connect,
+    start_server,
+};
+
+//溢出的过期时间要报错，而且不能影响之后的命令
+#[tokio::test]
+async fn huge_relative_expire_times_are_rejected() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    let huge = i64::MAX.to_string();
+
+    for (args, command) in [
+        (vec!["SET", "k", "v", "EX", &huge], "set"),
+        (vec!["SET", "k", "v", "PX", &huge], "set"),
+        (vec!["EXPIRE", "k", &huge], "expire"),
+        (vec!["EXPIREAT", "k", &huge], "expireat"),
+    ] {
+        let err = client.query::<(), _>(args).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("ERR invalid expire time in '{}' command", command)
+        );
+    }
+
+    client.set("k", "v").await.unwrap();
+    let value: Option<String> = client.get("k").await.unwrap();
+    assert_eq!(value.as_deref(), Some("v"));
+}
+
+#[tokio::test]
+async fn expire_times_up_to_the_largest_unix_time_are_kept() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    let huge = i64::MAX.to_string();
+
+    //PEXPIREAT i64::MAX 是允许的最大值，传播的时候不能溢出
+    client.set("k", "v").await.unwrap();
+    let set: i64 = client.query(("PEXPIREAT", "k", &huge)).await.unwrap();
+    assert_eq!(set, 1);
+    let ttl: i64 = client.query(("TTL", "k")).await.unwrap();
+    assert!(ttl > 1 << 52, "{}", ttl);
+
+    let dump: Bytes = client.query(("DUMP", "k")).await.unwrap();
+    let err = client
+        .query::<(), _>(("RESTORE", "relative", &huge, dump.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'restore' command");
+    let () = client
+        .query(("RESTORE", "absolute", &huge, dump, "ABSTTL"))
+        .await
+        .unwrap();
+    let ttl: i64 = client.query(("TTL", "absolute")).await.unwrap();
+    assert!(ttl > 1 << 52, "{}", ttl);
+}
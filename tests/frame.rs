@@ -0,0 +1,25 @@
+use my_redis::frame::{
+    Error,
+    Frame,
+};
+use std::io::Cursor;
+
+#[test]
+fn unknown_type_bytes_are_rejected() {
+    //parse 一般在 check 之后调用，但它本身也不能因为不认识的类型字节 panic
+    for data in [&b"?foo\r\n"[..], b"*2\r\n:1\r\n?foo\r\n"] {
+        let err = Frame::parse(&mut Cursor::new(data)).unwrap_err();
+        assert!(matches!(err, Error::Other(_)), "{:?}", err);
+        assert!(
+            err.to_string().contains("invalid frame type byte"),
+            "{}",
+            err
+        );
+    }
+
+    let frame = Frame::parse(&mut Cursor::new(&b"*2\r\n:1\r\n$3\r\nfoo\r\n"[..])).unwrap();
+    assert_eq!(
+        frame,
+        Frame::Array(vec![Frame::Integer(1), Frame::Bulk("foo".into())])
+    );
+}
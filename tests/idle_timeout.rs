@@ -0,0 +1,101 @@
+mod common;
+
+use common::{
+    connect,
+    start_server_with,
+};
+use my_redis::Config;
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+use tokio::time;
+
+#[tokio::test]
+async fn idle_connections_are_closed() {
+    let addr = start_server_with(Config {
+        timeout: Duration::from_millis(200),
+        ..Config::default()
+    })
+    .await;
+    let mut idle = connect(addr).await;
+    let mut busy = connect(addr).await;
+    idle.ping(None).await.unwrap();
+
+    //一直在发命令的连接不会被关闭
+    for _ in 0..6 {
+        busy.ping(None).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(idle.ping(None).await.is_err());
+    busy.ping(None).await.unwrap();
+}
+
+#[tokio::test]
+async fn slow_commands_are_not_idle() {
+    let addr = start_server_with(Config {
+        timeout: Duration::from_millis(200),
+        ..Config::default()
+    })
+    .await;
+    let mut client = connect(addr).await;
+
+    //WAIT 等待 replica 的时候连接没有空闲
+    let replicas: i64 = client.query(("WAIT", 1, 500)).await.unwrap();
+    assert_eq!(replicas, 0);
+    client.ping(None).await.unwrap();
+}
+
+#[tokio::test]
+async fn subscribers_are_not_idle() {
+    let addr = start_server_with(Config {
+        timeout: Duration::from_millis(200),
+        ..Config::default()
+    })
+    .await;
+    let mut subscriber = connect(addr).await.subscribe(&["news"]).await.unwrap();
+
+    //订阅之后只接收消息，不发命令也不会被关闭
+    time::sleep(Duration::from_millis(500)).await;
+    let mut publisher = connect(addr).await;
+    assert_eq!(publisher.publish("news", "hello".into()).await.unwrap(), 1);
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!(message.content, "hello");
+}
+
+#[tokio::test]
+async fn timeout_and_socket_options_are_configurable() {
+    let addr = start_server_with(Config {
+        timeout: Duration::ZERO,
+        ..Config::default()
+    })
+    .await;
+    let mut client = connect(addr).await;
+
+    let values: HashMap<String, String> = client
+        .query(("CONFIG", "GET", "timeout", "tcp-*"))
+        .await
+        .unwrap();
+    assert_eq!(values["timeout"], "0");
+    assert_eq!(values["tcp-keepalive"], "300");
+    assert_eq!(values["tcp-nodelay"], "yes");
+
+    let () = client
+        .query((
+            "CONFIG",
+            "SET",
+            ("tcp-keepalive", 60),
+            ("tcp-nodelay", "no"),
+        ))
+        .await
+        .unwrap();
+    //新的设置用于之后的连接
+    let mut other = connect(addr).await;
+    other.ping(None).await.unwrap();
+
+    //超时时间在运行时修改后，从下一次等待命令开始生效
+    let () = client.query(("CONFIG", "SET", "timeout", 1)).await.unwrap();
+    other.ping(None).await.unwrap();
+    time::sleep(Duration::from_millis(1500)).await;
+    assert!(other.ping(None).await.is_err());
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{
+    connect,
+    start_server,
+};
+use my_redis::{
+    script,
+    Client,
+};
+use std::collections::HashMap;
+use tokio::time::{
+    self,
+    Duration,
+};
+
+//INFO 的回复：`# Section` 标题和 `key:value` 行，按 \r\n 分隔
+async fn info(client: &mut Client, section: &str) -> (Vec<String>, HashMap<String, String>) {
+    let reply: String = client.query(("INFO", section)).await.unwrap();
+    let mut sections = Vec::new();
+    let mut fields = HashMap::new();
+    for line in reply.split("\r\n").filter(|line| !line.is_empty()) {
+        match line.strip_prefix("# ") {
+            Some(section) => sections.push(section.to_string()),
+            None => {
+                let (key, value) = line.split_once(':').unwrap();
+                fields.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    (sections, fields)
+}
+
+#[tokio::test]
+async fn default_sections_and_their_fields() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    let _other = connect(addr).await;
+
+    let (sections, fields) = info(&mut client, "default").await;
+    assert_eq!(
+        sections,
+        [
+            "Server",
+            "Clients",
+            "Memory",
+            "Stats",
+            "Replication",
+            "Cluster",
+            "Keyspace"
+        ]
+    );
+    assert_eq!(fields["tcp_port"], addr.port().to_string());
+    assert_eq!(fields["redis_mode"], "standalone");
+    assert_eq!(fields["run_id"].len(), 40);
+    assert_eq!(fields["connected_clients"], "2");
+    assert_eq!(fields["role"], "master");
+    assert!(fields["uptime_in_seconds"].parse::<u64>().is_ok());
+    assert!(fields["used_memory"].parse::<u64>().is_ok());
+    //空的数据库不列出来
+    assert!(!fields.contains_key("db0"));
+
+    let (sections, _) = info(&mut client, "all").await;
+    assert!(sections.contains(&"Commandstats".to_string()));
+    let (sections, _) = info(&mut client, "nosuchsection").await;
+    assert!(sections.is_empty());
+}
+
+#[tokio::test]
+async fn stats_count_commands_hits_misses_and_expired_keys() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client.set("a", "1").await.unwrap();
+    client.set("b", "2").await.unwrap();
+    let _: Option<String> = client.get("a").await.unwrap();
+    let _: Option<String> = client.get("missing").await.unwrap();
+    let () = client
+        .query(("SET", "short", "lived", "PX", 20))
+        .await
+        .unwrap();
+    let () = client
+        .query(("SET", "long", "lived", "EX", 1000))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(100)).await;
+
+    let (_, fields) = info(&mut client, "stats").await;
+    assert_eq!(fields["keyspace_hits"], "1");
+    assert_eq!(fields["keyspace_misses"], "1");
+    assert_eq!(fields["expired_keys"], "1");
+    assert_eq!(fields["total_connections_received"], "1");
+    assert!(fields["total_net_input_bytes"].parse::<u64>().unwrap() > 0);
+    assert!(fields["total_net_output_bytes"].parse::<u64>().unwrap() > 0);
+    //6 条命令，INFO 本身在回复之后才计数
+    assert_eq!(fields["total_commands_processed"], "6");
+
+    let (sections, fields) = info(&mut client, "keyspace").await;
+    assert_eq!(sections, ["Keyspace"]);
+    let db0 = &fields["db0"];
+    assert!(db0.starts_with("keys=3,expires=1,avg_ttl="), "{}", db0);
+    let avg_ttl: u64 = db0.rsplit('=').next().unwrap().parse().unwrap();
+    assert!(avg_ttl > 990_000 && avg_ttl <= 1_000_000, "{}", avg_ttl);
+}
+
+#[tokio::test]
+async fn commandstats_count_calls_and_failures() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client.set("key", "value").await.unwrap();
+    client.set("key", "value").await.unwrap();
+    //INCR 一个不是整数的值：执行失败
+    assert!(client.query::<i64, _>(("INCR", "key")).await.is_err());
+    //参数个数不对：没有执行就被拒绝
+    assert!(client.query::<(), _>(("GET", "a", "b")).await.is_err());
+
+    let (sections, fields) = info(&mut client, "commandstats").await;
+    assert_eq!(sections, ["Commandstats"]);
+    let stats = |name: &str| -> HashMap<String, String> {
+        fields[&format!("cmdstat_{}", name)]
+            .split(',')
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap();
+                (key.to_string(), value.to_string())
+            })
+            .collect()
+    };
+    assert_eq!(stats("set")["calls"], "2");
+    assert_eq!(stats("set")["failed_calls"], "0");
+    assert_eq!(stats("incr")["calls"], "1");
+    assert_eq!(stats("incr")["failed_calls"], "1");
+    assert_eq!(stats("get")["calls"], "0");
+    assert_eq!(stats("get")["rejected_calls"], "1");
+    assert!(stats("set")["usec_per_call"].parse::<f64>().is_ok());
+}
+
+#[tokio::test]
+async fn keyspace_sections_wait_for_a_running_script() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+    client.set("key", "value").await.unwrap();
+
+    let mut runner = connect(addr).await;
+    let script = tokio::spawn(async move {
+        runner
+            .query::<(), _>(("EVAL", "while true do end", 0))
+            .await
+            .unwrap_err()
+    });
+    time::sleep(script::DEFAULT_TIME_LIMIT + Duration::from_millis(200)).await;
+
+    //读 keyspace 的 section 和普通命令一样收到 BUSY，其他 section 照常返回
+    for section in ["memory", "stats", "keyspace", "default"] {
+        let err = client
+            .query::<String, _>(("INFO", section))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("BUSY"), "{}", err);
+    }
+    let (sections, _) = info(&mut client, "server").await;
+    assert_eq!(sections, ["Server"]);
+
+    let () = client.query(("SCRIPT", "KILL")).await.unwrap();
+    script.await.unwrap();
+    let (_, fields) = info(&mut client, "keyspace").await;
+    assert!(fields["db0"].starts_with("keys=1,"), "{:?}", fields);
+}
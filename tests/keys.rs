@@ -0,0 +1,85 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    bulk,
+    connect_raw,
+    ok,
+    start_server,
+};
+use my_redis::{
+    Connection,
+    Frame,
+};
+
+//参数原样发送，可以带上不是 UTF-8 的字节
+async fn call(connection: &mut Connection, args: &[&[u8]]) -> Frame {
+    let args = args
+        .iter()
+        .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg)))
+        .collect();
+    connection.write_frame(&Frame::Array(args)).await.unwrap();
+    connection.read_frame().await.unwrap().unwrap()
+}
+
+fn invalid_key() -> Frame {
+    Frame::Error("ERR invalid key: keys must be valid UTF-8".to_string())
+}
+
+#[tokio::test]
+async fn keys_that_are_not_utf8_are_rejected() {
+    let addr = start_server().await;
+    let mut connection = connect_raw(addr).await;
+
+    //两个不同的 key 按 UTF-8 有损转换之后都是 "\u{FFFD}"，不能把它们当成同一个 key
+    assert_eq!(
+        call(&mut connection, &[b"SET", b"\xff", b"1"]).await,
+        invalid_key()
+    );
+    assert_eq!(
+        call(&mut connection, &[b"GET", b"\xfe"]).await,
+        invalid_key()
+    );
+    let replacement = "\u{FFFD}".as_bytes();
+    assert_eq!(
+        call(&mut connection, &[b"SET", replacement, b"v"]).await,
+        ok()
+    );
+    assert_eq!(
+        call(&mut connection, &[b"GET", b"\xff"]).await,
+        invalid_key()
+    );
+    assert_eq!(
+        call(&mut connection, &[b"DEL", b"\xff"]).await,
+        invalid_key()
+    );
+    assert_eq!(
+        call(&mut connection, &[b"GET", replacement]).await,
+        bulk("v")
+    );
+
+    //有一个 key 不合法时整条命令都不执行
+    let mset: &[&[u8]] = &[b"MSET", b"a", b"1", b"\xff", b"2"];
+    assert_eq!(call(&mut connection, mset).await, invalid_key());
+    assert_eq!(call(&mut connection, &[b"GET", b"a"]).await, Frame::Null);
+
+    //值可以是任意字节
+    assert_eq!(call(&mut connection, &[b"SET", b"a", b"\xff"]).await, ok());
+    assert_eq!(
+        call(&mut connection, &[b"GET", b"a"]).await,
+        Frame::Bulk(Bytes::from_static(b"\xff"))
+    );
+    assert_eq!(call(&mut connection, &[b"DBSIZE"]).await, Frame::Integer(2));
+
+    //脚本里的 redis.call 也一样
+    let script: &[&[u8]] = &[
+        b"EVAL",
+        b"return redis.call('SET', KEYS[1], 1)",
+        b"1",
+        b"\xff",
+    ];
+    match call(&mut connection, script).await {
+        Frame::Error(err) => assert!(err.contains("keys must be valid UTF-8"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{
+    connect,
+    start_server_with,
+};
+use my_redis::Config;
+use std::{
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::time;
+
+//连接被拒绝时第一条命令就会收到错误
+async fn is_rejected(addr: SocketAddr) -> bool {
+    let mut client = connect(addr).await;
+    match client.ping(None).await {
+        Ok(_) => false,
+        Err(err) => {
+            assert_eq!(err.to_string(), "ERR max number of clients reached");
+            true
+        }
+    }
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_are_rejected() {
+    let addr = start_server_with(Config {
+        maxclients: 2,
+        ..Config::default()
+    })
+    .await;
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+    first.ping(None).await.unwrap();
+    second.ping(None).await.unwrap();
+    assert!(is_rejected(addr).await);
+
+    //断开一个之后就有空位了
+    drop(second);
+    time::sleep(Duration::from_millis(100)).await;
+    let mut third = connect(addr).await;
+    third.ping(None).await.unwrap();
+    assert!(is_rejected(addr).await);
+}
+
+#[tokio::test]
+async fn config_set_changes_the_limit() {
+    let addr = start_server_with(Config {
+        maxclients: 2,
+        ..Config::default()
+    })
+    .await;
+    let mut admin = connect(addr).await;
+    let mut other = connect(addr).await;
+    other.ping(None).await.unwrap();
+
+    let () = admin
+        .query(("CONFIG", "SET", "maxclients", 3))
+        .await
+        .unwrap();
+    let mut third = connect(addr).await;
+    third.ping(None).await.unwrap();
+    assert!(is_rejected(addr).await);
+
+    //调小上限不会断开已有的连接，但要等连接数降到上限以下才能接受新连接
+    let () = admin
+        .query(("CONFIG", "SET", "maxclients", 2))
+        .await
+        .unwrap();
+    third.ping(None).await.unwrap();
+    drop(third);
+    time::sleep(Duration::from_millis(100)).await;
+    assert!(is_rejected(addr).await);
+    drop(other);
+    time::sleep(Duration::from_millis(100)).await;
+    let mut client = connect(addr).await;
+    client.ping(None).await.unwrap();
+    assert!(is_rejected(addr).await);
+}
@@ -0,0 +1,132 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use common::{
+    call,
+    connect,
+    connect_raw,
+    free_port,
+    ok,
+    start_server_with,
+};
+use my_redis::{
+    Config,
+    Frame,
+};
+use std::net::SocketAddr;
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::TcpStream,
+    time::{
+        self,
+        Duration,
+    },
+};
+
+//返回客户端端口和 metrics 端口
+async fn start_server() -> (SocketAddr, SocketAddr) {
+    //先占一个空闲端口再释放，交给服务端绑定
+    let metrics_port = free_port();
+    let addr = start_server_with(Config {
+        metrics_port,
+        ..Config::default()
+    })
+    .await;
+    (addr, SocketAddr::from(([127, 0, 0, 1], metrics_port)))
+}
+
+//发一个 HTTP 请求，返回状态行和 body
+async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => break stream,
+            //服务端可能还没开始监听
+            Err(_) => time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+    (status, body.to_string())
+}
+
+fn value(body: &str, series: &str) -> f64 {
+    body.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {} in\n{}", series, body))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn metrics_are_served_in_prometheus_format() {
+    let (addr, metrics_addr) = start_server().await;
+    let mut client = connect(addr).await;
+    client.set("a", "1").await.unwrap();
+    client.set("b", "2").await.unwrap();
+    let _: Option<String> = client.get("a").await.unwrap();
+    let () = client
+        .query(("SET", "short", "lived", "PX", 10))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+
+    let (status, body) = get(metrics_addr, "/metrics").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body.contains("# TYPE redis_command_duration_seconds histogram"));
+    assert_eq!(
+        value(&body, "redis_command_duration_seconds_count{cmd=\"set\"}"),
+        3.0
+    );
+    assert_eq!(
+        value(
+            &body,
+            "redis_command_duration_seconds_bucket{cmd=\"set\",le=\"+Inf\"}"
+        ),
+        3.0
+    );
+    assert_eq!(value(&body, "redis_connected_clients"), 1.0);
+    assert_eq!(value(&body, "redis_db_keys{db=\"db0\"}"), 2.0);
+    assert_eq!(value(&body, "redis_expired_keys_total"), 1.0);
+    assert_eq!(value(&body, "redis_keyspace_hits_total"), 1.0);
+    assert_eq!(value(&body, "redis_rdb_changes_since_last_save"), 4.0);
+    assert_eq!(value(&body, "redis_aof_enabled"), 0.0);
+    assert!(value(&body, "redis_net_input_bytes_total") > 0.0);
+    assert!(value(&body, "redis_net_output_bytes_total") > 0.0);
+
+    let (status, _) = get(metrics_addr, "/other").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[tokio::test]
+async fn scrapes_during_a_script_serve_the_last_keyspace_figures() {
+    let (addr, metrics_addr) = start_server().await;
+    let mut client = connect(addr).await;
+    client.set("a", "1").await.unwrap();
+    let (_, body) = get(metrics_addr, "/metrics").await;
+    assert_eq!(value(&body, "redis_db_keys{db=\"db0\"}"), 1.0);
+
+    let mut runner = connect_raw(addr).await;
+    let script =
+        tokio::spawn(async move { call(&mut runner, &["EVAL", "while true do end", "0"]).await });
+    time::sleep(Duration::from_millis(200)).await;
+
+    //脚本还在执行，抓取不等它结束
+    let (status, body) = get(metrics_addr, "/metrics").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(value(&body, "redis_db_keys{db=\"db0\"}"), 1.0);
+
+    let mut admin = connect_raw(addr).await;
+    assert_eq!(call(&mut admin, &["SCRIPT", "KILL"]).await, ok());
+    match script.await.unwrap() {
+        Frame::Error(err) => assert!(err.contains("Script killed"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    bulk,
+    start_server,
+};
+use my_redis::{
+    BlockingClient,
+    Client,
+    Frame,
+    Pipeline,
+};
+
+#[tokio::test]
+async fn replies_come_back_in_order() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+
+    let mut pipeline = Pipeline::new();
+    for i in 0..10_000 {
+        pipeline.set(&format!("key{}", i), Bytes::from(i.to_string()));
+    }
+    let replies = client.pipeline(&pipeline).await.unwrap();
+    assert_eq!(replies.len(), 10_000);
+    assert!(replies.iter().all(|reply| *reply == "OK"));
+
+    //单条命令的错误回复留在结果里，不影响其他命令
+    let replies = client
+        .pipeline(
+            Pipeline::new()
+                .get("key42")
+                .incr("key42")
+                .set("text", "abc")
+                .incr("text")
+                .get("missing"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(replies[0], bulk("42"));
+    assert_eq!(replies[1], Frame::Integer(43));
+    assert_eq!(replies[2], "OK");
+    assert!(matches!(&replies[3], Frame::Error(err) if err.contains("not an integer")));
+    assert_eq!(replies[4], Frame::Null);
+    assert!(!client.is_broken());
+
+    assert!(client.pipeline(&Pipeline::new()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn atomic_pipelines_run_as_transactions() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+
+    let replies = client
+        .pipeline(
+            Pipeline::new()
+                .atomic()
+                .set("counter", 10)
+                .incr_by("counter", 5)
+                .get("counter"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        replies,
+        vec![Frame::Simple("OK".into()), Frame::Integer(15), bulk("15")]
+    );
+
+    //排队时出错的事务整个被丢弃
+    let err = client
+        .pipeline(
+            Pipeline::new()
+                .atomic()
+                .incr("counter")
+                .cmd(vec!["GET".into()]),
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("wrong number of arguments"),
+        "{}",
+        err
+    );
+    assert_eq!(
+        client.get::<Option<String>>("counter").await.unwrap(),
+        Some("15".into())
+    );
+
+    //执行时出错的命令不影响事务里的其他命令
+    let replies = client
+        .pipeline(
+            Pipeline::new()
+                .atomic()
+                .set("text", "abc")
+                .incr("text")
+                .incr("counter"),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(&replies[1], Frame::Error(_)));
+    assert_eq!(replies[2], Frame::Integer(16));
+}
+
+#[tokio::test]
+async fn multi_exec_commands() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+
+    let err = client.command(vec!["EXEC".into()]).await.unwrap_err();
+    assert!(err.to_string().contains("EXEC without MULTI"), "{}", err);
+    let err = client.command(vec!["DISCARD".into()]).await.unwrap_err();
+    assert!(err.to_string().contains("DISCARD without MULTI"), "{}", err);
+
+    client.command(vec!["MULTI".into()]).await.unwrap();
+    let err = client.command(vec!["MULTI".into()]).await.unwrap_err();
+    assert!(err.to_string().contains("nested"), "{}", err);
+    let queued = client
+        .command(vec!["SET".into(), "key".into(), "value".into()])
+        .await
+        .unwrap();
+    assert_eq!(queued, "QUEUED");
+    client.command(vec!["DISCARD".into()]).await.unwrap();
+    assert_eq!(client.get::<Option<String>>("key").await.unwrap(), None);
+
+    client.command(vec!["MULTI".into()]).await.unwrap();
+    let err = client.command(vec!["SAVE".into()]).await.unwrap_err();
+    assert!(err.to_string().contains("inside a transaction"), "{}", err);
+    let err = client.command(vec!["EXEC".into()]).await.unwrap_err();
+    assert!(err.to_string().starts_with("EXECABORT"), "{}", err);
+}
+
+#[test]
+fn blocking_client_pipelines() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let addr = runtime.block_on(start_server());
+    let mut client = BlockingClient::connect(addr).unwrap();
+
+    let mut pipeline = Pipeline::new();
+    for i in 0..10_000 {
+        pipeline.set(&format!("key{}", i), "value");
+    }
+    assert_eq!(client.pipeline(&pipeline).unwrap().len(), 10_000);
+
+    let replies = client
+        .pipeline(Pipeline::new().atomic().del(&["key0", "key1"]).get("key2"))
+        .unwrap();
+    assert_eq!(replies, vec![Frame::Integer(2), bulk("value")]);
+    assert_eq!(client.get::<Option<String>>("key0").unwrap(), None);
+}
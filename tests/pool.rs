@@ -0,0 +1,130 @@
+mod common;
+
+use common::start_server;
+use my_redis::{
+    pool::{
+        PoolConfig,
+        PoolStats,
+    },
+    Error,
+    Pool,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpListener,
+    time::{
+        self,
+        Duration,
+    },
+};
+
+fn stats(in_use: usize, idle: usize, waiters: usize) -> PoolStats {
+    PoolStats {
+        in_use,
+        idle,
+        waiters,
+    }
+}
+
+#[tokio::test]
+async fn connections_are_reused_and_limited() {
+    let addr = start_server().await.to_string();
+    let config = PoolConfig {
+        max_size: 2,
+        acquire_timeout: Duration::from_millis(200),
+        ..PoolConfig::default()
+    };
+    let pool = Pool::new(&addr, config);
+
+    let mut first = pool.get().await.unwrap();
+    first.set("key", "value").await.unwrap();
+    let second = pool.get().await.unwrap();
+    assert_eq!(pool.stats(), stats(2, 0, 0));
+
+    //两个连接都借出去了，第三个调用方排队直到超时
+    let waiter = {
+        let pool = pool.clone();
+        tokio::spawn(async move { pool.get().await.map(|_| ()) })
+    };
+    time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pool.stats(), stats(2, 0, 1));
+    let err = waiter.await.unwrap().unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{:?}", err);
+    assert_eq!(pool.stats(), stats(2, 0, 0));
+
+    //归还之后可以再借出，而且复用的是同一批连接
+    drop(first);
+    drop(second);
+    assert_eq!(pool.stats(), stats(0, 2, 0));
+    let mut client = pool.get().await.unwrap();
+    assert_eq!(
+        client.get::<Option<String>>("key").await.unwrap(),
+        Some("value".into())
+    );
+    assert_eq!(pool.stats(), stats(1, 1, 0));
+}
+
+#[tokio::test]
+async fn broken_connections_are_not_recycled() {
+    //服务端的错误回复不影响连接
+    let addr = start_server().await.to_string();
+    let pool = Pool::new(&addr, PoolConfig::default());
+    let mut client = pool.get().await.unwrap();
+    client.set("text", "abc").await.unwrap();
+    let reply = client.command(vec!["INCR".into(), "text".into()]).await;
+    assert!(reply.is_err());
+    assert!(!client.is_broken());
+    drop(client);
+    assert_eq!(pool.stats(), stats(0, 1, 0));
+
+    //一直不回复的 server：请求等到一半被取消，连接和服务端已经不同步
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        let _ = stream.read_to_end(&mut buf).await;
+    });
+    let config = PoolConfig {
+        health_check: false,
+        ..PoolConfig::default()
+    };
+    let pool = Pool::new(&addr, config);
+    let mut client = pool.get().await.unwrap();
+    let reply = time::timeout(
+        Duration::from_millis(50),
+        client.get::<Option<String>>("key"),
+    )
+    .await;
+    assert!(reply.is_err());
+    assert!(client.is_broken());
+    drop(client);
+    assert_eq!(pool.stats(), stats(0, 0, 0));
+}
+
+#[tokio::test]
+async fn idle_connections_are_evicted_down_to_min_size() {
+    let addr = start_server().await.to_string();
+    let config = PoolConfig {
+        min_size: 1,
+        idle_timeout: Duration::from_millis(200),
+        ..PoolConfig::default()
+    };
+    let pool = Pool::new(&addr, config);
+
+    //后台任务先补足 min_size
+    time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(pool.stats(), stats(0, 1, 0));
+
+    let clients = [
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+    ];
+    assert_eq!(pool.stats(), stats(3, 0, 0));
+    drop(clients);
+    assert_eq!(pool.stats(), stats(0, 3, 0));
+
+    time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(pool.stats(), stats(0, 1, 0));
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{
+    bulk,
+    call,
+    connect,
+    connect_raw,
+    start_server,
+};
+use my_redis::{
+    client::Message,
+    Frame,
+};
+
+fn push(kind: &str, channel: &str, count: i64) -> Frame {
+    Frame::Array(vec![bulk(kind), bulk(channel), Frame::Integer(count)])
+}
+
+#[tokio::test]
+async fn messages_reach_every_subscriber_of_the_channel() {
+    let addr = start_server().await;
+    let mut first = connect(addr).await.subscribe(&["news"]).await.unwrap();
+    let mut second = connect(addr)
+        .await
+        .subscribe(&["news", "sports"])
+        .await
+        .unwrap();
+    let mut publisher = connect(addr).await;
+
+    assert_eq!(publisher.publish("news", "hello".into()).await.unwrap(), 2);
+    assert_eq!(publisher.publish("sports", "goal".into()).await.unwrap(), 1);
+    assert_eq!(
+        publisher.publish("weather", "rain".into()).await.unwrap(),
+        0
+    );
+
+    let message = |channel: &str, content: &'static str| Message {
+        channel: channel.to_string(),
+        content: content.into(),
+    };
+    assert_eq!(
+        first.next_message().await.unwrap(),
+        Some(message("news", "hello"))
+    );
+    assert_eq!(
+        second.next_message().await.unwrap(),
+        Some(message("news", "hello"))
+    );
+    assert_eq!(
+        second.next_message().await.unwrap(),
+        Some(message("sports", "goal"))
+    );
+
+    //断开的订阅者不再计数
+    drop(first);
+    let subscribers: i64 = publisher.query(("PUBLISH", "news", "again")).await.unwrap();
+    assert_eq!(subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribed_connections_only_accept_subscribe_commands() {
+    let addr = start_server().await;
+    let mut connection = connect_raw(addr).await;
+
+    assert_eq!(
+        call(&mut connection, &["SUBSCRIBE", "news"]).await,
+        push("subscribe", "news", 1)
+    );
+    let err = call(&mut connection, &["GET", "foo"]).await;
+    assert!(
+        matches!(&err, Frame::Error(message) if message.contains("only (UN)SUBSCRIBE / PING")),
+        "{:?}",
+        err
+    );
+    assert_eq!(
+        call(&mut connection, &["PING"]).await,
+        Frame::Array(vec![bulk("pong"), bulk("")])
+    );
+
+    let mut client = connect(addr).await;
+    let list: String = client
+        .query(("CLIENT", "LIST", "TYPE", "pubsub"))
+        .await
+        .unwrap();
+    assert_eq!(list.lines().count(), 1, "{}", list);
+    assert!(
+        list.contains("flags=P") && list.contains("sub=1"),
+        "{}",
+        list
+    );
+
+    //退订所有频道之后回到普通状态
+    assert_eq!(
+        call(&mut connection, &["UNSUBSCRIBE"]).await,
+        push("unsubscribe", "news", 0)
+    );
+    assert_eq!(call(&mut connection, &["GET", "foo"]).await, Frame::Null);
+    assert_eq!(
+        call(&mut connection, &["UNSUBSCRIBE"]).await,
+        Frame::Array(vec![bulk("unsubscribe"), Frame::Null, Frame::Integer(0)])
+    );
+}
@@ -0,0 +1,285 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    bulk,
+    call,
+    connect_raw,
+    ok,
+    run_server,
+    test_dir,
+};
+use my_redis::{
+    db::{
+        Entry,
+        State,
+    },
+    rdb::{
+        self,
+        SaveRule,
+    },
+    script,
+    Config,
+    Connection,
+    Frame,
+};
+use std::{
+    io,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::time::{
+    self,
+    Duration,
+    Instant,
+};
+
+//拼一个完整的 RDB 文件：文件头、`body`、EOF 和 CRC64
+fn rdb_file(body: &[u8]) -> Vec<u8> {
+    let mut data = b"REDIS0009".to_vec();
+    data.extend_from_slice(body);
+    data.push(0xFF);
+    let checksum = rdb::crc64(0, &data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+#[test]
+fn oversized_lzf_strings_are_rejected() {
+    //key "k"，value 是 LZF 编码：压缩后 2 字节，声称解压后有 2^62 字节
+    let mut body = vec![0x00, 0x01, b'k', 0xC3, 0x02, 0x81];
+    body.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    body.extend_from_slice(&[0x00, b'v']);
+    let err = rdb::decode(&rdb_file(&body), &mut State::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    //解压出来的数据比声明的长度多
+    let body = [0x00, 0x01, b'k', 0xC3, 0x03, 0x01, 0x01, b'a', b'b'];
+    let err = rdb::decode(&rdb_file(&body), &mut State::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+fn entry(data: &[u8], expires_at: Option<Instant>) -> Entry {
+    Entry {
+        data: Bytes::copy_from_slice(data),
+        expires_at,
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn snapshots_round_trip() {
+    let later = Instant::now() + Duration::from_secs(100);
+    let long = vec![b'x'; 20_000];
+    let snapshot = vec![
+        ("plain".to_string(), entry(b"value", None)),
+        ("binary".to_string(), entry(b"\x00\xff\r\n", None)),
+        ("empty".to_string(), entry(b"", None)),
+        ("long".to_string(), entry(&long, None)),
+        ("later".to_string(), entry(b"v", Some(later))),
+    ];
+    let data = rdb::encode(snapshot);
+
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&data, &mut state).unwrap(), 5);
+    assert_eq!(state.get("plain").unwrap(), "value");
+    assert_eq!(state.get("binary").unwrap(), &b"\x00\xff\r\n"[..]);
+    assert_eq!(state.get("empty").unwrap(), "");
+    assert_eq!(state.get("long").unwrap(), long);
+    assert_eq!(state.entry("plain").unwrap().expires_at, None);
+
+    //文件里存的是 unix 毫秒时间，编码和解码时各自读一次时钟，所以只能比较到毫秒级附近
+    let expires_at = state.entry("later").unwrap().expires_at.unwrap();
+    let diff = expires_at.max(later) - expires_at.min(later);
+    assert!(diff < Duration::from_millis(100), "{:?}", diff);
+}
+
+#[test]
+fn checksum_mismatch_is_rejected() {
+    let mut data = rdb::encode(vec![("key".to_string(), entry(b"value", None))]);
+    let value = data.windows(5).position(|w| w == b"value").unwrap();
+    data[value] = b'V';
+    let err = rdb::decode(&data, &mut State::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("checksum"), "{}", err);
+
+    //checksum 为0表示写入方没有计算校验和，照常加载
+    let len = data.len();
+    data[len - 8..].fill(0);
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&data, &mut state).unwrap(), 1);
+    assert_eq!(state.get("key").unwrap(), "Value");
+}
+
+#[test]
+fn lzf_compressed_strings_are_loaded() {
+    //"aaaaaaaaaa"：一个字面量 'a'，再从前一个字节开始回溯复制 9 个字节
+    let body = [
+        0x00, 0x01, b'k', 0xC3, 0x05, 0x0A, 0x00, b'a', 0xE0, 0x00, 0x00,
+    ];
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&rdb_file(&body), &mut state).unwrap(), 1);
+    assert_eq!(state.get("k").unwrap(), "aaaaaaaaaa");
+
+    //回溯的位置在已经解压的数据之前
+    let body = [0x00, 0x01, b'k', 0xC3, 0x03, 0x0A, 0xE0, 0x00, 0x00];
+    let err = rdb::decode(&rdb_file(&body), &mut State::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn expired_keys_are_skipped() {
+    let past = Instant::now() - Duration::from_secs(1);
+    let data = rdb::encode(vec![
+        ("gone".to_string(), entry(b"v", Some(past))),
+        ("kept".to_string(), entry(b"v", None)),
+    ]);
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&data, &mut state).unwrap(), 1);
+    assert_eq!(state.get("gone"), None);
+
+    //老版本的文件用秒数记录过期时间
+    let seconds_opcode = |key: u8, secs: u64| {
+        let mut body = vec![0xFD];
+        body.extend_from_slice(&(secs as u32).to_le_bytes());
+        body.extend_from_slice(&[0x00, 0x01, key, 0x01, b'v']);
+        body
+    };
+    let mut body = seconds_opcode(b'a', unix_secs() + 100);
+    body.extend(seconds_opcode(b'b', unix_secs() - 100));
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&rdb_file(&body), &mut state).unwrap(), 1);
+    let ttl = state.entry("a").unwrap().expires_at.unwrap() - Instant::now();
+    assert!(ttl > Duration::from_secs(98), "{:?}", ttl);
+    assert_eq!(state.get("b"), None);
+
+    //超出 unix 毫秒范围的过期时间
+    let mut body = vec![0xFC];
+    body.extend_from_slice(&u64::MAX.to_le_bytes());
+    body.extend_from_slice(&[0x00, 0x01, b'k', 0x01, b'v']);
+    let err = rdb::decode(&rdb_file(&body), &mut State::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn save_rules_write_a_snapshot() {
+    let dir = test_dir("rdb-save-rules");
+    let config = Config {
+        dir: dir.clone(),
+        save: vec![SaveRule {
+            seconds: 1,
+            changes: 2,
+        }],
+        ..Config::default()
+    };
+    let (addr, _server) = run_server(config).await;
+    let mut c = connect_raw(addr).await;
+
+    //修改次数不够，不会保存
+    assert_eq!(call(&mut c, &["SET", "first", "1"]).await, ok());
+    time::sleep(Duration::from_millis(2500)).await;
+    assert!(!dir.join("dump.rdb").exists());
+
+    assert_eq!(call(&mut c, &["SET", "second", "2"]).await, ok());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !dir.join("dump.rdb").exists() {
+        assert!(Instant::now() < deadline, "save rule did not fire");
+        time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let data = std::fs::read(dir.join("dump.rdb")).unwrap();
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&data, &mut state).unwrap(), 2);
+    assert_eq!(state.get("second").unwrap(), "2");
+}
+
+#[tokio::test]
+async fn bgsave_updates_lastsave() {
+    let dir = test_dir("rdb-bgsave");
+    let config = Config {
+        dir: dir.clone(),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let (addr, server) = run_server(config.clone()).await;
+    let mut c = connect_raw(addr).await;
+    let started = lastsave(&mut c).await;
+    assert_eq!(call(&mut c, &["SET", "key", "value"]).await, ok());
+
+    //LASTSAVE 的单位是秒，等到下一秒再保存
+    time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        call(&mut c, &["BGSAVE"]).await,
+        Frame::Simple("Background saving started".to_string())
+    );
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while lastsave(&mut c).await <= started {
+        assert!(Instant::now() < deadline, "BGSAVE did not finish");
+        time::sleep(Duration::from_millis(50)).await;
+    }
+
+    //重启之后从快照加载
+    server.abort();
+    let (addr, _server) = run_server(config).await;
+    let mut c = connect_raw(addr).await;
+    assert_eq!(call(&mut c, &["GET", "key"]).await, bulk("value"));
+}
+
+async fn lastsave(connection: &mut Connection) -> i64 {
+    match call(connection, &["LASTSAVE"]).await {
+        Frame::Integer(secs) => secs,
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
+
+#[test]
+fn keys_that_are_not_utf8_are_rejected() {
+    //按 UTF-8 有损转换之后两个 key 会变成同一个
+    let body = [0x00, 0x01, 0xFF, 0x01, b'a', 0x00, 0x01, 0xFE, 0x01, b'b'];
+    let err = rdb::decode(&rdb_file(&body), &mut State::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("UTF-8"), "{}", err);
+}
+
+#[tokio::test]
+async fn saves_wait_for_a_running_script() {
+    let dir = test_dir("rdb-save-busy");
+    let config = Config {
+        dir: dir.clone(),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let (addr, _server) = run_server(config).await;
+    let mut c = connect_raw(addr).await;
+    assert_eq!(call(&mut c, &["SET", "key", "value"]).await, ok());
+
+    let mut runner = connect_raw(addr).await;
+    let script =
+        tokio::spawn(async move { call(&mut runner, &["EVAL", "while true do end", "0"]).await });
+    time::sleep(script::DEFAULT_TIME_LIMIT + Duration::from_millis(200)).await;
+
+    //保存要读 keyspace，和普通命令一样收到 BUSY
+    for command in ["SAVE", "BGSAVE"] {
+        match call(&mut c, &[command]).await {
+            Frame::Error(err) => assert!(err.starts_with("BUSY"), "{}: {}", command, err),
+            frame => panic!("unexpected reply to {}: {:?}", command, frame),
+        }
+    }
+    assert!(!dir.join("dump.rdb").exists());
+
+    assert_eq!(call(&mut c, &["SCRIPT", "KILL"]).await, ok());
+    script.await.unwrap();
+    assert_eq!(call(&mut c, &["SAVE"]).await, ok());
+    let data = std::fs::read(dir.join("dump.rdb")).unwrap();
+    let mut state = State::default();
+    assert_eq!(rdb::decode(&data, &mut state).unwrap(), 1);
+    assert_eq!(state.get("key").unwrap(), "value");
+}
@@ -0,0 +1,324 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    bulk,
+    start_server,
+};
+use my_redis::{
+    frame::Frame,
+    reconnect::{
+        ReconnectPolicy,
+        Retry,
+    },
+    BlockingClient,
+    Client,
+    Connection,
+    Error,
+    SharedClient,
+};
+use std::net::SocketAddr;
+use tokio::{
+    io,
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::broadcast,
+    task::{
+        JoinHandle,
+        JoinSet,
+    },
+    time::{
+        self,
+        Duration,
+    },
+};
+
+//挡在 backend 前面的代理，stop 断开所有连接并停止监听，start 在同一个端口上恢复，模拟 server 重启
+struct FlakyServer {
+    addr: SocketAddr,
+    backend: SocketAddr,
+    proxy: Option<JoinHandle<()>>,
+}
+
+impl FlakyServer {
+    async fn new(backend: SocketAddr) -> FlakyServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut flaky = FlakyServer {
+            addr: listener.local_addr().unwrap(),
+            backend,
+            proxy: None,
+        };
+        flaky.proxy = Some(tokio::spawn(proxy(listener, backend)));
+        flaky
+    }
+
+    async fn stop(&mut self) {
+        if let Some(proxy) = self.proxy.take() {
+            //等任务真正结束，保证 listener 和所有连接都已经关闭
+            proxy.abort();
+            let _ = proxy.await;
+        }
+    }
+
+    async fn start(&mut self) {
+        let listener = TcpListener::bind(self.addr).await.unwrap();
+        self.proxy = Some(tokio::spawn(proxy(listener, self.backend)));
+    }
+
+    //在后台过一会儿再恢复
+    async fn restart_later(mut self, delay: Duration) -> JoinHandle<FlakyServer> {
+        self.stop().await;
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            self.start().await;
+            self
+        })
+    }
+}
+
+async fn proxy(listener: TcpListener, backend: SocketAddr) {
+    //JoinSet 随任务一起被丢弃时会 abort 所有转发任务
+    let mut conns = JoinSet::new();
+    loop {
+        let (mut inbound, _) = listener.accept().await.unwrap();
+        conns.spawn(async move {
+            let mut outbound = TcpStream::connect(backend).await.unwrap();
+            let _ = io::copy_bidirectional(&mut inbound, &mut outbound).await;
+        });
+    }
+}
+
+//只会 SUBSCRIBE 的 server，把 broadcast 里的消息推给所有订阅了对应 channel 的连接
+async fn start_pubsub_server() -> (SocketAddr, broadcast::Sender<(String, Bytes)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, _) = broadcast::channel(16);
+    let publisher = tx.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut messages = tx.subscribe();
+            tokio::spawn(async move {
+                let mut connection = Connection::new(stream);
+                let mut channels = Vec::new();
+                loop {
+                    tokio::select! {
+                        frame = connection.read_frame() => {
+                            let parts = match frame {
+                                Ok(Some(Frame::Array(parts))) => parts,
+                                _ => return,
+                            };
+                            for channel in &parts[1..] {
+                                let channel = match channel {
+                                    Frame::Bulk(name) => String::from_utf8_lossy(name).into_owned(),
+                                    _ => return,
+                                };
+                                channels.push(channel.clone());
+                                let confirm = Frame::Array(vec![
+                                    bulk("subscribe"),
+                                    bulk(&channel),
+                                    Frame::Integer(channels.len() as i64),
+                                ]);
+                                connection.write_frame(&confirm).await.unwrap();
+                            }
+                        }
+                        Ok((channel, content)) = messages.recv() => {
+                            if channels.contains(&channel) {
+                                let message = Frame::Array(vec![
+                                    bulk("message"),
+                                    bulk(&channel),
+                                    Frame::Bulk(content),
+                                ]);
+                                connection.write_frame(&message).await.unwrap();
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (addr, publisher)
+}
+
+fn fast_policy() -> ReconnectPolicy {
+    ReconnectPolicy {
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(100),
+        max_retries: 50,
+        ..ReconnectPolicy::default()
+    }
+}
+
+#[test]
+fn backoff_grows_and_is_capped() {
+    let policy = ReconnectPolicy {
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_secs(1),
+        ..ReconnectPolicy::default()
+    };
+    for attempt in 1..20 {
+        let exp = (Duration::from_millis(100) * 2u32.pow(attempt.min(10) - 1))
+            .min(Duration::from_secs(1));
+        let backoff = policy.backoff(attempt);
+        assert!(backoff >= exp / 2 && backoff <= exp, "{:?}", backoff);
+    }
+
+    //默认只重发只读命令
+    let args = |args: &[&'static str]| -> Vec<Bytes> {
+        args.iter()
+            .map(|arg| Bytes::from_static(arg.as_bytes()))
+            .collect()
+    };
+    assert!(policy.is_retryable(&args(&["GET", "key"])));
+    assert!(policy.is_retryable(&args(&["ping"])));
+    assert!(!policy.is_retryable(&args(&["SET", "key", "value"])));
+    assert!(!policy.is_retryable(&args(&["EVAL", "return 1", "0"])));
+    assert!(!ReconnectPolicy::never().is_retryable(&args(&["GET", "key"])));
+}
+
+#[tokio::test]
+async fn reads_are_retried_across_a_restart() {
+    let flaky = FlakyServer::new(start_server().await).await;
+    let mut client = Client::connect_with_policy(flaky.addr, fast_policy())
+        .await
+        .unwrap();
+    client.set("key", "value").await.unwrap();
+
+    let restart = flaky.restart_later(Duration::from_millis(200)).await;
+    assert_eq!(
+        client.get::<Option<String>>("key").await.unwrap(),
+        Some("value".into())
+    );
+    assert!(!client.is_broken());
+    restart.await.unwrap();
+}
+
+#[tokio::test]
+async fn writes_are_not_retried_by_default() {
+    let mut flaky = FlakyServer::new(start_server().await).await;
+    let mut client = Client::connect_with_policy(flaky.addr, fast_policy())
+        .await
+        .unwrap();
+    client.set("key", "old").await.unwrap();
+
+    //SET 可能已经执行了，不能自动重发
+    flaky.stop().await;
+    assert!(client.set("key", "new").await.is_err());
+    assert!(client.is_broken());
+
+    //下一条命令重新连接
+    flaky.start().await;
+    assert_eq!(
+        client.get::<Option<String>>("key").await.unwrap(),
+        Some("old".into())
+    );
+
+    //Retry::Always 连写命令也重发
+    let policy = ReconnectPolicy {
+        retry: Retry::Always,
+        ..fast_policy()
+    };
+    let mut client = Client::connect_with_policy(flaky.addr, policy)
+        .await
+        .unwrap();
+    let restart = flaky.restart_later(Duration::from_millis(200)).await;
+    client.set("key", "new").await.unwrap();
+    assert_eq!(
+        client.get::<Option<String>>("key").await.unwrap(),
+        Some("new".into())
+    );
+    restart.await.unwrap();
+}
+
+#[tokio::test]
+async fn never_policy_does_not_reconnect() {
+    let mut flaky = FlakyServer::new(start_server().await).await;
+    let mut client = Client::connect_with_policy(flaky.addr, ReconnectPolicy::never())
+        .await
+        .unwrap();
+    flaky.stop().await;
+    assert!(client.get::<Option<String>>("key").await.is_err());
+
+    flaky.start().await;
+    let err = client.get::<Option<String>>("key").await.unwrap_err();
+    assert!(matches!(err, Error::ConnectionClosed), "{:?}", err);
+}
+
+#[tokio::test]
+async fn shared_client_reconnects() {
+    let flaky = FlakyServer::new(start_server().await).await;
+    let client = SharedClient::connect_with_policy(flaky.addr, 32, fast_policy())
+        .await
+        .unwrap();
+    client.set("key", "value".into()).await.unwrap();
+
+    let restart = flaky.restart_later(Duration::from_millis(200)).await;
+    let tasks: Vec<_> = (0..10)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get("key").await })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap().unwrap(), Some("value".into()));
+    }
+    //没有收到回复的写命令报错，之后的命令正常执行
+    let mut flaky = restart.await.unwrap();
+    flaky.stop().await;
+    assert!(client.incr("counter").await.is_err());
+    flaky.start().await;
+    assert_eq!(client.incr("counter").await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn subscriptions_are_restored_after_a_restart() {
+    let (backend, publisher) = start_pubsub_server().await;
+    let flaky = FlakyServer::new(backend).await;
+    let client = Client::connect_with_policy(flaky.addr, fast_policy())
+        .await
+        .unwrap();
+    let mut subscriber = client.subscribe(&["news"]).await.unwrap();
+    subscriber.subscribe(&["sports"]).await.unwrap();
+    assert_eq!(subscriber.channels(), &["news", "sports"]);
+
+    publisher.send(("news".into(), "first".into())).unwrap();
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!(message.channel, "news");
+    assert_eq!(message.content, "first");
+
+    let restart = flaky.restart_later(Duration::from_millis(200)).await;
+    //重新订阅之前发布的消息会丢失，所以一直发直到收到为止
+    let publishing = tokio::spawn(async move {
+        loop {
+            let _ = publisher.send(("sports".into(), "second".into()));
+            time::sleep(Duration::from_millis(20)).await;
+        }
+    });
+    let message = time::timeout(Duration::from_secs(5), subscriber.next_message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.channel, "sports");
+    assert_eq!(message.content, "second");
+    assert_eq!(subscriber.channels(), &["news", "sports"]);
+    publishing.abort();
+    restart.await.unwrap();
+}
+
+#[test]
+fn blocking_client_reconnects() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let flaky = runtime.block_on(async { FlakyServer::new(start_server().await).await });
+
+    let mut client = BlockingClient::connect_with_policy(flaky.addr, fast_policy()).unwrap();
+    client.set("key", "value").unwrap();
+    let restart = runtime.block_on(flaky.restart_later(Duration::from_millis(200)));
+    assert_eq!(
+        client.get::<Option<String>>("key").unwrap(),
+        Some("value".into())
+    );
+    runtime.block_on(restart).unwrap();
+}
@@ -0,0 +1,230 @@
+mod common;
+
+use common::{
+    bulk,
+    call,
+    connect_raw,
+    ok,
+    start_server,
+    start_server_with,
+};
+use my_redis::{
+    Config,
+    Connection,
+    Frame,
+};
+use std::net::SocketAddr;
+use tokio::time::{
+    self,
+    Duration,
+    Instant,
+};
+
+async fn start_replica(master: SocketAddr) -> SocketAddr {
+    start_server_with(Config {
+        replicaof: Some((master.ip().to_string(), master.port())),
+        ..Config::default()
+    })
+    .await
+}
+
+async fn wait_for(connection: &mut Connection, args: &[&str], expected: Frame) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let frame = call(connection, args).await;
+        if frame == expected {
+            return;
+        }
+        assert!(Instant::now() < deadline, "timed out waiting, last reply {:?}", frame);
+        time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+async fn info(connection: &mut Connection) -> String {
+    match call(connection, &["INFO", "replication"]).await {
+        Frame::Bulk(info) => String::from_utf8(info.to_vec()).unwrap(),
+        frame => panic!("unexpected INFO reply {:?}", frame),
+    }
+}
+
+fn info_field(info: &str, name: &str) -> String {
+    info.lines()
+        .find_map(|line| line.strip_prefix(name))
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn replica_receives_existing_and_new_writes() {
+    let master = start_server().await;
+    let mut m = connect_raw(master).await;
+    //全量同步之前就存在的数据
+    assert_eq!(call(&mut m, &["SET", "before", "1"]).await, Frame::Simple("OK".to_string()));
+
+    let replica = start_replica(master).await;
+    let mut r = connect_raw(replica).await;
+    wait_for(&mut r, &["GET", "before"], bulk("1")).await;
+
+    call(&mut m, &["SET", "after", "2"]).await;
+    call(&mut m, &["INCR", "counter"]).await;
+    call(&mut m, &["SET", "ttl", "x", "EX", "100"]).await;
+    wait_for(&mut r, &["GET", "after"], bulk("2")).await;
+    wait_for(&mut r, &["GET", "counter"], bulk("1")).await;
+    //过期时间以 PEXPIREAT 的形式跟在 SET 后面传过来
+    wait_for(&mut r, &["TTL", "ttl"], Frame::Integer(99)).await;
+
+    call(&mut m, &["DEL", "after"]).await;
+    wait_for(&mut r, &["EXISTS", "after"], Frame::Integer(0)).await;
+}
+
+#[tokio::test]
+async fn replica_is_read_only() {
+    let master = start_server().await;
+    let replica = start_replica(master).await;
+    let mut r = connect_raw(replica).await;
+
+    match call(&mut r, &["SET", "key", "value"]).await {
+        Frame::Error(err) => assert!(err.starts_with("READONLY"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    //读命令不受影响
+    assert_eq!(call(&mut r, &["GET", "key"]).await, Frame::Null);
+
+    //脚本里的写命令同样被拒绝
+    let script = "return redis.call('SET', KEYS[1], 'value')";
+    match call(&mut r, &["EVAL", script, "1", "key"]).await {
+        Frame::Error(err) => assert!(err.starts_with("READONLY"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    let script = "return redis.call('GET', KEYS[1])";
+    assert_eq!(call(&mut r, &["EVAL", script, "1", "key"]).await, Frame::Null);
+}
+
+#[tokio::test]
+async fn wait_counts_acknowledged_replicas() {
+    let master = start_server().await;
+    let mut m = connect_raw(master).await;
+    let replica = start_replica(master).await;
+    let mut r = connect_raw(replica).await;
+
+    wait_for(&mut m, &["WAIT", "1", "100"], Frame::Integer(1)).await;
+    call(&mut m, &["SET", "key", "value"]).await;
+    assert_eq!(call(&mut m, &["WAIT", "1", "5000"]).await, Frame::Integer(1));
+    //WAIT 返回之后 replica 一定已经执行了这条写命令
+    assert_eq!(call(&mut r, &["GET", "key"]).await, bulk("value"));
+
+    //没有那么多 replica 时等到超时，返回实际确认的数量
+    assert_eq!(call(&mut m, &["WAIT", "2", "100"]).await, Frame::Integer(1));
+
+    match call(&mut r, &["WAIT", "1", "100"]).await {
+        Frame::Error(_) => {}
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn info_reports_roles_and_offsets() {
+    let master = start_server().await;
+    let mut m = connect_raw(master).await;
+    let replica = start_replica(master).await;
+    let mut r = connect_raw(replica).await;
+
+    call(&mut m, &["SET", "key", "value"]).await;
+    assert_eq!(call(&mut m, &["WAIT", "1", "5000"]).await, Frame::Integer(1));
+
+    let master_info = info(&mut m).await;
+    assert!(master_info.contains("role:master\r\n"), "{}", master_info);
+    assert!(master_info.contains("connected_slaves:1\r\n"), "{}", master_info);
+    let line = format!("port={},state=online", replica.port());
+    assert!(master_info.contains(&line), "{}", master_info);
+
+    //两边的 replid 和 offset 一致，WAIT 发出的 GETACK 可能还在路上，所以轮询
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let replica_info = loop {
+        let replica_info = info(&mut r).await;
+        let master_info = info(&mut m).await;
+        if info_field(&master_info, "master_repl_offset:") == info_field(&replica_info, "master_repl_offset:") {
+            break replica_info;
+        }
+        assert!(Instant::now() < deadline, "{}\n{}", master_info, replica_info);
+        time::sleep(Duration::from_millis(20)).await;
+    };
+    assert!(replica_info.contains("role:slave\r\n"), "{}", replica_info);
+    assert!(replica_info.contains("master_link_status:up\r\n"), "{}", replica_info);
+    let line = format!("master_port:{}\r\n", master.port());
+    assert!(replica_info.contains(&line), "{}", replica_info);
+    assert_eq!(
+        info_field(&master_info, "master_replid:"),
+        info_field(&replica_info, "master_replid:")
+    );
+}
+
+#[tokio::test]
+async fn psync_continues_from_backlog() {
+    let master = start_server().await;
+    let mut m = connect_raw(master).await;
+    call(&mut m, &["SET", "a", "1"]).await;
+
+    //假装是一个刚断开的 replica，已经收到了到目前为止的所有数据
+    let master_info = info(&mut m).await;
+    let replid = info_field(&master_info, "master_replid:");
+    let offset: u64 = info_field(&master_info, "master_repl_offset:").parse().unwrap();
+    call(&mut m, &["SET", "b", "2"]).await;
+
+    let mut replica = connect_raw(master).await;
+    let next = (offset + 1).to_string();
+    let reply = call(&mut replica, &["PSYNC", &replid, &next]).await;
+    assert_eq!(reply, Frame::Simple(format!("CONTINUE {}", replid)));
+    //断开期间的写命令从 backlog 里补发
+    let frame = replica.read_frame().await.unwrap().unwrap();
+    assert_eq!(frame, Frame::Array(vec![bulk("SET"), bulk("b"), bulk("2")]));
+
+    //未知的 replid 只能全量同步
+    let mut replica = connect_raw(master).await;
+    match call(&mut replica, &["PSYNC", "unknown", &next]).await {
+        Frame::Simple(reply) => assert!(reply.starts_with("FULLRESYNC "), "{}", reply),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn replicas_over_the_output_buffer_limit_are_disconnected() {
+    let master = start_server().await;
+    let mut m = connect_raw(master).await;
+    let limit = "replica 1mb 0 0";
+    assert_eq!(call(&mut m, &["CONFIG", "SET", "client-output-buffer-limit", limit]).await, ok());
+    let reply = call(&mut m, &["CONFIG", "GET", "client-output-buffer-limit"]).await;
+    assert_eq!(
+        reply,
+        Frame::Array(vec![
+            bulk("client-output-buffer-limit"),
+            bulk("replica 1048576 0 0"),
+        ])
+    );
+    //其他类型的客户端没有输出缓冲区限制
+    let normal = ["CONFIG", "SET", "client-output-buffer-limit", "normal 0 0 0"];
+    assert!(matches!(call(&mut m, &normal).await, Frame::Error(_)));
+
+    //一个只发了 PSYNC、之后再也不读复制流的 replica
+    let mut replica = connect_raw(master).await;
+    match call(&mut replica, &["PSYNC", "?", "-1"]).await {
+        Frame::Simple(reply) => assert!(reply.starts_with("FULLRESYNC "), "{}", reply),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    assert!(info(&mut m).await.contains("connected_slaves:1\r\n"));
+
+    //写入的数据远超过 socket 缓冲区，积压在 master 上直到超过限制
+    let value = "x".repeat(256 * 1024);
+    for i in 0..200 {
+        call(&mut m, &["SET", &format!("key:{}", i), &value]).await;
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let master_info = info(&mut m).await;
+        if master_info.contains("connected_slaves:0\r\n") {
+            break;
+        }
+        assert!(Instant::now() < deadline, "{}", master_info);
+        time::sleep(Duration::from_millis(20)).await;
+    }
+}
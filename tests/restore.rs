@@ -0,0 +1,74 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    connect,
+    start_server,
+};
+use my_redis::rdb;
+
+//DUMP 的格式：value、2 字节的 RDB 版本和 CRC64
+fn payload(value: &[u8]) -> Bytes {
+    let mut data = value.to_vec();
+    data.extend_from_slice(&9u16.to_le_bytes());
+    let checksum = rdb::crc64(0, &data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    Bytes::from(data)
+}
+
+#[tokio::test]
+async fn dump_and_restore_round_trip() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    client.set("source", "value").await.unwrap();
+    let dump: Bytes = client.query(("DUMP", "source")).await.unwrap();
+    let () = client.query(("RESTORE", "copy", 0, &dump)).await.unwrap();
+    let value: Option<String> = client.get("copy").await.unwrap();
+    assert_eq!(value.as_deref(), Some("value"));
+
+    let err = client
+        .query::<(), _>(("RESTORE", "copy", 0, &dump))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("BUSYKEY"), "{}", err);
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_rejected() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    //校验和不对
+    let mut corrupt = payload(&[0x00, 0x01, b'v']).to_vec();
+    corrupt[2] = b'x';
+    let err = client
+        .query::<(), _>(("RESTORE", "k", 0, Bytes::from(corrupt)))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "ERR DUMP payload version or checksum are wrong"
+    );
+
+    //校验和正确，但是 LZF 声称解压后有 2^62 字节，不能按这个长度分配内存
+    let mut value = vec![0x00, 0xC3, 0x02, 0x81];
+    value.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    value.extend_from_slice(&[0x00, b'v']);
+    let err = client
+        .query::<(), _>(("RESTORE", "k", 0, payload(&value)))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("ERR "), "{}", err);
+
+    //字符串比 payload 还长
+    let err = client
+        .query::<(), _>(("RESTORE", "k", 0, payload(&[0x00, 0x05, b'v'])))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("ERR "), "{}", err);
+
+    //服务端还在正常工作
+    let exists: i64 = client.query(("EXISTS", "k")).await.unwrap();
+    assert_eq!(exists, 0);
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use common::{
+    bulk,
+    call,
+    connect,
+    connect_raw,
+    ok,
+    start_server,
+};
+use my_redis::{
+    script,
+    Frame,
+};
+use tokio::time::{
+    self,
+    Duration,
+    Instant,
+};
+
+fn error(message: &str) -> Frame {
+    Frame::Error(message.to_string())
+}
+
+#[tokio::test]
+async fn scripts_cannot_reach_the_host() {
+    let addr = start_server().await;
+    let mut client = connect(addr).await;
+
+    let script = "return {type(os), type(io), type(package), type(require), \
+                  type(loadfile), type(dofile)}";
+    let types: Vec<String> = client.query(("EVAL", script, 0)).await.unwrap();
+    assert_eq!(types, ["nil"; 6]);
+
+    //安全的标准库还在
+    let script = "return {type(string), type(table), type(math), type(utf8)}";
+    let types: Vec<String> = client.query(("EVAL", script, 0)).await.unwrap();
+    assert_eq!(types, ["table"; 4]);
+
+    let err = client
+        .query::<String, _>(("EVAL", "return os.getenv('HOME')", 0))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Error running script"), "{}", err);
+}
+
+#[tokio::test]
+async fn lua_values_become_replies() {
+    let addr = start_server().await;
+    let mut c = connect_raw(addr).await;
+
+    for (script, expected) in [
+        ("return nil", Frame::Null),
+        ("return false", Frame::Null),
+        ("return true", Frame::Integer(1)),
+        ("return 7", Frame::Integer(7)),
+        //浮点数被截断成整数
+        ("return 3.99", Frame::Integer(3)),
+        ("return 'text'", bulk("text")),
+        (
+            "return redis.status_reply('FINE')",
+            Frame::Simple("FINE".to_string()),
+        ),
+        ("return {ok = 'FINE'}", Frame::Simple("FINE".to_string())),
+        (
+            "return redis.error_reply('MY failure')",
+            error("MY failure"),
+        ),
+        ("return {err = 'ERR custom'}", error("ERR custom")),
+        //数组在第一个 nil 处截断
+        (
+            "return {1, 'two', {3}, nil, 5}",
+            Frame::Array(vec![
+                Frame::Integer(1),
+                bulk("two"),
+                Frame::Array(vec![Frame::Integer(3)]),
+            ]),
+        ),
+    ] {
+        assert_eq!(
+            call(&mut c, &["EVAL", script, "0"]).await,
+            expected,
+            "{}",
+            script
+        );
+    }
+}
+
+#[tokio::test]
+async fn replies_become_lua_values() {
+    let addr = start_server().await;
+    let mut c = connect_raw(addr).await;
+
+    let script = "
+        local set = redis.call('SET', KEYS[1], ARGV[1])
+        local missing = redis.call('GET', 'missing')
+        local counter = redis.call('INCR', KEYS[2])
+        local values = redis.call('MGET', KEYS[1], 'missing')
+        return {set['ok'], tostring(missing), math.type(counter), values[1], tostring(values[2])}
+    ";
+    assert_eq!(
+        call(&mut c, &["EVAL", script, "2", "key", "counter", "value"]).await,
+        Frame::Array(vec![
+            bulk("OK"),
+            bulk("false"),
+            bulk("integer"),
+            bulk("value"),
+            bulk("false"),
+        ])
+    );
+    assert_eq!(call(&mut c, &["GET", "key"]).await, bulk("value"));
+}
+
+#[tokio::test]
+async fn call_raises_errors_and_pcall_returns_them() {
+    let addr = start_server().await;
+    let mut c = connect_raw(addr).await;
+    assert_eq!(call(&mut c, &["SET", "text", "abc"]).await, ok());
+
+    let not_integer = error("ERR value is not an integer or out of range");
+    let script = "return redis.call('INCR', 'text')";
+    assert_eq!(call(&mut c, &["EVAL", script, "0"]).await, not_integer);
+
+    //pcall 不中断脚本，错误作为 {err = ...} 返回
+    let script = "local r = redis.pcall('INCR', 'text'); return {r['err'], 'after'}";
+    assert_eq!(
+        call(&mut c, &["EVAL", script, "0"]).await,
+        Frame::Array(vec![
+            bulk("ERR value is not an integer or out of range"),
+            bulk("after"),
+        ])
+    );
+    let script = "return redis.pcall('INCR', 'text')";
+    assert_eq!(call(&mut c, &["EVAL", script, "0"]).await, not_integer);
+
+    let script = "return redis.call('NOSUCHCOMMAND')";
+    assert_eq!(
+        call(&mut c, &["EVAL", script, "0"]).await,
+        error("ERR unknown command 'NOSUCHCOMMAND'")
+    );
+    let script = "return redis.call('EVAL', 'return 1', 0)";
+    assert_eq!(
+        call(&mut c, &["EVAL", script, "0"]).await,
+        error("ERR This Redis command is not allowed from scripts")
+    );
+
+    match call(&mut c, &["EVAL", "this is not lua", "0"]).await {
+        Frame::Error(err) => assert!(err.starts_with("ERR Error running script"), "{}", err),
+        frame => panic!("unexpected reply {:?}", frame),
+    }
+    assert_eq!(
+        call(&mut c, &["EVAL", "return 1", "2", "only-one-key"]).await,
+        error("ERR Number of keys can't be greater than number of args")
+    );
+}
+
+#[tokio::test]
+async fn scripts_are_cached_by_sha1() {
+    let addr = start_server().await;
+    let mut c = connect_raw(addr).await;
+    let body = "return ARGV[1]";
+    let sha = script::sha1_hex(body.as_bytes());
+    let missing = "0".repeat(40);
+
+    assert_eq!(
+        call(&mut c, &["EVALSHA", &sha, "0", "x"]).await,
+        error("NOSCRIPT No matching script. Please use EVAL.")
+    );
+    assert_eq!(call(&mut c, &["SCRIPT", "LOAD", body]).await, bulk(&sha));
+    assert_eq!(
+        call(&mut c, &["SCRIPT", "EXISTS", &sha, &missing]).await,
+        Frame::Array(vec![Frame::Integer(1), Frame::Integer(0)])
+    );
+    //sha1 不区分大小写
+    let upper = sha.to_ascii_uppercase();
+    assert_eq!(
+        call(&mut c, &["EVALSHA", &upper, "0", "x"]).await,
+        bulk("x")
+    );
+
+    assert_eq!(call(&mut c, &["SCRIPT", "FLUSH"]).await, ok());
+    assert_eq!(
+        call(&mut c, &["SCRIPT", "EXISTS", &sha]).await,
+        Frame::Array(vec![Frame::Integer(0)])
+    );
+    assert_eq!(
+        call(&mut c, &["EVALSHA", &sha, "0", "x"]).await,
+        error("NOSCRIPT No matching script. Please use EVAL.")
+    );
+
+    //EVAL 也会把脚本放进缓存
+    assert_eq!(call(&mut c, &["EVAL", body, "0", "y"]).await, bulk("y"));
+    assert_eq!(call(&mut c, &["EVALSHA", &sha, "0", "z"]).await, bulk("z"));
+}
+
+#[tokio::test]
+async fn long_scripts_make_others_busy_until_killed() {
+    let addr = start_server().await;
+    let mut admin = connect_raw(addr).await;
+    assert_eq!(
+        call(&mut admin, &["SCRIPT", "KILL"]).await,
+        error("NOTBUSY No scripts in execution right now.")
+    );
+
+    let mut runner = connect(addr).await;
+    let script = tokio::spawn(async move {
+        runner
+            .query::<(), _>(("EVAL", "while true do end", 0))
+            .await
+            .unwrap_err()
+    });
+
+    //超过时间限制之前其他命令排队等待，之后收到 BUSY
+    let mut other = connect_raw(addr).await;
+    let deadline = Instant::now() + script::DEFAULT_TIME_LIMIT * 2;
+    loop {
+        match call(&mut other, &["PING"]).await {
+            Frame::Error(err) if err.starts_with("BUSY") => break,
+            frame => assert_eq!(frame, Frame::Simple("PONG".to_string())),
+        }
+        assert!(Instant::now() < deadline, "never got BUSY");
+        time::sleep(Duration::from_millis(50)).await;
+    }
+
+    assert_eq!(call(&mut admin, &["SCRIPT", "KILL"]).await, ok());
+    let err = script.await.unwrap();
+    assert!(err.to_string().contains("Script killed"), "{}", err);
+    assert_eq!(
+        call(&mut other, &["PING"]).await,
+        Frame::Simple("PONG".to_string())
+    );
+}
+
+#[tokio::test]
+async fn keys_expiring_during_a_script_do_not_stall_the_server() {
+    let addr = start_server().await;
+    let mut admin = connect_raw(addr).await;
+    assert_eq!(
+        call(&mut admin, &["SET", "key", "value", "PX", "200"]).await,
+        ok()
+    );
+
+    let mut runner = connect(addr).await;
+    let script = tokio::spawn(async move {
+        runner
+            .query::<(), _>(("EVAL", "while true do end", 0))
+            .await
+            .unwrap_err()
+    });
+
+    //key 在脚本执行期间过期，后台清理任务不能卡住 runtime
+    time::sleep(script::DEFAULT_TIME_LIMIT + Duration::from_millis(500)).await;
+    assert_eq!(call(&mut admin, &["SCRIPT", "KILL"]).await, ok());
+    let err = script.await.unwrap();
+    assert!(err.to_string().contains("Script killed"), "{}", err);
+    assert_eq!(call(&mut admin, &["GET", "key"]).await, Frame::Null);
+}
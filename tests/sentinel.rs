@@ -0,0 +1,179 @@
+mod common;
+
+use common::{
+    bulk,
+    call,
+    connect_port,
+    free_port,
+    wait_until,
+    Process,
+};
+use my_redis::Frame;
+use std::process::{
+    Command,
+    Stdio,
+};
+use tokio::time::{
+    self,
+    Duration,
+};
+
+const MASTER_NAME: &str = "mymaster";
+
+fn spawn(bin: &str, args: &[String]) -> Process {
+    let child = Command::new(bin)
+        .args(args)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    Process(child)
+}
+
+fn start_server(port: u16, replicaof: Option<u16>) -> Process {
+    let dir = std::env::temp_dir().join(format!("my-redis-sentinel-test-{}", port));
+    let mut args = vec![
+        "--port".to_string(),
+        port.to_string(),
+        "--dir".to_string(),
+        dir.display().to_string(),
+        "--save".to_string(),
+        String::new(),
+    ];
+    if let Some(master) = replicaof {
+        args.extend([
+            "--replicaof".to_string(),
+            "127.0.0.1".to_string(),
+            master.to_string(),
+        ]);
+    }
+    spawn(env!("CARGO_BIN_EXE_server"), &args)
+}
+
+fn start_sentinel(port: u16, master: u16, peers: &[u16]) -> Process {
+    let mut args = vec![
+        "--bind 127.0.0.1".to_string(),
+        format!("--port {}", port),
+        format!("--monitor {} 127.0.0.1 {} 2", MASTER_NAME, master),
+        format!("--down-after-milliseconds {} 500", MASTER_NAME),
+        format!("--failover-timeout {} 2000", MASTER_NAME),
+    ];
+    for peer in peers {
+        args.push(format!(
+            "--known-sentinel {} 127.0.0.1 {}",
+            MASTER_NAME, peer
+        ));
+    }
+    let args: Vec<String> = args
+        .iter()
+        .flat_map(|arg| arg.split(' ').map(str::to_string))
+        .collect();
+    spawn(env!("CARGO_BIN_EXE_sentinel"), &args)
+}
+
+fn master_addr(port: u16) -> Frame {
+    Frame::Array(vec![bulk("127.0.0.1"), bulk(&port.to_string())])
+}
+
+fn is_replica_of(frame: &Frame, master: u16) -> bool {
+    match frame {
+        Frame::Array(role) => {
+            role.first() == Some(&bulk("slave"))
+                && role.get(2) == Some(&Frame::Integer(master as i64))
+        }
+        _ => false,
+    }
+}
+
+#[tokio::test]
+async fn failover_promotes_a_replica() {
+    let master = free_port();
+    let replicas = [free_port(), free_port()];
+    let sentinels = [free_port(), free_port(), free_port()];
+
+    let mut master_process = Some(start_server(master, None));
+    let _replicas: Vec<Process> = replicas
+        .iter()
+        .map(|port| start_server(*port, Some(master)))
+        .collect();
+    let _sentinels: Vec<Process> = sentinels
+        .iter()
+        .map(|port| {
+            let peers: Vec<u16> = sentinels.iter().copied().filter(|p| p != port).collect();
+            start_sentinel(*port, master, &peers)
+        })
+        .collect();
+
+    let mut m = connect_port(master).await;
+    call(&mut m, &["SET", "key", "value"]).await;
+    for replica in replicas {
+        wait_until(replica, &["GET", "key"], |frame| *frame == bulk("value")).await;
+    }
+
+    //所有 sentinel 都通过 master 的 INFO 发现了两个 replica
+    for sentinel in sentinels {
+        let args = ["SENTINEL", "get-master-addr-by-name", MASTER_NAME];
+        assert_eq!(
+            call(&mut connect_port(sentinel).await, &args).await,
+            master_addr(master)
+        );
+        wait_until(
+            sentinel,
+            &["SENTINEL", "replicas", MASTER_NAME],
+            |frame| matches!(frame, Frame::Array(replicas) if replicas.len() == 2),
+        )
+        .await;
+    }
+
+    let mut events = connect_port(sentinels[1]).await;
+    call(&mut events, &["SUBSCRIBE", "+switch-master"]).await;
+
+    drop(master_process.take());
+
+    let event = time::timeout(Duration::from_secs(30), events.read_frame())
+        .await
+        .expect("no +switch-master event")
+        .unwrap()
+        .unwrap();
+    let message = match event {
+        Frame::Array(parts) if parts.len() == 3 && parts[1] == "+switch-master" => {
+            match &parts[2] {
+                Frame::Bulk(message) => String::from_utf8(message.to_vec()).unwrap(),
+                frame => panic!("unexpected message {:?}", frame),
+            }
+        }
+        frame => panic!("unexpected event {:?}", frame),
+    };
+    let fields: Vec<&str> = message.split(' ').collect();
+    assert_eq!(
+        fields[..3],
+        [MASTER_NAME, "127.0.0.1", &master.to_string()[..]]
+    );
+    let promoted: u16 = fields[4].parse().unwrap();
+    assert!(replicas.contains(&promoted), "{}", message);
+    let other = replicas.into_iter().find(|port| *port != promoted).unwrap();
+
+    //所有 sentinel 最终都指向新的 master
+    for sentinel in sentinels {
+        let args = ["SENTINEL", "get-master-addr-by-name", MASTER_NAME];
+        wait_until(sentinel, &args, |frame| *frame == master_addr(promoted)).await;
+    }
+
+    //新 master 可以写入，另一个 replica 改为复制它
+    let role = call(&mut connect_port(promoted).await, &["ROLE"]).await;
+    assert!(
+        matches!(&role, Frame::Array(role) if role[0] == "master"),
+        "{:?}",
+        role
+    );
+    assert_eq!(
+        call(&mut connect_port(promoted).await, &["SET", "key", "new"]).await,
+        Frame::Simple("OK".to_string())
+    );
+    wait_until(other, &["ROLE"], |frame| is_replica_of(frame, promoted)).await;
+    wait_until(other, &["GET", "key"], |frame| *frame == bulk("new")).await;
+
+    //旧 master 恢复之后被降级为 replica
+    let _old_master = start_server(master, None);
+    wait_until(master, &["ROLE"], |frame| is_replica_of(frame, promoted)).await;
+    wait_until(master, &["GET", "key"], |frame| *frame == bulk("new")).await;
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::start_server;
+use bytes::Bytes;
+use my_redis::{
+    reconnect::ReconnectPolicy,
+    Error,
+    SharedClient,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn concurrent_requests_get_their_own_replies() {
+    let client = SharedClient::connect(start_server().await).await.unwrap();
+
+    //很多任务同时发请求，每个任务只能收到自己那条命令的回复
+    let tasks: Vec<_> = (0..100)
+        .map(|i| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                let key = format!("key{}", i);
+                client.set(&key, Bytes::from(i.to_string())).await.unwrap();
+                assert_eq!(client.incr_by(&key, i).await.unwrap(), 2 * i);
+                assert_eq!(
+                    client.get(&key).await.unwrap(),
+                    Some(Bytes::from((2 * i).to_string()))
+                );
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(client.dbsize().await.unwrap(), 100);
+
+    //错误回复只影响对应的请求
+    client.set("text", "abc".into()).await.unwrap();
+    let err = client.incr("text").await.unwrap_err();
+    assert!(err.to_string().contains("not an integer"), "{}", err);
+    assert_eq!(client.get("text").await.unwrap(), Some("abc".into()));
+}
+
+#[tokio::test]
+async fn waiters_learn_about_a_dead_connection() {
+    //读到第一批请求之后就断开连接的 server
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf).await;
+    });
+
+    let client = SharedClient::connect_with_policy(addr, 32, ReconnectPolicy::never())
+        .await
+        .unwrap();
+    let tasks: Vec<_> = (0..10)
+        .map(|i| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get(&format!("key{}", i)).await })
+        })
+        .collect();
+    for task in tasks {
+        assert!(task.await.unwrap().is_err());
+    }
+    //server 已经不在了，之后的请求重连失败
+    let err = client.get("key").await.unwrap_err();
+    assert!(matches!(err, Error::Io(_)), "{:?}", err);
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use common::{
+    connect,
+    run_server,
+    test_dir,
+};
+use my_redis::{
+    server,
+    Client,
+    Config,
+    Error,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+use tokio::{
+    net::TcpListener,
+    sync::oneshot,
+    time,
+};
+
+#[tokio::test]
+async fn shutdown_save_writes_a_final_snapshot() {
+    let dir = test_dir("shutdown-save");
+    let config = Config {
+        dir: dir.clone(),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let (addr, server) = run_server(config).await;
+    let mut client = connect(addr).await;
+    client.set("key", "value").await.unwrap();
+
+    //成功时服务端不回复，直接关闭连接
+    let err = client
+        .query::<(), _>(("SHUTDOWN", "SAVE"))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::ConnectionClosed | Error::Io(_)),
+        "{:?}",
+        err
+    );
+    server.await.unwrap().unwrap();
+    assert!(dir.join("dump.rdb").exists());
+
+    //不再接受新连接
+    assert!(Client::connect(addr).await.is_err());
+
+    //重启之后数据还在
+    let config = Config {
+        dir,
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let (addr, _server) = run_server(config).await;
+    let mut client = connect(addr).await;
+    assert_eq!(client.get::<String>("key").await.unwrap(), "value");
+}
+
+#[tokio::test]
+async fn shutdown_nosave_skips_the_snapshot() {
+    let dir = test_dir("shutdown-nosave");
+    let config = Config {
+        dir: dir.clone(),
+        ..Config::default()
+    };
+    let (addr, server) = run_server(config).await;
+    let mut client = connect(addr).await;
+    client.set("key", "value").await.unwrap();
+
+    let err = client
+        .query::<(), _>(("SHUTDOWN", "NOW", "PLEASE"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("syntax error"), "{}", err);
+    //事务里不能 SHUTDOWN
+    let () = client.query("MULTI").await.unwrap();
+    let err = client.query::<(), _>("SHUTDOWN").await.unwrap_err();
+    assert!(err.to_string().contains("inside a transaction"), "{}", err);
+    let () = client.query("DISCARD").await.unwrap();
+
+    assert!(client.query::<(), _>(("SHUTDOWN", "NOSAVE")).await.is_err());
+    server.await.unwrap().unwrap();
+    assert!(!dir.join("dump.rdb").exists());
+}
+
+#[tokio::test]
+async fn in_flight_commands_finish_before_exit() {
+    let config = Config {
+        dir: test_dir("shutdown-drain"),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (signal, stop) = oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        server::serve_with_shutdown(vec![listener.into()], config, stop).await
+    });
+
+    //WAIT 在没有 replica 的时候会一直等到超时，模拟一条执行中的慢命令
+    let mut slow = connect(addr).await;
+    let mut idle = connect(addr).await;
+    idle.set("key", "value").await.unwrap();
+    let wait = tokio::spawn(async move {
+        let replicas: i64 = slow.query(("WAIT", 1, 500)).await.unwrap();
+        (replicas, slow)
+    });
+    time::sleep(Duration::from_millis(100)).await;
+
+    let start = Instant::now();
+    signal.send(()).unwrap();
+    let (replicas, mut slow) = wait.await.unwrap();
+    assert_eq!(replicas, 0);
+    server.await.unwrap().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    //执行完手上的命令之后连接被关闭
+    assert!(slow.get::<Option<String>>("key").await.is_err());
+    assert!(idle.get::<Option<String>>("key").await.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_timeout_bounds_the_drain() {
+    let config = Config {
+        dir: test_dir("shutdown-timeout"),
+        save: Vec::new(),
+        shutdown_timeout: Duration::from_millis(200),
+        ..Config::default()
+    };
+    let (addr, server) = run_server(config).await;
+    let mut slow = connect(addr).await;
+    tokio::spawn(async move { slow.query::<i64, _>(("WAIT", 1, 10_000)).await });
+    time::sleep(Duration::from_millis(100)).await;
+
+    let start = Instant::now();
+    let mut client = connect(addr).await;
+    assert!(client.query::<(), _>("SHUTDOWN").await.is_err());
+    server.await.unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::start_server;
+use my_redis::SyncClient;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+};
+use tokio::{
+    io,
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    runtime::Runtime,
+};
+
+//记录建立了多少条连接的转发代理
+async fn start_counting_proxy(backend: SocketAddr, accepted: Arc<AtomicUsize>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut inbound, _) = listener.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut outbound = TcpStream::connect(backend).await.unwrap();
+                let _ = io::copy_bidirectional(&mut inbound, &mut outbound).await;
+            });
+        }
+    });
+    addr
+}
+
+#[test]
+fn sync_client_is_send_sync_and_clone() {
+    fn assert_shareable<T: Send + Sync + Clone>() {}
+    assert_shareable::<SyncClient>();
+}
+
+#[test]
+fn threads_share_one_connection() {
+    let runtime = Runtime::new().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let addr = runtime.block_on(async {
+        let backend = start_server().await;
+        start_counting_proxy(backend, accepted.clone()).await
+    });
+    let client = SyncClient::connect(addr).unwrap();
+
+    //同一个 &SyncClient 在很多线程里同时使用，每个线程只能收到自己那条命令的回复
+    thread::scope(|scope| {
+        for i in 0..16 {
+            let client = &client;
+            scope.spawn(move || {
+                let key = format!("key{}", i);
+                for j in 0..100 {
+                    client.set(&key, j).unwrap();
+                    assert_eq!(client.get::<i64>(&key).unwrap(), j);
+                    client.incr("counter").unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(client.get::<i64>("counter").unwrap(), 1600);
+
+    //克隆也走同一条连接
+    let clone = client.clone();
+    thread::spawn(move || clone.incr_by("counter", 10).unwrap())
+        .join()
+        .unwrap();
+    assert_eq!(client.get::<i64>("counter").unwrap(), 1610);
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    //错误回复只影响对应的请求
+    client.set("text", "abc").unwrap();
+    let err = client.incr("text").unwrap_err();
+    assert_eq!(err.code(), Some("ERR"));
+    assert_eq!(client.get::<String>("text").unwrap(), "abc");
+    assert_eq!(client.del(&["text", "missing"]).unwrap(), 1);
+}
@@ -0,0 +1,191 @@
+#![cfg(feature = "tls")]
+
+use my_redis::{
+    config::TlsAuthClients,
+    reconnect::ReconnectPolicy,
+    server::{
+        self,
+        Listener,
+    },
+    tls,
+    BlockingClient,
+    Client,
+    Config,
+    TlsConnector,
+};
+use rcgen::{
+    BasicConstraints,
+    CertificateParams,
+    ExtendedKeyUsagePurpose,
+    IsCa,
+    KeyPair,
+};
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use tokio::net::TcpListener;
+
+//用 rcgen 生成一个 CA，以及它签发的 localhost 服务端证书和客户端证书；
+//另外一个 CA 用来测试不受信任的证书
+fn generate_certs(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("my-redis-tls-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+    let issue = |file: &str, names: Vec<String>, usage: ExtendedKeyUsagePurpose| {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(names).unwrap();
+        params.extended_key_usages = vec![usage];
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+        fs::write(dir.join(format!("{}.pem", file)), cert.pem()).unwrap();
+        fs::write(dir.join(format!("{}.key", file)), key.serialize_pem()).unwrap();
+    };
+    issue(
+        "server",
+        vec!["localhost".to_string()],
+        ExtendedKeyUsagePurpose::ServerAuth,
+    );
+    issue("client", Vec::new(), ExtendedKeyUsagePurpose::ClientAuth);
+
+    let other_key = KeyPair::generate().unwrap();
+    let mut other_params = CertificateParams::new(Vec::new()).unwrap();
+    other_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let other = other_params.self_signed(&other_key).unwrap();
+    fs::write(dir.join("other-ca.pem"), other.pem()).unwrap();
+    dir
+}
+
+fn tls_config(dir: &Path, auth: TlsAuthClients) -> Config {
+    Config {
+        tls_cert_file: Some(dir.join("server.pem")),
+        tls_key_file: Some(dir.join("server.key")),
+        tls_ca_cert_file: Some(dir.join("ca.pem")),
+        tls_auth_clients: auth,
+        dir: dir.to_path_buf(),
+        save: Vec::new(),
+        ..Config::default()
+    }
+}
+
+//返回明文端口和 TLS 端口
+async fn start_server(dir: &Path, auth: TlsAuthClients) -> (u16, u16) {
+    let config = tls_config(dir, auth);
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tls = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let ports = (
+        tcp.local_addr().unwrap().port(),
+        tls.local_addr().unwrap().port(),
+    );
+    let acceptor = tls::acceptor(&config).unwrap();
+    let listeners = vec![tcp.into(), Listener::Tls(tls, acceptor)];
+    tokio::spawn(server::serve(listeners, config));
+    ports
+}
+
+fn connector(dir: &Path) -> TlsConnector {
+    TlsConnector::new(dir.join("ca.pem"))
+        .unwrap()
+        .with_client_cert(dir.join("client.pem"), dir.join("client.key"))
+        .unwrap()
+}
+
+//握手失败可能在连接时发现，也可能在第一条命令时才发现（TLS 1.3 的服务端在客户端握手完成之后才检查证书）
+async fn is_rejected(port: u16, tls: TlsConnector) -> bool {
+    let policy = ReconnectPolicy::never();
+    match Client::connect_tls_with_policy("localhost", port, tls, policy).await {
+        Ok(mut client) => client.ping(None).await.is_err(),
+        Err(_) => true,
+    }
+}
+
+#[tokio::test]
+async fn clients_with_certificates_connect_over_tls() {
+    let dir = generate_certs("auth");
+    let (tcp_port, tls_port) = start_server(&dir, TlsAuthClients::Yes).await;
+
+    let mut client = Client::connect_tls("localhost", tls_port, connector(&dir))
+        .await
+        .unwrap();
+    client.set("key", "secret").await.unwrap();
+
+    //明文端口照常工作，两边看到的是同一份数据
+    let mut plain = Client::connect(("127.0.0.1", tcp_port)).await.unwrap();
+    let value: Option<String> = plain.get("key").await.unwrap();
+    assert_eq!(value.as_deref(), Some("secret"));
+}
+
+#[tokio::test]
+async fn clients_without_certificates_are_rejected() {
+    let dir = generate_certs("noauth");
+    let (_, tls_port) = start_server(&dir, TlsAuthClients::Yes).await;
+
+    let anonymous = TlsConnector::new(dir.join("ca.pem")).unwrap();
+    assert!(is_rejected(tls_port, anonymous).await);
+}
+
+#[tokio::test]
+async fn client_certificates_can_be_optional() {
+    let dir = generate_certs("optional");
+    for auth in [TlsAuthClients::No, TlsAuthClients::Optional] {
+        let (_, tls_port) = start_server(&dir, auth).await;
+
+        let anonymous = TlsConnector::new(dir.join("ca.pem")).unwrap();
+        let mut client = Client::connect_tls("localhost", tls_port, anonymous)
+            .await
+            .unwrap();
+        client.ping(None).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn untrusted_servers_are_rejected() {
+    let dir = generate_certs("untrusted");
+    let (_, tls_port) = start_server(&dir, TlsAuthClients::Yes).await;
+
+    let untrusted = TlsConnector::new(dir.join("other-ca.pem"))
+        .unwrap()
+        .with_client_cert(dir.join("client.pem"), dir.join("client.key"))
+        .unwrap();
+    assert!(is_rejected(tls_port, untrusted).await);
+
+    //证书里的名字对不上
+    let policy = ReconnectPolicy::never();
+    let res = Client::connect_tls_with_policy("127.0.0.1", tls_port, connector(&dir), policy).await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn blocking_clients_connect_over_tls() {
+    let dir = generate_certs("blocking");
+    let (_, tls_port) = start_server(&dir, TlsAuthClients::Yes).await;
+
+    let value: Option<String> = tokio::task::spawn_blocking(move || {
+        let mut client =
+            BlockingClient::connect_tls("localhost", tls_port, connector(&dir)).unwrap();
+        client.set("key", "blocking").unwrap();
+        client.get("key").unwrap()
+    })
+    .await
+    .unwrap();
+    assert_eq!(value.as_deref(), Some("blocking"));
+}
+
+#[tokio::test]
+async fn tls_port_needs_a_certificate() {
+    let config = Config {
+        tls_port: 16380,
+        ..Config::default()
+    };
+    let err = server::bind(&config).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    bulk,
+    start_server,
+};
+use my_redis::{
+    types::{
+        FromRedisValue,
+        ToRedisArgs,
+    },
+    BlockingClient,
+    Client,
+    Error,
+    Frame,
+    Pipeline,
+};
+use std::collections::HashMap;
+
+fn convert<T: FromRedisValue>(frame: Frame) -> T {
+    T::from_redis_value(frame).unwrap()
+}
+
+#[test]
+fn values_encode_as_arguments() {
+    let args = (
+        "SET",
+        "key",
+        42u32,
+        Some(1.5),
+        None::<&str>,
+        vec![true, false],
+    )
+        .to_args();
+    let expected: Vec<Bytes> = ["SET", "key", "42", "1.5", "1", "0"]
+        .iter()
+        .map(|arg| Bytes::from_static(arg.as_bytes()))
+        .collect();
+    assert_eq!(args, expected);
+    assert_eq!(
+        [String::from("a"), String::from("b")].to_args(),
+        vec![Bytes::from("a"), Bytes::from("b")]
+    );
+}
+
+#[test]
+fn replies_convert_to_rust_types() {
+    assert_eq!(convert::<i64>(bulk("-7")), -7);
+    assert_eq!(convert::<u8>(Frame::Integer(255)), 255);
+    assert_eq!(convert::<f64>(bulk("2.5")), 2.5);
+    assert_eq!(convert::<String>(Frame::Integer(3)), "3");
+    assert_eq!(convert::<String>(Frame::Simple("OK".into())), "OK");
+    assert!(convert::<bool>(Frame::Integer(1)));
+    assert_eq!(convert::<Option<i64>>(Frame::Null), None);
+    assert_eq!(convert::<Option<i64>>(bulk("1")), Some(1));
+
+    let array = Frame::Array(vec![bulk("a"), Frame::Null, bulk("c")]);
+    assert_eq!(
+        convert::<Vec<Option<String>>>(array),
+        vec![Some("a".into()), None, Some("c".into())]
+    );
+    let array = Frame::Array(vec![bulk("a"), Frame::Integer(2), bulk("3.5")]);
+    assert_eq!(
+        convert::<(String, i64, f64)>(array),
+        ("a".to_string(), 2, 3.5)
+    );
+
+    //HGETALL 的回复
+    let array = Frame::Array(vec![
+        bulk("name"),
+        bulk("redis"),
+        bulk("port"),
+        bulk("6379"),
+    ]);
+    let map = convert::<HashMap<String, String>>(array);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["name"], "redis");
+    assert_eq!(map["port"], "6379");
+}
+
+#[test]
+fn conversion_failures_are_typed() {
+    match i64::from_redis_value(bulk("abc")) {
+        Err(Error::TypeConversion(err)) => {
+            assert_eq!(err.target, "i64");
+            assert_eq!(err.reply, "abc");
+        }
+        other => panic!("{:?}", other),
+    }
+
+    let err = u8::from_redis_value(Frame::Integer(256)).unwrap_err();
+    assert!(matches!(err, Error::TypeConversion(_)), "{:?}", err);
+    let err = i64::from_redis_value(Frame::Null).unwrap_err();
+    assert!(matches!(err, Error::TypeConversion(_)), "{:?}", err);
+    let err = <(i64, i64)>::from_redis_value(Frame::Array(vec![Frame::Integer(1)])).unwrap_err();
+    assert!(matches!(err, Error::TypeConversion(_)), "{:?}", err);
+
+    //错误回复原样返回，不是转换错误
+    let err = String::from_redis_value(Frame::Error("WRONGTYPE bad".into())).unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+    assert_eq!(err.to_string(), "WRONGTYPE bad");
+}
+
+#[tokio::test]
+async fn client_gets_typed_values() {
+    let mut client = Client::connect(start_server().await).await.unwrap();
+
+    client.set("number", 42u32).await.unwrap();
+    client.set("name", String::from("redis")).await.unwrap();
+    assert_eq!(client.get::<i64>("number").await.unwrap(), 42);
+    assert_eq!(client.get::<String>("number").await.unwrap(), "42");
+    assert_eq!(client.get::<Option<u32>>("missing").await.unwrap(), None);
+    assert!(client.get::<i64>("name").await.is_err());
+
+    let incremented: i64 = client.query(("INCRBY", "number", 8)).await.unwrap();
+    assert_eq!(incremented, 50);
+    let values: Vec<Option<String>> = client
+        .query(("MGET", ["number", "missing", "name"]))
+        .await
+        .unwrap();
+    assert_eq!(values, vec![Some("50".into()), None, Some("redis".into())]);
+
+    let replies = client
+        .pipeline(Pipeline::new().incr("number").get("name"))
+        .await
+        .unwrap();
+    let (number, name): (i64, String) = convert(Frame::Array(replies));
+    assert_eq!((number, name.as_str()), (51, "redis"));
+}
+
+#[test]
+fn blocking_client_gets_typed_values() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let addr = runtime.block_on(start_server());
+    let mut client = BlockingClient::connect(addr).unwrap();
+
+    client.set("pi", 3.25).unwrap();
+    assert_eq!(client.get::<f64>("pi").unwrap(), 3.25);
+    assert_eq!(
+        client.get::<Option<Bytes>>("pi").unwrap(),
+        Some("3.25".into())
+    );
+    assert_eq!(client.query::<i64, _>(("EXISTS", "pi", "e")).unwrap(), 1);
+}
@@ -0,0 +1,125 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::test_dir;
+use my_redis::{
+    server,
+    BlockingClient,
+    Client,
+    Config,
+    SyncClient,
+};
+use std::{
+    collections::HashMap,
+    os::unix::fs::PermissionsExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+use tokio::time;
+
+async fn start_server(dir: &Path, port: u16, perm: u32) -> PathBuf {
+    let path = dir.join("redis.sock");
+    let config = Config {
+        port,
+        unixsocket: Some(path.clone()),
+        unixsocketperm: perm,
+        dir: dir.to_path_buf(),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let listeners = server::bind(&config).await.unwrap();
+    tokio::spawn(server::serve(listeners, config));
+    path
+}
+
+#[tokio::test]
+async fn clients_connect_over_unix_socket() {
+    let dir = test_dir("unix-async");
+    //port 0：只监听 Unix socket
+    let path = start_server(&dir, 0, 0o700).await;
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+
+    let mut client = Client::connect_unix(&path).await.unwrap();
+    client.set("key", "value").await.unwrap();
+    let value: Option<String> = client.get("key").await.unwrap();
+    assert_eq!(value.as_deref(), Some("value"));
+
+    let values: HashMap<String, String> = client
+        .query(("CONFIG", "GET", "unixsocket*"))
+        .await
+        .unwrap();
+    assert_eq!(values["unixsocket"], path.display().to_string());
+    assert_eq!(values["unixsocketperm"], "700");
+}
+
+#[tokio::test]
+async fn blocking_clients_connect_over_unix_socket() {
+    let dir = test_dir("unix-blocking");
+    let path = start_server(&dir, 0, 0).await;
+
+    let value: Option<String> = tokio::task::spawn_blocking(move || {
+        let mut client = BlockingClient::connect_unix(&path).unwrap();
+        client.set("key", "blocking").unwrap();
+
+        let shared = SyncClient::connect_unix(&path).unwrap();
+        shared.get("key").unwrap()
+    })
+    .await
+    .unwrap();
+    assert_eq!(value.as_deref(), Some("blocking"));
+}
+
+#[tokio::test]
+async fn tcp_and_unix_socket_share_the_keyspace() {
+    let dir = test_dir("unix-both");
+    //先拿一个空闲端口
+    let port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let path = start_server(&dir, port, 0).await;
+
+    let mut unix = Client::connect_unix(&path).await.unwrap();
+    let mut tcp = Client::connect(("127.0.0.1", port)).await.unwrap();
+    unix.set("shared", "over unix").await.unwrap();
+    let value: Option<String> = tcp.get("shared").await.unwrap();
+    assert_eq!(value.as_deref(), Some("over unix"));
+
+    //复制需要 TCP 连接
+    let err = unix.query::<String, _>("SYNC").await.unwrap_err();
+    assert!(err.to_string().contains("TCP"), "{}", err);
+}
+
+#[tokio::test]
+async fn socket_file_is_replaced_and_removed() {
+    let dir = test_dir("unix-stale");
+    let path = dir.join("redis.sock");
+    //上次异常退出留下的文件
+    std::fs::write(&path, b"stale").unwrap();
+
+    let config = Config {
+        port: 0,
+        unixsocket: Some(path.clone()),
+        dir: dir.clone(),
+        save: Vec::new(),
+        ..Config::default()
+    };
+    let listeners = server::bind(&config).await.unwrap();
+    let server = tokio::spawn(server::serve(listeners, config));
+
+    let mut client = Client::connect_unix(&path).await.unwrap();
+    client.ping(None).await.unwrap();
+    client.query::<(), _>("SHUTDOWN").await.unwrap_err();
+    time::timeout(Duration::from_secs(5), server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(!path.exists());
+}